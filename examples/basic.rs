@@ -81,10 +81,11 @@ fn main() -> io::Result<()> {
 
     // Create a memory with our program
     let mut mem: Mem = Mem { mem: code };
+    let mut disk: Mem = Mem { mem: Vec::new() };
 
-    let mut vm = VM::new(&mut mem, 1024 * 1024 * 128);
+    let mut vm = VM::new(&mut mem, 1024 * 1024 * 128, &mut disk);
 
-    vm.cpu.pc = RAM_BASE;
+    vm.harts[0].pc = RAM_BASE;
 
     // let mut byte = [0];
     // let mut val: Option<char> = None;
@@ -109,7 +110,7 @@ fn main() -> io::Result<()> {
                 }
             }
             Err(err) => {
-                println!("Err: {:?}, Cause: {}", err, vm.cpu.csr[MCAUSE]);
+                println!("Err: {:?}, Cause: {}", err, vm.harts[0].csr[MCAUSE]);
                 break;
             }
         }
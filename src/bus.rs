@@ -1,6 +1,9 @@
+use alloc::vec::Vec;
+
 use crate::{
+    cpu::Reservation,
     exceptions::Exception,
-    prelude::{Clint, Plic, Uart, VirtioBlock},
+    prelude::{Clint, Plic, Uart, VirtioBlock, VIRTIO_QUEUE_NOTIFY},
 };
 
 pub const RAM_BASE: u64 = 0x8000_0000;
@@ -25,6 +28,27 @@ pub trait MemIntf {
     fn reset(&mut self);
     fn load(&mut self, addr: u64, size: u64) -> Result<u64, Exception>;
     fn store(&mut self, addr: u64, val: u64, size: u64) -> Result<(), Exception>;
+
+    /// Replaces the value at `addr` with `op` of it and returns the old
+    /// one. Memory shared between threads must do this atomically, calling
+    /// `op` again if it has to retry.
+    fn amo(
+        &mut self,
+        addr: u64,
+        size: u64,
+        op: &mut dyn FnMut(u64) -> u64,
+    ) -> Result<u64, Exception> {
+        let old = self.load(addr, size)?;
+        self.store(addr, op(old), size)?;
+        Ok(old)
+    }
+}
+
+/// Devices shared between threads, which a bus forwards every access
+/// outside of RAM to instead of using its own.
+pub trait SharedDevices: Sync {
+    fn load(&self, addr: u64, size: u64) -> Result<u64, Exception>;
+    fn store(&self, addr: u64, val: u64, size: u64) -> Result<(), Exception>;
 }
 
 pub struct Bus<'a> {
@@ -37,6 +61,15 @@ pub struct Bus<'a> {
     pub uart: Uart,
 
     pub virt_blk: VirtioBlock<'a>,
+
+    /// RAM stores, recorded while other harts need to see them to drop
+    /// their reservations.
+    pub(crate) snoop: bool,
+    pub(crate) stores: Vec<Reservation>,
+
+    /// Where device accesses go instead, for the hart-local buses of a
+    /// threaded machine.
+    pub(crate) shared: Option<&'a dyn SharedDevices>,
 }
 
 impl<'a> Bus<'a> {
@@ -44,21 +77,35 @@ impl<'a> Bus<'a> {
         Bus {
             ram,
             ram_size,
-            plic: Plic::new(),
-            clint: Clint::new(),
+            plic: Plic::new(1),
+            clint: Clint::new(1),
             uart: Uart::new(),
             virt_blk: VirtioBlock::new(disk),
+            snoop: false,
+            stores: Vec::new(),
+            shared: None,
         }
     }
 
+    /// Passes what changed on the devices' interrupt lines to the PLIC.
+    pub fn update_interrupts(&mut self) {
+        self.uart.irq.deliver(&mut self.plic);
+        self.virt_blk.irq.deliver(&mut self.plic);
+    }
+
     pub fn reset(&mut self) {
         self.ram.reset();
         self.plic.reset();
         self.clint.reset();
         self.uart.reset();
+        self.virt_blk.reset();
     }
 
     pub fn load(&mut self, addr: u64, size: u64) -> Result<u64, Exception> {
+        if let Some(shared) = self.shared.filter(|_| addr < RAM_BASE) {
+            return shared.load(addr, size);
+        }
+
         match addr {
             RAM_BASE..=u64::MAX => self.ram.load(addr - RAM_BASE, size),
             PLIC_BASE..=PLIC_END => self.plic.load(addr - PLIC_BASE, size),
@@ -70,13 +117,50 @@ impl<'a> Bus<'a> {
     }
 
     pub fn store(&mut self, addr: u64, val: u64, size: u64) -> Result<(), Exception> {
+        if let Some(shared) = self.shared.filter(|_| addr < RAM_BASE) {
+            return shared.store(addr, val, size);
+        }
+
         match addr {
-            RAM_BASE.. => self.ram.store(addr - RAM_BASE, val, size),
+            RAM_BASE.. => {
+                if self.snoop {
+                    self.stores.push(Reservation { addr, size });
+                }
+                self.ram.store(addr - RAM_BASE, val, size)
+            }
             PLIC_BASE..=PLIC_END => self.plic.store(addr - PLIC_BASE, val, size),
             CLINT_BASE..=CLINT_END => self.clint.store(addr - CLINT_BASE, val, size),
             UART_BASE..=UART_END => self.uart.store(addr - UART_BASE, val, size),
-            VIRTIO_BASE..=VIRTIO_END => self.virt_blk.store(addr, val, size),
+            VIRTIO_BASE..=VIRTIO_END => {
+                self.virt_blk.store(addr, val, size)?;
+                // The disk works through the queue as soon as it is told
+                // to, which takes access to RAM.
+                if addr == VIRTIO_QUEUE_NOTIFY {
+                    self.virt_blk.process_queue(&mut *self.ram);
+                }
+                Ok(())
+            }
             _ => Err(Exception::StoreAMOAccessFault(addr)),
         }
     }
+
+    /// An atomic read-modify-write, see [`MemIntf::amo`]. Only RAM accesses
+    /// are atomic.
+    pub fn amo(
+        &mut self,
+        addr: u64,
+        size: u64,
+        op: &mut dyn FnMut(u64) -> u64,
+    ) -> Result<u64, Exception> {
+        if addr < RAM_BASE {
+            let old = self.load(addr, size)?;
+            self.store(addr, op(old), size)?;
+            return Ok(old);
+        }
+
+        if self.snoop {
+            self.stores.push(Reservation { addr, size });
+        }
+        self.ram.amo(addr - RAM_BASE, size, op)
+    }
 }
@@ -1,49 +1,163 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+
 use crate::prelude::{Exception, MemIntf, CLINT_BASE};
 
+/// Each hart's 32-bit software interrupt register, 4 bytes apart.
+pub const CLINT_MSIP: u64 = 0x0;
+/// Each hart's timer compare, 8 bytes apart.
 pub const CLINT_MTIMECMP: u64 = 0x4000;
 pub const CLINT_MTIME: u64 = 0xbff8;
 
+/// The frequency `mtime` counts at unless configured otherwise, as on
+/// QEMU's virt machine.
+pub const DEFAULT_TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+/// What advances `mtime`.
+pub enum TimeSource {
+    /// Simulated time, as if the machine retired `per_second` instructions
+    /// a second across all of its harts. Runs are then reproducible.
+    Instructions { per_second: u64 },
+    /// The host's clock, as nanoseconds since any fixed point.
+    Host(Box<dyn Fn() -> u64 + Send>),
+}
+
 pub struct Clint {
     mtime: u64,
-    mtimecmp: u64,
+    msip: Vec<bool>,
+    mtimecmp: Vec<u64>,
+
+    /// How many times a second `mtime` counts up.
+    pub frequency: u64,
+    pub source: TimeSource,
+
+    /// Ticks short of a whole one, out of `per_second`, for simulated time.
+    fraction: u64,
+    /// The host time and `mtime` that host time is counted from. Writing
+    /// `mtime` resets it.
+    origin: Option<(u64, u64)>,
 }
 
 impl Clint {
-    pub fn new() -> Self {
+    pub fn new(harts: usize) -> Self {
         Self {
             mtime: 0,
-            mtimecmp: 0,
+            msip: vec![false; harts],
+            mtimecmp: vec![u64::MAX; harts],
+            frequency: DEFAULT_TIMEBASE_FREQUENCY,
+            source: TimeSource::Instructions {
+                per_second: DEFAULT_TIMEBASE_FREQUENCY,
+            },
+            fraction: 0,
+            origin: None,
+        }
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+
+    /// Sets the time, e.g. to skip ahead while a hart waits for the timer.
+    pub fn set_mtime(&mut self, mtime: u64) {
+        self.mtime = mtime;
+        self.fraction = 0;
+        self.origin = None;
+    }
+
+    /// Moves time on after the machine retired `instructions` more
+    /// instructions, or to the host's time.
+    pub fn advance(&mut self, instructions: u64) {
+        match &self.source {
+            TimeSource::Instructions { per_second } => {
+                let ticks = self.fraction as u128 + instructions as u128 * self.frequency as u128;
+                let per_second = (*per_second).max(1) as u128;
+                self.mtime = self.mtime.wrapping_add((ticks / per_second) as u64);
+                self.fraction = (ticks % per_second) as u64;
+            }
+            TimeSource::Host(now) => {
+                let now = now();
+                let (start, mtime) = *self.origin.get_or_insert((now, self.mtime));
+                let elapsed = now.saturating_sub(start) as u128 * self.frequency as u128;
+                self.mtime = mtime.wrapping_add((elapsed / 1_000_000_000) as u64);
+            }
         }
     }
+
+    pub fn mtimecmp(&self, hart: usize) -> u64 {
+        self.mtimecmp[hart]
+    }
+
+    /// Whether `hart`'s machine timer interrupt (MTIP) is pending.
+    pub fn is_interrupting(&self, hart: usize) -> bool {
+        self.mtime >= self.mtimecmp[hart]
+    }
+
+    /// Whether `hart`'s machine software interrupt (MSIP) is pending.
+    pub fn msip(&self, hart: usize) -> bool {
+        self.msip[hart]
+    }
+
+    /// The hart whose register is at `addr`, if it is one of an array of
+    /// `stride`-byte registers from `base`.
+    fn hart(&self, addr: u64, base: u64, stride: u64) -> Option<usize> {
+        let offset = addr.checked_sub(base)?;
+        let hart = (offset / stride) as usize;
+        (offset.is_multiple_of(stride) && hart < self.msip.len()).then_some(hart)
+    }
+
+    /// The 64-bit register at `addr`, which is 8-byte aligned.
+    fn register(&mut self, addr: u64) -> Option<&mut u64> {
+        if addr == CLINT_MTIME {
+            return Some(&mut self.mtime);
+        }
+        let hart = self.hart(addr, CLINT_MTIMECMP, 8)?;
+        Some(&mut self.mtimecmp[hart])
+    }
 }
 
 impl MemIntf for Clint {
     fn reset(&mut self) {
-        self.mtime = 0;
-        self.mtimecmp = 0;
+        self.msip.fill(false);
+        self.mtimecmp.fill(u64::MAX);
+        self.set_mtime(0);
     }
 
     fn load(&mut self, addr: u64, size: u64) -> Result<u64, Exception> {
-        if size != 64 {
-            return Err(Exception::LoadAccessFault(addr + CLINT_BASE));
+        if let Some(hart) = self.hart(addr, CLINT_MSIP, 4).filter(|_| size == 32) {
+            return Ok(self.msip[hart] as u64);
         }
 
-        match addr {
-            CLINT_MTIME => Ok(self.mtime),
-            CLINT_MTIMECMP => Ok(self.mtimecmp),
+        // The 64-bit registers can also be read a 32-bit half at a time.
+        let shift = (addr % 8) * 8;
+        match (self.register(addr & !7), size, shift) {
+            (Some(reg), 64, 0) => Ok(*reg),
+            (Some(reg), 32, 0 | 32) => Ok((*reg >> shift) & 0xffff_ffff),
             _ => Err(Exception::LoadAccessFault(addr + CLINT_BASE)),
         }
     }
 
     fn store(&mut self, addr: u64, val: u64, size: u64) -> Result<(), Exception> {
-        if size != 64 {
-            return Err(Exception::LoadAccessFault(addr + CLINT_BASE));
+        if let Some(hart) = self.hart(addr, CLINT_MSIP, 4).filter(|_| size == 32) {
+            self.msip[hart] = val & 1 != 0;
+            return Ok(());
         }
 
-        match addr {
-            CLINT_MTIME => Ok(self.mtime = val),
-            CLINT_MTIMECMP => Ok(self.mtimecmp = val),
-            _ => Err(Exception::StoreAMOAccessFault(addr + CLINT_BASE)),
+        let shift = (addr % 8) * 8;
+        let reg = match (self.register(addr & !7), size, shift) {
+            (Some(reg), 64, 0) => {
+                *reg = val;
+                *reg
+            }
+            (Some(reg), 32, 0 | 32) => {
+                let mask = 0xffff_ffff << shift;
+                *reg = (*reg & !mask) | ((val << shift) & mask);
+                *reg
+            }
+            _ => return Err(Exception::StoreAMOAccessFault(addr + CLINT_BASE)),
+        };
+
+        if addr & !7 == CLINT_MTIME {
+            self.set_mtime(reg);
         }
+        Ok(())
     }
 }
@@ -0,0 +1,259 @@
+use alloc::string::{String, ToString};
+use core::{fmt, str::FromStr};
+
+use crate::csrs::{
+    MISA_A, MISA_B, MISA_C, MISA_D, MISA_F, MISA_H, MISA_I, MISA_M, MISA_MXL_64, MISA_S, MISA_U,
+    MISA_V,
+};
+
+/// An ISA extension the hart can implement, in the canonical order of ISA
+/// strings: single letters first, then Z extensions by category, then S
+/// extensions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Extension {
+    I,
+    M,
+    A,
+    F,
+    D,
+    C,
+    V,
+    H,
+    Zicntr,
+    Zicsr,
+    Zifencei,
+    Zihpm,
+    Zba,
+    Zbb,
+    Zbc,
+    Zbs,
+    Sdtrig,
+    Sstc,
+}
+
+impl Extension {
+    pub const ALL: [Extension; 18] = [
+        Extension::I,
+        Extension::M,
+        Extension::A,
+        Extension::F,
+        Extension::D,
+        Extension::C,
+        Extension::V,
+        Extension::H,
+        Extension::Zicntr,
+        Extension::Zicsr,
+        Extension::Zifencei,
+        Extension::Zihpm,
+        Extension::Zba,
+        Extension::Zbb,
+        Extension::Zbc,
+        Extension::Zbs,
+        Extension::Sdtrig,
+        Extension::Sstc,
+    ];
+
+    /// The name in ISA strings.
+    pub fn name(self) -> &'static str {
+        match self {
+            Extension::I => "i",
+            Extension::M => "m",
+            Extension::A => "a",
+            Extension::F => "f",
+            Extension::D => "d",
+            Extension::C => "c",
+            Extension::V => "v",
+            Extension::H => "h",
+            Extension::Zicntr => "zicntr",
+            Extension::Zicsr => "zicsr",
+            Extension::Zifencei => "zifencei",
+            Extension::Zihpm => "zihpm",
+            Extension::Zba => "zba",
+            Extension::Zbb => "zbb",
+            Extension::Zbc => "zbc",
+            Extension::Zbs => "zbs",
+            Extension::Sdtrig => "sdtrig",
+            Extension::Sstc => "sstc",
+        }
+    }
+
+    /// The `misa` bit of a single-letter extension.
+    pub fn misa_bit(self) -> Option<u64> {
+        match self {
+            Extension::I => Some(MISA_I),
+            Extension::M => Some(MISA_M),
+            Extension::A => Some(MISA_A),
+            Extension::F => Some(MISA_F),
+            Extension::D => Some(MISA_D),
+            Extension::C => Some(MISA_C),
+            Extension::V => Some(MISA_V),
+            Extension::H => Some(MISA_H),
+            _ => None,
+        }
+    }
+
+    /// Extensions this one depends on, which listing it implies.
+    fn implies(self) -> &'static [Extension] {
+        match self {
+            Extension::F => &[Extension::Zicsr],
+            Extension::D => &[Extension::F],
+            Extension::V => &[Extension::D],
+            Extension::H
+            | Extension::Zicntr
+            | Extension::Zihpm
+            | Extension::Sstc
+            | Extension::Sdtrig => &[Extension::Zicsr],
+            _ => &[],
+        }
+    }
+}
+
+/// Why an ISA string couldn't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IsaError {
+    /// It doesn't start with `rv64i` or `rv64g`.
+    Base,
+    /// An extension this crate doesn't implement, or a malformed name.
+    Unsupported(String),
+}
+
+/// What a hart implements: its extensions, and the IDs it reports in
+/// `mvendorid`, `marchid` and `mimpid`. S- and U-mode are always present.
+///
+/// It parses from ISA strings such as `rv64imafdc_zicsr_zifencei_zba`,
+/// where `g` and `b` stand for their extensions and dependencies are
+/// added, and prints back in canonical form with those expanded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuConfig {
+    extensions: u32,
+    pub mvendorid: u64,
+    pub marchid: u64,
+    pub mimpid: u64,
+}
+
+impl Default for CpuConfig {
+    /// Everything this crate implements.
+    fn default() -> Self {
+        let mut config = CpuConfig::empty();
+        for ext in Extension::ALL {
+            config.add(ext);
+        }
+        config
+    }
+}
+
+impl CpuConfig {
+    /// No extensions at all, not even I.
+    pub fn empty() -> Self {
+        CpuConfig {
+            extensions: 0,
+            mvendorid: 0,
+            marchid: 0,
+            mimpid: 0,
+        }
+    }
+
+    pub fn has(&self, ext: Extension) -> bool {
+        self.extensions & (1 << ext as u32) != 0
+    }
+
+    /// Adds `ext` and the extensions it depends on.
+    pub fn add(&mut self, ext: Extension) -> &mut Self {
+        self.extensions |= 1 << ext as u32;
+        for &dep in ext.implies() {
+            self.add(dep);
+        }
+        self
+    }
+
+    /// Removes `ext` and the extensions that depend on it.
+    pub fn remove(&mut self, ext: Extension) -> &mut Self {
+        self.extensions &= !(1 << ext as u32);
+        for dependent in Extension::ALL {
+            if self.has(dependent) && dependent.implies().contains(&ext) {
+                self.remove(dependent);
+            }
+        }
+        self
+    }
+
+    /// The value of `misa`. B is set when all of Zba, Zbb and Zbs are.
+    pub fn misa(&self) -> u64 {
+        let mut misa = MISA_MXL_64 | MISA_S | MISA_U;
+        for ext in Extension::ALL {
+            if self.has(ext) {
+                misa |= ext.misa_bit().unwrap_or(0);
+            }
+        }
+        if self.has(Extension::Zba) && self.has(Extension::Zbb) && self.has(Extension::Zbs) {
+            misa |= MISA_B;
+        }
+        misa
+    }
+}
+
+impl FromStr for CpuConfig {
+    type Err = IsaError;
+
+    fn from_str(isa: &str) -> Result<Self, Self::Err> {
+        let isa = isa.to_ascii_lowercase();
+        let mut parts = isa.split('_');
+        let letters = parts
+            .next()
+            .and_then(|base| base.strip_prefix("rv64"))
+            .filter(|letters| letters.starts_with(['i', 'g']))
+            .ok_or(IsaError::Base)?;
+
+        let mut config = CpuConfig::empty();
+        for letter in letters.chars() {
+            match letter {
+                'g' => {
+                    for ext in "imafd".chars().filter_map(single_letter) {
+                        config.add(ext);
+                    }
+                    config.add(Extension::Zicsr).add(Extension::Zifencei);
+                }
+                'b' => {
+                    config
+                        .add(Extension::Zba)
+                        .add(Extension::Zbb)
+                        .add(Extension::Zbs);
+                }
+                _ => {
+                    let ext = single_letter(letter)
+                        .ok_or_else(|| IsaError::Unsupported(letter.to_string()))?;
+                    config.add(ext);
+                }
+            }
+        }
+
+        for name in parts {
+            let ext = Extension::ALL
+                .into_iter()
+                .find(|ext| ext.name().len() > 1 && ext.name() == name)
+                .ok_or_else(|| IsaError::Unsupported(name.to_string()))?;
+            config.add(ext);
+        }
+
+        Ok(config)
+    }
+}
+
+fn single_letter(letter: char) -> Option<Extension> {
+    Extension::ALL
+        .into_iter()
+        .find(|ext| ext.name().len() == 1 && ext.name().starts_with(letter))
+}
+
+impl fmt::Display for CpuConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("rv64")?;
+        for ext in Extension::ALL.into_iter().filter(|&ext| self.has(ext)) {
+            if ext.name().len() > 1 {
+                f.write_str("_")?;
+            }
+            f.write_str(ext.name())?;
+        }
+        Ok(())
+    }
+}
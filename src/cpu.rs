@@ -1,132 +1,375 @@
-type Mode = u64;
-const USER: Mode = 0b00;
-const SUPERVISOR: Mode = 0b00;
-const MACHINE: Mode = 0b00;
+/// Privilege level of a hart, ordered from least to most privileged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mode {
+    User = 0b00,
+    Supervisor = 0b01,
+    Machine = 0b11,
+}
+
+impl Mode {
+    /// Decodes a privilege field such as mstatus.MPP. The reserved value
+    /// 0b10 reads as User.
+    pub fn from_bits(bits: u64) -> Self {
+        match bits & 0b11 {
+            0b01 => Mode::Supervisor,
+            0b11 => Mode::Machine,
+            _ => Mode::User,
+        }
+    }
+}
+
+/// How a hart handles loads and stores that aren't naturally aligned.
+/// AMOs, LR and SC always trap.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum MisalignedAccess {
+    /// Split them into byte accesses, which may cross page boundaries.
+    #[default]
+    Emulate,
+    /// Raise address-misaligned exceptions for M-mode software to emulate.
+    Trap,
+}
 
-use core::mem::size_of;
+use core::sync::atomic::{fence, Ordering};
 
 use crate::{
     bus::Bus,
+    config::{CpuConfig, Extension},
     csrs::*,
     exceptions::Exception,
-    inst::{Inst, ENCODING_TABLE},
-    prelude::{
-        Interrupt, VirtioBlkRequest, VirtqAvail, VirtqDesc, VirtqUsed, DESC_NUM, PAGE_SIZE,
-        PLIC_SCLAIM, SECTOR_SIZE, UART_IRQ,
-    },
+    float::{self, Format, F32, F64, RM_DYN, RM_RMM},
+    inst::{decode_compressed, Inst, ENCODING_TABLE},
+    interrupt::MASK_INTERRUPT_BIT,
+    mmu::{AccessContext, AccessType, Mmu},
+    pmp::Pmp,
+    prelude::{plic_context, Interrupt},
+    trigger::{Triggers, DEFAULT_TRIGGERS},
+    vector::{VectorRegs, DEFAULT_VLEN},
 };
 
+// The read and write bits of a fence's predecessor and successor sets.
+const FENCE_R: i64 = 1 << 1;
+const FENCE_W: i64 = 1;
+
+/// Extensions that have to be implemented for `csr` to exist, beyond the
+/// ones needed to access it at all.
+fn csr_extensions(csr: usize) -> &'static [Extension] {
+    match csr {
+        CYCLE..=INSTRET => &[Extension::Zicntr],
+        HPMCOUNTER3..=HPMCOUNTER31 => &[Extension::Zihpm],
+        STIMECMP => &[Extension::Sstc],
+        VSTIMECMP => &[Extension::Sstc, Extension::H],
+        TSELECT..=TCONTROL => &[Extension::Sdtrig],
+        MTVAL2 | MTINST => &[Extension::H],
+        // Hypervisor and VS CSRs.
+        _ if (csr >> 8) & 0b11 == 0b10 => &[Extension::H],
+        _ => &[],
+    }
+}
+
+/// The address range claimed by an `lr` and checked by the matching `sc`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    pub addr: u64,
+    pub size: u64,
+}
+
+impl Reservation {
+    fn overlaps(&self, addr: u64, size: u64) -> bool {
+        addr < self.addr + self.size / 8 && self.addr < addr + size / 8
+    }
+}
+
 pub struct Cpu {
     pub x: [u64; 32],
 
+    /// Floating-point registers. Single-precision values are NaN-boxed.
+    pub f: [u64; 32],
+
     pub pc: u64,
 
+    /// Length in bytes of the instruction being executed (2 or 4).
+    pub inst_len: u64,
+
     pub mode: Mode,
 
-    pub csr: [u64; 4096],
+    /// Virtualization mode (V). While set, `mode` Supervisor and User are
+    /// VS- and VU-mode.
+    pub virt: bool,
+
+    pub csr: CsrFile,
+
+    /// Reservation held by the last `lr`, if it hasn't been invalidated by
+    /// a store, a trap or an `sc` since.
+    pub reservation: Option<Reservation>,
+
+    /// The value the last `lr` read.
+    reserved: u64,
+
+    pub mmu: Mmu,
+
+    pub pmp: Pmp,
+
+    /// Vector registers. Replace with a new `VectorRegs` to change VLEN.
+    pub v: VectorRegs,
+
+    /// Debug triggers. Replace with a new `Triggers` to change their number.
+    pub triggers: Triggers,
+
+    /// How misaligned loads and stores are handled.
+    pub misaligned: MisalignedAccess,
+
+    config: CpuConfig,
+
+    /// Set by `wfi` until an interrupt becomes pending, whether or not it is
+    /// globally enabled. A waiting hart doesn't execute.
+    pub waiting: bool,
+
+    /// Set once the current instruction accesses memory as a guest, which
+    /// `hlv`, `hsv` and MPRV with MPV also do outside of virtualization.
+    /// Their faults report guest virtual addresses.
+    guest_access: bool,
 }
 
 impl Cpu {
     pub fn new() -> Self {
-        let cpu = Cpu {
+        let mut cpu = Cpu {
             x: [0; 32],
+            f: [0; 32],
             pc: 0,
-            csr: [0; 4096],
-            mode: MACHINE,
+            inst_len: 4,
+            csr: CsrFile::new(),
+            mode: Mode::Machine,
+            virt: false,
+            reservation: None,
+            reserved: 0,
+            mmu: Mmu::new(),
+            pmp: Pmp::new(),
+            v: VectorRegs::new(DEFAULT_VLEN),
+            triggers: Triggers::new(DEFAULT_TRIGGERS),
+            misaligned: MisalignedAccess::default(),
+            config: CpuConfig::default(),
+            waiting: false,
+            guest_access: false,
         };
+        cpu.set_config(CpuConfig::default());
+
+        // Start with the FPU and vector unit enabled (FS = VS = Initial) so
+        // bare-metal code doesn't need to flip mstatus before its first
+        // float or vector instruction.
+        cpu.csr[MSTATUS] |= (0b01 << 13) | (0b01 << 9);
 
         cpu
     }
 
     pub fn tick(&mut self, bus: &mut Bus) -> Result<Inst, Exception> {
+        if self.csr[MCOUNTINHIBIT] & MASK_CY == 0 {
+            self.csr[MCYCLE] = self.csr[MCYCLE].wrapping_add(1);
+        }
+
+        self.guest_access = false;
+
+        // Pending icount triggers fire before the next instruction.
+        if self.triggers.icount_fires(self.mode, self.virt) {
+            return Err(Exception::Breakpoint(0));
+        }
+
         let inst = self.fetch(bus)?;
 
-        self.execute(inst, bus)
+        // Execute triggers match the address or the encoding of the
+        // instruction, and fire before it runs.
+        if self.triggers.check(
+            AccessType::Instruction,
+            self.pc,
+            Some(inst as u64),
+            self.mode,
+            self.virt,
+        ) {
+            return Err(Exception::Breakpoint(self.pc));
+        }
+        let (mode, virt) = (self.mode, self.virt);
+
+        // Instructions see the address of the next instruction in pc. If
+        // one traps, rewind so that the exception points back at it.
+        self.pc = self.pc.wrapping_add(self.inst_len);
+        let next_pc = self.pc;
+
+        match self.execute(inst, bus) {
+            Ok(inst) => {
+                self.retire(inst, next_pc);
+                self.triggers.count_instruction(mode, virt);
+                Ok(inst)
+            }
+            Err(e) => {
+                self.pc = self.pc.wrapping_sub(self.inst_len);
+                Err(e)
+            }
+        }
+    }
+
+    /// Updates the counters for a retired instruction.
+    fn retire(&mut self, inst: Inst, next_pc: u64) {
+        // An explicit write to minstret takes precedence over the increment.
+        if self.csr[MCOUNTINHIBIT] & MASK_IR == 0 && inst.csr_access() != Some((MINSTRET, true)) {
+            self.csr[MINSTRET] = self.csr[MINSTRET].wrapping_add(1);
+        }
+
+        if inst.is_load() {
+            self.csr.count_event(HPM_EVENT_LOADS);
+        }
+        if inst.is_store() {
+            self.csr.count_event(HPM_EVENT_STORES);
+        }
+        if inst.is_branch() {
+            self.csr.count_event(HPM_EVENT_BRANCHES);
+            if self.pc != next_pc {
+                self.csr.count_event(HPM_EVENT_BRANCHES_TAKEN);
+            }
+        }
+    }
+
+    pub fn config(&self) -> &CpuConfig {
+        &self.config
+    }
+
+    /// Changes what the hart implements, and the `misa` and ID registers
+    /// describing it.
+    pub fn set_config(&mut self, config: CpuConfig) -> &mut Self {
+        self.csr[MISA] = config.misa();
+        self.csr[MVENDORID] = config.mvendorid;
+        self.csr[MARCHID] = config.marchid;
+        self.csr[MIMPID] = config.mimpid;
+        self.config = config;
+
+        self
     }
 
     pub fn reset(&mut self) -> &mut Self {
         self.pc = 0;
         self.x = [0; 32];
+        self.f = [0; 32];
+        self.v.reset();
+        self.reservation = None;
+        self.waiting = false;
+        self.mmu.flush_all();
+        self.pmp.reset();
+        self.triggers.reset();
 
         self
     }
 
     pub fn handle_exception(&mut self, e: Exception) {
-        let pc = self.pc;
-        let mode = self.mode;
-        let cause = e.code();
-
-        let trap_in_s_mode =
-            mode <= SUPERVISOR && (self.csr[MEDELEG].wrapping_shr(cause as u32) & 1) == 1;
-        let (STATUS, TVEC, CAUSE, TVAL, EPC, MASK_PIE, pie_i, MASK_IE, ie_i, MASK_PP, pp_i) =
-            if trap_in_s_mode {
-                self.mode = SUPERVISOR;
-                (
-                    SSTATUS, STVEC, SCAUSE, STVAL, SEPC, MASK_SPIE, 5, MASK_SIE, 1, MASK_SPP, 8,
-                )
-            } else {
-                self.mode = MACHINE;
-                (
-                    MSTATUS, MTVEC, MCAUSE, MTVAL, MEPC, MASK_MPIE, 7, MASK_MIE, 3, MASK_MPP, 11,
-                )
-            };
-
-        self.pc = self.csr[TVEC] & !0b11;
+        self.csr.count_event(HPM_EVENT_EXCEPTIONS);
 
-        self.csr[EPC] = pc;
+        let cause = e.code();
+        let delegated = |deleg: u64| deleg.wrapping_shr(cause as u32) & 1 == 1;
 
-        self.csr[CAUSE] = cause;
+        // Address-valued trap values are guest virtual addresses for
+        // guest accesses.
+        let gva = e.has_address() && (self.virt || self.guest_access);
 
-        self.csr[TVAL] = e.value();
+        if self.mode == Mode::Machine || !delegated(self.csr[MEDELEG]) {
+            self.trap(Mode::Machine, false, cause, e.value(), e.guest_addr(), gva);
+        } else if self.virt && delegated(self.csr[HEDELEG]) {
+            self.trap(Mode::Supervisor, true, cause, e.value(), 0, false);
+        } else {
+            self.trap(
+                Mode::Supervisor,
+                false,
+                cause,
+                e.value(),
+                e.guest_addr(),
+                gva,
+            );
+        }
+    }
 
-        let mut status = self.csr[STATUS];
-        let ie = (status & MASK_IE) >> ie_i;
+    pub fn handle_interrupt(&mut self, interrupt: Interrupt) {
+        self.csr.count_event(HPM_EVENT_INTERRUPTS);
 
-        status = (status & !MASK_PIE) | (ie << pie_i);
-        status &= !MASK_IE;
-        status = (status & !MASK_PP) | (mode << pp_i);
+        let cause = interrupt.code();
+        let bit = 1 << (cause & !MASK_INTERRUPT_BIT);
 
-        self.csr[STATUS] = status;
+        if self.mode == Mode::Machine || self.csr[MIDELEG] & bit == 0 {
+            self.trap(Mode::Machine, false, cause, 0, 0, false);
+        } else if self.virt && self.csr[HIDELEG] & bit != 0 {
+            // VS-level interrupts appear to the guest as supervisor ones.
+            self.trap(Mode::Supervisor, true, cause - 1, 0, 0, false);
+        } else {
+            self.trap(Mode::Supervisor, false, cause, 0, 0, false);
+        }
     }
 
-    pub fn handle_interrupt(&mut self, interrupt: Interrupt) {
+    /// Enters the trap handler of `mode` (VS-mode if `virt`), saving the
+    /// interrupted context in that mode's CSRs. `gpa` is the guest physical
+    /// address of a guest-page fault, and `gva` whether `tval` is a guest
+    /// virtual address.
+    fn trap(&mut self, mode: Mode, virt: bool, cause: u64, tval: u64, gpa: u64, gva: bool) {
+        self.reservation = None;
+
         let pc = self.pc;
-        let mode = self.mode;
-        let cause = interrupt.code();
+        let prev_mode = self.mode as u64;
+        let prev_virt = self.virt as u64;
+
+        let tvec = match (mode, virt) {
+            (Mode::Machine, _) => {
+                self.csr[MEPC] = pc;
+                self.csr[MCAUSE] = cause;
+                self.csr[MTVAL] = tval;
+                self.csr[MTVAL2] = gpa >> 2;
+                self.csr[MTINST] = 0;
+                self.triggers.trap_to_machine();
+
+                let mut status = push_ie(self.csr[MSTATUS], MASK_MIE, MASK_MPIE);
+                status &= !(MASK_MPP | MASK_MPV | MASK_GVA);
+                status |= (prev_mode << 11) | (prev_virt << 39) | ((gva as u64) << 38);
+                self.csr[MSTATUS] = status;
+
+                self.csr[MTVEC]
+            }
+            (_, false) => {
+                self.csr[SEPC] = pc;
+                self.csr[SCAUSE] = cause;
+                self.csr[STVAL] = tval;
+                self.csr[HTVAL] = gpa >> 2;
+                self.csr[HTINST] = 0;
+
+                let mut status = push_ie(self.csr[MSTATUS], MASK_SIE, MASK_SPIE);
+                status = (status & !MASK_SPP) | ((prev_mode & 1) << 8);
+                self.csr[MSTATUS] = status;
+
+                // SPVP only changes on traps from a guest.
+                let mut hstatus = self.csr[HSTATUS] & !(MASK_SPV | MASK_HSTATUS_GVA);
+                hstatus |= (prev_virt << 7) | ((gva as u64) << 6);
+                if prev_virt != 0 {
+                    hstatus = (hstatus & !MASK_SPVP) | ((prev_mode & 1) << 8);
+                }
+                self.csr[HSTATUS] = hstatus;
 
-        let trap_in_s_mode =
-            mode <= SUPERVISOR && (self.csr[MIDELEG].wrapping_shr(cause as u32) & 1) == 1;
-        let (STATUS, TVEC, CAUSE, TVAL, EPC, MASK_PIE, pie_i, MASK_IE, ie_i, MASK_PP, pp_i) =
-            if trap_in_s_mode {
-                self.mode = SUPERVISOR;
-                (
-                    SSTATUS, STVEC, SCAUSE, STVAL, SEPC, MASK_SPIE, 5, MASK_SIE, 1, MASK_SPP, 8,
-                )
-            } else {
-                self.mode = MACHINE;
-                (
-                    MSTATUS, MTVEC, MCAUSE, MTVAL, MEPC, MASK_MPIE, 7, MASK_MIE, 3, MASK_MPP, 11,
-                )
-            };
+                self.csr[STVEC]
+            }
+            (_, true) => {
+                self.csr[VSEPC] = pc;
+                self.csr[VSCAUSE] = cause;
+                self.csr[VSTVAL] = tval;
 
-        let tvec = self.csr[TVEC];
-        let tvec_mode = tvec & 0b11;
-        let tvec_base = tvec & !0b11;
-        match tvec_mode {
-            0 => self.pc = tvec_base,
-            1 => self.pc = tvec_base + cause << 2,
-            _ => unreachable!(),
-        }
+                let mut status = push_ie(self.csr[VSSTATUS], MASK_SIE, MASK_SPIE);
+                status = (status & !MASK_SPP) | ((prev_mode & 1) << 8);
+                self.csr[VSSTATUS] = status;
+
+                self.csr[VSTVEC]
+            }
+        };
 
-        self.csr[EPC] = pc;
-        self.csr[CAUSE] = cause;
-        self.csr[TVAL] = 0;
+        self.mode = mode;
+        self.virt = virt;
 
-        let mut status = self.csr[STATUS];
-        status = (status & !MASK_PIE) | (ie_i << pie_i);
-        status &= !MASK_IE;
-        status = (status & !MASK_PP) | (mode << pp_i);
-        self.csr[STATUS] = status;
+        // Vectored mode sends interrupts to base + 4 * cause.
+        let base = tvec & !MASK_TVEC_MODE;
+        self.pc = if tvec & MASK_TVEC_MODE == 1 && cause & MASK_INTERRUPT_BIT != 0 {
+            base + 4 * (cause & !MASK_INTERRUPT_BIT)
+        } else {
+            base
+        };
     }
 
     pub fn check_pending_interrupt(
@@ -135,116 +378,634 @@ impl Cpu {
     ) -> Result<Option<Interrupt>, Exception> {
         use Interrupt::*;
 
-        if (self.mode == MACHINE) && (self.csr[MSTATUS] & MASK_MIE) == 0 {
-            return Ok(None);
+        let hart = self.csr[MHARTID] as usize;
+
+        self.set_pending(
+            MASK_MEIP,
+            bus.plic.is_interrupting(plic_context(hart, false)),
+        );
+        self.set_pending(
+            MASK_SEIP,
+            bus.plic.is_interrupting(plic_context(hart, true)),
+        );
+        self.set_pending(MASK_MSIP, bus.clint.msip(hart));
+        self.set_pending(MASK_MTIP, bus.clint.is_interrupting(hart));
+
+        // With Sstc, STIP and VSTIP are driven by the timer compares. The
+        // latter then replaces hvip.VSTIP rather than adding to it.
+        let time = bus.clint.mtime();
+        if self.csr[MENVCFG] & MASK_STCE != 0 {
+            self.set_pending(MASK_STIP, time >= self.csr[STIMECMP]);
         }
-        if (self.mode == SUPERVISOR) && (self.csr[SSTATUS] & MASK_SIE) == 0 {
-            return Ok(None);
+        if self.csr.read(HENVCFG) & MASK_STCE != 0 {
+            let guest_time = time.wrapping_add(self.csr[HTIMEDELTA]);
+            self.set_pending(MASK_VSTIP, guest_time >= self.csr[VSTIMECMP]);
         }
 
-        if bus.uart.is_interrupting() {
-            bus.store(PLIC_SCLAIM, UART_IRQ, 32)?;
-            self.csr[MIP] |= MASK_SEIP;
+        // Interrupts handled by a more privileged level than the current
+        // one are always enabled, those handled by the current level only
+        // when its xIE bit allows.
+        let mstatus = self.csr[MSTATUS];
+        let mideleg = self.csr[MIDELEG];
+        let hideleg = self.csr[HIDELEG];
+        let mut enabled = 0;
+        if self.mode < Mode::Machine || mstatus & MASK_MIE != 0 {
+            enabled |= !mideleg;
+        }
+        if self.virt
+            || self.mode == Mode::User
+            || (self.mode == Mode::Supervisor && mstatus & MASK_SIE != 0)
+        {
+            enabled |= mideleg & !hideleg;
+        }
+        if self.virt && (self.mode == Mode::User || self.csr[VSSTATUS] & MASK_SIE != 0) {
+            enabled |= mideleg & hideleg;
         }
 
-        let pending = self.csr[MIE] & self.csr[MIP];
+        let mip = self.csr.read(MIP);
+        if self.csr[MIE] & mip != 0 {
+            self.waiting = false;
+        }
 
-        if (pending & MASK_MEIP) != 0 {
-            self.csr[MIP] &= !MASK_MEIP;
-            return Ok(Some(MachineExternalInterrupt));
+        let pending = self.csr[MIE] & mip & enabled;
+
+        // In decreasing priority.
+        for (mask, interrupt) in [
+            (MASK_MEIP, MachineExternalInterrupt),
+            (MASK_MSIP, MachineSoftwareInterrupt),
+            (MASK_MTIP, MachineTimerInterrupt),
+            (MASK_SEIP, SupervisorExternalInterrupt),
+            (MASK_SSIP, SupervisorSoftwareInterrupt),
+            (MASK_STIP, SupervisorTimerInterrupt),
+            (MASK_SGEIP, SupervisorGuestExternalInterrupt),
+            (MASK_VSEIP, VirtualSupervisorExternalInterrupt),
+            (MASK_VSSIP, VirtualSupervisorSoftwareInterrupt),
+            (MASK_VSTIP, VirtualSupervisorTimerInterrupt),
+        ] {
+            if pending & mask != 0 {
+                return Ok(Some(interrupt));
+            }
         }
-        if (pending & MASK_MSIP) != 0 {
-            self.csr[MIP] &= !MASK_MSIP;
-            return Ok(Some(MachineSoftwareInterrupt));
+
+        Ok(None)
+    }
+
+    /// The earliest time after the current one at which one of the timers
+    /// fires. A compare of `u64::MAX` counts as disarmed.
+    pub fn next_timer_deadline(&self, bus: &Bus) -> Option<u64> {
+        let time = bus.clint.mtime();
+        let mtimecmp = bus.clint.mtimecmp(self.csr[MHARTID] as usize);
+        let stimecmp = (self.csr[MENVCFG] & MASK_STCE != 0).then_some(self.csr[STIMECMP]);
+        let vstimecmp = (self.csr.read(HENVCFG) & MASK_STCE != 0)
+            .then(|| self.csr[VSTIMECMP].wrapping_sub(self.csr[HTIMEDELTA]));
+
+        [Some(mtimecmp), stimecmp, vstimecmp]
+            .into_iter()
+            .flatten()
+            .filter(|&deadline| deadline > time && deadline != u64::MAX)
+            .min()
+    }
+
+    fn set_pending(&mut self, mask: u64, pending: bool) {
+        if pending {
+            self.csr[MIP] |= mask;
+        } else {
+            self.csr[MIP] &= !mask;
         }
-        if (pending & MASK_MTIP) != 0 {
-            self.csr[MIP] &= !MASK_MTIP;
-            return Ok(Some(MachineTimerInterrupt));
+    }
+
+    /// Checks that the current privilege level may access `csr`. Bits 9:8
+    /// of the address give the lowest privilege allowed, 0b10 marking
+    /// hypervisor and VS CSRs that need HS-mode, and bits 11:10 set to
+    /// 0b11 mark it read-only. Accesses HS-mode could make but the current
+    /// guest mode can't raise virtual-instruction exceptions.
+    fn check_csr_access(&self, csr: usize, write: bool, raw: u32) -> Result<(), Exception> {
+        let illegal = Exception::IllegalInstruction(raw as u64);
+        let virtual_inst = Exception::VirtualInstruction(raw as u64);
+
+        if !self.csr.exists(csr) || (write && (csr >> 10) & 0b11 == 0b11) {
+            return Err(illegal);
         }
-        if (pending & MASK_SEIP) != 0 {
-            self.csr[MIP] &= !MASK_SEIP;
-            return Ok(Some(SupervisorExternalInterrupt));
+        if !csr_extensions(csr).iter().all(|&ext| self.config.has(ext)) {
+            return Err(illegal);
         }
-        if (pending & MASK_STIP) != 0 {
-            self.csr[MIP] &= !MASK_STIP;
-            return Ok(Some(SupervisorTimerInterrupt));
+        match (csr >> 8) & 0b11 {
+            0b11 if self.mode < Mode::Machine => return Err(illegal),
+            0b01 | 0b10 if self.mode == Mode::User && !self.virt => return Err(illegal),
+            0b10 if self.virt => return Err(virtual_inst),
+            0b01 if self.virt && self.mode == Mode::User => return Err(virtual_inst),
+            _ => {}
         }
-        if (pending & MASK_SSIP) != 0 {
-            self.csr[MIP] &= !MASK_SSIP;
-            return Ok(Some(SupervisorSoftwareInterrupt));
+
+        // Counters are visible below M-mode only when enabled by
+        // mcounteren, to guests also by hcounteren, and in U-mode also by
+        // scounteren.
+        if let CYCLE..=HPMCOUNTER31 = csr {
+            let bit = 1 << (csr - CYCLE);
+            if self.mode < Mode::Machine && self.csr[MCOUNTEREN] & bit == 0 {
+                return Err(illegal);
+            }
+            if self.virt && self.csr[HCOUNTEREN] & bit == 0 {
+                return Err(virtual_inst);
+            }
+            if self.mode == Mode::User && self.csr[SCOUNTEREN] & bit == 0 {
+                return Err(if self.virt { virtual_inst } else { illegal });
+            }
         }
 
-        Ok(None)
-    }
+        // Sstc's timer compares need time to be visible and menvcfg.STCE
+        // (henvcfg.STCE for guests) to be set.
+        if let STIMECMP | VSTIMECMP = csr {
+            if self.mode < Mode::Machine
+                && (self.csr[MCOUNTEREN] & MASK_TM == 0 || self.csr[MENVCFG] & MASK_STCE == 0)
+            {
+                return Err(illegal);
+            }
+            if self.virt
+                && (self.csr[HCOUNTEREN] & MASK_TM == 0 || self.csr.read(HENVCFG) & MASK_STCE == 0)
+            {
+                return Err(virtual_inst);
+            }
+        }
 
-    fn disk_access(&mut self, bus: &mut Bus) {
-        const desc_size: u64 = size_of::<VirtqDesc>() as u64;
+        // mstatus.TVM traps HS-mode satp and hgatp accesses, and
+        // hstatus.VTVM VS-mode satp ones.
+        if self.mode == Mode::Supervisor {
+            if !self.virt && matches!(csr, SATP | HGATP) && self.csr[MSTATUS] & MASK_TVM != 0 {
+                return Err(illegal);
+            }
+            if self.virt && csr == SATP && self.csr[HSTATUS] & MASK_VTVM != 0 {
+                return Err(virtual_inst);
+            }
+        }
 
-        let desc_addr = bus.virt_blk.desc_addr();
-        let avail_addr = desc_addr + DESC_NUM as u64 * desc_size;
-        let used_addr = desc_addr + PAGE_SIZE;
+        Ok(())
+    }
 
-        let virtq_avail = unsafe { &(*(avail_addr as *const VirtqAvail)) };
-        let virtq_used = unsafe { &(*(used_addr as *const VirtqUsed)) };
+    /// In VS-mode, supervisor CSRs are replaced by their VS counterparts.
+    fn redirect_csr(&self, csr: usize) -> usize {
+        if !self.virt {
+            return csr;
+        }
 
-        let idx = bus.load(&virtq_avail.idx as *const _ as u64, 16).unwrap() as usize;
-        let index = bus
-            .load(&virtq_avail.ring[idx % DESC_NUM] as *const _ as u64, 16)
-            .unwrap();
+        match csr {
+            SSTATUS => VSSTATUS,
+            SIE => VSIE,
+            STVEC => VSTVEC,
+            SSCRATCH => VSSCRATCH,
+            SEPC => VSEPC,
+            SCAUSE => VSCAUSE,
+            STVAL => VSTVAL,
+            SIP => VSIP,
+            STIMECMP => VSTIMECMP,
+            SATP => VSATP,
+            _ => csr,
+        }
+    }
 
-        // The first descriptor:
-        // which contains the request information and a pointer to the data descriptor.
-        let desc_addr0 = desc_addr + desc_size * index;
-        let virtq_desc0 = unsafe { &(*(desc_addr0 as *const VirtqDesc)) };
-        // The addr field points to a virtio block request. We need the sector number stored
-        // in the sector field. The iotype tells us whether to read or write.
-        let req_addr = bus.load(&virtq_desc0.addr as *const _ as u64, 64).unwrap();
-        let virtq_blk_req = unsafe { &(*(req_addr as *const VirtioBlkRequest)) };
-        let blk_sector = bus
-            .load(&virtq_blk_req.sector as *const _ as u64, 64)
-            .unwrap();
-        let iotype = bus
-            .load(&virtq_blk_req.iotype as *const _ as u64, 32)
-            .unwrap() as u32;
-        // The next field points to the second descriptor. (data descriptor)
-        let next0 = bus.load(&virtq_desc0.next as *const _ as u64, 16).unwrap();
+    /// Reads a CSR on behalf of a Zicsr instruction.
+    fn read_csr(&self, bus: &Bus, csr: usize) -> u64 {
+        match self.redirect_csr(csr) {
+            // Guests see time shifted by htimedelta.
+            TIME if self.virt => bus.clint.mtime().wrapping_add(self.csr[HTIMEDELTA]),
+            TIME => bus.clint.mtime(),
+            VLENB => self.v.vlenb(),
+            PMPCFG0..=PMPCFG15 => self.pmp.read_cfg(csr - PMPCFG0),
+            PMPADDR0..=PMPADDR63 => self.pmp.read_addr(csr - PMPADDR0),
+            TSELECT..=TCONTROL => self.triggers.read(csr),
+            csr => self.csr.read(csr),
+        }
+    }
 
-        // the second descriptor.
-        let desc_addr1 = desc_addr + desc_size * next0;
-        let virtq_desc1 = unsafe { &(*(desc_addr1 as *const VirtqDesc)) };
-        // The addr field points to the data to read or write
-        let addr1 = bus.load(&virtq_desc1.addr as *const _ as u64, 64).unwrap();
-        // the len donates the size of the data
-        let len1 = bus.load(&virtq_desc1.len as *const _ as u64, 32).unwrap();
+    /// The value csrrs and csrrc update. For mip, the PLIC's SEIP is left
+    /// out so that it isn't written back as software's.
+    fn read_csr_for_update(&self, bus: &Bus, csr: usize) -> u64 {
+        match csr {
+            MIP => self.csr.written_mip(),
+            csr => self.read_csr(bus, csr),
+        }
+    }
 
-        match iotype {
-            VIRTIO_BLK_T_OUT => {
-                for i in 0..len1 {
-                    let data = bus.load(addr1 + i, 8).unwrap();
-                    bus.virt_blk.write_disk(blk_sector * SECTOR_SIZE + i, data);
+    /// Writes a CSR on behalf of a Zicsr instruction, applying side effects
+    /// on the rest of the hart.
+    fn write_csr(&mut self, csr: usize, val: u64) {
+        let csr = self.redirect_csr(csr);
+
+        match csr {
+            PMPCFG0..=PMPCFG15 => self.pmp.write_cfg(csr - PMPCFG0, val),
+            PMPADDR0..=PMPADDR63 => self.pmp.write_addr(csr - PMPADDR0, val),
+            TSELECT..=TCONTROL => self.triggers.write(csr, val),
+            // Without H, MPV and GVA are read-only zero.
+            MSTATUS if !self.config.has(Extension::H) => {
+                self.csr.write(MSTATUS, val & !(MASK_MPV | MASK_GVA))
+            }
+            FFLAGS | FRM | FCSR => {
+                self.csr.write(csr, val);
+                self.dirty_fs();
+            }
+            // vstart only needs to hold the largest element index.
+            VSTART => {
+                self.csr.write(VSTART, val & (self.v.vlen() - 1));
+                self.dirty_vs();
+            }
+            VXSAT | VXRM | VCSR => {
+                self.csr.write(csr, val);
+                self.dirty_vs();
+            }
+            SATP | VSATP => {
+                // Writes selecting an unsupported mode have no effect.
+                if self.mmu.supports((val & MASK_SATP_MODE) >> 60) {
+                    self.csr.write(csr, val);
+                    match csr {
+                        SATP => self.mmu.flush(None, None),
+                        _ => self.mmu.flush_guest(None, None, None),
+                    }
                 }
             }
-            VIRTIO_BLK_T_IN => {
-                for i in 0..len1 {
-                    let data = bus.virt_blk.read_disk(blk_sector * SECTOR_SIZE + i);
-                    bus.store(addr1 + i, 8, data as u64).unwrap();
+            HGATP => {
+                // The root table is 16 KiB aligned.
+                if self.mmu.supports((val & MASK_HGATP_MODE) >> 60) {
+                    let mask = MASK_HGATP_MODE | MASK_HGATP_VMID | (MASK_HGATP_PPN & !0b11);
+                    self.csr.write(HGATP, val & mask);
+                    self.mmu.flush_guest(None, None, None);
                 }
             }
-            _ => unreachable!(),
+            _ => self.csr.write(csr, val),
+        }
+    }
+
+    /// Privilege of a data or instruction access by the current hart, and
+    /// whether it is a guest access. With MPRV set, loads and stores use
+    /// the privilege in MPP and MPV.
+    fn access_privilege(&self, access: AccessType) -> (Mode, bool) {
+        let mstatus = self.csr[MSTATUS];
+
+        if access != AccessType::Instruction && mstatus & MASK_MPRV != 0 {
+            let mode = Mode::from_bits((mstatus & MASK_MPP) >> 11);
+            (mode, mode != Mode::Machine && mstatus & MASK_MPV != 0)
+        } else {
+            (self.mode, self.virt)
+        }
+    }
+
+    /// Translates a virtual address for an access of `size` bits by the
+    /// current hart and checks the physical address against the PMP.
+    fn translate(
+        &mut self,
+        bus: &mut Bus,
+        addr: u64,
+        size: u64,
+        access: AccessType,
+    ) -> Result<u64, Exception> {
+        let (mode, virt) = self.access_privilege(access);
+        self.translate_as(bus, addr, size, access, mode, virt, false)
+    }
+
+    /// Translates an access made with privilege `mode`, by a guest if
+    /// `virt`, and checks the physical address against the PMP. `hlvx`
+    /// makes loads require execute permission.
+    #[allow(clippy::too_many_arguments)]
+    fn translate_as(
+        &mut self,
+        bus: &mut Bus,
+        addr: u64,
+        size: u64,
+        access: AccessType,
+        mode: Mode,
+        virt: bool,
+        hlvx: bool,
+    ) -> Result<u64, Exception> {
+        let mstatus = self.csr[MSTATUS];
+        self.guest_access |= virt;
+
+        // Address triggers on loads and stores fire before the access.
+        if access != AccessType::Instruction
+            && self
+                .triggers
+                .check(access, addr, None, self.mode, self.virt)
+        {
+            return Err(Exception::Breakpoint(addr));
         }
 
-        let new_id = bus.virt_blk.get_new_id();
-        bus.store(&virtq_used.idx as *const _ as u64, 16, new_id % 8)
-            .unwrap();
+        let paddr = if mode == Mode::Machine {
+            addr
+        } else {
+            // Guests translate through vsatp, under their own vsstatus
+            // SUM and MXR, and then through hgatp.
+            let status = if virt { self.csr[VSSTATUS] } else { mstatus };
+            let ctx = AccessContext {
+                satp: self.csr[if virt { VSATP } else { SATP }],
+                hgatp: virt.then_some(self.csr[HGATP]),
+                mode,
+                sum: status & MASK_SUM != 0,
+                mxr: (status | mstatus) & MASK_MXR != 0,
+                hs_mxr: mstatus & MASK_MXR != 0,
+                hlvx,
+            };
+            self.mmu.translate(bus, &self.pmp, addr, access, &ctx)?
+        };
+
+        if !self.pmp.check(paddr, size, access, mode) {
+            return Err(access.access_fault(addr));
+        }
+
+        Ok(paddr)
+    }
+
+    /// Loads from virtual memory.
+    pub(crate) fn load(&mut self, bus: &mut Bus, addr: u64, size: u64) -> Result<u64, Exception> {
+        let (mode, virt) = self.access_privilege(AccessType::Load);
+        let val = self.load_as(bus, addr, size, mode, virt, false)?;
+
+        // Load data triggers see the value, so they fire after the access
+        // but before the destination is written.
+        if self
+            .triggers
+            .check(AccessType::Load, addr, Some(val), self.mode, self.virt)
+        {
+            return Err(Exception::Breakpoint(addr));
+        }
+
+        Ok(val)
+    }
+
+    /// Stores to virtual memory, dropping the reservation if the store
+    /// touches it.
+    pub(crate) fn store(
+        &mut self,
+        bus: &mut Bus,
+        addr: u64,
+        val: u64,
+        size: u64,
+    ) -> Result<(), Exception> {
+        let data = val & (u64::MAX >> (64 - size));
+        if self
+            .triggers
+            .check(AccessType::Store, addr, Some(data), self.mode, self.virt)
+        {
+            return Err(Exception::Breakpoint(addr));
+        }
+
+        let (mode, virt) = self.access_privilege(AccessType::Store);
+        self.store_as(bus, addr, val, size, mode, virt)
+    }
+
+    /// Loads with privilege `mode`, by a guest if `virt`. Misaligned loads
+    /// are read byte by byte, each translated on its own, unless the
+    /// policy is to trap.
+    fn load_as(
+        &mut self,
+        bus: &mut Bus,
+        addr: u64,
+        size: u64,
+        mode: Mode,
+        virt: bool,
+        hlvx: bool,
+    ) -> Result<u64, Exception> {
+        if addr.is_multiple_of(size / 8) {
+            let paddr = self.translate_as(bus, addr, size, AccessType::Load, mode, virt, hlvx)?;
+            return bus.load(paddr, size);
+        }
+        if self.misaligned == MisalignedAccess::Trap {
+            return Err(Exception::LoadAccessMisaligned(addr));
+        }
+
+        let mut val = 0;
+        for i in 0..size / 8 {
+            let byte = addr.wrapping_add(i);
+            let paddr = self.translate_as(bus, byte, 8, AccessType::Load, mode, virt, hlvx)?;
+            val |= bus.load(paddr, 8)? << (i * 8);
+        }
+
+        Ok(val)
+    }
+
+    /// Stores with privilege `mode`, by a guest if `virt`. Misaligned
+    /// stores are written byte by byte unless the policy is to trap, with
+    /// every byte translated first so a fault leaves memory unchanged.
+    fn store_as(
+        &mut self,
+        bus: &mut Bus,
+        addr: u64,
+        val: u64,
+        size: u64,
+        mode: Mode,
+        virt: bool,
+    ) -> Result<(), Exception> {
+        if addr.is_multiple_of(size / 8) {
+            let paddr = self.translate_as(bus, addr, size, AccessType::Store, mode, virt, false)?;
+            bus.store(paddr, val, size)?;
+            self.invalidate_reservation(paddr, size);
+            return Ok(());
+        }
+        if self.misaligned == MisalignedAccess::Trap {
+            return Err(Exception::StoreAMOAddrMisaligned(addr));
+        }
+
+        let len = (size / 8) as usize;
+        let mut paddrs = [0; 8];
+        for (i, paddr) in paddrs[..len].iter_mut().enumerate() {
+            let byte = addr.wrapping_add(i as u64);
+            *paddr = self.translate_as(bus, byte, 8, AccessType::Store, mode, virt, false)?;
+        }
+        for (i, &paddr) in paddrs[..len].iter().enumerate() {
+            bus.store(paddr, (val >> (i * 8)) & 0xff, 8)?;
+            self.invalidate_reservation(paddr, 8);
+        }
+
+        Ok(())
+    }
+
+    /// Loads for `hlv` and `hlvx`, as a guest with the privilege in
+    /// `hstatus.SPVP`.
+    fn hypervisor_load(
+        &mut self,
+        bus: &mut Bus,
+        addr: u64,
+        size: u64,
+        hlvx: bool,
+    ) -> Result<u64, Exception> {
+        let mode = Mode::from_bits((self.csr[HSTATUS] & MASK_SPVP) >> 8);
+        self.load_as(bus, addr, size, mode, true, hlvx)
+    }
+
+    /// Stores for `hsv`, as a guest with the privilege in `hstatus.SPVP`.
+    fn hypervisor_store(
+        &mut self,
+        bus: &mut Bus,
+        addr: u64,
+        val: u64,
+        size: u64,
+    ) -> Result<(), Exception> {
+        let mode = Mode::from_bits((self.csr[HSTATUS] & MASK_SPVP) >> 8);
+        self.store_as(bus, addr, val, size, mode, true)
+    }
+
+    /// Hypervisor loads, stores and fences may be used in M- and HS-mode,
+    /// and the loads and stores in U-mode too when `hstatus.HU` is set.
+    fn check_hypervisor_access(&self, raw: u32, fence: bool) -> Result<(), Exception> {
+        if self.virt {
+            Err(Exception::VirtualInstruction(raw as u64))
+        } else if self.mode == Mode::User && (fence || self.csr[HSTATUS] & MASK_HU == 0) {
+            Err(Exception::IllegalInstruction(raw as u64))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Drops the reservation if a store to `paddr` touches it.
+    pub(crate) fn invalidate_reservation(&mut self, paddr: u64, size: u64) {
+        if matches!(self.reservation, Some(r) if r.overlaps(paddr, size)) {
+            self.reservation = None;
+        }
+    }
+
+    /// Performs an atomic read-modify-write of `size` bits at `x[rs1]`,
+    /// storing `op(old, x[rs2])` and returning the old value in `x[rd]`.
+    /// Word operands are sign-extended before `op` sees them.
+    fn amo(
+        &mut self,
+        bus: &mut Bus,
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+        size: u64,
+        op: impl Fn(u64, u64) -> u64,
+    ) -> Result<(), Exception> {
+        let addr = self.x[rs1];
+        if !addr.is_multiple_of(size / 8) {
+            return Err(Exception::StoreAMOAddrMisaligned(addr));
+        }
+
+        // AMOs fault as stores even if the load half is what fails.
+        let paddr = self.translate(bus, addr, size, AccessType::Store)?;
+        let sext = |val: u64| match size {
+            32 => val as i32 as u64,
+            _ => val,
+        };
+        let src = sext(self.x[rs2]);
+        let old = bus
+            .amo(paddr, size, &mut |old| op(sext(old), src))
+            .map_err(|_| Exception::StoreAMOAccessFault(addr))?;
+        let old = sext(old);
+
+        self.invalidate_reservation(paddr, size);
+        self.x[rd] = old;
+
+        Ok(())
+    }
+
+    /// Marks the floating-point state dirty, in `vsstatus` too for guests.
+    fn dirty_fs(&mut self) {
+        self.csr.dirty_fs();
+        if self.virt {
+            self.csr[VSSTATUS] |= MASK_FS | MASK_SD;
+        }
+    }
+
+    /// Marks the vector state dirty, in `vsstatus` too for guests.
+    pub(crate) fn dirty_vs(&mut self) {
+        self.csr.dirty_vs();
+        if self.virt {
+            self.csr[VSSTATUS] |= MASK_VS | MASK_SD;
+        }
+    }
+
+    /// Reads a floating-point operand. Single-precision values that aren't
+    /// properly NaN-boxed read as the canonical NaN.
+    pub(crate) fn read_fp(&self, fmt: Format, reg: usize) -> u64 {
+        if fmt == F64 {
+            self.f[reg]
+        } else if self.f[reg] >> 32 == 0xffff_ffff {
+            self.f[reg] & 0xffff_ffff
+        } else {
+            F32.canonical_nan()
+        }
+    }
+
+    /// Writes a floating-point result, NaN-boxing single-precision values.
+    pub(crate) fn write_fp(&mut self, fmt: Format, reg: usize, val: u64) {
+        self.f[reg] = if fmt == F64 {
+            val
+        } else {
+            val | 0xffff_ffff_0000_0000
+        };
+        self.dirty_fs();
+    }
+
+    /// Resolves the static or dynamic rounding mode of an instruction.
+    pub(crate) fn rounding_mode(&self, rm: u64, inst: u32) -> Result<u64, Exception> {
+        let rm = if rm == RM_DYN { self.csr.read(FRM) } else { rm };
+
+        if rm > RM_RMM {
+            Err(Exception::IllegalInstruction(inst as u64))
+        } else {
+            Ok(rm)
+        }
+    }
+
+    pub(crate) fn accrue_fflags(&mut self, flags: u64) {
+        if flags != 0 {
+            self.csr[FCSR] |= flags;
+            self.dirty_fs();
+        }
+    }
+
+    /// Instruction address alignment: 2 bytes with the C extension, 4 without.
+    fn ialign(&self) -> u64 {
+        if self.csr[MISA] & MISA_C != 0 {
+            2
+        } else {
+            4
+        }
+    }
+
+    /// Checks the target of a control transfer and returns it.
+    fn jump_target(&self, target: u64) -> Result<u64, Exception> {
+        if target & (self.ialign() - 1) != 0 {
+            Err(Exception::InstructionAddrMisalignment(target))
+        } else {
+            Ok(target)
+        }
+    }
+
+    /// Address of the instruction being executed.
+    fn inst_addr(&self) -> u64 {
+        self.pc.wrapping_sub(self.inst_len)
     }
 
+    /// Fetches the instruction at pc in 16-bit parcels, so a 32-bit
+    /// instruction may straddle a page boundary.
     fn fetch(&mut self, bus: &mut Bus) -> Result<u32, Exception> {
-        let inst = bus.load(self.pc, 32)? as u32;
-        self.pc += 4;
-        Ok(inst)
+        let paddr = self.translate(bus, self.pc, 16, AccessType::Instruction)?;
+        let low = bus
+            .load(paddr, 16)
+            .map_err(|_| Exception::InstructionAccessFault(self.pc))? as u32;
+
+        if low & 0b11 != 0b11 {
+            if self.csr[MISA] & MISA_C == 0 {
+                return Err(Exception::IllegalInstruction(low as u64));
+            }
+
+            self.inst_len = 2;
+            return Ok(low);
+        }
+
+        let addr = self.pc.wrapping_add(2);
+        let paddr = self.translate(bus, addr, 16, AccessType::Instruction)?;
+        let high = bus
+            .load(paddr, 16)
+            .map_err(|_| Exception::InstructionAccessFault(addr))? as u32;
+
+        self.inst_len = 4;
+        Ok(low | (high << 16))
     }
 
     fn decode(&self, inst: u32) -> Result<Inst, Exception> {
+        if inst & 0b11 != 0b11 {
+            return decode_compressed(inst as u16);
+        }
+
         let opcode = inst & 0b1111111;
 
         if let Some(typ) = &ENCODING_TABLE[opcode as usize] {
@@ -254,8 +1015,36 @@ impl Cpu {
         }
     }
 
-    fn execute(&mut self, inst: u32, bus: &mut Bus) -> Result<Inst, Exception> {
-        let inst = self.decode(inst)?;
+    /// Decodes `inst` if the hart implements the extensions it needs.
+    fn decode_supported(&self, inst: u32) -> Result<Inst, Exception> {
+        let decoded = self.decode(inst)?;
+
+        let has = |ext| self.config.has(ext);
+        if !has(decoded.extension())
+            || (decoded.is_fp() && !has(Extension::F))
+            || (decoded.is_vector() && !has(Extension::V))
+        {
+            return Err(Exception::IllegalInstruction(inst.into()));
+        }
+        Ok(decoded)
+    }
+
+    fn execute(&mut self, raw: u32, bus: &mut Bus) -> Result<Inst, Exception> {
+        let inst = self.decode_supported(raw)?;
+
+        // Guests also need the unit enabled in vsstatus.
+        let off =
+            |mask| self.csr[MSTATUS] & mask == 0 || (self.virt && self.csr[VSSTATUS] & mask == 0);
+        if inst.is_fp() && off(MASK_FS) {
+            return Err(Exception::IllegalInstruction(raw as u64));
+        }
+        if inst.is_vector() && off(MASK_VS) {
+            return Err(Exception::IllegalInstruction(raw as u64));
+        }
+
+        if let Some((csr, write)) = inst.csr_access() {
+            self.check_csr_access(csr, write, raw)?;
+        }
 
         self.x[0] = 0;
 
@@ -325,7 +1114,7 @@ impl Cpu {
                 Ok(inst)
             }
             Inst::Auipc { rd, imm } => {
-                self.x[rd] = (self.pc).wrapping_add(imm as u64);
+                self.x[rd] = self.inst_addr().wrapping_add(imm as u64);
                 Ok(inst)
             }
             Inst::Add { rd, rs1, rs2 } => {
@@ -353,15 +1142,15 @@ impl Cpu {
                 Ok(inst)
             }
             Inst::Sll { rd, rs1, rs2 } => {
-                self.x[rd] = self.x[rs1] << (self.x[rs2] & 0x0000001f);
+                self.x[rd] = self.x[rs1] << (self.x[rs2] & 0x3f);
                 Ok(inst)
             }
             Inst::Srl { rd, rs1, rs2 } => {
-                self.x[rd] = self.x[rs1] >> (self.x[rs2] & 0x0000001f);
+                self.x[rd] = self.x[rs1] >> (self.x[rs2] & 0x3f);
                 Ok(inst)
             }
             Inst::Sra { rd, rs1, rs2 } => {
-                self.x[rd] = ((self.x[rs1] as i64) >> (self.x[rs2] & 0x0000001f)) as u64;
+                self.x[rd] = ((self.x[rs1] as i64) >> (self.x[rs2] & 0x3f)) as u64;
                 Ok(inst)
             }
             Inst::Addw { rd, rs1, rs2 } => {
@@ -379,13 +1168,13 @@ impl Cpu {
             Inst::Sllw { rd, rs1, rs2 } => {
                 let x = (self.x[rs1] & 0xffffffff) as u32;
                 let y = (self.x[rs2] & 0xffffffff) as u32;
-                self.x[rd] = (x << (y & 0x1f)) as u64;
+                self.x[rd] = (x << (y & 0x1f)) as i32 as u64;
                 Ok(inst)
             }
             Inst::Srlw { rd, rs1, rs2 } => {
                 let x = (self.x[rs1] & 0xffffffff) as u32;
                 let y = (self.x[rs2] & 0xffffffff) as u32;
-                self.x[rd] = (x >> (y & 0x1f)) as u64;
+                self.x[rd] = (x >> (y & 0x1f)) as i32 as u64;
                 Ok(inst)
             }
             Inst::Sraw { rd, rs1, rs2 } => {
@@ -395,106 +1184,111 @@ impl Cpu {
                 Ok(inst)
             }
             Inst::Jal { rd, imm } => {
+                let target = self.jump_target(self.inst_addr().wrapping_add(imm as u64))?;
                 self.x[rd] = self.pc;
-                self.pc = (self.pc).wrapping_add(imm as u64).wrapping_sub(4);
+                self.pc = target;
                 Ok(inst)
             }
             Inst::Jalr { rd, rs1, imm } => {
+                let target = self.jump_target((self.x[rs1]).wrapping_add(imm as u64) & (!0b1))?;
                 self.x[rd] = self.pc;
-                self.pc = (self.x[rs1]).wrapping_add(imm as u64) & (!0b1);
+                self.pc = target;
                 Ok(inst)
             }
             Inst::Beq { rs1, rs2, imm } => {
                 if self.x[rs1] == self.x[rs2] {
-                    self.pc = (self.pc).wrapping_add(imm as u64).wrapping_sub(4);
+                    self.pc = self.jump_target(self.inst_addr().wrapping_add(imm as u64))?;
                 }
                 Ok(inst)
             }
             Inst::Bne { rs1, rs2, imm } => {
                 if self.x[rs1] != self.x[rs2] {
-                    self.pc = (self.pc).wrapping_add(imm as u64).wrapping_sub(4);
+                    self.pc = self.jump_target(self.inst_addr().wrapping_add(imm as u64))?;
                 }
                 Ok(inst)
             }
             Inst::Blt { rs1, rs2, imm } => {
                 if (self.x[rs1] as i64) < self.x[rs2] as i64 {
-                    self.pc = (self.pc).wrapping_add(imm as u64).wrapping_sub(4);
+                    self.pc = self.jump_target(self.inst_addr().wrapping_add(imm as u64))?;
                 }
                 Ok(inst)
             }
             Inst::Bltu { rs1, rs2, imm } => {
                 if self.x[rs1] < self.x[rs2] {
-                    self.pc = (self.pc).wrapping_add(imm as u64).wrapping_sub(4);
+                    self.pc = self.jump_target(self.inst_addr().wrapping_add(imm as u64))?;
                 }
                 Ok(inst)
             }
             Inst::Bge { rs1, rs2, imm } => {
                 if (self.x[rs1] as i64) >= (self.x[rs2] as i64) {
-                    self.pc = (self.pc).wrapping_add(imm as u64).wrapping_sub(4);
+                    self.pc = self.jump_target(self.inst_addr().wrapping_add(imm as u64))?;
                 }
                 Ok(inst)
             }
             Inst::Bgeu { rs1, rs2, imm } => {
                 if self.x[rs1] >= self.x[rs2] {
-                    self.pc = (self.pc).wrapping_add(imm as u64).wrapping_sub(4);
+                    self.pc = self.jump_target(self.inst_addr().wrapping_add(imm as u64))?;
                 }
                 Ok(inst)
             }
             Inst::Ld { rd, rs1, imm } => {
-                self.x[rd] = bus.load((self.x[rs1]).wrapping_add(imm as u64), 64)?;
+                self.x[rd] = self.load(bus, (self.x[rs1]).wrapping_add(imm as u64), 64)?;
                 Ok(inst)
             }
             Inst::Lw { rd, rs1, imm } => {
-                self.x[rd] =
-                    bus.load((self.x[rs1]).wrapping_add(imm as u64), 32)? as i32 as i64 as u64;
+                self.x[rd] = self.load(bus, (self.x[rs1]).wrapping_add(imm as u64), 32)? as i32
+                    as i64 as u64;
                 Ok(inst)
             }
             Inst::Lwu { rd, rs1, imm } => {
-                self.x[rd] = bus.load((self.x[rs1]).wrapping_add(imm as u64), 32)? as u64;
+                self.x[rd] = self.load(bus, (self.x[rs1]).wrapping_add(imm as u64), 32)?;
                 Ok(inst)
             }
             Inst::Lh { rd, rs1, imm } => {
-                self.x[rd] =
-                    bus.load((self.x[rs1]).wrapping_add(imm as u64), 16)? as i16 as i64 as u64;
+                self.x[rd] = self.load(bus, (self.x[rs1]).wrapping_add(imm as u64), 16)? as i16
+                    as i64 as u64;
                 Ok(inst)
             }
             Inst::Lhu { rd, rs1, imm } => {
-                self.x[rd] = bus.load((self.x[rs1]).wrapping_add(imm as u64), 16)? as u64;
+                self.x[rd] = self.load(bus, (self.x[rs1]).wrapping_add(imm as u64), 16)?;
                 Ok(inst)
             }
             Inst::Lb { rd, rs1, imm } => {
                 self.x[rd] =
-                    bus.load((self.x[rs1]).wrapping_add(imm as u64), 8)? as i8 as i64 as u64;
+                    self.load(bus, (self.x[rs1]).wrapping_add(imm as u64), 8)? as i8 as i64 as u64;
                 Ok(inst)
             }
             Inst::Lbu { rd, rs1, imm } => {
-                self.x[rd] = bus.load((self.x[rs1]).wrapping_add(imm as u64), 8)? as u64;
+                self.x[rd] = self.load(bus, (self.x[rs1]).wrapping_add(imm as u64), 8)?;
                 Ok(inst)
             }
             Inst::Sd { rs1, rs2, imm } => {
-                bus.store((self.x[rs1]).wrapping_add(imm as u64), self.x[rs2], 64)?;
+                self.store(bus, (self.x[rs1]).wrapping_add(imm as u64), self.x[rs2], 64)?;
                 Ok(inst)
             }
             Inst::Sw { rs1, rs2, imm } => {
-                bus.store(
+                self.store(
+                    bus,
                     (self.x[rs1]).wrapping_add(imm as u64),
-                    (self.x[rs2] & 0xffffffff),
+                    self.x[rs2] & 0xffffffff,
                     32,
                 )?;
                 Ok(inst)
             }
             Inst::Sh { rs1, rs2, imm } => {
-                bus.store(
+                self.store(
+                    bus,
                     (self.x[rs1]).wrapping_add(imm as u64),
-                    (self.x[rs2] & 0xffff),
+                    self.x[rs2] & 0xffff,
                     16,
                 )?;
                 Ok(inst)
             }
             Inst::Sb { rs1, rs2, imm } => {
-                bus.store(
+                self.store(
+                    bus,
                     (self.x[rs1]).wrapping_add(imm as u64),
-                    (self.x[rs2] & 0xff),
+                    self.x[rs2] & 0xff,
                     8,
                 )?;
                 Ok(inst)
@@ -502,86 +1296,294 @@ impl Cpu {
             Inst::Fence {
                 rd: _rd,
                 rs1: _rs1,
-                imm: _imm,
-            } => Ok(inst),
-            Inst::Sfencevma => Ok(inst),
-            Inst::Ecall => Ok(inst),
-            Inst::Ebreak => Ok(inst),
-
-            // CSRs implementation
-            Inst::Csrrw { rd, rs1, csr } => {
-                if rd != 0 {
-                    self.x[rd] = self.csr[csr];
+                imm,
+            } => {
+                // Harts on other threads see memory through the host's model,
+                // where only a full fence orders earlier stores before later
+                // loads.
+                let (pred, succ) = ((imm >> 4) & 0b1111, imm & 0b1111);
+                if pred & FENCE_W != 0 && succ & FENCE_R != 0 {
+                    fence(Ordering::SeqCst);
+                } else if pred != 0 && succ != 0 {
+                    fence(Ordering::AcqRel);
                 }
-
-                self.csr[csr] = self.x[rs1];
                 Ok(inst)
             }
-            Inst::Csrrs { rd, rs1, csr } => {
-                self.x[rd] = self.csr[csr];
+            // Instructions are fetched and decoded afresh every time, so
+            // there is nothing to synchronize.
+            Inst::Fencei { .. } => Ok(inst),
+            Inst::Sfencevma { rs1, rs2 } => {
+                if self.virt && (self.mode == Mode::User || self.csr[HSTATUS] & MASK_VTVM != 0) {
+                    return Err(Exception::VirtualInstruction(raw as u64));
+                }
+                if self.mode == Mode::User
+                    || (self.mode == Mode::Supervisor
+                        && !self.virt
+                        && self.csr[MSTATUS] & MASK_TVM != 0)
+                {
+                    return Err(Exception::IllegalInstruction(raw as u64));
+                }
 
-                if rs1 != 0 {
-                    self.csr[csr] |= self.x[rs1];
+                let vaddr = if rs1 == 0 { None } else { Some(self.x[rs1]) };
+                let asid = if rs2 == 0 {
+                    None
+                } else {
+                    Some(self.x[rs2] & 0xffff)
+                };
+                // In VS-mode this only affects the guest's translations.
+                if self.virt {
+                    let vmid = (self.csr[HGATP] & MASK_HGATP_VMID) >> 44;
+                    self.mmu.flush_guest(Some(vmid), vaddr, asid);
+                } else {
+                    self.mmu.flush(vaddr, asid);
                 }
+
                 Ok(inst)
             }
-            Inst::Csrrc { rd, rs1, csr } => {
-                self.x[rd] = self.csr[csr];
+            Inst::Hfencevvma { rs1, rs2 } => {
+                self.check_hypervisor_access(raw, true)?;
 
-                if rs1 != 0 {
-                    self.csr[csr] &= !self.x[rs1];
-                }
+                let vmid = (self.csr[HGATP] & MASK_HGATP_VMID) >> 44;
+                let vaddr = if rs1 == 0 { None } else { Some(self.x[rs1]) };
+                let asid = if rs2 == 0 {
+                    None
+                } else {
+                    Some(self.x[rs2] & 0xffff)
+                };
+                self.mmu.flush_guest(Some(vmid), vaddr, asid);
+
+                Ok(inst)
+            }
+            Inst::Hfencegvma { rs1: _, rs2 } => {
+                self.check_hypervisor_access(raw, true)?;
+                if self.mode == Mode::Supervisor && self.csr[MSTATUS] & MASK_TVM != 0 {
+                    return Err(Exception::IllegalInstruction(raw as u64));
+                }
+
+                // Translations are cached by guest virtual address, so a
+                // guest physical address can only flush the whole guest.
+                let vmid = if rs2 == 0 {
+                    None
+                } else {
+                    Some(self.x[rs2] & (MASK_HGATP_VMID >> 44))
+                };
+                self.mmu.flush_guest(vmid, None, None);
+
+                Ok(inst)
+            }
+            Inst::HlvB { rd, rs1 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.x[rd] = self.hypervisor_load(bus, self.x[rs1], 8, false)? as i8 as u64;
+                Ok(inst)
+            }
+            Inst::HlvBu { rd, rs1 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.x[rd] = self.hypervisor_load(bus, self.x[rs1], 8, false)?;
+                Ok(inst)
+            }
+            Inst::HlvH { rd, rs1 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.x[rd] = self.hypervisor_load(bus, self.x[rs1], 16, false)? as i16 as u64;
+                Ok(inst)
+            }
+            Inst::HlvHu { rd, rs1 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.x[rd] = self.hypervisor_load(bus, self.x[rs1], 16, false)?;
+                Ok(inst)
+            }
+            Inst::HlvxHu { rd, rs1 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.x[rd] = self.hypervisor_load(bus, self.x[rs1], 16, true)?;
+                Ok(inst)
+            }
+            Inst::HlvW { rd, rs1 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.x[rd] = self.hypervisor_load(bus, self.x[rs1], 32, false)? as i32 as u64;
+                Ok(inst)
+            }
+            Inst::HlvWu { rd, rs1 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.x[rd] = self.hypervisor_load(bus, self.x[rs1], 32, false)?;
+                Ok(inst)
+            }
+            Inst::HlvxWu { rd, rs1 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.x[rd] = self.hypervisor_load(bus, self.x[rs1], 32, true)?;
+                Ok(inst)
+            }
+            Inst::HlvD { rd, rs1 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.x[rd] = self.hypervisor_load(bus, self.x[rs1], 64, false)?;
+                Ok(inst)
+            }
+            Inst::HsvB { rs1, rs2 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.hypervisor_store(bus, self.x[rs1], self.x[rs2], 8)?;
+                Ok(inst)
+            }
+            Inst::HsvH { rs1, rs2 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.hypervisor_store(bus, self.x[rs1], self.x[rs2], 16)?;
+                Ok(inst)
+            }
+            Inst::HsvW { rs1, rs2 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.hypervisor_store(bus, self.x[rs1], self.x[rs2], 32)?;
+                Ok(inst)
+            }
+            Inst::HsvD { rs1, rs2 } => {
+                self.check_hypervisor_access(raw, false)?;
+                self.hypervisor_store(bus, self.x[rs1], self.x[rs2], 64)?;
+                Ok(inst)
+            }
+            Inst::Ecall => Err(match (self.mode, self.virt) {
+                (Mode::User, _) => Exception::EnvironmentCallFromUMode(self.inst_addr()),
+                (Mode::Supervisor, false) => Exception::EnvironmentCallFromSMode(self.inst_addr()),
+                (Mode::Supervisor, true) => Exception::EnvironmentCallFromVSMode(self.inst_addr()),
+                (Mode::Machine, _) => Exception::EnvironmentCallFromMMode(self.inst_addr()),
+            }),
+            Inst::Ebreak => Err(Exception::Breakpoint(self.inst_addr())),
+            // With TW set, wfi below M-mode traps, as if it timed out
+            // immediately. U-mode may never wait, and guests only in VS-mode
+            // without hstatus.VTW.
+            Inst::Wfi => {
+                if (self.mode == Mode::User && !self.virt)
+                    || (self.mode < Mode::Machine && self.csr[MSTATUS] & MASK_TW != 0)
+                {
+                    return Err(Exception::IllegalInstruction(raw as u64));
+                }
+                if self.virt && (self.mode == Mode::User || self.csr[HSTATUS] & MASK_VTW != 0) {
+                    return Err(Exception::VirtualInstruction(raw as u64));
+                }
+
+                self.waiting = true;
+                Ok(inst)
+            }
+
+            // CSRs implementation
+            Inst::Csrrw { rd, rs1, csr } => {
+                let val = self.x[rs1];
+
+                if rd != 0 {
+                    self.x[rd] = self.read_csr(bus, csr);
+                }
+
+                self.write_csr(csr, val);
+                Ok(inst)
+            }
+            Inst::Csrrs { rd, rs1, csr } => {
+                let old = self.read_csr(bus, csr);
+                let mask = self.x[rs1];
+
+                self.x[rd] = old;
+
+                if rs1 != 0 {
+                    self.write_csr(csr, self.read_csr_for_update(bus, csr) | mask);
+                }
+                Ok(inst)
+            }
+            Inst::Csrrc { rd, rs1, csr } => {
+                let old = self.read_csr(bus, csr);
+                let mask = self.x[rs1];
+
+                self.x[rd] = old;
+
+                if rs1 != 0 {
+                    self.write_csr(csr, self.read_csr_for_update(bus, csr) & !mask);
+                }
                 Ok(inst)
             }
             Inst::Csrrwi { rd, uimm, csr } => {
                 if rd != 0 {
-                    self.x[rd] = self.csr[csr];
+                    self.x[rd] = self.read_csr(bus, csr);
                 }
 
-                self.csr[csr] = uimm;
+                self.write_csr(csr, uimm);
                 Ok(inst)
             }
             Inst::Csrrsi { rd, uimm, csr } => {
-                self.x[rd] = self.csr[csr];
+                let old = self.read_csr(bus, csr);
+
+                self.x[rd] = old;
 
                 if uimm != 0 {
-                    self.csr[csr] |= uimm;
+                    self.write_csr(csr, self.read_csr_for_update(bus, csr) | uimm);
                 }
                 Ok(inst)
             }
             Inst::Csrrci { rd, uimm, csr } => {
-                self.x[rd] = self.csr[csr];
+                let old = self.read_csr(bus, csr);
+
+                self.x[rd] = old;
 
                 if uimm != 0 {
-                    self.csr[csr] &= !uimm;
+                    self.write_csr(csr, self.read_csr_for_update(bus, csr) & !uimm);
                 }
                 Ok(inst)
             }
             Inst::Sret => {
-                let mut sstatus = self.csr[SSTATUS];
-                self.mode = (sstatus & MASK_SPP) >> 8;
+                if self.virt {
+                    if self.mode == Mode::User || self.csr[HSTATUS] & MASK_VTSR != 0 {
+                        return Err(Exception::VirtualInstruction(raw as u64));
+                    }
+
+                    // Returns within the guest use its own vsstatus and vsepc.
+                    let mut vsstatus = self.csr[VSSTATUS];
+                    self.mode = Mode::from_bits((vsstatus & MASK_SPP) >> 8);
+                    let spie = (vsstatus & MASK_SPIE) >> 5;
+                    vsstatus = (vsstatus & !MASK_SIE) | (spie << 1);
+                    vsstatus |= MASK_SPIE;
+                    vsstatus &= !MASK_SPP;
+                    self.csr[VSSTATUS] = vsstatus;
+
+                    self.pc = self.csr[VSEPC] & !(self.ialign() - 1);
+
+                    return Ok(inst);
+                }
+                if self.mode == Mode::User
+                    || (self.mode == Mode::Supervisor && self.csr[MSTATUS] & MASK_TSR != 0)
+                {
+                    return Err(Exception::IllegalInstruction(raw as u64));
+                }
+
+                let mut sstatus = self.csr[MSTATUS];
+                self.mode = Mode::from_bits((sstatus & MASK_SPP) >> 8);
                 let spie = (sstatus & MASK_SPIE) >> 5;
                 sstatus = (sstatus & !MASK_SIE) | (spie << 1);
                 sstatus |= MASK_SPIE;
                 sstatus &= !MASK_SPP;
-                self.csr[SSTATUS] = sstatus;
+                sstatus &= !MASK_MPRV;
+                self.csr[MSTATUS] = sstatus;
+
+                // hstatus.SPV says whether to return into a guest.
+                self.virt = self.csr[HSTATUS] & MASK_SPV != 0;
+                self.csr[HSTATUS] &= !MASK_SPV;
 
-                self.pc = self.csr[SEPC] & !0b11;
+                self.pc = self.csr[SEPC] & !(self.ialign() - 1);
 
                 Ok(inst)
             }
             Inst::Mret => {
+                if self.mode != Mode::Machine {
+                    return Err(Exception::IllegalInstruction(raw as u64));
+                }
+
                 let mut mstatus = self.csr[MSTATUS];
 
-                self.mode = (mstatus & MASK_MPP) >> 11;
+                self.mode = Mode::from_bits((mstatus & MASK_MPP) >> 11);
+                self.virt = self.mode != Mode::Machine && mstatus & MASK_MPV != 0;
                 let mpie = (mstatus & MASK_MPIE) >> 7;
                 mstatus = (mstatus & !MASK_MIE) | (mpie << 3);
                 mstatus |= MASK_MPIE;
-                mstatus &= !MASK_MPP;
-                mstatus &= !MASK_MPRV;
+                mstatus &= !(MASK_MPP | MASK_MPV);
+                self.triggers.machine_return();
+                // MPRV only survives returns to M-mode.
+                if self.mode != Mode::Machine {
+                    mstatus &= !MASK_MPRV;
+                }
                 self.csr[MSTATUS] = mstatus;
 
-                self.pc = self.csr[MEPC] & !0b11;
+                self.pc = self.csr[MEPC] & !(self.ialign() - 1);
 
                 Ok(inst)
             }
@@ -590,89 +1592,766 @@ impl Cpu {
 
                 Ok(inst)
             }
+            Inst::Mulh { rd, rs1, rs2 } => {
+                let product = (self.x[rs1] as i64 as i128) * (self.x[rs2] as i64 as i128);
+                self.x[rd] = (product >> 64) as u64;
+
+                Ok(inst)
+            }
+            Inst::Mulhsu { rd, rs1, rs2 } => {
+                let product =
+                    (self.x[rs1] as i64 as i128).wrapping_mul(self.x[rs2] as u128 as i128);
+                self.x[rd] = (product >> 64) as u64;
+
+                Ok(inst)
+            }
+            Inst::Mulhu { rd, rs1, rs2 } => {
+                let product = (self.x[rs1] as u128) * (self.x[rs2] as u128);
+                self.x[rd] = (product >> 64) as u64;
+
+                Ok(inst)
+            }
+            Inst::Mulw { rd, rs1, rs2 } => {
+                let x = self.x[rs1] as i32;
+                let y = self.x[rs2] as i32;
+                self.x[rd] = x.wrapping_mul(y) as i64 as u64;
+
+                Ok(inst)
+            }
+            // Division never traps: dividing by zero yields all ones for the
+            // quotient and the dividend for the remainder, and signed overflow
+            // (MIN / -1) yields MIN with a remainder of zero.
             Inst::Div { rd, rs1, rs2 } => {
-                self.x[rd] = self.x[rs1].wrapping_div(self.x[rs2]);
+                let dividend = self.x[rs1] as i64;
+                let divisor = self.x[rs2] as i64;
+                self.x[rd] = match divisor {
+                    0 => u64::MAX,
+                    _ => dividend.wrapping_div(divisor) as u64,
+                };
 
                 Ok(inst)
             }
             Inst::Divu { rd, rs1, rs2 } => {
-                self.x[rd] = match self.x[rs2] {
-                    0 => 0xffff_ffff_ffff_ffff,
-                    _ => {
-                        let dividend = self.x[rs1];
-                        let divisor = self.x[rs2];
+                let dividend = self.x[rs1];
+                let divisor = self.x[rs2];
+                self.x[rd] = match divisor {
+                    0 => u64::MAX,
+                    _ => dividend / divisor,
+                };
 
-                        dividend.wrapping_div(divisor)
-                    }
+                Ok(inst)
+            }
+            Inst::Rem { rd, rs1, rs2 } => {
+                let dividend = self.x[rs1] as i64;
+                let divisor = self.x[rs2] as i64;
+                self.x[rd] = match divisor {
+                    0 => dividend as u64,
+                    _ => dividend.wrapping_rem(divisor) as u64,
+                };
+
+                Ok(inst)
+            }
+            Inst::Remu { rd, rs1, rs2 } => {
+                let dividend = self.x[rs1];
+                let divisor = self.x[rs2];
+                self.x[rd] = match divisor {
+                    0 => dividend,
+                    _ => dividend % divisor,
+                };
+
+                Ok(inst)
+            }
+            Inst::Divw { rd, rs1, rs2 } => {
+                let dividend = self.x[rs1] as i32;
+                let divisor = self.x[rs2] as i32;
+                self.x[rd] = match divisor {
+                    0 => u64::MAX,
+                    _ => dividend.wrapping_div(divisor) as i64 as u64,
+                };
+
+                Ok(inst)
+            }
+            Inst::Divuw { rd, rs1, rs2 } => {
+                let dividend = self.x[rs1] as u32;
+                let divisor = self.x[rs2] as u32;
+                self.x[rd] = match divisor {
+                    0 => u64::MAX,
+                    _ => (dividend / divisor) as i32 as i64 as u64,
+                };
+
+                Ok(inst)
+            }
+            Inst::Remw { rd, rs1, rs2 } => {
+                let dividend = self.x[rs1] as i32;
+                let divisor = self.x[rs2] as i32;
+                self.x[rd] = match divisor {
+                    0 => dividend as i64 as u64,
+                    _ => dividend.wrapping_rem(divisor) as i64 as u64,
                 };
 
                 Ok(inst)
             }
             Inst::Remuw { rd, rs1, rs2 } => {
-                self.x[rd] = match self.x[rs2] {
-                    0 => self.x[rs1],
-                    _ => {
-                        let dividend = self.x[rs1] as u32;
-                        let divisor = self.x[rs2] as u32;
-                        dividend.wrapping_rem(divisor) as i32 as u64
-                    }
+                let dividend = self.x[rs1] as u32;
+                let divisor = self.x[rs2] as u32;
+                self.x[rd] = match divisor {
+                    0 => dividend as i32 as i64 as u64,
+                    _ => (dividend % divisor) as i32 as i64 as u64,
+                };
+
+                Ok(inst)
+            }
+            Inst::Lrw { rd, rs1, .. } | Inst::Lrd { rd, rs1, .. } => {
+                let size = match inst {
+                    Inst::Lrw { .. } => 32,
+                    _ => 64,
                 };
+                let addr = self.x[rs1];
+                if !addr.is_multiple_of(size / 8) {
+                    return Err(Exception::LoadAccessMisaligned(addr));
+                }
+
+                let paddr = self.translate(bus, addr, size, AccessType::Load)?;
+                self.reserved = bus.load(paddr, size)?;
+                self.x[rd] = match size {
+                    32 => self.reserved as i32 as u64,
+                    _ => self.reserved,
+                };
+                self.reservation = Some(Reservation { addr: paddr, size });
+
+                Ok(inst)
+            }
+            Inst::Scw { rd, rs1, rs2, .. } | Inst::Scd { rd, rs1, rs2, .. } => {
+                let size = match inst {
+                    Inst::Scw { .. } => 32,
+                    _ => 64,
+                };
+                let addr = self.x[rs1];
+                if !addr.is_multiple_of(size / 8) {
+                    return Err(Exception::StoreAMOAddrMisaligned(addr));
+                }
+
+                let paddr = self.translate(bus, addr, size, AccessType::Store)?;
+
+                // An sc always clears the reservation, whether it succeeds or not.
+                // It only stores if memory still holds what the lr read, which
+                // also catches stores by harts on other threads.
+                let (reserved, val) = (self.reserved, self.x[rs2]);
+                let succeeded = self.reservation.take() == Some(Reservation { addr: paddr, size })
+                    && bus.amo(paddr, size, &mut |old| {
+                        if old == reserved {
+                            val
+                        } else {
+                            old
+                        }
+                    })? == reserved;
+                self.x[rd] = !succeeded as u64;
+
+                Ok(inst)
+            }
+            Inst::Amoswapw { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 32, |_, b| b)?;
+                Ok(inst)
+            }
+            Inst::Amoswapd { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 64, |_, b| b)?;
+                Ok(inst)
+            }
+            Inst::Amoaddw { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 32, u64::wrapping_add)?;
+                Ok(inst)
+            }
+            Inst::Amoaddd { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 64, u64::wrapping_add)?;
+                Ok(inst)
+            }
+            Inst::Amoxorw { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 32, |a, b| a ^ b)?;
+                Ok(inst)
+            }
+            Inst::Amoxord { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 64, |a, b| a ^ b)?;
+                Ok(inst)
+            }
+            Inst::Amoandw { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 32, |a, b| a & b)?;
+                Ok(inst)
+            }
+            Inst::Amoandd { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 64, |a, b| a & b)?;
+                Ok(inst)
+            }
+            Inst::Amoorw { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 32, |a, b| a | b)?;
+                Ok(inst)
+            }
+            Inst::Amoord { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 64, |a, b| a | b)?;
+                Ok(inst)
+            }
+            Inst::Amominw { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 32, |a, b| {
+                    (a as i64).min(b as i64) as u64
+                })?;
+                Ok(inst)
+            }
+            Inst::Amomind { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 64, |a, b| {
+                    (a as i64).min(b as i64) as u64
+                })?;
+                Ok(inst)
+            }
+            Inst::Amomaxw { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 32, |a, b| {
+                    (a as i64).max(b as i64) as u64
+                })?;
+                Ok(inst)
+            }
+            Inst::Amomaxd { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 64, |a, b| {
+                    (a as i64).max(b as i64) as u64
+                })?;
+                Ok(inst)
+            }
+            // Sign extension preserves the unsigned order of word operands.
+            Inst::Amominuw { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 32, u64::min)?;
+                Ok(inst)
+            }
+            Inst::Amominud { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 64, u64::min)?;
+                Ok(inst)
+            }
+            Inst::Amomaxuw { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 32, u64::max)?;
+                Ok(inst)
+            }
+            Inst::Amomaxud { rd, rs1, rs2, .. } => {
+                self.amo(bus, rd, rs1, rs2, 64, u64::max)?;
+                Ok(inst)
+            }
 
+            // F and D extensions
+            Inst::Flw { rd, rs1, imm } => {
+                let val = self.load(bus, (self.x[rs1]).wrapping_add(imm as u64), 32)?;
+                self.write_fp(F32, rd, val);
                 Ok(inst)
             }
-            Inst::Amoaddw {
+            Inst::Fld { rd, rs1, imm } => {
+                let val = self.load(bus, (self.x[rs1]).wrapping_add(imm as u64), 64)?;
+                self.write_fp(F64, rd, val);
+                Ok(inst)
+            }
+            Inst::Fsw { rs1, rs2, imm } => {
+                self.store(
+                    bus,
+                    (self.x[rs1]).wrapping_add(imm as u64),
+                    self.f[rs2] & 0xffff_ffff,
+                    32,
+                )?;
+                Ok(inst)
+            }
+            Inst::Fsd { rs1, rs2, imm } => {
+                self.store(bus, (self.x[rs1]).wrapping_add(imm as u64), self.f[rs2], 64)?;
+                Ok(inst)
+            }
+            Inst::Fmadds {
                 rd,
                 rs1,
                 rs2,
-                aq: _aq,
-                rl: _rl,
-            } => {
-                let t = bus.load(self.x[rs1], 32)? as u64;
-                bus.store(self.x[rs1], t.wrapping_add(self.x[rs2]), 32)?;
-                self.x[rd] = t;
-
-                Ok(inst)
+                rs3,
+                rm,
             }
-            Inst::Amoaddd {
+            | Inst::Fmsubs {
                 rd,
                 rs1,
                 rs2,
-                aq: _aq,
-                rl: _rl,
-            } => {
-                let t = bus.load(self.x[rs1], 64)?;
-                bus.store(self.x[rs1], t.wrapping_add(self.x[rs2]), 64)?;
-                self.x[rd] = t;
-
-                Ok(inst)
+                rs3,
+                rm,
             }
-            Inst::Amoswapw {
+            | Inst::Fnmsubs {
                 rd,
                 rs1,
                 rs2,
-                aq: _aq,
-                rl: _rl,
-            } => {
-                let t = bus.load(self.x[rs1], 32)?;
-                bus.store(self.x[rs1], self.x[rs2], 32)?;
-                self.x[rd] = t as u64;
-
-                Ok(inst)
+                rs3,
+                rm,
+            }
+            | Inst::Fnmadds {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | Inst::Fmaddd {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
             }
-            Inst::Amoswapd {
+            | Inst::Fmsubd {
                 rd,
                 rs1,
                 rs2,
-                aq: _aq,
-                rl: _rl,
+                rs3,
+                rm,
+            }
+            | Inst::Fnmsubd {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | Inst::Fnmaddd {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
             } => {
-                let t = bus.load(self.x[rs1], 64)?;
-                bus.store(self.x[rs1], self.x[rs2], 64)?;
-                self.x[rd] = t;
+                let rm = self.rounding_mode(rm, raw)?;
+                let (fmt, negate_product, negate_addend) = match inst {
+                    Inst::Fmadds { .. } => (F32, false, false),
+                    Inst::Fmsubs { .. } => (F32, false, true),
+                    Inst::Fnmsubs { .. } => (F32, true, false),
+                    Inst::Fnmadds { .. } => (F32, true, true),
+                    Inst::Fmaddd { .. } => (F64, false, false),
+                    Inst::Fmsubd { .. } => (F64, false, true),
+                    Inst::Fnmsubd { .. } => (F64, true, false),
+                    _ => (F64, true, true),
+                };
+
+                let sign = fmt.sign_bit();
+                let a = self.read_fp(fmt, rs1) ^ if negate_product { sign } else { 0 };
+                let b = self.read_fp(fmt, rs2);
+                let c = self.read_fp(fmt, rs3) ^ if negate_addend { sign } else { 0 };
+
+                let mut flags = 0;
+                let val = float::mul_add(fmt, a, b, c, rm, &mut flags);
+                self.write_fp(fmt, rd, val);
+                self.accrue_fflags(flags);
+                Ok(inst)
+            }
+            Inst::Fadds { rd, rs1, rs2, rm }
+            | Inst::Fsubs { rd, rs1, rs2, rm }
+            | Inst::Fmuls { rd, rs1, rs2, rm }
+            | Inst::Fdivs { rd, rs1, rs2, rm }
+            | Inst::Faddd { rd, rs1, rs2, rm }
+            | Inst::Fsubd { rd, rs1, rs2, rm }
+            | Inst::Fmuld { rd, rs1, rs2, rm }
+            | Inst::Fdivd { rd, rs1, rs2, rm } => {
+                let rm = self.rounding_mode(rm, raw)?;
+                let fmt = match inst {
+                    Inst::Fadds { .. }
+                    | Inst::Fsubs { .. }
+                    | Inst::Fmuls { .. }
+                    | Inst::Fdivs { .. } => F32,
+                    _ => F64,
+                };
+                let a = self.read_fp(fmt, rs1);
+                let b = self.read_fp(fmt, rs2);
+
+                let mut flags = 0;
+                let val = match inst {
+                    Inst::Fadds { .. } | Inst::Faddd { .. } => {
+                        float::add(fmt, a, b, rm, &mut flags)
+                    }
+                    Inst::Fsubs { .. } | Inst::Fsubd { .. } => {
+                        float::sub(fmt, a, b, rm, &mut flags)
+                    }
+                    Inst::Fmuls { .. } | Inst::Fmuld { .. } => {
+                        float::mul(fmt, a, b, rm, &mut flags)
+                    }
+                    _ => float::div(fmt, a, b, rm, &mut flags),
+                };
+                self.write_fp(fmt, rd, val);
+                self.accrue_fflags(flags);
+                Ok(inst)
+            }
+            Inst::Fsqrts { rd, rs1, rm } | Inst::Fsqrtd { rd, rs1, rm } => {
+                let rm = self.rounding_mode(rm, raw)?;
+                let fmt = if let Inst::Fsqrts { .. } = inst {
+                    F32
+                } else {
+                    F64
+                };
+
+                let mut flags = 0;
+                let val = float::sqrt(fmt, self.read_fp(fmt, rs1), rm, &mut flags);
+                self.write_fp(fmt, rd, val);
+                self.accrue_fflags(flags);
+                Ok(inst)
+            }
+            Inst::Fsgnjs { rd, rs1, rs2 }
+            | Inst::Fsgnjns { rd, rs1, rs2 }
+            | Inst::Fsgnjxs { rd, rs1, rs2 }
+            | Inst::Fsgnjd { rd, rs1, rs2 }
+            | Inst::Fsgnjnd { rd, rs1, rs2 }
+            | Inst::Fsgnjxd { rd, rs1, rs2 } => {
+                let fmt = match inst {
+                    Inst::Fsgnjs { .. } | Inst::Fsgnjns { .. } | Inst::Fsgnjxs { .. } => F32,
+                    _ => F64,
+                };
 
+                let sign_bit = fmt.sign_bit();
+                let a = self.read_fp(fmt, rs1);
+                let b = self.read_fp(fmt, rs2);
+
+                let sign = match inst {
+                    Inst::Fsgnjs { .. } | Inst::Fsgnjd { .. } => b,
+                    Inst::Fsgnjns { .. } | Inst::Fsgnjnd { .. } => !b,
+                    _ => a ^ b,
+                } & sign_bit;
+
+                self.write_fp(fmt, rd, (a & !sign_bit) | sign);
+                Ok(inst)
+            }
+            Inst::Fmins { rd, rs1, rs2 }
+            | Inst::Fmaxs { rd, rs1, rs2 }
+            | Inst::Fmind { rd, rs1, rs2 }
+            | Inst::Fmaxd { rd, rs1, rs2 } => {
+                let fmt = match inst {
+                    Inst::Fmins { .. } | Inst::Fmaxs { .. } => F32,
+                    _ => F64,
+                };
+                let a = self.read_fp(fmt, rs1);
+                let b = self.read_fp(fmt, rs2);
+
+                let mut flags = 0;
+                let val = match inst {
+                    Inst::Fmins { .. } | Inst::Fmind { .. } => float::min(fmt, a, b, &mut flags),
+                    _ => float::max(fmt, a, b, &mut flags),
+                };
+                self.write_fp(fmt, rd, val);
+                self.accrue_fflags(flags);
+                Ok(inst)
+            }
+            Inst::Fcvtsd { rd, rs1, rm } | Inst::Fcvtds { rd, rs1, rm } => {
+                let rm = self.rounding_mode(rm, raw)?;
+                let (from, to) = if let Inst::Fcvtsd { .. } = inst {
+                    (F64, F32)
+                } else {
+                    (F32, F64)
+                };
+
+                let mut flags = 0;
+                let val = float::convert(from, to, self.read_fp(from, rs1), rm, &mut flags);
+                self.write_fp(to, rd, val);
+                self.accrue_fflags(flags);
+                Ok(inst)
+            }
+            Inst::Fcvtws { rd, rs1, rm }
+            | Inst::Fcvtwus { rd, rs1, rm }
+            | Inst::Fcvtls { rd, rs1, rm }
+            | Inst::Fcvtlus { rd, rs1, rm }
+            | Inst::Fcvtwd { rd, rs1, rm }
+            | Inst::Fcvtwud { rd, rs1, rm }
+            | Inst::Fcvtld { rd, rs1, rm }
+            | Inst::Fcvtlud { rd, rs1, rm } => {
+                let rm = self.rounding_mode(rm, raw)?;
+                let (fmt, bits, signed) = match inst {
+                    Inst::Fcvtws { .. } => (F32, 32, true),
+                    Inst::Fcvtwus { .. } => (F32, 32, false),
+                    Inst::Fcvtls { .. } => (F32, 64, true),
+                    Inst::Fcvtlus { .. } => (F32, 64, false),
+                    Inst::Fcvtwd { .. } => (F64, 32, true),
+                    Inst::Fcvtwud { .. } => (F64, 32, false),
+                    Inst::Fcvtld { .. } => (F64, 64, true),
+                    _ => (F64, 64, false),
+                };
+
+                let mut flags = 0;
+                let val = float::to_int(fmt, self.read_fp(fmt, rs1), bits, signed, rm, &mut flags);
+                // 32-bit results are sign-extended, even for the unsigned variants.
+                self.x[rd] = if bits == 32 {
+                    val as i32 as i64 as u64
+                } else {
+                    val
+                };
+                self.accrue_fflags(flags);
+                Ok(inst)
+            }
+            Inst::Fcvtsw { rd, rs1, rm }
+            | Inst::Fcvtswu { rd, rs1, rm }
+            | Inst::Fcvtsl { rd, rs1, rm }
+            | Inst::Fcvtslu { rd, rs1, rm }
+            | Inst::Fcvtdw { rd, rs1, rm }
+            | Inst::Fcvtdwu { rd, rs1, rm }
+            | Inst::Fcvtdl { rd, rs1, rm }
+            | Inst::Fcvtdlu { rd, rs1, rm } => {
+                let rm = self.rounding_mode(rm, raw)?;
+                let (fmt, bits, signed) = match inst {
+                    Inst::Fcvtsw { .. } => (F32, 32, true),
+                    Inst::Fcvtswu { .. } => (F32, 32, false),
+                    Inst::Fcvtsl { .. } => (F32, 64, true),
+                    Inst::Fcvtslu { .. } => (F32, 64, false),
+                    Inst::Fcvtdw { .. } => (F64, 32, true),
+                    Inst::Fcvtdwu { .. } => (F64, 32, false),
+                    Inst::Fcvtdl { .. } => (F64, 64, true),
+                    _ => (F64, 64, false),
+                };
+
+                let mut flags = 0;
+                let val = float::from_int(fmt, self.x[rs1], bits, signed, rm, &mut flags);
+                self.write_fp(fmt, rd, val);
+                self.accrue_fflags(flags);
+                Ok(inst)
+            }
+            Inst::Feqs { rd, rs1, rs2 }
+            | Inst::Flts { rd, rs1, rs2 }
+            | Inst::Fles { rd, rs1, rs2 }
+            | Inst::Feqd { rd, rs1, rs2 }
+            | Inst::Fltd { rd, rs1, rs2 }
+            | Inst::Fled { rd, rs1, rs2 } => {
+                let fmt = match inst {
+                    Inst::Feqs { .. } | Inst::Flts { .. } | Inst::Fles { .. } => F32,
+                    _ => F64,
+                };
+                let a = self.read_fp(fmt, rs1);
+                let b = self.read_fp(fmt, rs2);
+
+                let mut flags = 0;
+                self.x[rd] = match inst {
+                    Inst::Feqs { .. } | Inst::Feqd { .. } => float::eq(fmt, a, b, &mut flags),
+                    Inst::Flts { .. } | Inst::Fltd { .. } => float::lt(fmt, a, b, &mut flags),
+                    _ => float::le(fmt, a, b, &mut flags),
+                } as u64;
+                self.accrue_fflags(flags);
+                Ok(inst)
+            }
+            Inst::Fclasss { rd, rs1 } => {
+                self.x[rd] = float::classify(F32, self.read_fp(F32, rs1));
+                Ok(inst)
+            }
+            Inst::Fclassd { rd, rs1 } => {
+                self.x[rd] = float::classify(F64, self.read_fp(F64, rs1));
+                Ok(inst)
+            }
+            Inst::Fmvxw { rd, rs1 } => {
+                self.x[rd] = self.f[rs1] as i32 as i64 as u64;
+                Ok(inst)
+            }
+            Inst::Fmvxd { rd, rs1 } => {
+                self.x[rd] = self.f[rs1];
+                Ok(inst)
+            }
+            Inst::Fmvwx { rd, rs1 } => {
+                self.write_fp(F32, rd, self.x[rs1] & 0xffff_ffff);
+                Ok(inst)
+            }
+            Inst::Fmvdx { rd, rs1 } => {
+                self.write_fp(F64, rd, self.x[rs1]);
+                Ok(inst)
+            }
+            Inst::Adduw { rd, rs1, rs2 } => {
+                self.x[rd] = (self.x[rs1] as u32 as u64).wrapping_add(self.x[rs2]);
+                Ok(inst)
+            }
+            Inst::Sh1add { rd, rs1, rs2 } => {
+                self.x[rd] = (self.x[rs1] << 1).wrapping_add(self.x[rs2]);
+                Ok(inst)
+            }
+            Inst::Sh2add { rd, rs1, rs2 } => {
+                self.x[rd] = (self.x[rs1] << 2).wrapping_add(self.x[rs2]);
+                Ok(inst)
+            }
+            Inst::Sh3add { rd, rs1, rs2 } => {
+                self.x[rd] = (self.x[rs1] << 3).wrapping_add(self.x[rs2]);
+                Ok(inst)
+            }
+            Inst::Sh1adduw { rd, rs1, rs2 } => {
+                self.x[rd] = ((self.x[rs1] as u32 as u64) << 1).wrapping_add(self.x[rs2]);
+                Ok(inst)
+            }
+            Inst::Sh2adduw { rd, rs1, rs2 } => {
+                self.x[rd] = ((self.x[rs1] as u32 as u64) << 2).wrapping_add(self.x[rs2]);
+                Ok(inst)
+            }
+            Inst::Sh3adduw { rd, rs1, rs2 } => {
+                self.x[rd] = ((self.x[rs1] as u32 as u64) << 3).wrapping_add(self.x[rs2]);
+                Ok(inst)
+            }
+            Inst::Slliuw { rd, rs1, shamt } => {
+                self.x[rd] = (self.x[rs1] as u32 as u64) << shamt;
+                Ok(inst)
+            }
+            Inst::Andn { rd, rs1, rs2 } => {
+                self.x[rd] = self.x[rs1] & !self.x[rs2];
+                Ok(inst)
+            }
+            Inst::Orn { rd, rs1, rs2 } => {
+                self.x[rd] = self.x[rs1] | !self.x[rs2];
+                Ok(inst)
+            }
+            Inst::Xnor { rd, rs1, rs2 } => {
+                self.x[rd] = !(self.x[rs1] ^ self.x[rs2]);
+                Ok(inst)
+            }
+            Inst::Max { rd, rs1, rs2 } => {
+                self.x[rd] = (self.x[rs1] as i64).max(self.x[rs2] as i64) as u64;
+                Ok(inst)
+            }
+            Inst::Maxu { rd, rs1, rs2 } => {
+                self.x[rd] = self.x[rs1].max(self.x[rs2]);
+                Ok(inst)
+            }
+            Inst::Min { rd, rs1, rs2 } => {
+                self.x[rd] = (self.x[rs1] as i64).min(self.x[rs2] as i64) as u64;
+                Ok(inst)
+            }
+            Inst::Minu { rd, rs1, rs2 } => {
+                self.x[rd] = self.x[rs1].min(self.x[rs2]);
+                Ok(inst)
+            }
+            Inst::Rol { rd, rs1, rs2 } => {
+                self.x[rd] = self.x[rs1].rotate_left((self.x[rs2] & 0x3f) as u32);
+                Ok(inst)
+            }
+            Inst::Ror { rd, rs1, rs2 } => {
+                self.x[rd] = self.x[rs1].rotate_right((self.x[rs2] & 0x3f) as u32);
+                Ok(inst)
+            }
+            Inst::Rori { rd, rs1, shamt } => {
+                self.x[rd] = self.x[rs1].rotate_right(shamt);
+                Ok(inst)
+            }
+            Inst::Rolw { rd, rs1, rs2 } => {
+                let x = self.x[rs1] as u32;
+                self.x[rd] = x.rotate_left((self.x[rs2] & 0x1f) as u32) as i32 as u64;
+                Ok(inst)
+            }
+            Inst::Rorw { rd, rs1, rs2 } => {
+                let x = self.x[rs1] as u32;
+                self.x[rd] = x.rotate_right((self.x[rs2] & 0x1f) as u32) as i32 as u64;
+                Ok(inst)
+            }
+            Inst::Roriw { rd, rs1, shamt } => {
+                self.x[rd] = (self.x[rs1] as u32).rotate_right(shamt) as i32 as u64;
+                Ok(inst)
+            }
+            Inst::Clz { rd, rs1 } => {
+                self.x[rd] = self.x[rs1].leading_zeros() as u64;
+                Ok(inst)
+            }
+            Inst::Ctz { rd, rs1 } => {
+                self.x[rd] = self.x[rs1].trailing_zeros() as u64;
+                Ok(inst)
+            }
+            Inst::Cpop { rd, rs1 } => {
+                self.x[rd] = self.x[rs1].count_ones() as u64;
+                Ok(inst)
+            }
+            Inst::Clzw { rd, rs1 } => {
+                self.x[rd] = (self.x[rs1] as u32).leading_zeros() as u64;
+                Ok(inst)
+            }
+            Inst::Ctzw { rd, rs1 } => {
+                self.x[rd] = (self.x[rs1] as u32).trailing_zeros() as u64;
+                Ok(inst)
+            }
+            Inst::Cpopw { rd, rs1 } => {
+                self.x[rd] = (self.x[rs1] as u32).count_ones() as u64;
+                Ok(inst)
+            }
+            Inst::Sextb { rd, rs1 } => {
+                self.x[rd] = self.x[rs1] as i8 as u64;
+                Ok(inst)
+            }
+            Inst::Sexth { rd, rs1 } => {
+                self.x[rd] = self.x[rs1] as i16 as u64;
+                Ok(inst)
+            }
+            Inst::Zexth { rd, rs1 } => {
+                self.x[rd] = self.x[rs1] as u16 as u64;
+                Ok(inst)
+            }
+            Inst::Orcb { rd, rs1 } => {
+                let x = self.x[rs1]
+                    .to_le_bytes()
+                    .map(|b| if b != 0 { 0xff } else { 0 });
+                self.x[rd] = u64::from_le_bytes(x);
+                Ok(inst)
+            }
+            Inst::Rev8 { rd, rs1 } => {
+                self.x[rd] = self.x[rs1].swap_bytes();
+                Ok(inst)
+            }
+            // Carry-less products: clmul keeps the low half, clmulh the high
+            // half and clmulr bits 126:63.
+            Inst::Clmul { rd, rs1, rs2 } => {
+                self.x[rd] = clmul(self.x[rs1], self.x[rs2]) as u64;
+                Ok(inst)
+            }
+            Inst::Clmulh { rd, rs1, rs2 } => {
+                self.x[rd] = (clmul(self.x[rs1], self.x[rs2]) >> 64) as u64;
+                Ok(inst)
+            }
+            Inst::Clmulr { rd, rs1, rs2 } => {
+                self.x[rd] = (clmul(self.x[rs1], self.x[rs2]) >> 63) as u64;
+                Ok(inst)
+            }
+            Inst::Bclr { rd, rs1, rs2 } => {
+                self.x[rd] = self.x[rs1] & !(1 << (self.x[rs2] & 0x3f));
+                Ok(inst)
+            }
+            Inst::Bext { rd, rs1, rs2 } => {
+                self.x[rd] = (self.x[rs1] >> (self.x[rs2] & 0x3f)) & 1;
+                Ok(inst)
+            }
+            Inst::Binv { rd, rs1, rs2 } => {
+                self.x[rd] = self.x[rs1] ^ (1 << (self.x[rs2] & 0x3f));
+                Ok(inst)
+            }
+            Inst::Bset { rd, rs1, rs2 } => {
+                self.x[rd] = self.x[rs1] | (1 << (self.x[rs2] & 0x3f));
+                Ok(inst)
+            }
+            Inst::Bclri { rd, rs1, shamt } => {
+                self.x[rd] = self.x[rs1] & !(1 << shamt);
+                Ok(inst)
+            }
+            Inst::Bexti { rd, rs1, shamt } => {
+                self.x[rd] = (self.x[rs1] >> shamt) & 1;
+                Ok(inst)
+            }
+            Inst::Binvi { rd, rs1, shamt } => {
+                self.x[rd] = self.x[rs1] ^ (1 << shamt);
+                Ok(inst)
+            }
+            Inst::Bseti { rd, rs1, shamt } => {
+                self.x[rd] = self.x[rs1] | (1 << shamt);
+                Ok(inst)
+            }
+            Inst::Vsetvli { .. }
+            | Inst::Vsetivli { .. }
+            | Inst::Vsetvl { .. }
+            | Inst::Vload { .. }
+            | Inst::Vstore { .. }
+            | Inst::Varith { .. } => {
+                self.execute_vector(bus, inst, raw)?;
                 Ok(inst)
             }
             _ => Err(Exception::Breakpoint(self.pc)),
         }
     }
 }
+
+/// Full 128-bit carry-less product of `x` and `y`.
+fn clmul(x: u64, y: u64) -> u128 {
+    (0..64)
+        .filter(|i| (y >> i) & 1 != 0)
+        .fold(0, |acc, i| acc ^ ((x as u128) << i))
+}
+
+/// Saves the interrupt-enable bit `ie` of `status` in `pie` and clears it,
+/// as trap entry does.
+fn push_ie(status: u64, ie: u64, pie: u64) -> u64 {
+    let status = if status & ie != 0 {
+        status | pie
+    } else {
+        status & !pie
+    };
+    status & !ie
+}
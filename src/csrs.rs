@@ -1,8 +1,55 @@
+// Unprivileged floating-point CSRs
+/// Floating-point accrued exceptions.
+pub const FFLAGS: usize = 0x001;
+/// Floating-point dynamic rounding mode.
+pub const FRM: usize = 0x002;
+/// Floating-point control and status register (frm + fflags).
+pub const FCSR: usize = 0x003;
+
+// Unprivileged vector CSRs
+/// Vector start element index.
+pub const VSTART: usize = 0x008;
+/// Fixed-point saturation flag.
+pub const VXSAT: usize = 0x009;
+/// Fixed-point rounding mode.
+pub const VXRM: usize = 0x00a;
+/// Vector control and status register (vxrm + vxsat).
+pub const VCSR: usize = 0x00f;
+/// Vector length.
+pub const VL: usize = 0xc20;
+/// Vector data type register.
+pub const VTYPE: usize = 0xc21;
+/// Vector register length in bytes.
+pub const VLENB: usize = 0xc22;
+
+use core::ops::{Index, IndexMut};
+
+// Unprivileged counters and timers
+/// Cycle counter for RDCYCLE instruction.
+pub const CYCLE: usize = 0xc00;
+/// Timer for RDTIME instruction.
+pub const TIME: usize = 0xc01;
+/// Instructions-retired counter for RDINSTRET instruction.
+pub const INSTRET: usize = 0xc02;
+/// Performance-monitoring counters, hpmcounter3 to hpmcounter31.
+pub const HPMCOUNTER3: usize = 0xc03;
+pub const HPMCOUNTER31: usize = 0xc1f;
+
 // Machine-level CSRs
+/// Vendor ID.
+pub const MVENDORID: usize = 0xf11;
+/// Architecture ID.
+pub const MARCHID: usize = 0xf12;
+/// Implementation ID.
+pub const MIMPID: usize = 0xf13;
 /// Hardware thread ID
 pub const MHARTID: usize = 0xf14;
+/// Pointer to configuration data structure.
+pub const MCONFIGPTR: usize = 0xf15;
 /// Machine status register.
 pub const MSTATUS: usize = 0x300;
+/// ISA and extensions.
+pub const MISA: usize = 0x301;
 /// Machine exception delefation register.
 pub const MEDELEG: usize = 0x302;
 /// Machine interrupt delefation register.
@@ -13,6 +60,8 @@ pub const MIE: usize = 0x304;
 pub const MTVEC: usize = 0x305;
 /// Machine counter enable.
 pub const MCOUNTEREN: usize = 0x306;
+/// Machine environment configuration.
+pub const MENVCFG: usize = 0x30a;
 /// Scratch register for machine trap handlers.
 pub const MSCRATCH: usize = 0x340;
 /// Machine exception program counter.
@@ -23,6 +72,40 @@ pub const MCAUSE: usize = 0x342;
 pub const MTVAL: usize = 0x343;
 /// Machine interrupt pending.
 pub const MIP: usize = 0x344;
+/// Machine trap instruction (transformed).
+pub const MTINST: usize = 0x34a;
+/// Machine bad guest physical address.
+pub const MTVAL2: usize = 0x34b;
+/// Machine counter-inhibit register.
+pub const MCOUNTINHIBIT: usize = 0x320;
+/// Machine performance-monitoring event selectors, mhpmevent3 to mhpmevent31.
+pub const MHPMEVENT3: usize = 0x323;
+pub const MHPMEVENT31: usize = 0x33f;
+/// Machine cycle counter.
+pub const MCYCLE: usize = 0xb00;
+/// Machine instructions-retired counter.
+pub const MINSTRET: usize = 0xb02;
+/// Machine performance-monitoring counters, mhpmcounter3 to mhpmcounter31.
+pub const MHPMCOUNTER3: usize = 0xb03;
+pub const MHPMCOUNTER31: usize = 0xb1f;
+/// Trigger select.
+pub const TSELECT: usize = 0x7a0;
+/// Trigger data 1: the selected trigger's type and configuration.
+pub const TDATA1: usize = 0x7a1;
+/// Trigger data 2: the selected trigger's match value.
+pub const TDATA2: usize = 0x7a2;
+/// Trigger data 3: the selected trigger's extra match conditions.
+pub const TDATA3: usize = 0x7a3;
+/// Trigger info: the supported trigger types.
+pub const TINFO: usize = 0x7a4;
+/// Trigger control: M-mode trigger enable.
+pub const TCONTROL: usize = 0x7a5;
+/// Physical memory protection configuration, pmpcfg0 to pmpcfg15.
+pub const PMPCFG0: usize = 0x3a0;
+pub const PMPCFG15: usize = 0x3af;
+/// Physical memory protection address, pmpaddr0 to pmpaddr63.
+pub const PMPADDR0: usize = 0x3b0;
+pub const PMPADDR63: usize = 0x3ef;
 
 // Supervisor-level CSRs.
 /// Supervisor status register.
@@ -31,6 +114,8 @@ pub const SSTATUS: usize = 0x100;
 pub const SIE: usize = 0x104;
 /// Supervisor trap handler base address.
 pub const STVEC: usize = 0x105;
+/// Supervisor counter enable.
+pub const SCOUNTEREN: usize = 0x106;
 /// Scratch register for supervisor trap handlers.
 pub const SSCRATCH: usize = 0x140;
 /// Supervisor exception program counter.
@@ -41,9 +126,63 @@ pub const SCAUSE: usize = 0x142;
 pub const STVAL: usize = 0x143;
 /// Supervisor interrupt pending.
 pub const SIP: usize = 0x144;
+/// Supervisor timer compare.
+pub const STIMECMP: usize = 0x14d;
 /// Supervisor address translation and protection.
 pub const SATP: usize = 0x180;
 
+// Hypervisor CSRs
+/// Hypervisor status register.
+pub const HSTATUS: usize = 0x600;
+/// Hypervisor exception delegation register.
+pub const HEDELEG: usize = 0x602;
+/// Hypervisor interrupt delegation register.
+pub const HIDELEG: usize = 0x603;
+/// Hypervisor interrupt-enable register.
+pub const HIE: usize = 0x604;
+/// Delta for VS/VU-mode timer.
+pub const HTIMEDELTA: usize = 0x605;
+/// Hypervisor counter enable.
+pub const HCOUNTEREN: usize = 0x606;
+/// Hypervisor guest external interrupt-enable register.
+pub const HGEIE: usize = 0x607;
+/// Hypervisor environment configuration.
+pub const HENVCFG: usize = 0x60a;
+/// Hypervisor bad guest physical address.
+pub const HTVAL: usize = 0x643;
+/// Hypervisor interrupt pending.
+pub const HIP: usize = 0x644;
+/// Hypervisor virtual interrupt pending.
+pub const HVIP: usize = 0x645;
+/// Hypervisor trap instruction (transformed).
+pub const HTINST: usize = 0x64a;
+/// Hypervisor guest external interrupt pending.
+pub const HGEIP: usize = 0xe12;
+/// Hypervisor guest address translation and protection.
+pub const HGATP: usize = 0x680;
+
+// Virtual supervisor CSRs, which replace the supervisor ones in VS-mode
+/// Virtual supervisor status register.
+pub const VSSTATUS: usize = 0x200;
+/// Virtual supervisor interrupt-enable register.
+pub const VSIE: usize = 0x204;
+/// Virtual supervisor trap handler base address.
+pub const VSTVEC: usize = 0x205;
+/// Virtual supervisor scratch register.
+pub const VSSCRATCH: usize = 0x240;
+/// Virtual supervisor exception program counter.
+pub const VSEPC: usize = 0x241;
+/// Virtual supervisor trap cause.
+pub const VSCAUSE: usize = 0x242;
+/// Virtual supervisor bad address or instruction.
+pub const VSTVAL: usize = 0x243;
+/// Virtual supervisor interrupt pending.
+pub const VSIP: usize = 0x244;
+/// Virtual supervisor timer compare.
+pub const VSTIMECMP: usize = 0x24d;
+/// Virtual supervisor address translation and protection.
+pub const VSATP: usize = 0x280;
+
 // mstatus and sstatus field mask
 pub const MASK_SIE: u64 = 1 << 1;
 pub const MASK_MIE: u64 = 1 << 3;
@@ -65,14 +204,398 @@ pub const MASK_UXL: u64 = 0b11 << 32;
 pub const MASK_SXL: u64 = 0b11 << 34;
 pub const MASK_SBE: u64 = 1 << 36;
 pub const MASK_MBE: u64 = 1 << 37;
+pub const MASK_GVA: u64 = 1 << 38;
+pub const MASK_MPV: u64 = 1 << 39;
 pub const MASK_SD: u64 = 1 << 63;
-pub const MASK_SSTATUS: u64 = MASK_SIE | MASK_SPIE | MASK_UBE | MASK_SPP | MASK_FS
-                            | MASK_XS  | MASK_SUM  | MASK_MXR | MASK_UXL | MASK_SD;
+pub const MASK_SSTATUS: u64 = MASK_SIE | MASK_SPIE | MASK_UBE | MASK_SPP | MASK_VS
+                            | MASK_FS  | MASK_XS   | MASK_SUM | MASK_MXR | MASK_UXL
+                            | MASK_SD;
+// Fields software can change through mstatus and sstatus.
+pub const MASK_MSTATUS_WRITE: u64 = MASK_SIE  | MASK_MIE  | MASK_SPIE | MASK_MPIE | MASK_SPP
+                                  | MASK_VS   | MASK_MPP  | MASK_FS   | MASK_MPRV | MASK_SUM
+                                  | MASK_MXR  | MASK_TVM  | MASK_TW   | MASK_TSR  | MASK_GVA
+                                  | MASK_MPV;
+pub const MASK_SSTATUS_WRITE: u64 = MASK_SIE | MASK_SPIE | MASK_SPP | MASK_VS | MASK_FS | MASK_SUM
+                                  | MASK_MXR;
+/// UXL and SXL are fixed to 64-bit.
+pub const MSTATUS_XL_64: u64 = (2 << 32) | (2 << 34);
+
+// hstatus field mask
+pub const MASK_HSTATUS_GVA: u64 = 1 << 6;
+pub const MASK_SPV: u64 = 1 << 7;
+pub const MASK_SPVP: u64 = 1 << 8;
+pub const MASK_HU: u64 = 1 << 9;
+pub const MASK_VTVM: u64 = 1 << 20;
+pub const MASK_VTW: u64 = 1 << 21;
+pub const MASK_VTSR: u64 = 1 << 22;
+pub const MASK_HSTATUS_WRITE: u64 = MASK_HSTATUS_GVA | MASK_SPV  | MASK_SPVP | MASK_HU | MASK_VTVM
+                                  | MASK_VTW         | MASK_VTSR;
+/// VSXL is fixed to 64-bit.
+pub const HSTATUS_VSXL_64: u64 = 2 << 32;
 
 // MIP / SIP field mask
 pub const MASK_SSIP: u64 = 1 << 1;
+pub const MASK_VSSIP: u64 = 1 << 2;
 pub const MASK_MSIP: u64 = 1 << 3;
 pub const MASK_STIP: u64 = 1 << 5;
+pub const MASK_VSTIP: u64 = 1 << 6;
 pub const MASK_MTIP: u64 = 1 << 7;
 pub const MASK_SEIP: u64 = 1 << 9;
+pub const MASK_VSEIP: u64 = 1 << 10;
 pub const MASK_MEIP: u64 = 1 << 11;
+pub const MASK_SGEIP: u64 = 1 << 12;
+/// Interrupts of supervisor level.
+pub const MASK_S_INTERRUPTS: u64 = MASK_SSIP | MASK_STIP | MASK_SEIP;
+/// Interrupts of virtual supervisor level, which hideleg can delegate to VS-mode.
+pub const MASK_VS_INTERRUPTS: u64 = MASK_VSSIP | MASK_VSTIP | MASK_VSEIP;
+pub const MASK_MIE_WRITE: u64 = MASK_SSIP | MASK_MSIP | MASK_STIP | MASK_MTIP | MASK_SEIP | MASK_MEIP
+                              | MASK_VS_INTERRUPTS | MASK_SGEIP;
+/// mip bits software may write; the M-level bits are driven by hardware.
+pub const MASK_MIP_WRITE: u64 = MASK_SSIP | MASK_STIP | MASK_SEIP | MASK_VSSIP;
+/// Interrupts that can be delegated to S-mode.
+pub const MASK_MIDELEG_WRITE: u64 = MASK_SSIP | MASK_STIP | MASK_SEIP;
+/// Interrupts always delegated to HS-mode when the H extension is present.
+pub const MIDELEG_FIXED: u64 = MASK_VS_INTERRUPTS | MASK_SGEIP;
+/// Exceptions that can be delegated: all but ecall from M-mode.
+pub const MASK_MEDELEG_WRITE: u64 = 0xf0b7ff;
+/// Exceptions HS-mode can delegate to VS-mode: not ecalls from HS- or
+/// VS-mode, nor virtual-instruction and guest-page faults.
+pub const MASK_HEDELEG_WRITE: u64 = 0xb1ff;
+
+// mcounteren / scounteren / mcountinhibit fields
+pub const MASK_CY: u64 = 1 << 0;
+pub const MASK_TM: u64 = 1 << 1;
+pub const MASK_IR: u64 = 1 << 2;
+
+// menvcfg / henvcfg fields
+/// Sstc enable: stimecmp (vstimecmp for henvcfg) drives STIP (VSTIP).
+pub const MASK_STCE: u64 = 1 << 63;
+
+// mhpmevent selectors
+pub const HPM_EVENT_NONE: u64 = 0;
+/// Retired loads, including LR, AMOs and floating-point loads.
+pub const HPM_EVENT_LOADS: u64 = 1;
+/// Retired stores, including SC, AMOs and floating-point stores.
+pub const HPM_EVENT_STORES: u64 = 2;
+/// Retired conditional branches.
+pub const HPM_EVENT_BRANCHES: u64 = 3;
+/// Retired conditional branches that were taken.
+pub const HPM_EVENT_BRANCHES_TAKEN: u64 = 4;
+/// Exceptions taken.
+pub const HPM_EVENT_EXCEPTIONS: u64 = 5;
+/// Interrupts taken.
+pub const HPM_EVENT_INTERRUPTS: u64 = 6;
+
+// mtvec / stvec fields
+pub const MASK_TVEC_MODE: u64 = 0b11;
+
+// fcsr field mask
+pub const MASK_FFLAGS: u64 = 0b11111;
+pub const MASK_FRM: u64 = 0b111 << 5;
+
+// vcsr field mask
+pub const MASK_VXSAT: u64 = 1 << 0;
+pub const MASK_VXRM: u64 = 0b11 << 1;
+
+// vxrm rounding modes
+pub const VXRM_RNU: u64 = 0b00;
+pub const VXRM_RNE: u64 = 0b01;
+pub const VXRM_RDN: u64 = 0b10;
+pub const VXRM_ROD: u64 = 0b11;
+
+// vtype fields
+pub const MASK_VTYPE_VLMUL: u64 = 0b111;
+pub const MASK_VTYPE_VSEW: u64 = 0b111 << 3;
+pub const MASK_VTYPE_VTA: u64 = 1 << 6;
+pub const MASK_VTYPE_VMA: u64 = 1 << 7;
+pub const MASK_VTYPE_VILL: u64 = 1 << 63;
+
+// misa fields
+pub const MISA_MXL_64: u64 = 2 << 62;
+pub const MISA_A: u64 = 1 << 0;
+pub const MISA_B: u64 = 1 << 1;
+pub const MISA_C: u64 = 1 << 2;
+pub const MISA_D: u64 = 1 << 3;
+pub const MISA_F: u64 = 1 << 5;
+pub const MISA_H: u64 = 1 << 7;
+pub const MISA_I: u64 = 1 << 8;
+pub const MISA_M: u64 = 1 << 12;
+pub const MISA_S: u64 = 1 << 18;
+pub const MISA_U: u64 = 1 << 20;
+pub const MISA_V: u64 = 1 << 21;
+
+// satp fields
+pub const MASK_SATP_PPN: u64 = (1 << 44) - 1;
+pub const MASK_SATP_ASID: u64 = 0xffff << 44;
+pub const MASK_SATP_MODE: u64 = 0xf << 60;
+pub const SATP_MODE_BARE: u64 = 0;
+pub const SATP_MODE_SV39: u64 = 8;
+pub const SATP_MODE_SV48: u64 = 9;
+pub const SATP_MODE_SV57: u64 = 10;
+
+// hgatp fields; modes use the satp encodings for their "x4" variants
+pub const MASK_HGATP_PPN: u64 = (1 << 44) - 1;
+pub const MASK_HGATP_VMID: u64 = 0x3fff << 44;
+pub const MASK_HGATP_MODE: u64 = 0xf << 60;
+
+/// The hart's control and status registers.
+///
+/// `read` and `write` implement the architectural behaviour of the Zicsr
+/// instructions: unimplemented registers are rejected, WARL fields are
+/// legalized and views such as `sstatus` and `sie` alias their machine-level
+/// registers. Indexing gives the raw stored value, for the emulator's own
+/// use when taking traps or raising interrupts.
+///
+/// The raw mip holds SEIP as the PLIC drives it. What software writes to
+/// SEIP is kept apart, and `read` shows the two ORed together.
+pub struct CsrFile {
+    regs: [u64; 4096],
+    seip: u64,
+}
+
+impl Default for CsrFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CsrFile {
+    pub fn new() -> Self {
+        let mut csrs = CsrFile { regs: [0; 4096], seip: 0 };
+
+        csrs[MISA] = MISA_MXL_64
+            | MISA_I | MISA_M | MISA_A | MISA_F | MISA_D | MISA_C | MISA_B | MISA_V | MISA_H
+            | MISA_S | MISA_U;
+        csrs[MSTATUS] = MSTATUS_XL_64;
+        csrs[MIDELEG] = MIDELEG_FIXED;
+        csrs[HSTATUS] = HSTATUS_VSXL_64;
+        csrs[VSSTATUS] = MSTATUS_XL_64 & MASK_UXL;
+        csrs[VTYPE] = MASK_VTYPE_VILL;
+        // mvendorid, marchid and mimpid stay zero, as allowed for
+        // non-commercial implementations, and mhartid is 0 until the
+        // hart is given an ID.
+
+        csrs
+    }
+
+    /// Whether `csr` is implemented. Accesses to anything else are illegal.
+    pub fn exists(&self, csr: usize) -> bool {
+        match csr {
+            FFLAGS | FRM | FCSR => true,
+            VSTART | VXSAT | VXRM | VCSR | VL | VTYPE | VLENB => true,
+            CYCLE | TIME | INSTRET | HPMCOUNTER3..=HPMCOUNTER31 => true,
+            SSTATUS | SIE | STVEC | SCOUNTEREN => true,
+            SSCRATCH | SEPC | SCAUSE | STVAL | SIP | STIMECMP | SATP => true,
+            HSTATUS | HEDELEG | HIDELEG | HIE | HTIMEDELTA | HCOUNTEREN | HGEIE => true,
+            HENVCFG | HTVAL | HIP | HVIP | HTINST | HGEIP | HGATP => true,
+            VSSTATUS | VSIE | VSTVEC | VSSCRATCH | VSEPC | VSCAUSE | VSTVAL | VSIP => true,
+            VSTIMECMP | VSATP => true,
+            MVENDORID | MARCHID | MIMPID | MHARTID | MCONFIGPTR => true,
+            MSTATUS | MISA | MEDELEG | MIDELEG | MIE | MTVEC | MCOUNTEREN | MENVCFG => true,
+            MSCRATCH | MEPC | MCAUSE | MTVAL | MIP | MTINST | MTVAL2 => true,
+            MCYCLE | MINSTRET | MHPMCOUNTER3..=MHPMCOUNTER31 => true,
+            MCOUNTINHIBIT | MHPMEVENT3..=MHPMEVENT31 => true,
+            // Odd pmpcfg registers only exist on RV32.
+            PMPCFG0..=PMPCFG15 => csr.is_multiple_of(2),
+            PMPADDR0..=PMPADDR63 => true,
+            TSELECT..=TCONTROL => true,
+            _ => false,
+        }
+    }
+
+    /// mip with only the software-written SEIP, the value csrrs and csrrc
+    /// set and clear bits of.
+    pub fn written_mip(&self) -> u64 {
+        (self[MIP] & !MASK_SEIP) | self.seip
+    }
+
+    pub fn read(&self, csr: usize) -> u64 {
+        match csr {
+            FFLAGS => self[FCSR] & MASK_FFLAGS,
+            FRM => (self[FCSR] & MASK_FRM) >> 5,
+            VXSAT => self[VCSR] & MASK_VXSAT,
+            VXRM => (self[VCSR] & MASK_VXRM) >> 1,
+            SSTATUS => self[MSTATUS] & MASK_SSTATUS,
+            SIE => self[MIE] & self[MIDELEG] & MASK_S_INTERRUPTS,
+            SIP => self.read(MIP) & self[MIDELEG] & MASK_S_INTERRUPTS,
+            MIP => self[MIP] | self.seip,
+            HIE => self[MIE] & (MASK_VS_INTERRUPTS | MASK_SGEIP),
+            HIP => self[MIP] & (MASK_VS_INTERRUPTS | MASK_SGEIP),
+            HVIP => self[MIP] & MASK_VS_INTERRUPTS,
+            // VS-level interrupts appear at the supervisor positions.
+            VSIE => (self[MIE] & self[HIDELEG]) >> 1,
+            VSIP => (self[MIP] & self[HIDELEG]) >> 1,
+            // henvcfg.STCE is read-only zero unless menvcfg.STCE is set.
+            HENVCFG => self[HENVCFG] & (self[MENVCFG] | !MASK_STCE),
+            // The unprivileged counters shadow the machine ones. time is
+            // provided by the CLINT, so the hart has to supply it.
+            CYCLE => self[MCYCLE],
+            INSTRET => self[MINSTRET],
+            HPMCOUNTER3..=HPMCOUNTER31 => self[csr - HPMCOUNTER3 + MHPMCOUNTER3],
+            _ => self[csr],
+        }
+    }
+
+    pub fn write(&mut self, csr: usize, val: u64) {
+        match csr {
+            FFLAGS => {
+                self[FCSR] = (self[FCSR] & !MASK_FFLAGS) | (val & MASK_FFLAGS);
+                self.dirty_fs();
+            }
+            FRM => {
+                self[FCSR] = (self[FCSR] & !MASK_FRM) | ((val << 5) & MASK_FRM);
+                self.dirty_fs();
+            }
+            FCSR => {
+                self[FCSR] = val & (MASK_FRM | MASK_FFLAGS);
+                self.dirty_fs();
+            }
+            VSTART => {
+                self[VSTART] = val;
+                self.dirty_vs();
+            }
+            VXSAT => {
+                self[VCSR] = (self[VCSR] & !MASK_VXSAT) | (val & MASK_VXSAT);
+                self.dirty_vs();
+            }
+            VXRM => {
+                self[VCSR] = (self[VCSR] & !MASK_VXRM) | ((val << 1) & MASK_VXRM);
+                self.dirty_vs();
+            }
+            VCSR => {
+                self[VCSR] = val & (MASK_VXRM | MASK_VXSAT);
+                self.dirty_vs();
+            }
+            MSTATUS => self.write_status(MSTATUS, MASK_MSTATUS_WRITE, val),
+            SSTATUS => self.write_status(MSTATUS, MASK_SSTATUS_WRITE, val),
+            VSSTATUS => self.write_status(VSSTATUS, MASK_SSTATUS_WRITE, val),
+            HSTATUS => self[HSTATUS] = (val & MASK_HSTATUS_WRITE) | HSTATUS_VSXL_64,
+            MEDELEG => self[MEDELEG] = val & MASK_MEDELEG_WRITE,
+            MIDELEG => self[MIDELEG] = (val & MASK_MIDELEG_WRITE) | MIDELEG_FIXED,
+            HEDELEG => self[HEDELEG] = val & MASK_HEDELEG_WRITE,
+            HIDELEG => self[HIDELEG] = val & MASK_VS_INTERRUPTS,
+            MIE => self[MIE] = val & MASK_MIE_WRITE,
+            MIP => {
+                // With Sstc enabled, STIP follows stimecmp instead. SEIP
+                // goes to the software-written bit, not the PLIC's.
+                let mask = if self[MENVCFG] & MASK_STCE != 0 {
+                    MASK_MIP_WRITE & !(MASK_STIP | MASK_SEIP)
+                } else {
+                    MASK_MIP_WRITE & !MASK_SEIP
+                };
+                self[MIP] = (self[MIP] & !mask) | (val & mask);
+                self.seip = val & MASK_SEIP;
+            }
+            SIE => {
+                let mask = self[MIDELEG] & MASK_S_INTERRUPTS;
+                self[MIE] = (self[MIE] & !mask) | (val & mask);
+            }
+            SIP => {
+                let mask = self[MIDELEG] & MASK_SSIP;
+                self[MIP] = (self[MIP] & !mask) | (val & mask);
+            }
+            HIE => {
+                let mask = MASK_VS_INTERRUPTS | MASK_SGEIP;
+                self[MIE] = (self[MIE] & !mask) | (val & mask);
+            }
+            // Without guest external interrupts, hvip is the only source
+            // of VS-level interrupts, so it lives in mip directly.
+            HIP => self[MIP] = (self[MIP] & !MASK_VSSIP) | (val & MASK_VSSIP),
+            HVIP => self[MIP] = (self[MIP] & !MASK_VS_INTERRUPTS) | (val & MASK_VS_INTERRUPTS),
+            VSIE => {
+                let mask = self[HIDELEG];
+                self[MIE] = (self[MIE] & !mask) | ((val << 1) & mask);
+            }
+            VSIP => {
+                let mask = self[HIDELEG] & MASK_VSSIP;
+                self[MIP] = (self[MIP] & !mask) | ((val << 1) & mask);
+            }
+            MTVEC | STVEC | VSTVEC => {
+                // Only Direct and Vectored modes exist; keep the old mode
+                // when a reserved one is written.
+                let mode = match val & MASK_TVEC_MODE {
+                    mode @ (0 | 1) => mode,
+                    _ => self[csr] & MASK_TVEC_MODE,
+                };
+                self[csr] = (val & !MASK_TVEC_MODE) | mode;
+            }
+            MEPC | SEPC | VSEPC => self[csr] = val & !1,
+            MCOUNTEREN | SCOUNTEREN | HCOUNTEREN => self[csr] = val & 0xffff_ffff,
+            // mcountinhibit.TM doesn't exist: time can't be stopped.
+            MCOUNTINHIBIT => self[csr] = val & 0xffff_ffff & !MASK_TM,
+            MCYCLE | MINSTRET | MHPMCOUNTER3..=MHPMCOUNTER31 => self[csr] = val,
+            MHPMEVENT3..=MHPMEVENT31 => {
+                // Unknown events legalize to "no event".
+                self[csr] = match val {
+                    HPM_EVENT_NONE..=HPM_EVENT_INTERRUPTS => val,
+                    _ => HPM_EVENT_NONE,
+                }
+            }
+            SCAUSE | STVAL | SSCRATCH | SATP | MSCRATCH | MCAUSE | MTVAL => self[csr] = val,
+            VSCAUSE | VSTVAL | VSSCRATCH | VSATP | HGATP | HTIMEDELTA => self[csr] = val,
+            HTVAL | HTINST | MTVAL2 | MTINST => self[csr] = val,
+            STIMECMP | VSTIMECMP => self[csr] = val,
+            MENVCFG | HENVCFG => self[csr] = val & MASK_STCE,
+            // misa, the ID registers, hgeie and hgeip (no guest external
+            // interrupts are implemented) and anything else are read-only.
+            _ => {}
+        }
+    }
+
+    /// Counts one occurrence of a performance-monitoring event in every
+    /// counter selecting it that isn't inhibited.
+    pub fn count_event(&mut self, event: u64) {
+        for i in 3..32 {
+            if self[MHPMEVENT3 + i - 3] == event && self[MCOUNTINHIBIT] & (1 << i) == 0 {
+                let counter = MHPMCOUNTER3 + i - 3;
+                self[counter] = self[counter].wrapping_add(1);
+            }
+        }
+    }
+
+    /// Marks the floating-point state as modified (mstatus.FS = Dirty).
+    pub fn dirty_fs(&mut self) {
+        self[MSTATUS] |= MASK_FS | MASK_SD;
+    }
+
+    /// Marks the vector state as modified (mstatus.VS = Dirty).
+    pub fn dirty_vs(&mut self) {
+        self[MSTATUS] |= MASK_VS | MASK_SD;
+    }
+
+    /// Writes the fields in `mask` of `mstatus` or `vsstatus`.
+    fn write_status(&mut self, csr: usize, mask: u64, val: u64) {
+        let mut status = (self[csr] & !mask) | (val & mask);
+
+        // MPP is WARL and 0b10 is reserved.
+        if (status & MASK_MPP) >> 11 == 0b10 {
+            status &= !MASK_MPP;
+        }
+
+        // SD summarizes whether any extension state is dirty.
+        let dirty = status & MASK_FS == MASK_FS
+            || status & MASK_XS == MASK_XS
+            || status & MASK_VS == MASK_VS;
+        status = if dirty {
+            status | MASK_SD
+        } else {
+            status & !MASK_SD
+        };
+
+        self[csr] = status;
+    }
+}
+
+impl Index<usize> for CsrFile {
+    type Output = u64;
+
+    fn index(&self, csr: usize) -> &u64 {
+        &self.regs[csr]
+    }
+}
+
+impl IndexMut<usize> for CsrFile {
+    fn index_mut(&mut self, csr: usize) -> &mut u64 {
+        &mut self.regs[csr]
+    }
+}
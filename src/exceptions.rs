@@ -12,10 +12,16 @@ pub enum Exception {
 	StoreAMOAccessFault(u64),
 	EnvironmentCallFromUMode(u64),
 	EnvironmentCallFromSMode(u64),
+	EnvironmentCallFromVSMode(u64),
 	EnvironmentCallFromMMode(u64),
 	InstructionPageFault(u64),
 	LoadPageFault(u64),
 	StoreAMOPageFault(u64),
+	// Guest-page faults carry the guest virtual and guest physical address.
+	InstructionGuestPageFault(u64, u64),
+	LoadGuestPageFault(u64, u64),
+	VirtualInstruction(u64),
+	StoreAMOGuestPageFault(u64, u64),
 }
 
 impl Exception {
@@ -30,15 +36,44 @@ impl Exception {
 			Exception::LoadAccessFault(addr) => addr,
 			Exception::StoreAMOAddrMisaligned(addr) => addr,
 			Exception::StoreAMOAccessFault(addr) => addr,
-			Exception::EnvironmentCallFromUMode(pc) => pc,
-			Exception::EnvironmentCallFromSMode(pc) => pc,
-			Exception::EnvironmentCallFromMMode(pc) => pc,
+			// Environment calls report no trap value.
+			Exception::EnvironmentCallFromUMode(_) => 0,
+			Exception::EnvironmentCallFromSMode(_) => 0,
+			Exception::EnvironmentCallFromVSMode(_) => 0,
+			Exception::EnvironmentCallFromMMode(_) => 0,
 			Exception::InstructionPageFault(addr) => addr,
 			Exception::LoadPageFault(addr) => addr,
 			Exception::StoreAMOPageFault(addr) => addr,
+			Exception::InstructionGuestPageFault(addr, _) => addr,
+			Exception::LoadGuestPageFault(addr, _) => addr,
+			Exception::VirtualInstruction(inst) => inst,
+			Exception::StoreAMOGuestPageFault(addr, _) => addr,
 		}
 	}
 
+	/// Guest physical address of a guest-page fault, or 0.
+	pub fn guest_addr(self) -> u64 {
+		match self {
+			Exception::InstructionGuestPageFault(_, gpa)
+			| Exception::LoadGuestPageFault(_, gpa)
+			| Exception::StoreAMOGuestPageFault(_, gpa) => gpa,
+			_ => 0,
+		}
+	}
+
+	/// Whether `value` is a virtual address.
+	pub fn has_address(self) -> bool {
+		!matches!(
+			self,
+			Exception::IllegalInstruction(_)
+			| Exception::VirtualInstruction(_)
+			| Exception::EnvironmentCallFromUMode(_)
+			| Exception::EnvironmentCallFromSMode(_)
+			| Exception::EnvironmentCallFromVSMode(_)
+			| Exception::EnvironmentCallFromMMode(_)
+		)
+	}
+
 	pub fn code(self) -> u64 {
 		match self {
 			Exception::InstructionAddrMisalignment(_) => 0,
@@ -51,10 +86,15 @@ impl Exception {
 			Exception::StoreAMOAccessFault(_) => 7,
 			Exception::EnvironmentCallFromUMode(_) => 8,
 			Exception::EnvironmentCallFromSMode(_) => 9,
+			Exception::EnvironmentCallFromVSMode(_) => 10,
 			Exception::EnvironmentCallFromMMode(_) => 11,
 			Exception::InstructionPageFault(_) => 12,
 			Exception::LoadPageFault(_) => 13,
 			Exception::StoreAMOPageFault(_) => 15,
+			Exception::InstructionGuestPageFault(..) => 20,
+			Exception::LoadGuestPageFault(..) => 21,
+			Exception::VirtualInstruction(_) => 22,
+			Exception::StoreAMOGuestPageFault(..) => 23,
 		}
 	}	
 
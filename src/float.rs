@@ -0,0 +1,775 @@
+//! Software IEEE-754 binary floating point.
+//!
+//! The host FPU can't be told which rounding mode to use and doesn't report
+//! exception flags, so every F/D instruction goes through these routines.
+//! Values are passed around as raw bit patterns and the format is selected
+//! with a [`Format`] descriptor.
+
+use core::cmp::Ordering;
+
+// Rounding modes (the `rm` instruction field and `frm` CSR).
+pub const RM_RNE: u64 = 0b000;
+pub const RM_RTZ: u64 = 0b001;
+pub const RM_RDN: u64 = 0b010;
+pub const RM_RUP: u64 = 0b011;
+pub const RM_RMM: u64 = 0b100;
+pub const RM_DYN: u64 = 0b111;
+
+// Accrued exception flags (the `fflags` CSR).
+pub const FLAG_NX: u64 = 1 << 0;
+pub const FLAG_UF: u64 = 1 << 1;
+pub const FLAG_OF: u64 = 1 << 2;
+pub const FLAG_DZ: u64 = 1 << 3;
+pub const FLAG_NV: u64 = 1 << 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Format {
+    exp_bits: u32,
+    man_bits: u32,
+}
+
+/// IEEE-754 binary32.
+pub const F32: Format = Format {
+    exp_bits: 8,
+    man_bits: 23,
+};
+
+/// IEEE-754 binary64.
+pub const F64: Format = Format {
+    exp_bits: 11,
+    man_bits: 52,
+};
+
+impl Format {
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+
+    fn exp_mask(self) -> u64 {
+        (1 << self.exp_bits) - 1
+    }
+
+    fn man_mask(self) -> u64 {
+        (1 << self.man_bits) - 1
+    }
+
+    pub fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits + self.man_bits)
+    }
+
+    fn exp(self, a: u64) -> u64 {
+        (a >> self.man_bits) & self.exp_mask()
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        (self.exp_mask() << self.man_bits) | (1 << (self.man_bits - 1))
+    }
+
+    pub fn inf(self, sign: bool) -> u64 {
+        self.zero(sign) | (self.exp_mask() << self.man_bits)
+    }
+
+    pub fn zero(self, sign: bool) -> u64 {
+        if sign {
+            self.sign_bit()
+        } else {
+            0
+        }
+    }
+
+    fn max_finite(self, sign: bool) -> u64 {
+        self.inf(sign) - 1
+    }
+
+    pub fn sign(self, a: u64) -> bool {
+        a & self.sign_bit() != 0
+    }
+
+    pub fn is_nan(self, a: u64) -> bool {
+        self.exp(a) == self.exp_mask() && a & self.man_mask() != 0
+    }
+
+    pub fn is_snan(self, a: u64) -> bool {
+        self.is_nan(a) && a & (1 << (self.man_bits - 1)) == 0
+    }
+
+    pub fn is_inf(self, a: u64) -> bool {
+        self.exp(a) == self.exp_mask() && a & self.man_mask() == 0
+    }
+
+    pub fn is_zero(self, a: u64) -> bool {
+        a & (self.sign_bit() - 1) == 0
+    }
+
+    /// Splits a finite value into `(sign, exp, sig)` with `value = sig * 2^exp`.
+    fn unpack(self, a: u64) -> (bool, i32, u128) {
+        let sign = self.sign(a);
+        let exp = self.exp(a) as i32;
+        let man = (a & self.man_mask()) as u128;
+        let man_bits = self.man_bits as i32;
+
+        if exp == 0 {
+            (sign, 1 - self.bias() - man_bits, man)
+        } else {
+            (sign, exp - self.bias() - man_bits, man | (1 << man_bits))
+        }
+    }
+
+    /// Rounds `sig * 2^exp` to this format and packs it.
+    fn round_pack(self, sign: bool, exp: i32, sig: u128, rm: u64, flags: &mut u64) -> u64 {
+        if sig == 0 {
+            return self.zero(sign);
+        }
+
+        let m = self.man_bits as i32;
+        let emin = 1 - self.bias();
+        let lead = exp + 127 - sig.leading_zeros() as i32;
+
+        let mut lsb = lead.max(emin) - m;
+        let (mut q, inexact) = round_shift(sig, lsb - exp, sign, rm);
+        if q >> (m + 1) != 0 {
+            q >>= 1;
+            lsb += 1;
+        }
+
+        if inexact {
+            *flags |= FLAG_NX;
+
+            // Tininess is detected after rounding.
+            if lead < emin {
+                let (unbounded, _) = round_shift(sig, lead - m - exp, sign, rm);
+                let carried = unbounded >> (m + 1) != 0;
+                if !(carried && lead + 1 == emin) {
+                    *flags |= FLAG_UF;
+                }
+            }
+        }
+
+        if q >> m == 0 {
+            return self.zero(sign) | q as u64;
+        }
+
+        if lsb + m > self.bias() {
+            *flags |= FLAG_OF | FLAG_NX;
+            let to_inf = match rm {
+                RM_RTZ => false,
+                RM_RDN => sign,
+                RM_RUP => !sign,
+                _ => true,
+            };
+            return if to_inf {
+                self.inf(sign)
+            } else {
+                self.max_finite(sign)
+            };
+        }
+
+        let biased = (lsb + m + self.bias()) as u64;
+        self.zero(sign) | (biased << self.man_bits) | (q as u64 & self.man_mask())
+    }
+}
+
+/// Shifts `sig` right by `shift` bits, rounding the result according to `rm`.
+/// Returns the rounded value and whether any non-zero bits were discarded.
+fn round_shift(sig: u128, shift: i32, sign: bool, rm: u64) -> (u128, bool) {
+    if shift <= 0 {
+        return (sig << -shift, false);
+    }
+
+    let (q, rem, half) = if shift >= 128 {
+        (0, sig, if shift == 128 { Some(1 << 127) } else { None })
+    } else {
+        (
+            sig >> shift,
+            sig & ((1 << shift) - 1),
+            Some(1 << (shift - 1)),
+        )
+    };
+
+    if rem == 0 {
+        return (q, false);
+    }
+
+    let cmp = match half {
+        Some(half) => rem.cmp(&half),
+        None => Ordering::Less,
+    };
+
+    let up = match rm {
+        RM_RNE => cmp == Ordering::Greater || (cmp == Ordering::Equal && q & 1 == 1),
+        RM_RDN => sign,
+        RM_RUP => !sign,
+        RM_RMM => cmp != Ordering::Less,
+        _ => false,
+    };
+
+    (q + up as u128, true)
+}
+
+/// Shifts right, OR-ing every discarded bit into the lowest bit of the result.
+fn shift_right_jam(sig: u128, shift: i32) -> u128 {
+    if shift <= 0 {
+        sig
+    } else if shift >= 128 {
+        (sig != 0) as u128
+    } else {
+        (sig >> shift) | ((sig & ((1 << shift) - 1)) != 0) as u128
+    }
+}
+
+/// Normalizes a non-zero significand so that its leading bit is bit 125.
+fn normalize(exp: i32, sig: u128) -> (i32, u128) {
+    let shift = sig.leading_zeros() as i32 - 2;
+    if shift >= 0 {
+        (exp - shift, sig << shift)
+    } else {
+        (exp - shift, shift_right_jam(sig, -shift))
+    }
+}
+
+/// Propagates NaN operands: raises NV for any signaling NaN and returns the
+/// canonical NaN if any operand is a NaN.
+fn propagate_nan(f: Format, ops: &[u64], flags: &mut u64) -> Option<u64> {
+    if ops.iter().any(|&a| f.is_snan(a)) {
+        *flags |= FLAG_NV;
+    }
+    if ops.iter().any(|&a| f.is_nan(a)) {
+        Some(f.canonical_nan())
+    } else {
+        None
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn add_unpacked(
+    f: Format,
+    sa: bool,
+    ea: i32,
+    ma: u128,
+    sb: bool,
+    eb: i32,
+    mb: u128,
+    rm: u64,
+    flags: &mut u64,
+) -> u64 {
+    let (ea, ma) = normalize(ea, ma);
+    let (eb, mb) = normalize(eb, mb);
+
+    let ((sa, ea, ma), (sb, eb, mb)) = if ea >= eb {
+        ((sa, ea, ma), (sb, eb, mb))
+    } else {
+        ((sb, eb, mb), (sa, ea, ma))
+    };
+    let mb = shift_right_jam(mb, ea - eb);
+
+    if sa == sb {
+        return f.round_pack(sa, ea, ma + mb, rm, flags);
+    }
+
+    match ma.cmp(&mb) {
+        Ordering::Greater => f.round_pack(sa, ea, ma - mb, rm, flags),
+        Ordering::Less => f.round_pack(sb, ea, mb - ma, rm, flags),
+        Ordering::Equal => f.zero(rm == RM_RDN),
+    }
+}
+
+pub fn add(f: Format, a: u64, b: u64, rm: u64, flags: &mut u64) -> u64 {
+    if let Some(nan) = propagate_nan(f, &[a, b], flags) {
+        return nan;
+    }
+
+    let (sa, ea, ma) = f.unpack(a);
+    let (sb, eb, mb) = f.unpack(b);
+
+    if f.is_inf(a) {
+        if f.is_inf(b) && sa != sb {
+            *flags |= FLAG_NV;
+            return f.canonical_nan();
+        }
+        return a;
+    }
+    if f.is_inf(b) {
+        return b;
+    }
+
+    match (ma == 0, mb == 0) {
+        (true, true) => f.zero(if sa == sb { sa } else { rm == RM_RDN }),
+        (true, false) => b,
+        (false, true) => a,
+        (false, false) => add_unpacked(f, sa, ea, ma, sb, eb, mb, rm, flags),
+    }
+}
+
+pub fn sub(f: Format, a: u64, b: u64, rm: u64, flags: &mut u64) -> u64 {
+    add(f, a, b ^ f.sign_bit(), rm, flags)
+}
+
+pub fn mul(f: Format, a: u64, b: u64, rm: u64, flags: &mut u64) -> u64 {
+    if let Some(nan) = propagate_nan(f, &[a, b], flags) {
+        return nan;
+    }
+
+    let sign = f.sign(a) != f.sign(b);
+
+    if (f.is_inf(a) && f.is_zero(b)) || (f.is_zero(a) && f.is_inf(b)) {
+        *flags |= FLAG_NV;
+        return f.canonical_nan();
+    }
+    if f.is_inf(a) || f.is_inf(b) {
+        return f.inf(sign);
+    }
+    if f.is_zero(a) || f.is_zero(b) {
+        return f.zero(sign);
+    }
+
+    let (_, ea, ma) = f.unpack(a);
+    let (_, eb, mb) = f.unpack(b);
+    f.round_pack(sign, ea + eb, ma * mb, rm, flags)
+}
+
+/// Computes `a * b + c` with a single rounding.
+pub fn mul_add(f: Format, a: u64, b: u64, c: u64, rm: u64, flags: &mut u64) -> u64 {
+    let invalid_product = (f.is_inf(a) && f.is_zero(b)) || (f.is_zero(a) && f.is_inf(b));
+
+    if let Some(nan) = propagate_nan(f, &[a, b, c], flags) {
+        if invalid_product {
+            *flags |= FLAG_NV;
+        }
+        return nan;
+    }
+    if invalid_product {
+        *flags |= FLAG_NV;
+        return f.canonical_nan();
+    }
+
+    let sp = f.sign(a) != f.sign(b);
+    let sc = f.sign(c);
+
+    if f.is_inf(a) || f.is_inf(b) {
+        if f.is_inf(c) && sp != sc {
+            *flags |= FLAG_NV;
+            return f.canonical_nan();
+        }
+        return f.inf(sp);
+    }
+    if f.is_inf(c) {
+        return c;
+    }
+
+    if f.is_zero(a) || f.is_zero(b) {
+        if f.is_zero(c) {
+            return f.zero(if sp == sc { sp } else { rm == RM_RDN });
+        }
+        return c;
+    }
+
+    let (_, ea, ma) = f.unpack(a);
+    let (_, eb, mb) = f.unpack(b);
+
+    if f.is_zero(c) {
+        return f.round_pack(sp, ea + eb, ma * mb, rm, flags);
+    }
+
+    let (_, ec, mc) = f.unpack(c);
+    add_unpacked(f, sp, ea + eb, ma * mb, sc, ec, mc, rm, flags)
+}
+
+pub fn div(f: Format, a: u64, b: u64, rm: u64, flags: &mut u64) -> u64 {
+    if let Some(nan) = propagate_nan(f, &[a, b], flags) {
+        return nan;
+    }
+
+    let sign = f.sign(a) != f.sign(b);
+
+    if (f.is_inf(a) && f.is_inf(b)) || (f.is_zero(a) && f.is_zero(b)) {
+        *flags |= FLAG_NV;
+        return f.canonical_nan();
+    }
+    if f.is_inf(a) {
+        return f.inf(sign);
+    }
+    if f.is_inf(b) || f.is_zero(a) {
+        return f.zero(sign);
+    }
+    if f.is_zero(b) {
+        *flags |= FLAG_DZ;
+        return f.inf(sign);
+    }
+
+    let (_, ea, ma) = f.unpack(a);
+    let (_, eb, mb) = f.unpack(b);
+
+    let shift_a = ma.leading_zeros() as i32 - 1;
+    let shift_b = mb.leading_zeros() as i32 - 64;
+    let num = ma << shift_a;
+    let den = mb << shift_b;
+
+    let sig = ((num / den) << 1) | !num.is_multiple_of(den) as u128;
+    f.round_pack(sign, ea - shift_a - eb + shift_b - 1, sig, rm, flags)
+}
+
+pub fn sqrt(f: Format, a: u64, rm: u64, flags: &mut u64) -> u64 {
+    if let Some(nan) = propagate_nan(f, &[a], flags) {
+        return nan;
+    }
+    if f.is_zero(a) {
+        return a;
+    }
+    if f.sign(a) {
+        *flags |= FLAG_NV;
+        return f.canonical_nan();
+    }
+    if f.is_inf(a) {
+        return a;
+    }
+
+    let (_, mut exp, mut sig) = f.unpack(a);
+    if exp & 1 != 0 {
+        sig <<= 1;
+        exp -= 1;
+    }
+
+    let shift = (sig.leading_zeros() as i32 - 2) & !1;
+    sig <<= shift;
+    exp -= shift;
+
+    let (root, rem) = isqrt(sig);
+    f.round_pack(
+        false,
+        exp / 2 - 1,
+        (root << 1) | (rem != 0) as u128,
+        rm,
+        flags,
+    )
+}
+
+/// Integer square root, returning the root and the remainder.
+fn isqrt(n: u128) -> (u128, u128) {
+    let mut rem = n;
+    let mut root = 0u128;
+    let mut bit = 1u128 << 126;
+
+    while bit > n {
+        bit >>= 2;
+    }
+
+    while bit != 0 {
+        if rem >= root + bit {
+            rem -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+
+    (root, rem)
+}
+
+/// Ordering of two non-NaN values, treating both zeros as equal.
+fn compare(f: Format, a: u64, b: u64) -> Ordering {
+    if f.is_zero(a) && f.is_zero(b) {
+        return Ordering::Equal;
+    }
+
+    match (f.sign(a), f.sign(b)) {
+        (false, true) => Ordering::Greater,
+        (true, false) => Ordering::Less,
+        (false, false) => a.cmp(&b),
+        (true, true) => b.cmp(&a),
+    }
+}
+
+/// Quiet equality comparison (`feq`).
+pub fn eq(f: Format, a: u64, b: u64, flags: &mut u64) -> bool {
+    if propagate_nan(f, &[a, b], flags).is_some() {
+        return false;
+    }
+    compare(f, a, b) == Ordering::Equal
+}
+
+/// Signaling less-than comparison (`flt`).
+pub fn lt(f: Format, a: u64, b: u64, flags: &mut u64) -> bool {
+    if f.is_nan(a) || f.is_nan(b) {
+        *flags |= FLAG_NV;
+        return false;
+    }
+    compare(f, a, b) == Ordering::Less
+}
+
+/// Signaling less-than-or-equal comparison (`fle`).
+pub fn le(f: Format, a: u64, b: u64, flags: &mut u64) -> bool {
+    if f.is_nan(a) || f.is_nan(b) {
+        *flags |= FLAG_NV;
+        return false;
+    }
+    compare(f, a, b) != Ordering::Greater
+}
+
+fn min_max(f: Format, a: u64, b: u64, max: bool, flags: &mut u64) -> u64 {
+    if f.is_snan(a) || f.is_snan(b) {
+        *flags |= FLAG_NV;
+    }
+
+    match (f.is_nan(a), f.is_nan(b)) {
+        (true, true) => return f.canonical_nan(),
+        (true, false) => return b,
+        (false, true) => return a,
+        _ => {}
+    }
+
+    let a_first = match compare(f, a, b) {
+        Ordering::Less => !max,
+        Ordering::Greater => max,
+        Ordering::Equal => f.sign(a) != max,
+    };
+
+    if a_first {
+        a
+    } else {
+        b
+    }
+}
+
+/// `fmin`: IEEE 754-2019 minimumNumber, with -0 ordered below +0.
+pub fn min(f: Format, a: u64, b: u64, flags: &mut u64) -> u64 {
+    min_max(f, a, b, false, flags)
+}
+
+/// `fmax`: IEEE 754-2019 maximumNumber, with -0 ordered below +0.
+pub fn max(f: Format, a: u64, b: u64, flags: &mut u64) -> u64 {
+    min_max(f, a, b, true, flags)
+}
+
+/// `fclass`: returns a one-hot mask describing the class of `a`.
+pub fn classify(f: Format, a: u64) -> u64 {
+    let sign = f.sign(a);
+    let bit = if f.is_nan(a) {
+        if f.is_snan(a) {
+            8
+        } else {
+            9
+        }
+    } else if f.is_inf(a) {
+        if sign {
+            0
+        } else {
+            7
+        }
+    } else if f.is_zero(a) {
+        if sign {
+            3
+        } else {
+            4
+        }
+    } else if f.exp(a) == 0 {
+        if sign {
+            2
+        } else {
+            5
+        }
+    } else if sign {
+        1
+    } else {
+        6
+    };
+
+    1 << bit
+}
+
+/// Converts to a `bits`-wide integer, saturating out-of-range values.
+/// The result is returned zero-extended from `bits`.
+pub fn to_int(f: Format, a: u64, bits: u32, signed: bool, rm: u64, flags: &mut u64) -> u64 {
+    let mask = if bits == 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    };
+    let (max, min) = if signed {
+        (mask >> 1, (mask >> 1) + 1)
+    } else {
+        (mask, 0)
+    };
+
+    if f.is_nan(a) {
+        *flags |= FLAG_NV;
+        return max;
+    }
+
+    let (sign, exp, sig) = f.unpack(a);
+    if f.is_inf(a) {
+        *flags |= FLAG_NV;
+        return if sign { min } else { max };
+    }
+
+    // Anything with more than 65 integer bits is out of range for every
+    // destination width, so clamp the exponent before shifting.
+    let (q, inexact) = round_shift(sig, -exp.min(66), sign, rm);
+
+    let limit = if sign { min as u128 } else { max as u128 };
+    if q > limit || (sign && !signed && q != 0) {
+        *flags |= FLAG_NV;
+        return if sign { min } else { max };
+    }
+
+    if inexact {
+        *flags |= FLAG_NX;
+    }
+
+    if sign {
+        (q as u64).wrapping_neg() & mask
+    } else {
+        q as u64
+    }
+}
+
+/// Converts a `bits`-wide integer to a float.
+pub fn from_int(f: Format, val: u64, bits: u32, signed: bool, rm: u64, flags: &mut u64) -> u64 {
+    let val = if bits == 32 {
+        if signed {
+            val as i32 as i64 as u64
+        } else {
+            val as u32 as u64
+        }
+    } else {
+        val
+    };
+
+    let sign = signed && (val as i64) < 0;
+    let mag = if sign { val.wrapping_neg() } else { val };
+
+    f.round_pack(sign, 0, mag as u128, rm, flags)
+}
+
+/// Converts between formats, rounding if the destination is narrower.
+pub fn convert(from: Format, to: Format, a: u64, rm: u64, flags: &mut u64) -> u64 {
+    if propagate_nan(from, &[a], flags).is_some() {
+        return to.canonical_nan();
+    }
+
+    let (sign, exp, sig) = from.unpack(a);
+    if from.is_inf(a) {
+        return to.inf(sign);
+    }
+
+    to.round_pack(sign, exp, sig, rm, flags)
+}
+
+/// Significand estimates for `vfrec7`, indexed by the top seven bits of the
+/// normalized input significand.
+const REC7_TABLE: [u8; 128] = [
+    127, 125, 123, 121, 119, 117, 116, 114, 112, 110, 109, 107, 105, 104, 102, 100, 99, 97, 96, 94,
+    93, 91, 90, 88, 87, 85, 84, 83, 81, 80, 79, 77, 76, 75, 74, 72, 71, 70, 69, 68, 66, 65, 64, 63,
+    62, 61, 60, 59, 58, 57, 56, 55, 54, 53, 52, 51, 50, 49, 48, 47, 46, 45, 44, 43, 42, 41, 40, 40,
+    39, 38, 37, 36, 35, 35, 34, 33, 32, 31, 31, 30, 29, 28, 28, 27, 26, 25, 25, 24, 23, 23, 22, 21,
+    21, 20, 19, 19, 18, 17, 17, 16, 15, 15, 14, 14, 13, 12, 12, 11, 11, 10, 9, 9, 8, 8, 7, 7, 6, 5,
+    5, 4, 4, 3, 3, 2, 2, 1, 1, 0,
+];
+
+/// Significand estimates for `vfrsqrt7`, indexed by the low bit of the
+/// normalized input exponent and the top six bits of its significand.
+const RSQRT7_TABLE: [u8; 128] = [
+    52, 51, 50, 48, 47, 46, 44, 43, 42, 41, 40, 39, 38, 36, 35, 34, 33, 32, 31, 30, 30, 29, 28, 27,
+    26, 25, 24, 23, 23, 22, 21, 20, 19, 19, 18, 17, 16, 16, 15, 14, 14, 13, 12, 12, 11, 10, 10, 9,
+    9, 8, 7, 7, 6, 6, 5, 4, 4, 3, 3, 2, 2, 1, 1, 0, 127, 125, 123, 121, 119, 118, 116, 114, 113,
+    111, 109, 108, 106, 105, 103, 102, 100, 99, 97, 96, 95, 93, 92, 91, 90, 88, 87, 86, 85, 84, 83,
+    82, 80, 79, 78, 77, 76, 75, 74, 73, 72, 71, 70, 70, 69, 68, 67, 66, 65, 64, 63, 63, 62, 61, 60,
+    59, 59, 58, 57, 56, 56, 55, 54, 53,
+];
+
+impl Format {
+    /// Biased exponent and significand (without the leading one) of a
+    /// finite non-zero value. Subnormals are normalized, giving exponents
+    /// of zero or below.
+    fn normalized(self, a: u64) -> (i64, u64) {
+        let exp = self.exp(a) as i64;
+        let man = a & self.man_mask();
+        if exp != 0 {
+            return (exp, man);
+        }
+
+        let zeros = man.leading_zeros() as i64 - (64 - self.man_bits as i64);
+        (-zeros, (man << (zeros + 1)) & self.man_mask())
+    }
+}
+
+/// `vfrec7`: estimates `1 / a` to 7 bits, as specified by the vector
+/// extension.
+pub fn rec7(f: Format, a: u64, rm: u64, flags: &mut u64) -> u64 {
+    let sign = f.sign(a);
+    if f.is_nan(a) {
+        if f.is_snan(a) {
+            *flags |= FLAG_NV;
+        }
+        return f.canonical_nan();
+    }
+    if f.is_inf(a) {
+        return f.zero(sign);
+    }
+    if f.is_zero(a) {
+        *flags |= FLAG_DZ;
+        return f.inf(sign);
+    }
+
+    let m = f.man_bits;
+    let bias = f.bias() as i64;
+    let (exp, sig) = f.normalized(a);
+
+    let mut out_exp = 2 * bias - 1 - exp;
+    if out_exp > 2 * bias {
+        // The reciprocal of a tiny subnormal overflows.
+        *flags |= FLAG_OF | FLAG_NX;
+        let to_inf = match rm {
+            RM_RTZ => false,
+            RM_RDN => sign,
+            RM_RUP => !sign,
+            _ => true,
+        };
+        return if to_inf {
+            f.inf(sign)
+        } else {
+            f.max_finite(sign)
+        };
+    }
+
+    let mut out_sig = (REC7_TABLE[(sig >> (m - 7)) as usize] as u64) << (m - 7);
+    if out_exp <= 0 {
+        out_sig = (out_sig | (1 << m)) >> (1 - out_exp);
+        out_exp = 0;
+    }
+
+    f.zero(sign) | (out_exp as u64) << m | out_sig
+}
+
+/// `vfrsqrt7`: estimates `1 / sqrt(a)` to 7 bits, as specified by the
+/// vector extension.
+pub fn rsqrt7(f: Format, a: u64, flags: &mut u64) -> u64 {
+    let sign = f.sign(a);
+    if f.is_nan(a) {
+        if f.is_snan(a) {
+            *flags |= FLAG_NV;
+        }
+        return f.canonical_nan();
+    }
+    if f.is_zero(a) {
+        *flags |= FLAG_DZ;
+        return f.inf(sign);
+    }
+    if sign {
+        *flags |= FLAG_NV;
+        return f.canonical_nan();
+    }
+    if f.is_inf(a) {
+        return f.zero(false);
+    }
+
+    let m = f.man_bits;
+    let bias = f.bias() as i64;
+    let (exp, sig) = f.normalized(a);
+
+    let index = ((exp & 1) << 6) as u64 | (sig >> (m - 6));
+    let out_exp = (3 * bias - 1 - exp) / 2;
+    let out_sig = (RSQRT7_TABLE[index as usize] as u64) << (m - 7);
+
+    (out_exp as u64) << m | out_sig
+}
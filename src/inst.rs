@@ -1,6 +1,6 @@
-use crate::prelude::Exception;
+use crate::prelude::{Exception, Extension, VAddressing, VOp, VSrc, FCSR, FFLAGS, VCSR, VL, VLENB, VSTART};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum Inst {
 	// RV64I instuctions
     Addi  { rd: usize, rs1: usize, imm: i64 },
@@ -108,13 +108,84 @@ pub enum Inst {
 	Amomaxuw { rd: usize, rs1: usize, rs2: usize, rl: bool, aq: bool },
 	Amomaxud { rd: usize, rs1: usize, rs2: usize, rl: bool, aq: bool },
 
-	// TODO
 	// F extension
-	Flw { rd: usize, rs1: usize, imm: i64 }, 
+	Flw { rd: usize, rs1: usize, imm: i64 },
 	Fsw { rs1: usize, rs2: usize, imm: i64 },
-	
-	// TODO
+
+	Fmadds  { rd: usize, rs1: usize, rs2: usize, rs3: usize, rm: u64 },
+	Fmsubs  { rd: usize, rs1: usize, rs2: usize, rs3: usize, rm: u64 },
+	Fnmsubs { rd: usize, rs1: usize, rs2: usize, rs3: usize, rm: u64 },
+	Fnmadds { rd: usize, rs1: usize, rs2: usize, rs3: usize, rm: u64 },
+
+	Fadds  { rd: usize, rs1: usize, rs2: usize, rm: u64 },
+	Fsubs  { rd: usize, rs1: usize, rs2: usize, rm: u64 },
+	Fmuls  { rd: usize, rs1: usize, rs2: usize, rm: u64 },
+	Fdivs  { rd: usize, rs1: usize, rs2: usize, rm: u64 },
+	Fsqrts { rd: usize, rs1: usize, rm: u64 },
+
+	Fsgnjs  { rd: usize, rs1: usize, rs2: usize },
+	Fsgnjns { rd: usize, rs1: usize, rs2: usize },
+	Fsgnjxs { rd: usize, rs1: usize, rs2: usize },
+	Fmins   { rd: usize, rs1: usize, rs2: usize },
+	Fmaxs   { rd: usize, rs1: usize, rs2: usize },
+
+	Fcvtws  { rd: usize, rs1: usize, rm: u64 },
+	Fcvtwus { rd: usize, rs1: usize, rm: u64 },
+	Fcvtls  { rd: usize, rs1: usize, rm: u64 },
+	Fcvtlus { rd: usize, rs1: usize, rm: u64 },
+	Fcvtsw  { rd: usize, rs1: usize, rm: u64 },
+	Fcvtswu { rd: usize, rs1: usize, rm: u64 },
+	Fcvtsl  { rd: usize, rs1: usize, rm: u64 },
+	Fcvtslu { rd: usize, rs1: usize, rm: u64 },
+
+	Feqs { rd: usize, rs1: usize, rs2: usize },
+	Flts { rd: usize, rs1: usize, rs2: usize },
+	Fles { rd: usize, rs1: usize, rs2: usize },
+
+	Fclasss { rd: usize, rs1: usize },
+	Fmvxw   { rd: usize, rs1: usize },
+	Fmvwx   { rd: usize, rs1: usize },
+
 	// D extension
+	Fld { rd: usize, rs1: usize, imm: i64 },
+	Fsd { rs1: usize, rs2: usize, imm: i64 },
+
+	Fmaddd  { rd: usize, rs1: usize, rs2: usize, rs3: usize, rm: u64 },
+	Fmsubd  { rd: usize, rs1: usize, rs2: usize, rs3: usize, rm: u64 },
+	Fnmsubd { rd: usize, rs1: usize, rs2: usize, rs3: usize, rm: u64 },
+	Fnmaddd { rd: usize, rs1: usize, rs2: usize, rs3: usize, rm: u64 },
+
+	Faddd  { rd: usize, rs1: usize, rs2: usize, rm: u64 },
+	Fsubd  { rd: usize, rs1: usize, rs2: usize, rm: u64 },
+	Fmuld  { rd: usize, rs1: usize, rs2: usize, rm: u64 },
+	Fdivd  { rd: usize, rs1: usize, rs2: usize, rm: u64 },
+	Fsqrtd { rd: usize, rs1: usize, rm: u64 },
+
+	Fsgnjd  { rd: usize, rs1: usize, rs2: usize },
+	Fsgnjnd { rd: usize, rs1: usize, rs2: usize },
+	Fsgnjxd { rd: usize, rs1: usize, rs2: usize },
+	Fmind   { rd: usize, rs1: usize, rs2: usize },
+	Fmaxd   { rd: usize, rs1: usize, rs2: usize },
+
+	Fcvtsd { rd: usize, rs1: usize, rm: u64 },
+	Fcvtds { rd: usize, rs1: usize, rm: u64 },
+
+	Fcvtwd  { rd: usize, rs1: usize, rm: u64 },
+	Fcvtwud { rd: usize, rs1: usize, rm: u64 },
+	Fcvtld  { rd: usize, rs1: usize, rm: u64 },
+	Fcvtlud { rd: usize, rs1: usize, rm: u64 },
+	Fcvtdw  { rd: usize, rs1: usize, rm: u64 },
+	Fcvtdwu { rd: usize, rs1: usize, rm: u64 },
+	Fcvtdl  { rd: usize, rs1: usize, rm: u64 },
+	Fcvtdlu { rd: usize, rs1: usize, rm: u64 },
+
+	Feqd { rd: usize, rs1: usize, rs2: usize },
+	Fltd { rd: usize, rs1: usize, rs2: usize },
+	Fled { rd: usize, rs1: usize, rs2: usize },
+
+	Fclassd { rd: usize, rs1: usize },
+	Fmvxd   { rd: usize, rs1: usize },
+	Fmvdx   { rd: usize, rs1: usize },
 
 	// Zicsr extension
 	Csrrw  { rd: usize, rs1: usize, csr: usize },
@@ -127,20 +198,263 @@ pub enum Inst {
 	// Zifencei extension
 	Fencei { rd: usize, rs1: usize, imm: i64 },
 
+	// Zba extension
+	Adduw    { rd: usize, rs1: usize, rs2: usize },
+	Sh1add   { rd: usize, rs1: usize, rs2: usize },
+	Sh2add   { rd: usize, rs1: usize, rs2: usize },
+	Sh3add   { rd: usize, rs1: usize, rs2: usize },
+	Sh1adduw { rd: usize, rs1: usize, rs2: usize },
+	Sh2adduw { rd: usize, rs1: usize, rs2: usize },
+	Sh3adduw { rd: usize, rs1: usize, rs2: usize },
+	Slliuw   { rd: usize, rs1: usize, shamt: u32 },
+
+	// Zbb extension
+	Andn { rd: usize, rs1: usize, rs2: usize },
+	Orn  { rd: usize, rs1: usize, rs2: usize },
+	Xnor { rd: usize, rs1: usize, rs2: usize },
+	Max  { rd: usize, rs1: usize, rs2: usize },
+	Maxu { rd: usize, rs1: usize, rs2: usize },
+	Min  { rd: usize, rs1: usize, rs2: usize },
+	Minu { rd: usize, rs1: usize, rs2: usize },
+	Rol  { rd: usize, rs1: usize, rs2: usize },
+	Ror  { rd: usize, rs1: usize, rs2: usize },
+	Rolw { rd: usize, rs1: usize, rs2: usize },
+	Rorw { rd: usize, rs1: usize, rs2: usize },
+
+	Rori  { rd: usize, rs1: usize, shamt: u32 },
+	Roriw { rd: usize, rs1: usize, shamt: u32 },
+
+	Clz   { rd: usize, rs1: usize },
+	Ctz   { rd: usize, rs1: usize },
+	Cpop  { rd: usize, rs1: usize },
+	Clzw  { rd: usize, rs1: usize },
+	Ctzw  { rd: usize, rs1: usize },
+	Cpopw { rd: usize, rs1: usize },
+	Sextb { rd: usize, rs1: usize },
+	Sexth { rd: usize, rs1: usize },
+	Zexth { rd: usize, rs1: usize },
+	Orcb  { rd: usize, rs1: usize },
+	Rev8  { rd: usize, rs1: usize },
+
+	// Zbc extension
+	Clmul  { rd: usize, rs1: usize, rs2: usize },
+	Clmulh { rd: usize, rs1: usize, rs2: usize },
+	Clmulr { rd: usize, rs1: usize, rs2: usize },
+
+	// Zbs extension
+	Bclr { rd: usize, rs1: usize, rs2: usize },
+	Bext { rd: usize, rs1: usize, rs2: usize },
+	Binv { rd: usize, rs1: usize, rs2: usize },
+	Bset { rd: usize, rs1: usize, rs2: usize },
+
+	Bclri { rd: usize, rs1: usize, shamt: u32 },
+	Bexti { rd: usize, rs1: usize, shamt: u32 },
+	Binvi { rd: usize, rs1: usize, shamt: u32 },
+	Bseti { rd: usize, rs1: usize, shamt: u32 },
+
+	// V extension
+	Vsetvli  { rd: usize, rs1: usize, vtypei: u64 },
+	Vsetivli { rd: usize, uimm: u64, vtypei: u64 },
+	Vsetvl   { rd: usize, rs1: usize, rs2: usize },
+
+	Vload  { vd: usize, rs1: usize, addressing: VAddressing, eew: u64, nf: u64, vm: bool },
+	Vstore { vs3: usize, rs1: usize, addressing: VAddressing, eew: u64, nf: u64, vm: bool },
+
+	Varith { op: VOp, vd: usize, vs2: usize, src: VSrc, vm: bool },
+
 	// Privilaged mode instuction
 	Sret,
 	Mret,
+	Wfi,
+
+	Sfencevma { rs1: usize, rs2: usize },
+
+	// H extension
+	HlvB   { rd: usize, rs1: usize },
+	HlvBu  { rd: usize, rs1: usize },
+	HlvH   { rd: usize, rs1: usize },
+	HlvHu  { rd: usize, rs1: usize },
+	HlvxHu { rd: usize, rs1: usize },
+	HlvW   { rd: usize, rs1: usize },
+	HlvWu  { rd: usize, rs1: usize },
+	HlvxWu { rd: usize, rs1: usize },
+	HlvD   { rd: usize, rs1: usize },
+
+	HsvB { rs1: usize, rs2: usize },
+	HsvH { rs1: usize, rs2: usize },
+	HsvW { rs1: usize, rs2: usize },
+	HsvD { rs1: usize, rs2: usize },
+
+	Hfencevvma { rs1: usize, rs2: usize },
+	Hfencegvma { rs1: usize, rs2: usize },
+}
+
+impl Inst {
+    /// Instructions that touch the floating-point register file or `fcsr`,
+    /// which are illegal while `mstatus.FS` is Off.
+    pub fn is_fp(&self) -> bool {
+        use Inst::*;
+
+        matches!(
+            self,
+            Flw { .. } | Fsw { .. }
+                | Fmadds { .. } | Fmsubs { .. } | Fnmsubs { .. } | Fnmadds { .. }
+                | Fadds { .. } | Fsubs { .. } | Fmuls { .. } | Fdivs { .. } | Fsqrts { .. }
+                | Fsgnjs { .. } | Fsgnjns { .. } | Fsgnjxs { .. } | Fmins { .. } | Fmaxs { .. }
+                | Fcvtws { .. } | Fcvtwus { .. } | Fcvtls { .. } | Fcvtlus { .. }
+                | Fcvtsw { .. } | Fcvtswu { .. } | Fcvtsl { .. } | Fcvtslu { .. }
+                | Feqs { .. } | Flts { .. } | Fles { .. }
+                | Fclasss { .. } | Fmvxw { .. } | Fmvwx { .. }
+                | Fld { .. } | Fsd { .. }
+                | Fmaddd { .. } | Fmsubd { .. } | Fnmsubd { .. } | Fnmaddd { .. }
+                | Faddd { .. } | Fsubd { .. } | Fmuld { .. } | Fdivd { .. } | Fsqrtd { .. }
+                | Fsgnjd { .. } | Fsgnjnd { .. } | Fsgnjxd { .. } | Fmind { .. } | Fmaxd { .. }
+                | Fcvtsd { .. } | Fcvtds { .. }
+                | Fcvtwd { .. } | Fcvtwud { .. } | Fcvtld { .. } | Fcvtlud { .. }
+                | Fcvtdw { .. } | Fcvtdwu { .. } | Fcvtdl { .. } | Fcvtdlu { .. }
+                | Feqd { .. } | Fltd { .. } | Fled { .. }
+                | Fclassd { .. } | Fmvxd { .. } | Fmvdx { .. }
+                | Csrrw { csr: FFLAGS..=FCSR, .. } | Csrrs { csr: FFLAGS..=FCSR, .. }
+                | Csrrc { csr: FFLAGS..=FCSR, .. } | Csrrwi { csr: FFLAGS..=FCSR, .. }
+                | Csrrsi { csr: FFLAGS..=FCSR, .. } | Csrrci { csr: FFLAGS..=FCSR, .. }
+        ) || matches!(self, Varith { op, .. } if op.is_fp())
+    }
+
+    /// V extension instructions and accesses to the vector CSRs, which are
+    /// illegal while `mstatus.VS` is Off.
+    pub fn is_vector(&self) -> bool {
+        use Inst::*;
+
+        matches!(
+            self,
+            Vsetvli { .. } | Vsetivli { .. } | Vsetvl { .. }
+                | Vload { .. } | Vstore { .. } | Varith { .. }
+        ) || matches!(self.csr_access(), Some((VSTART..=VCSR | VL..=VLENB, _)))
+    }
+
+    /// The extension that defines the instruction, which the hart has to
+    /// implement for it to decode.
+    pub fn extension(&self) -> Extension {
+        use Inst::*;
+
+        match self {
+            Mul { .. } | Mulh { .. } | Mulw { .. } | Mulhsu { .. } | Mulhu { .. } | Div { .. }
+                | Divw { .. } | Divu { .. } | Divuw { .. } | Rem { .. } | Remw { .. }
+                | Remu { .. } | Remuw { .. } => Extension::M,
+            Lrw { .. } | Lrd { .. } | Scw { .. } | Scd { .. } | Amoswapw { .. }
+                | Amoswapd { .. } | Amoaddw { .. } | Amoaddd { .. } | Amoxorw { .. }
+                | Amoxord { .. } | Amoandw { .. } | Amoandd { .. } | Amoorw { .. }
+                | Amoord { .. } | Amominw { .. } | Amomind { .. } | Amomaxw { .. }
+                | Amomaxd { .. } | Amominuw { .. } | Amominud { .. } | Amomaxuw { .. }
+                | Amomaxud { .. } => Extension::A,
+            Flw { .. } | Fsw { .. } | Fmadds { .. } | Fmsubs { .. } | Fnmsubs { .. }
+                | Fnmadds { .. } | Fadds { .. } | Fsubs { .. } | Fmuls { .. } | Fdivs { .. }
+                | Fsqrts { .. } | Fsgnjs { .. } | Fsgnjns { .. } | Fsgnjxs { .. } | Fmins { .. }
+                | Fmaxs { .. } | Fcvtws { .. } | Fcvtwus { .. } | Fcvtls { .. } | Fcvtlus { .. }
+                | Fcvtsw { .. } | Fcvtswu { .. } | Fcvtsl { .. } | Fcvtslu { .. } | Feqs { .. }
+                | Flts { .. } | Fles { .. } | Fclasss { .. } | Fmvxw { .. }
+                | Fmvwx { .. } => Extension::F,
+            Fld { .. } | Fsd { .. } | Fmaddd { .. } | Fmsubd { .. } | Fnmsubd { .. }
+                | Fnmaddd { .. } | Faddd { .. } | Fsubd { .. } | Fmuld { .. } | Fdivd { .. }
+                | Fsqrtd { .. } | Fsgnjd { .. } | Fsgnjnd { .. } | Fsgnjxd { .. } | Fmind { .. }
+                | Fmaxd { .. } | Fcvtsd { .. } | Fcvtds { .. } | Fcvtwd { .. } | Fcvtwud { .. }
+                | Fcvtld { .. } | Fcvtlud { .. } | Fcvtdw { .. } | Fcvtdwu { .. }
+                | Fcvtdl { .. } | Fcvtdlu { .. } | Feqd { .. } | Fltd { .. } | Fled { .. }
+                | Fclassd { .. } | Fmvxd { .. } | Fmvdx { .. } => Extension::D,
+            Csrrw { .. } | Csrrs { .. } | Csrrc { .. } | Csrrwi { .. } | Csrrsi { .. }
+                | Csrrci { .. } => Extension::Zicsr,
+            Fencei { .. } => Extension::Zifencei,
+            Adduw { .. } | Sh1add { .. } | Sh2add { .. } | Sh3add { .. } | Sh1adduw { .. }
+                | Sh2adduw { .. } | Sh3adduw { .. } | Slliuw { .. } => Extension::Zba,
+            Andn { .. } | Orn { .. } | Xnor { .. } | Max { .. } | Maxu { .. } | Min { .. }
+                | Minu { .. } | Rol { .. } | Ror { .. } | Rolw { .. } | Rorw { .. }
+                | Rori { .. } | Roriw { .. } | Clz { .. } | Ctz { .. } | Cpop { .. }
+                | Clzw { .. } | Ctzw { .. } | Cpopw { .. } | Sextb { .. } | Sexth { .. }
+                | Zexth { .. } | Orcb { .. } | Rev8 { .. } => Extension::Zbb,
+            Clmul { .. } | Clmulh { .. } | Clmulr { .. } => Extension::Zbc,
+            Bclr { .. } | Bext { .. } | Binv { .. } | Bset { .. } | Bclri { .. } | Bexti { .. }
+                | Binvi { .. } | Bseti { .. } => Extension::Zbs,
+            Vsetvli { .. } | Vsetivli { .. } | Vsetvl { .. } | Vload { .. } | Vstore { .. }
+                | Varith { .. } => Extension::V,
+            HlvB { .. } | HlvBu { .. } | HlvH { .. } | HlvHu { .. } | HlvxHu { .. }
+                | HlvW { .. } | HlvWu { .. } | HlvxWu { .. } | HlvD { .. } | HsvB { .. }
+                | HsvH { .. } | HsvW { .. } | HsvD { .. } | Hfencevvma { .. }
+                | Hfencegvma { .. } => Extension::H,
+            _ => Extension::I,
+        }
+    }
+
+    /// Loads, including LR, AMOs, floating-point and vector loads.
+    pub fn is_load(&self) -> bool {
+        use Inst::*;
+
+        matches!(
+            self,
+            Lb { .. } | Lh { .. } | Lw { .. } | Ld { .. } | Lbu { .. } | Lhu { .. } | Lwu { .. }
+                | Flw { .. } | Fld { .. } | Lrw { .. } | Lrd { .. } | Vload { .. }
+                | HlvB { .. } | HlvBu { .. } | HlvH { .. } | HlvHu { .. } | HlvxHu { .. }
+                | HlvW { .. } | HlvWu { .. } | HlvxWu { .. } | HlvD { .. }
+        ) || self.is_amo()
+    }
+
+    /// Stores, including SC, AMOs, floating-point and vector stores.
+    pub fn is_store(&self) -> bool {
+        use Inst::*;
+
+        matches!(
+            self,
+            Sb { .. } | Sh { .. } | Sw { .. } | Sd { .. } | Fsw { .. } | Fsd { .. }
+                | Scw { .. } | Scd { .. } | Vstore { .. }
+                | HsvB { .. } | HsvH { .. } | HsvW { .. } | HsvD { .. }
+        ) || self.is_amo()
+    }
 
-	Sfencevma,
+    pub fn is_amo(&self) -> bool {
+        use Inst::*;
+
+        matches!(
+            self,
+            Amoswapw { .. } | Amoswapd { .. } | Amoaddw { .. } | Amoaddd { .. }
+                | Amoxorw { .. } | Amoxord { .. } | Amoandw { .. } | Amoandd { .. }
+                | Amoorw { .. } | Amoord { .. } | Amominw { .. } | Amomind { .. }
+                | Amomaxw { .. } | Amomaxd { .. } | Amominuw { .. } | Amominud { .. }
+                | Amomaxuw { .. } | Amomaxud { .. }
+        )
+    }
+
+    /// Conditional branches.
+    pub fn is_branch(&self) -> bool {
+        use Inst::*;
+
+        matches!(
+            self,
+            Beq { .. } | Bne { .. } | Blt { .. } | Bge { .. } | Bltu { .. } | Bgeu { .. }
+        )
+    }
+
+    /// The CSR accessed by a Zicsr instruction, and whether it is written.
+    /// csrrs/csrrc with x0 and their immediate forms with 0 only read.
+    pub fn csr_access(&self) -> Option<(usize, bool)> {
+        use Inst::*;
+
+        match *self {
+            Csrrw { csr, .. } | Csrrwi { csr, .. } => Some((csr, true)),
+            Csrrs { csr, rs1, .. } | Csrrc { csr, rs1, .. } => Some((csr, rs1 != 0)),
+            Csrrsi { csr, uimm, .. } | Csrrci { csr, uimm, .. } => Some((csr, uimm != 0)),
+            _ => None,
+        }
+    }
 }
 
 pub enum ImmType {
     R,
+    R4,
     I,
     S,
     B,
     U,
     J,
+    V,
 }
 
 impl ImmType {
@@ -155,9 +469,12 @@ impl ImmType {
                 let func3 = (inst >> 12) & 0b111;
                 let rs1 = ((inst >> 15) & 0b11111) as usize;
 
+                // Shifts split the immediate into a shift amount (6 bits,
+                // or 5 for the word forms) and a function selector above it.
                 let shamt = imm & 0b111111;
-                // Differentiate between SRLI and SRAI
-                let shiftop = (imm * 0x400) == 0;
+                let shamtw = imm & 0b11111;
+                let func6 = imm >> 6;
+                let func7 = imm >> 5;
 
 				// CSR selector
 				let csr = imm as usize;
@@ -177,8 +494,15 @@ impl ImmType {
                         0b011 => Ok(Inst::Ld { rd, rs1, imm }),
                         _ => Err(Exception::IllegalInstruction(inst as u64)),
                     },
+                    0b0000111 => match func3 {
+                        0b010 => Ok(Inst::Flw { rd, rs1, imm }),
+                        0b011 => Ok(Inst::Fld { rd, rs1, imm }),
+                        0b000 | 0b101 | 0b110 | 0b111 => decode_vector_access(inst, false),
+                        _ => Err(Exception::IllegalInstruction(inst as u64)),
+                    },
                     0b0001111 => match func3 {
-                        0b000 => Ok(Inst::Fence { rd, rs1, imm }),
+                        0b000 => Ok(Inst::Fence  { rd, rs1, imm }),
+                        0b001 => Ok(Inst::Fencei { rd, rs1, imm }),
                         _ => Err(Exception::IllegalInstruction(inst as u64)),
                     },
                     0b0010011 => match func3 {
@@ -188,14 +512,27 @@ impl ImmType {
                         0b100 => Ok(Inst::Xori { rd, rs1, imm }),
                         0b110 => Ok(Inst::Ori { rd, rs1, imm }),
                         0b111 => Ok(Inst::Andi { rd, rs1, imm }),
-                        0b001 => Ok(Inst::Slli { rd, rs1, shamt }),
-                        0b101 => {
-                            if shiftop {
-                                Ok(Inst::Srli { rd, rs1, shamt })
-                            } else {
-                                Ok(Inst::Srai { rd, rs1, shamt })
-                            }
-                        }
+                        0b001 => match (func6, shamt) {
+                            (0b000000, _) => Ok(Inst::Slli  { rd, rs1, shamt }),
+                            (0b001010, _) => Ok(Inst::Bseti { rd, rs1, shamt }),
+                            (0b010010, _) => Ok(Inst::Bclri { rd, rs1, shamt }),
+                            (0b011010, _) => Ok(Inst::Binvi { rd, rs1, shamt }),
+                            (0b011000, 0b000000) => Ok(Inst::Clz   { rd, rs1 }),
+                            (0b011000, 0b000001) => Ok(Inst::Ctz   { rd, rs1 }),
+                            (0b011000, 0b000010) => Ok(Inst::Cpop  { rd, rs1 }),
+                            (0b011000, 0b000100) => Ok(Inst::Sextb { rd, rs1 }),
+                            (0b011000, 0b000101) => Ok(Inst::Sexth { rd, rs1 }),
+                            _ => Err(Exception::IllegalInstruction(inst as u64)),
+                        },
+                        0b101 => match (func6, shamt) {
+                            (0b000000, _) => Ok(Inst::Srli  { rd, rs1, shamt }),
+                            (0b010000, _) => Ok(Inst::Srai  { rd, rs1, shamt }),
+                            (0b011000, _) => Ok(Inst::Rori  { rd, rs1, shamt }),
+                            (0b010010, _) => Ok(Inst::Bexti { rd, rs1, shamt }),
+                            (0b001010, 0b000111) => Ok(Inst::Orcb { rd, rs1 }),
+                            (0b011010, 0b111000) => Ok(Inst::Rev8 { rd, rs1 }),
+                            _ => Err(Exception::IllegalInstruction(inst as u64)),
+                        },
                         _ => Err(Exception::IllegalInstruction(inst as u64)),
                     },
                     0b1100111 => match func3 {
@@ -204,33 +541,63 @@ impl ImmType {
                     },
                     0b1110011 => {
 						match func3 {
-							0b000 => Ok(Inst::Sfencevma),
+							0b000 => match (csr >> 5, rd) {
+								(0b0001001, 0) => Ok(Inst::Sfencevma  { rs1, rs2: csr & 0b11111 }),
+								(0b0010001, 0) => Ok(Inst::Hfencevvma { rs1, rs2: csr & 0b11111 }),
+								(0b0110001, 0) => Ok(Inst::Hfencegvma { rs1, rs2: csr & 0b11111 }),
+								_ if rs1 == 0 && rd == 0 => match csr {
+									0x000 => Ok(Inst::Ecall),
+									0x001 => Ok(Inst::Ebreak),
+									0x102 => Ok(Inst::Sret),
+									0x302 => Ok(Inst::Mret),
+									0x105 => Ok(Inst::Wfi),
+									_ => Err(Exception::IllegalInstruction(inst as u64)),
+								},
+								_ => Err(Exception::IllegalInstruction(inst as u64)),
+							},
+							// Hypervisor loads and stores, with the source or
+							// rs2 in the low bits of the CSR field.
+							0b100 => match (csr >> 5, csr & 0b11111, rd) {
+								(0b0110000, 0b00000, _) => Ok(Inst::HlvB   { rd, rs1 }),
+								(0b0110000, 0b00001, _) => Ok(Inst::HlvBu  { rd, rs1 }),
+								(0b0110010, 0b00000, _) => Ok(Inst::HlvH   { rd, rs1 }),
+								(0b0110010, 0b00001, _) => Ok(Inst::HlvHu  { rd, rs1 }),
+								(0b0110010, 0b00011, _) => Ok(Inst::HlvxHu { rd, rs1 }),
+								(0b0110100, 0b00000, _) => Ok(Inst::HlvW   { rd, rs1 }),
+								(0b0110100, 0b00001, _) => Ok(Inst::HlvWu  { rd, rs1 }),
+								(0b0110100, 0b00011, _) => Ok(Inst::HlvxWu { rd, rs1 }),
+								(0b0110110, 0b00000, _) => Ok(Inst::HlvD   { rd, rs1 }),
+								(0b0110001, rs2, 0) => Ok(Inst::HsvB { rs1, rs2 }),
+								(0b0110011, rs2, 0) => Ok(Inst::HsvH { rs1, rs2 }),
+								(0b0110101, rs2, 0) => Ok(Inst::HsvW { rs1, rs2 }),
+								(0b0110111, rs2, 0) => Ok(Inst::HsvD { rs1, rs2 }),
+								_ => Err(Exception::IllegalInstruction(inst as u64)),
+							},
 							0b001 => Ok(Inst::Csrrw     { rd, rs1, csr }),
 							0b010 => Ok(Inst::Csrrs     { rd, rs1, csr }),
 							0b011 => Ok(Inst::Csrrc     { rd, rs1, csr }),
 							0b101 => Ok(Inst::Csrrwi    { rd, uimm: rs1 as u64, csr }),
 							0b110 => Ok(Inst::Csrrsi    { rd, uimm: rs1 as u64, csr }),
 							0b111 => Ok(Inst::Csrrci    { rd, uimm: rs1 as u64, csr }),
-	                        _ => if func3 == 0 && rs1 == 0 && rd == 0 {
-	                            match imm {
-	                                0 => Ok(Inst::Ecall),
-	                                1 => Ok(Inst::Ebreak),
-									258 => Ok(Inst::Sret),
-									770 => Ok(Inst::Mret),
-	                                _ => Err(Exception::IllegalInstruction(inst as u64)),
-	                            }
-	                        } else {
-	                            Err(Exception::IllegalInstruction(inst as u64))
-                        	} 
+							_ => Err(Exception::IllegalInstruction(inst as u64)),
 						}
                     }
                     0b0011011 => match func3 {
                         0b000 => Ok(Inst::Addiw { rd, rs1, imm }),
-                        0b001 => Ok(Inst::Slliw { rd, rs1, shamt }),
-                        0b101 => if shiftop {
-                            Ok(Inst::Srliw { rd, rs1, shamt })
-                        } else {
-                            Ok(Inst::Sraiw { rd, rs1, shamt })
+                        0b001 => match (func7, shamtw) {
+                            (0b0000000, _) => Ok(Inst::Slliw { rd, rs1, shamt: shamtw }),
+                            // slli.uw takes a full 6-bit shift amount.
+                            (0b0000100 | 0b0000101, _) => Ok(Inst::Slliuw { rd, rs1, shamt }),
+                            (0b0110000, 0b00000) => Ok(Inst::Clzw  { rd, rs1 }),
+                            (0b0110000, 0b00001) => Ok(Inst::Ctzw  { rd, rs1 }),
+                            (0b0110000, 0b00010) => Ok(Inst::Cpopw { rd, rs1 }),
+                            _ => Err(Exception::IllegalInstruction(inst as u64)),
+                        },
+                        0b101 => match func7 {
+                            0b0000000 => Ok(Inst::Srliw { rd, rs1, shamt: shamtw }),
+                            0b0100000 => Ok(Inst::Sraiw { rd, rs1, shamt: shamtw }),
+                            0b0110000 => Ok(Inst::Roriw { rd, rs1, shamt: shamtw }),
+                            _ => Err(Exception::IllegalInstruction(inst as u64)),
                         },
                         _ => Err(Exception::IllegalInstruction(inst as u64)),
                     },
@@ -270,6 +637,12 @@ impl ImmType {
                         0b011 => Ok(Inst::Sd { rs1, rs2, imm }),
                         _ => Err(Exception::IllegalInstruction(inst as u64)),
                     },
+                    0b0100111 => match func3 {
+                        0b010 => Ok(Inst::Fsw { rs1, rs2, imm }),
+                        0b011 => Ok(Inst::Fsd { rs1, rs2, imm }),
+                        0b000 | 0b101 | 0b110 | 0b111 => decode_vector_access(inst, true),
+                        _ => Err(Exception::IllegalInstruction(inst as u64)),
+                    },
                     _ => Err(Exception::IllegalInstruction(inst as u64)),
                 }
             },
@@ -304,6 +677,25 @@ impl ImmType {
 						(0b101, 0b0000001) => Ok(Inst::Divu   { rd, rs1, rs2 }),
 						(0b110, 0b0000001) => Ok(Inst::Rem    { rd, rs1, rs2 }),
 						(0b111, 0b0000001) => Ok(Inst::Remu   { rd, rs1, rs2 }),
+						(0b010, 0b0010000) => Ok(Inst::Sh1add { rd, rs1, rs2 }),
+						(0b100, 0b0010000) => Ok(Inst::Sh2add { rd, rs1, rs2 }),
+						(0b110, 0b0010000) => Ok(Inst::Sh3add { rd, rs1, rs2 }),
+						(0b111, 0b0100000) => Ok(Inst::Andn   { rd, rs1, rs2 }),
+						(0b110, 0b0100000) => Ok(Inst::Orn    { rd, rs1, rs2 }),
+						(0b100, 0b0100000) => Ok(Inst::Xnor   { rd, rs1, rs2 }),
+						(0b110, 0b0000101) => Ok(Inst::Max    { rd, rs1, rs2 }),
+						(0b111, 0b0000101) => Ok(Inst::Maxu   { rd, rs1, rs2 }),
+						(0b100, 0b0000101) => Ok(Inst::Min    { rd, rs1, rs2 }),
+						(0b101, 0b0000101) => Ok(Inst::Minu   { rd, rs1, rs2 }),
+						(0b001, 0b0110000) => Ok(Inst::Rol    { rd, rs1, rs2 }),
+						(0b101, 0b0110000) => Ok(Inst::Ror    { rd, rs1, rs2 }),
+						(0b001, 0b0000101) => Ok(Inst::Clmul  { rd, rs1, rs2 }),
+						(0b011, 0b0000101) => Ok(Inst::Clmulh { rd, rs1, rs2 }),
+						(0b010, 0b0000101) => Ok(Inst::Clmulr { rd, rs1, rs2 }),
+						(0b001, 0b0100100) => Ok(Inst::Bclr   { rd, rs1, rs2 }),
+						(0b101, 0b0100100) => Ok(Inst::Bext   { rd, rs1, rs2 }),
+						(0b001, 0b0110100) => Ok(Inst::Binv   { rd, rs1, rs2 }),
+						(0b001, 0b0010100) => Ok(Inst::Bset   { rd, rs1, rs2 }),
                     	(_, _) => Err(Exception::IllegalInstruction(inst as u64)),
 				    },
 					0b0111011 => match (func3, func7) {
@@ -317,6 +709,13 @@ impl ImmType {
 						(0b101, 0b0000001) => Ok(Inst::Divuw { rd, rs1, rs2 }),
 						(0b110, 0b0000001) => Ok(Inst::Remw { rd, rs1, rs2 }),
 						(0b111, 0b0000001) => Ok(Inst::Remuw { rd, rs1, rs2 }),
+						(0b000, 0b0000100) => Ok(Inst::Adduw { rd, rs1, rs2 }),
+						(0b010, 0b0010000) => Ok(Inst::Sh1adduw { rd, rs1, rs2 }),
+						(0b100, 0b0010000) => Ok(Inst::Sh2adduw { rd, rs1, rs2 }),
+						(0b110, 0b0010000) => Ok(Inst::Sh3adduw { rd, rs1, rs2 }),
+						(0b100, 0b0000100) if rs2 == 0 => Ok(Inst::Zexth { rd, rs1 }),
+						(0b001, 0b0110000) => Ok(Inst::Rolw { rd, rs1, rs2 }),
+						(0b101, 0b0110000) => Ok(Inst::Rorw { rd, rs1, rs2 }),
                     	(_, _) => Err(Exception::IllegalInstruction(inst as u64)),
 					},
 					0b0101111 => match (func3, func7>>2) {
@@ -344,9 +743,87 @@ impl ImmType {
 						(0b011, 0b11100) => Ok(Inst::Amomaxud { rd, rs1, rs2, rl, aq }),
 						(_, _) => Err(Exception::IllegalInstruction(inst as u64)),
 					},
+					0b1010011 => {
+						// Rounding mode for the instructions that take one
+						let rm = func3 as u64;
+
+						match (func7, rs2, func3) {
+							(0b0000000, _, _) => Ok(Inst::Fadds { rd, rs1, rs2, rm }),
+							(0b0000100, _, _) => Ok(Inst::Fsubs { rd, rs1, rs2, rm }),
+							(0b0001000, _, _) => Ok(Inst::Fmuls { rd, rs1, rs2, rm }),
+							(0b0001100, _, _) => Ok(Inst::Fdivs { rd, rs1, rs2, rm }),
+							(0b0101100, 0, _) => Ok(Inst::Fsqrts { rd, rs1, rm }),
+							(0b0010000, _, 0b000) => Ok(Inst::Fsgnjs { rd, rs1, rs2 }),
+							(0b0010000, _, 0b001) => Ok(Inst::Fsgnjns { rd, rs1, rs2 }),
+							(0b0010000, _, 0b010) => Ok(Inst::Fsgnjxs { rd, rs1, rs2 }),
+							(0b0010100, _, 0b000) => Ok(Inst::Fmins { rd, rs1, rs2 }),
+							(0b0010100, _, 0b001) => Ok(Inst::Fmaxs { rd, rs1, rs2 }),
+							(0b1100000, 0, _) => Ok(Inst::Fcvtws { rd, rs1, rm }),
+							(0b1100000, 1, _) => Ok(Inst::Fcvtwus { rd, rs1, rm }),
+							(0b1100000, 2, _) => Ok(Inst::Fcvtls { rd, rs1, rm }),
+							(0b1100000, 3, _) => Ok(Inst::Fcvtlus { rd, rs1, rm }),
+							(0b1101000, 0, _) => Ok(Inst::Fcvtsw { rd, rs1, rm }),
+							(0b1101000, 1, _) => Ok(Inst::Fcvtswu { rd, rs1, rm }),
+							(0b1101000, 2, _) => Ok(Inst::Fcvtsl { rd, rs1, rm }),
+							(0b1101000, 3, _) => Ok(Inst::Fcvtslu { rd, rs1, rm }),
+							(0b1010000, _, 0b010) => Ok(Inst::Feqs { rd, rs1, rs2 }),
+							(0b1010000, _, 0b001) => Ok(Inst::Flts { rd, rs1, rs2 }),
+							(0b1010000, _, 0b000) => Ok(Inst::Fles { rd, rs1, rs2 }),
+							(0b1110000, 0, 0b000) => Ok(Inst::Fmvxw { rd, rs1 }),
+							(0b1110000, 0, 0b001) => Ok(Inst::Fclasss { rd, rs1 }),
+							(0b1111000, 0, 0b000) => Ok(Inst::Fmvwx { rd, rs1 }),
+							(0b0000001, _, _) => Ok(Inst::Faddd { rd, rs1, rs2, rm }),
+							(0b0000101, _, _) => Ok(Inst::Fsubd { rd, rs1, rs2, rm }),
+							(0b0001001, _, _) => Ok(Inst::Fmuld { rd, rs1, rs2, rm }),
+							(0b0001101, _, _) => Ok(Inst::Fdivd { rd, rs1, rs2, rm }),
+							(0b0101101, 0, _) => Ok(Inst::Fsqrtd { rd, rs1, rm }),
+							(0b0010001, _, 0b000) => Ok(Inst::Fsgnjd { rd, rs1, rs2 }),
+							(0b0010001, _, 0b001) => Ok(Inst::Fsgnjnd { rd, rs1, rs2 }),
+							(0b0010001, _, 0b010) => Ok(Inst::Fsgnjxd { rd, rs1, rs2 }),
+							(0b0010101, _, 0b000) => Ok(Inst::Fmind { rd, rs1, rs2 }),
+							(0b0010101, _, 0b001) => Ok(Inst::Fmaxd { rd, rs1, rs2 }),
+							(0b0100000, 1, _) => Ok(Inst::Fcvtsd { rd, rs1, rm }),
+							(0b0100001, 0, _) => Ok(Inst::Fcvtds { rd, rs1, rm }),
+							(0b1100001, 0, _) => Ok(Inst::Fcvtwd { rd, rs1, rm }),
+							(0b1100001, 1, _) => Ok(Inst::Fcvtwud { rd, rs1, rm }),
+							(0b1100001, 2, _) => Ok(Inst::Fcvtld { rd, rs1, rm }),
+							(0b1100001, 3, _) => Ok(Inst::Fcvtlud { rd, rs1, rm }),
+							(0b1101001, 0, _) => Ok(Inst::Fcvtdw { rd, rs1, rm }),
+							(0b1101001, 1, _) => Ok(Inst::Fcvtdwu { rd, rs1, rm }),
+							(0b1101001, 2, _) => Ok(Inst::Fcvtdl { rd, rs1, rm }),
+							(0b1101001, 3, _) => Ok(Inst::Fcvtdlu { rd, rs1, rm }),
+							(0b1010001, _, 0b010) => Ok(Inst::Feqd { rd, rs1, rs2 }),
+							(0b1010001, _, 0b001) => Ok(Inst::Fltd { rd, rs1, rs2 }),
+							(0b1010001, _, 0b000) => Ok(Inst::Fled { rd, rs1, rs2 }),
+							(0b1110001, 0, 0b000) => Ok(Inst::Fmvxd { rd, rs1 }),
+							(0b1110001, 0, 0b001) => Ok(Inst::Fclassd { rd, rs1 }),
+							(0b1111001, 0, 0b000) => Ok(Inst::Fmvdx { rd, rs1 }),
+							(_, _, _) => Err(Exception::IllegalInstruction(inst as u64)),
+						}
+					},
                     _ => Err(Exception::IllegalInstruction(inst as u64)),
                 }
             },
+			ImmType::R4 => {
+				let rd = ((inst >> 7) & 0b11111) as usize;
+				let rm = ((inst >> 12) & 0b111) as u64;
+				let rs1 = ((inst >> 15) & 0b11111) as usize;
+				let rs2 = ((inst >> 20) & 0b11111) as usize;
+				let fmt = (inst >> 25) & 0b11;
+				let rs3 = ((inst >> 27) & 0b11111) as usize;
+
+				match (opcode, fmt) {
+					(0b1000011, 0b00) => Ok(Inst::Fmadds { rd, rs1, rs2, rs3, rm }),
+					(0b1000111, 0b00) => Ok(Inst::Fmsubs { rd, rs1, rs2, rs3, rm }),
+					(0b1001011, 0b00) => Ok(Inst::Fnmsubs { rd, rs1, rs2, rs3, rm }),
+					(0b1001111, 0b00) => Ok(Inst::Fnmadds { rd, rs1, rs2, rs3, rm }),
+					(0b1000011, 0b01) => Ok(Inst::Fmaddd { rd, rs1, rs2, rs3, rm }),
+					(0b1000111, 0b01) => Ok(Inst::Fmsubd { rd, rs1, rs2, rs3, rm }),
+					(0b1001011, 0b01) => Ok(Inst::Fnmsubd { rd, rs1, rs2, rs3, rm }),
+					(0b1001111, 0b01) => Ok(Inst::Fnmaddd { rd, rs1, rs2, rs3, rm }),
+					(_, _) => Err(Exception::IllegalInstruction(inst as u64)),
+				}
+			},
 			ImmType::B => {
 				let imm12105 = (inst >> 25) & 0b1111111;
                 let imm4111  = (inst >> 7) & 0b11111;
@@ -401,10 +878,220 @@ impl ImmType {
             		_ => Err(Exception::IllegalInstruction(inst as u64)),
 				}
 			},
+			ImmType::V => {
+				let vd    = ((inst >> 7) & 0b11111) as usize;
+				let func3 = (inst >> 12) & 0b111;
+				let rs1   = ((inst >> 15) & 0b11111) as usize;
+				let vs2   = ((inst >> 20) & 0b11111) as usize;
+				let vm    = (inst >> 25) & 0b1 != 0;
+				let func6 = inst >> 26;
+
+				// OPCFG: the vtype immediate is 11 bits for vsetvli and 10
+				// for vsetivli, whose rs1 field holds the AVL.
+				if func3 == 0b111 {
+					return match inst >> 30 {
+						0b00 | 0b01 => Ok(Inst::Vsetvli { rd: vd, rs1, vtypei: ((inst >> 20) & 0x7ff) as u64 }),
+						0b11 => Ok(Inst::Vsetivli { rd: vd, uimm: rs1 as u64, vtypei: ((inst >> 20) & 0x3ff) as u64 }),
+						_ if (inst >> 25) & 0b111111 == 0 => Ok(Inst::Vsetvl { rd: vd, rs1, rs2: vs2 }),
+						_ => Err(Exception::IllegalInstruction(inst as u64)),
+					};
+				}
+
+				let op = VOp::decode(func3, func6, rs1, vs2, vm)
+					.ok_or(Exception::IllegalInstruction(inst as u64))?;
+
+				// OPIVV, OPFVV and OPMVV take vs1; the other categories take
+				// an immediate, x or f register.
+				let src = match func3 {
+					0b000..=0b010 => VSrc::V(rs1),
+					0b011 if op.unsigned_imm() => VSrc::I(rs1 as i64),
+					0b011 => VSrc::I(((rs1 as i64) << 59) >> 59),
+					0b100 | 0b110 => VSrc::X(rs1),
+					_ => VSrc::F(rs1),
+				};
+
+				Ok(Inst::Varith { op, vd, vs2, src, vm })
+			},
         }
     }
 }
 
+/// Decodes the vector loads and stores, which share the LOAD-FP and
+/// STORE-FP opcodes with the scalar ones and select the element width
+/// with the remaining width encodings.
+fn decode_vector_access(inst: u32, store: bool) -> Result<Inst, Exception> {
+	let reg   = ((inst >> 7) & 0b11111) as usize;
+	let width = (inst >> 12) & 0b111;
+	let rs1   = ((inst >> 15) & 0b11111) as usize;
+	let rs2   = ((inst >> 20) & 0b11111) as usize;
+	let vm    = (inst >> 25) & 0b1 != 0;
+	let mop   = (inst >> 26) & 0b11;
+	let mew   = (inst >> 28) & 0b1;
+	let nf    = ((inst >> 29) & 0b111) as u64 + 1;
+
+	let eew = match (width, mew) {
+		(0b000, 0) => 8,
+		(0b101, 0) => 16,
+		(0b110, 0) => 32,
+		(0b111, 0) => 64,
+		_ => return Err(Exception::IllegalInstruction(inst as u64)),
+	};
+
+	// Unit-stride accesses use the rs2 field to select a variant.
+	let addressing = match (mop, rs2) {
+		(0b00, 0b00000) => VAddressing::UnitStride,
+		(0b00, 0b01000) if vm && nf.is_power_of_two() && (!store || eew == 8) => VAddressing::WholeRegister,
+		(0b00, 0b01011) if vm && nf == 1 && eew == 8 => VAddressing::Mask,
+		(0b00, 0b10000) if !store => VAddressing::FaultOnlyFirst,
+		(0b01 | 0b11, _) => VAddressing::Indexed { vs2: rs2 },
+		(0b10, _) => VAddressing::Strided { rs2 },
+		_ => return Err(Exception::IllegalInstruction(inst as u64)),
+	};
+
+	if store {
+		Ok(Inst::Vstore { vs3: reg, rs1, addressing, eew, nf, vm })
+	} else {
+		Ok(Inst::Vload { vd: reg, rs1, addressing, eew, nf, vm })
+	}
+}
+
+/// Expands a 16-bit RVC instruction into the equivalent base instruction.
+pub fn decode_compressed(inst: u16) -> Result<Inst, Exception> {
+	let inst = inst as u32;
+	let illegal = Err(Exception::IllegalInstruction(inst as u64));
+
+	let op = inst & 0b11;
+	let func3 = (inst >> 13) & 0b111;
+
+	// Full register fields
+	let rd = ((inst >> 7) & 0b11111) as usize;
+	let rs2 = ((inst >> 2) & 0b11111) as usize;
+
+	// Compressed register fields (x8-x15)
+	let rd_ = (((inst >> 2) & 0b111) + 8) as usize;
+	let rs1_ = (((inst >> 7) & 0b111) + 8) as usize;
+
+	let bit = |i: u32| (inst >> i) & 1;
+	let bits = |hi: u32, lo: u32| (inst >> lo) & ((1 << (hi - lo + 1)) - 1);
+	let sext = |imm: u32, width: u32| (((imm << (32 - width)) as i32) >> (32 - width)) as i64;
+
+	// CI-format 6-bit immediate
+	let imm6 = (bit(12) << 5) | bits(6, 2);
+	// CL/CS-format offsets for word and doubleword accesses
+	let uimm_w = ((bits(12, 10) << 3) | (bit(6) << 2) | (bit(5) << 6)) as i64;
+	let uimm_d = ((bits(12, 10) << 3) | (bits(6, 5) << 6)) as i64;
+
+	match (op, func3) {
+		(0b00, 0b000) => {
+			let imm = (bits(12, 11) << 4) | (bits(10, 7) << 6) | (bit(6) << 2) | (bit(5) << 3);
+			if imm == 0 {
+				return illegal;
+			}
+			Ok(Inst::Addi { rd: rd_, rs1: 2, imm: imm as i64 })
+		},
+		(0b00, 0b001) => Ok(Inst::Fld { rd: rd_, rs1: rs1_, imm: uimm_d }),
+		(0b00, 0b010) => Ok(Inst::Lw { rd: rd_, rs1: rs1_, imm: uimm_w }),
+		(0b00, 0b011) => Ok(Inst::Ld { rd: rd_, rs1: rs1_, imm: uimm_d }),
+		(0b00, 0b101) => Ok(Inst::Fsd { rs1: rs1_, rs2: rd_, imm: uimm_d }),
+		(0b00, 0b110) => Ok(Inst::Sw { rs1: rs1_, rs2: rd_, imm: uimm_w }),
+		(0b00, 0b111) => Ok(Inst::Sd { rs1: rs1_, rs2: rd_, imm: uimm_d }),
+
+		(0b01, 0b000) => Ok(Inst::Addi { rd, rs1: rd, imm: sext(imm6, 6) }),
+		(0b01, 0b001) => {
+			if rd == 0 {
+				return illegal;
+			}
+			Ok(Inst::Addiw { rd, rs1: rd, imm: sext(imm6, 6) })
+		},
+		(0b01, 0b010) => Ok(Inst::Addi { rd, rs1: 0, imm: sext(imm6, 6) }),
+		(0b01, 0b011) => {
+			if imm6 == 0 {
+				return illegal;
+			}
+			if rd == 2 {
+				let imm = (bit(12) << 9) | (bit(6) << 4) | (bit(5) << 6) | (bits(4, 3) << 7) | (bit(2) << 5);
+				Ok(Inst::Addi { rd: 2, rs1: 2, imm: sext(imm, 10) })
+			} else {
+				Ok(Inst::Lui { rd, imm: sext(imm6 << 12, 18) })
+			}
+		},
+		(0b01, 0b100) => {
+			let rd = rs1_;
+			let rs2 = rd_;
+			let shamt = imm6;
+
+			match (bits(11, 10), bit(12), bits(6, 5)) {
+				(0b00, _, _) => Ok(Inst::Srli { rd, rs1: rd, shamt }),
+				(0b01, _, _) => Ok(Inst::Srai { rd, rs1: rd, shamt }),
+				(0b10, _, _) => Ok(Inst::Andi { rd, rs1: rd, imm: sext(imm6, 6) }),
+				(0b11, 0, 0b00) => Ok(Inst::Sub { rd, rs1: rd, rs2 }),
+				(0b11, 0, 0b01) => Ok(Inst::Xor { rd, rs1: rd, rs2 }),
+				(0b11, 0, 0b10) => Ok(Inst::Or { rd, rs1: rd, rs2 }),
+				(0b11, 0, 0b11) => Ok(Inst::And { rd, rs1: rd, rs2 }),
+				(0b11, 1, 0b00) => Ok(Inst::Subw { rd, rs1: rd, rs2 }),
+				(0b11, 1, 0b01) => Ok(Inst::Addw { rd, rs1: rd, rs2 }),
+				(_, _, _) => illegal,
+			}
+		},
+		(0b01, 0b101) => {
+			let imm = (bit(12) << 11) | (bit(11) << 4) | (bits(10, 9) << 8) | (bit(8) << 10)
+				| (bit(7) << 6) | (bit(6) << 7) | (bits(5, 3) << 1) | (bit(2) << 5);
+			Ok(Inst::Jal { rd: 0, imm: sext(imm, 12) })
+		},
+		(0b01, 0b110) | (0b01, 0b111) => {
+			let imm = (bit(12) << 8) | (bits(11, 10) << 3) | (bits(6, 5) << 6) | (bits(4, 3) << 1) | (bit(2) << 5);
+			let imm = sext(imm, 9);
+			if func3 == 0b110 {
+				Ok(Inst::Beq { rs1: rs1_, rs2: 0, imm })
+			} else {
+				Ok(Inst::Bne { rs1: rs1_, rs2: 0, imm })
+			}
+		},
+
+		(0b10, 0b000) => Ok(Inst::Slli { rd, rs1: rd, shamt: imm6 }),
+		(0b10, 0b001) => {
+			let imm = (bit(12) << 5) | (bits(6, 5) << 3) | (bits(4, 2) << 6);
+			Ok(Inst::Fld { rd, rs1: 2, imm: imm as i64 })
+		},
+		(0b10, 0b010) => {
+			if rd == 0 {
+				return illegal;
+			}
+			let imm = (bit(12) << 5) | (bits(6, 4) << 2) | (bits(3, 2) << 6);
+			Ok(Inst::Lw { rd, rs1: 2, imm: imm as i64 })
+		},
+		(0b10, 0b011) => {
+			if rd == 0 {
+				return illegal;
+			}
+			let imm = (bit(12) << 5) | (bits(6, 5) << 3) | (bits(4, 2) << 6);
+			Ok(Inst::Ld { rd, rs1: 2, imm: imm as i64 })
+		},
+		(0b10, 0b100) => match (bit(12), rd, rs2) {
+			(0, 0, 0) => illegal,
+			(0, rs1, 0) => Ok(Inst::Jalr { rd: 0, rs1, imm: 0 }),
+			(0, rd, rs2) => Ok(Inst::Add { rd, rs1: 0, rs2 }),
+			(1, 0, 0) => Ok(Inst::Ebreak),
+			(1, rs1, 0) => Ok(Inst::Jalr { rd: 1, rs1, imm: 0 }),
+			(_, rd, rs2) => Ok(Inst::Add { rd, rs1: rd, rs2 }),
+		},
+		(0b10, 0b101) => {
+			let imm = (bits(12, 10) << 3) | (bits(9, 7) << 6);
+			Ok(Inst::Fsd { rs1: 2, rs2, imm: imm as i64 })
+		},
+		(0b10, 0b110) => {
+			let imm = (bits(12, 9) << 2) | (bits(8, 7) << 6);
+			Ok(Inst::Sw { rs1: 2, rs2, imm: imm as i64 })
+		},
+		(0b10, 0b111) => {
+			let imm = (bits(12, 10) << 3) | (bits(9, 7) << 6);
+			Ok(Inst::Sd { rs1: 2, rs2, imm: imm as i64 })
+		},
+
+		(_, _) => illegal,
+	}
+}
+
 pub const ENCODING_TABLE: [Option<ImmType>; 128] = [
     /* 0b0000000 */ None,
     /* 0b0000001 */ None,
@@ -413,7 +1100,7 @@ pub const ENCODING_TABLE: [Option<ImmType>; 128] = [
     /* 0b0000100 */ None,
     /* 0b0000101 */ None,
     /* 0b0000110 */ None,
-    /* 0b0000111 */ None,
+    /* 0b0000111 */ Some(ImmType::I),
     /* 0b0001000 */ None,
     /* 0b0001001 */ None,
     /* 0b0001010 */ None,
@@ -445,7 +1132,7 @@ pub const ENCODING_TABLE: [Option<ImmType>; 128] = [
     /* 0b0100100 */ None,
     /* 0b0100101 */ None,
     /* 0b0100110 */ None,
-    /* 0b0100111 */ None,
+    /* 0b0100111 */ Some(ImmType::S),
     /* 0b0101000 */ None,
     /* 0b0101001 */ None,
     /* 0b0101010 */ None,
@@ -473,27 +1160,27 @@ pub const ENCODING_TABLE: [Option<ImmType>; 128] = [
     /* 0b1000000 */ None,
     /* 0b1000001 */ None,
     /* 0b1000010 */ None,
-    /* 0b1000011 */ None,
+    /* 0b1000011 */ Some(ImmType::R4),
     /* 0b1000100 */ None,
     /* 0b1000101 */ None,
     /* 0b1000110 */ None,
-    /* 0b1000111 */ None,
+    /* 0b1000111 */ Some(ImmType::R4),
     /* 0b1001000 */ None,
     /* 0b1001001 */ None,
     /* 0b1001010 */ None,
-    /* 0b1001011 */ None,
+    /* 0b1001011 */ Some(ImmType::R4),
     /* 0b1001100 */ None,
     /* 0b1001101 */ None,
     /* 0b1001110 */ None,
-    /* 0b1001111 */ None,
+    /* 0b1001111 */ Some(ImmType::R4),
     /* 0b1010000 */ None,
     /* 0b1010001 */ None,
     /* 0b1010010 */ None,
-    /* 0b1010011 */ None,
+    /* 0b1010011 */ Some(ImmType::R),
     /* 0b1010100 */ None,
     /* 0b1010101 */ None,
     /* 0b1010110 */ None,
-    /* 0b1010111 */ None,
+    /* 0b1010111 */ Some(ImmType::V),
     /* 0b1011000 */ None,
     /* 0b1011001 */ None,
     /* 0b1011010 */ None,
@@ -2,11 +2,15 @@ pub const MASK_INTERRUPT_BIT: u64 = 1 << 63;
 
 pub enum Interrupt {
     SupervisorSoftwareInterrupt,
+    VirtualSupervisorSoftwareInterrupt,
     MachineSoftwareInterrupt,
     SupervisorTimerInterrupt,
+    VirtualSupervisorTimerInterrupt,
     MachineTimerInterrupt,
     SupervisorExternalInterrupt,
+    VirtualSupervisorExternalInterrupt,
     MachineExternalInterrupt,
+    SupervisorGuestExternalInterrupt,
 }
 
 impl Interrupt {
@@ -15,11 +19,15 @@ impl Interrupt {
 
         match self {
             SupervisorSoftwareInterrupt => 1 | MASK_INTERRUPT_BIT,
+            VirtualSupervisorSoftwareInterrupt => 2 | MASK_INTERRUPT_BIT,
             MachineSoftwareInterrupt => 3 | MASK_INTERRUPT_BIT,
             SupervisorTimerInterrupt => 5 | MASK_INTERRUPT_BIT,
+            VirtualSupervisorTimerInterrupt => 6 | MASK_INTERRUPT_BIT,
             MachineTimerInterrupt => 7 | MASK_INTERRUPT_BIT,
             SupervisorExternalInterrupt => 9 | MASK_INTERRUPT_BIT,
+            VirtualSupervisorExternalInterrupt => 10 | MASK_INTERRUPT_BIT,
             MachineExternalInterrupt => 11 | MASK_INTERRUPT_BIT,
+            SupervisorGuestExternalInterrupt => 12 | MASK_INTERRUPT_BIT,
         }
     }
 }
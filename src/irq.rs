@@ -0,0 +1,86 @@
+/// How a device signals on its interrupt line.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// Raised for as long as the device wants attention.
+    Level,
+    /// Pulsed once for every event.
+    Edge,
+}
+
+/// Something that takes interrupt requests by IRQ number, as the PLIC does.
+pub trait InterruptSink {
+    /// Raises or lowers a level-triggered line.
+    fn set_level(&mut self, irq: usize, level: bool);
+    /// Signals an edge-triggered line once.
+    fn pulse(&mut self, irq: usize);
+}
+
+/// A device's interrupt output. The device raises and lowers it as its
+/// state changes, and the bus passes what changed on to the PLIC, so
+/// devices don't need to know where their interrupts end up.
+#[derive(Debug, Clone)]
+pub struct IrqLine {
+    irq: usize,
+    trigger: Trigger,
+    level: bool,
+    /// Whether there is a change, or for edge-triggered lines a pulse, not
+    /// delivered yet.
+    changed: bool,
+}
+
+impl IrqLine {
+    pub fn new(irq: usize, trigger: Trigger) -> Self {
+        Self {
+            irq,
+            trigger,
+            level: false,
+            changed: false,
+        }
+    }
+
+    pub fn irq(&self) -> usize {
+        self.irq
+    }
+
+    pub fn trigger(&self) -> Trigger {
+        self.trigger
+    }
+
+    /// Whether a level-triggered line is raised.
+    pub fn is_raised(&self) -> bool {
+        self.level
+    }
+
+    /// Raises a level-triggered line, or pulses an edge-triggered one.
+    /// Pulses before the next delivery count as one.
+    pub fn raise(&mut self) {
+        self.changed |= self.trigger == Trigger::Edge || !self.level;
+        self.level = self.trigger == Trigger::Level;
+    }
+
+    /// Lowers a level-triggered line. Edge-triggered ones are always low.
+    pub fn lower(&mut self) {
+        self.changed |= self.level;
+        self.level = false;
+    }
+
+    /// Raises or lowers the line.
+    pub fn set(&mut self, raised: bool) {
+        if raised {
+            self.raise();
+        } else {
+            self.lower();
+        }
+    }
+
+    /// Passes the change since the last delivery, if any, on to `sink`.
+    pub fn deliver(&mut self, sink: &mut dyn InterruptSink) {
+        if !core::mem::take(&mut self.changed) {
+            return;
+        }
+        match self.trigger {
+            Trigger::Level => sink.set_level(self.irq, self.level),
+            Trigger::Edge => sink.pulse(self.irq),
+        }
+    }
+}
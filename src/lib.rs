@@ -1,16 +1,27 @@
 #![no_std]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 pub mod bus;
 pub mod clint;
+pub mod config;
 pub mod cpu;
 pub mod csrs;
 pub mod exceptions;
+pub mod float;
 pub mod inst;
 pub mod interrupt;
+pub mod irq;
+pub mod mmu;
 pub mod plic;
+pub mod pmp;
+#[cfg(feature = "std")]
+pub mod threaded;
+pub mod trigger;
 pub mod uart;
+pub mod vector;
 pub mod virtio;
 pub mod virtqueue;
 pub mod vm;
@@ -18,12 +29,20 @@ pub mod vm;
 pub mod prelude {
     pub use super::bus::*;
     pub use super::clint::*;
+    pub use super::config::*;
     pub use super::cpu::*;
     pub use super::csrs::*;
     pub use super::exceptions::*;
     pub use super::interrupt::*;
+    pub use super::irq::*;
+    pub use super::mmu::*;
     pub use super::plic::*;
+    pub use super::pmp::*;
+    #[cfg(feature = "std")]
+    pub use super::threaded::*;
+    pub use super::trigger::*;
     pub use super::uart::*;
+    pub use super::vector::*;
     pub use super::virtio::*;
     pub use super::virtqueue::*;
     pub use super::vm::*;
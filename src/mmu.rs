@@ -0,0 +1,497 @@
+use crate::{
+    bus::Bus,
+    cpu::Mode,
+    csrs::{
+        MASK_HGATP_MODE, MASK_HGATP_PPN, MASK_HGATP_VMID, MASK_SATP_ASID, MASK_SATP_MODE,
+        MASK_SATP_PPN, SATP_MODE_BARE, SATP_MODE_SV39, SATP_MODE_SV48, SATP_MODE_SV57,
+    },
+    exceptions::Exception,
+    pmp::Pmp,
+};
+
+// Page table entry fields
+pub const PTE_V: u64 = 1 << 0;
+pub const PTE_R: u64 = 1 << 1;
+pub const PTE_W: u64 = 1 << 2;
+pub const PTE_X: u64 = 1 << 3;
+pub const PTE_U: u64 = 1 << 4;
+pub const PTE_G: u64 = 1 << 5;
+pub const PTE_A: u64 = 1 << 6;
+pub const PTE_D: u64 = 1 << 7;
+pub const MASK_PTE_PPN: u64 = ((1 << 44) - 1) << 10;
+/// Bits reserved for Svpbmt/Svnapot, which aren't implemented.
+pub const MASK_PTE_RESERVED: u64 = 0x3ff << 54;
+
+pub const TLB_SIZE: usize = 64;
+
+const PAGE_SHIFT: u64 = 12;
+const PTE_SIZE: u64 = 8;
+const VPN_BITS: u64 = 9;
+
+/// Translation schemes selectable through `satp.MODE`, in the order the
+/// spec requires them to be implemented.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PagingMode {
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl PagingMode {
+    pub fn from_satp(mode: u64) -> Option<Self> {
+        match mode {
+            SATP_MODE_SV39 => Some(PagingMode::Sv39),
+            SATP_MODE_SV48 => Some(PagingMode::Sv48),
+            SATP_MODE_SV57 => Some(PagingMode::Sv57),
+            _ => None,
+        }
+    }
+
+    pub fn levels(self) -> u64 {
+        match self {
+            PagingMode::Sv39 => 3,
+            PagingMode::Sv48 => 4,
+            PagingMode::Sv57 => 5,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessType {
+    Instruction,
+    Load,
+    Store,
+}
+
+impl AccessType {
+    pub fn page_fault(self, addr: u64) -> Exception {
+        match self {
+            AccessType::Instruction => Exception::InstructionPageFault(addr),
+            AccessType::Load => Exception::LoadPageFault(addr),
+            AccessType::Store => Exception::StoreAMOPageFault(addr),
+        }
+    }
+
+    pub fn guest_page_fault(self, addr: u64, gpa: u64) -> Exception {
+        match self {
+            AccessType::Instruction => Exception::InstructionGuestPageFault(addr, gpa),
+            AccessType::Load => Exception::LoadGuestPageFault(addr, gpa),
+            AccessType::Store => Exception::StoreAMOGuestPageFault(addr, gpa),
+        }
+    }
+
+    pub fn access_fault(self, addr: u64) -> Exception {
+        match self {
+            AccessType::Instruction => Exception::InstructionAccessFault(addr),
+            AccessType::Load => Exception::LoadAccessFault(addr),
+            AccessType::Store => Exception::StoreAMOAccessFault(addr),
+        }
+    }
+}
+
+/// Privilege and `mstatus` state a translation is performed under.
+#[derive(Debug, Copy, Clone)]
+pub struct AccessContext {
+    /// `satp`, or `vsatp` for guest accesses.
+    pub satp: u64,
+    /// `hgatp` for guest accesses, whose VS-stage translation is followed
+    /// by a G-stage one. `None` for the host's own accesses.
+    pub hgatp: Option<u64>,
+    /// Effective privilege of the access (after applying `mstatus.MPRV`).
+    pub mode: Mode,
+    pub sum: bool,
+    pub mxr: bool,
+    /// `mstatus.MXR`, which alone applies to the G-stage. For guest
+    /// accesses `mxr` also includes `vsstatus.MXR`.
+    pub hs_mxr: bool,
+    /// Loads need execute rather than read permission, as for `hlvx`.
+    pub hlvx: bool,
+}
+
+/// A cached translation. Superpages are cached whole: `vpn` and `ppn` are
+/// page numbers of the superpage base and `level` gives its size. Guest
+/// translations combine both stages, at the smaller of their page sizes.
+#[derive(Debug, Copy, Clone)]
+struct TlbEntry {
+    vpn: u64,
+    ppn: u64,
+    level: u64,
+    asid: u64,
+    /// VMID of a guest translation, `None` for the host's own.
+    vmid: Option<u64>,
+    /// Leaf PTE of the first stage, `None` when a guest's VS-stage is Bare.
+    pte: Option<u64>,
+    /// Leaf PTE of the G-stage, for guest translations that have one.
+    gpte: Option<u64>,
+}
+
+impl TlbEntry {
+    fn covers(&self, vpn: u64) -> bool {
+        (vpn >> (self.level * VPN_BITS)) == (self.vpn >> (self.level * VPN_BITS))
+    }
+
+    fn global(&self) -> bool {
+        self.pte.is_none_or(|pte| pte & PTE_G != 0)
+    }
+
+    /// Whether the cached PTEs allow `access`. Stores to clean pages walk
+    /// again to set D.
+    fn allows(&self, access: AccessType, ctx: &AccessContext) -> bool {
+        let allows = |pte: u64, g_stage| {
+            permitted(pte, access, ctx, g_stage)
+                && (access != AccessType::Store || pte & PTE_D != 0)
+        };
+
+        self.pte.is_none_or(|pte| allows(pte, false))
+            && self.gpte.is_none_or(|pte| allows(pte, true))
+    }
+
+    fn translate(&self, vaddr: u64) -> u64 {
+        let offset_mask = (1 << (PAGE_SHIFT + self.level * VPN_BITS)) - 1;
+        (self.ppn << PAGE_SHIFT) | (vaddr & offset_mask)
+    }
+}
+
+/// The access a walk is made for, to report faults against.
+#[derive(Debug, Copy, Clone)]
+struct Origin {
+    access: AccessType,
+    vaddr: u64,
+}
+
+/// Leaf PTE found by a walk, its level and the translated address.
+#[derive(Debug, Copy, Clone)]
+struct Leaf {
+    pte: u64,
+    level: u64,
+    addr: u64,
+}
+
+pub struct Mmu {
+    tlb: [Option<TlbEntry>; TLB_SIZE],
+
+    /// Set A/D bits in memory on access (Svadu). When false, accessing a
+    /// page whose A bit (or D bit, for stores) is clear raises a page fault
+    /// and leaves the update to software (Svade).
+    pub update_ad: bool,
+
+    /// Widest paging mode the hart implements. Narrower modes are always
+    /// available too.
+    pub max_mode: PagingMode,
+}
+
+impl Default for Mmu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mmu {
+    pub fn new() -> Self {
+        Mmu {
+            tlb: [None; TLB_SIZE],
+            update_ad: true,
+            max_mode: PagingMode::Sv57,
+        }
+    }
+
+    /// Whether `satp.MODE` (or `hgatp.MODE`) may be set to `mode`.
+    pub fn supports(&self, mode: u64) -> bool {
+        mode == SATP_MODE_BARE || PagingMode::from_satp(mode).is_some_and(|m| m <= self.max_mode)
+    }
+
+    /// Drops the host's cached translations, as `sfence.vma` does. `vaddr`
+    /// restricts the flush to the page containing it, and `asid` to
+    /// non-global translations of that address space.
+    pub fn flush(&mut self, vaddr: Option<u64>, asid: Option<u64>) {
+        self.flush_matching(None, vaddr, asid);
+    }
+
+    /// Drops cached guest translations, as `hfence.vvma` and `hfence.gvma`
+    /// do. `vmid` restricts the flush to one guest, while `vaddr` and
+    /// `asid` work as for `flush`.
+    pub fn flush_guest(&mut self, vmid: Option<u64>, vaddr: Option<u64>, asid: Option<u64>) {
+        match vmid {
+            Some(vmid) => self.flush_matching(Some(vmid), vaddr, asid),
+            None => {
+                for slot in self.tlb.iter_mut() {
+                    if slot.is_some_and(|entry| entry.vmid.is_some()) {
+                        *slot = None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drops every cached translation, of the host and of all guests.
+    pub fn flush_all(&mut self) {
+        self.tlb = [None; TLB_SIZE];
+    }
+
+    /// Drops the translations of the address space `vmid` selects (the
+    /// host's for `None`) matching `vaddr` and `asid`.
+    fn flush_matching(&mut self, vmid: Option<u64>, vaddr: Option<u64>, asid: Option<u64>) {
+        for slot in self.tlb.iter_mut() {
+            let Some(entry) = slot else { continue };
+
+            let addr_match = vaddr.is_none_or(|addr| entry.covers(addr >> PAGE_SHIFT));
+            let asid_match = asid.is_none_or(|asid| entry.asid == asid && !entry.global());
+            if entry.vmid == vmid && addr_match && asid_match {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Translates `vaddr` into a physical address. Callers are expected to
+    /// skip translation in M-mode. Page table accesses are checked against
+    /// `pmp` as S-mode accesses.
+    pub fn translate(
+        &mut self,
+        bus: &mut Bus,
+        pmp: &Pmp,
+        vaddr: u64,
+        access: AccessType,
+        ctx: &AccessContext,
+    ) -> Result<u64, Exception> {
+        let mode = PagingMode::from_satp((ctx.satp & MASK_SATP_MODE) >> 60);
+        let g_mode = ctx
+            .hgatp
+            .and_then(|hgatp| PagingMode::from_satp((hgatp & MASK_HGATP_MODE) >> 60));
+        if mode.is_none() && g_mode.is_none() {
+            return Ok(vaddr);
+        }
+
+        // Addresses must be sign-extended from the top translated bit.
+        if let Some(mode) = mode {
+            let va_bits = PAGE_SHIFT + mode.levels() * VPN_BITS;
+            if ((vaddr << (64 - va_bits)) as i64 >> (64 - va_bits)) as u64 != vaddr {
+                return Err(access.page_fault(vaddr));
+            }
+        }
+
+        let asid = (ctx.satp & MASK_SATP_ASID) >> 44;
+        let vmid = ctx.hgatp.map(|hgatp| (hgatp & MASK_HGATP_VMID) >> 44);
+        let vpn = vaddr >> PAGE_SHIFT;
+        let slot = vpn as usize % TLB_SIZE;
+
+        if let Some(entry) = self.tlb[slot] {
+            if entry.covers(vpn)
+                && entry.vmid == vmid
+                && (entry.asid == asid || entry.global())
+                && entry.allows(access, ctx)
+            {
+                return Ok(entry.translate(vaddr));
+            }
+        }
+
+        // Anything not allowed by the cached entry walks again, which
+        // reports the precise fault.
+        let origin = Origin { access, vaddr };
+        let leaf = match mode {
+            Some(mode) => {
+                let root = ctx.satp & MASK_SATP_PPN;
+                Some(self.walk(bus, pmp, root, mode, false, vaddr, access, origin, ctx)?)
+            }
+            None => None,
+        };
+        let gpa = leaf.map_or(vaddr, |leaf| leaf.addr);
+        let g_leaf = match g_mode {
+            Some(g_mode) => {
+                let root = ctx.hgatp.unwrap_or(0) & MASK_HGATP_PPN;
+                Some(self.walk(bus, pmp, root, g_mode, true, gpa, access, origin, ctx)?)
+            }
+            None => None,
+        };
+        let paddr = g_leaf.map_or(gpa, |leaf| leaf.addr);
+
+        let level = leaf
+            .iter()
+            .chain(&g_leaf)
+            .map(|leaf| leaf.level)
+            .min()
+            .unwrap_or(0);
+        let superpage_mask = (1 << (level * VPN_BITS)) - 1;
+        self.tlb[slot] = Some(TlbEntry {
+            vpn: vpn & !superpage_mask,
+            ppn: (paddr >> PAGE_SHIFT) & !superpage_mask,
+            level,
+            asid,
+            vmid,
+            pte: leaf.map(|leaf| leaf.pte),
+            gpte: g_leaf.map(|leaf| leaf.pte),
+        });
+
+        Ok(paddr)
+    }
+
+    /// Walks the page tables rooted at page `root` to translate `addr` for
+    /// `access`. A G-stage walk translates guest physical addresses, using
+    /// tables whose root is four pages wide. Faults are reported against
+    /// `origin`, which differs from the access itself for the G-stage
+    /// translation of a VS-stage page table entry.
+    #[allow(clippy::too_many_arguments)]
+    fn walk(
+        &mut self,
+        bus: &mut Bus,
+        pmp: &Pmp,
+        root: u64,
+        mode: PagingMode,
+        g_stage: bool,
+        addr: u64,
+        access: AccessType,
+        origin: Origin,
+        ctx: &AccessContext,
+    ) -> Result<Leaf, Exception> {
+        let page_fault = || match g_stage {
+            true => origin.access.guest_page_fault(origin.vaddr, addr),
+            false => origin.access.page_fault(origin.vaddr),
+        };
+        let access_fault = origin.access.access_fault(origin.vaddr);
+
+        let levels = mode.levels();
+        let root_bits = if g_stage { VPN_BITS + 2 } else { VPN_BITS };
+        // Guest physical addresses are zero-extended.
+        if g_stage && addr >> (PAGE_SHIFT + (levels - 1) * VPN_BITS + root_bits) != 0 {
+            return Err(page_fault());
+        }
+
+        let vpn = addr >> PAGE_SHIFT;
+        let mut table = root << PAGE_SHIFT;
+
+        for level in (0..levels).rev() {
+            let bits = if level == levels - 1 {
+                root_bits
+            } else {
+                VPN_BITS
+            };
+            let index = (vpn >> (level * VPN_BITS)) & ((1 << bits) - 1);
+            let pte_gaddr = table + index * PTE_SIZE;
+            let pte_addr =
+                self.table_address(bus, pmp, pte_gaddr, AccessType::Load, origin, ctx, g_stage)?;
+            if !pmp.check(pte_addr, 64, AccessType::Load, Mode::Supervisor) {
+                return Err(access_fault);
+            }
+            let mut pte = bus.load(pte_addr, 64).map_err(|_| access_fault)?;
+
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+                return Err(page_fault());
+            }
+            if pte & MASK_PTE_RESERVED != 0 {
+                return Err(page_fault());
+            }
+
+            let ppn = (pte & MASK_PTE_PPN) >> 10;
+
+            if pte & (PTE_R | PTE_X) == 0 {
+                // Pointer to the next level table; A, D and U are reserved.
+                if pte & (PTE_A | PTE_D | PTE_U) != 0 {
+                    return Err(page_fault());
+                }
+                table = ppn << PAGE_SHIFT;
+                continue;
+            }
+
+            // Superpages must be aligned to their size.
+            let superpage_mask = (1 << (level * VPN_BITS)) - 1;
+            if ppn & superpage_mask != 0 {
+                return Err(page_fault());
+            }
+
+            if !permitted(pte, access, ctx, g_stage) {
+                return Err(page_fault());
+            }
+
+            let mut ad = PTE_A;
+            if access == AccessType::Store {
+                ad |= PTE_D;
+            }
+            if pte & ad != ad {
+                if !self.update_ad {
+                    return Err(page_fault());
+                }
+                let pte_addr = self.table_address(
+                    bus,
+                    pmp,
+                    pte_gaddr,
+                    AccessType::Store,
+                    origin,
+                    ctx,
+                    g_stage,
+                )?;
+                if !pmp.check(pte_addr, 64, AccessType::Store, Mode::Supervisor) {
+                    return Err(access_fault);
+                }
+                pte |= ad;
+                bus.store(pte_addr, pte, 64).map_err(|_| access_fault)?;
+            }
+
+            let offset_mask = (1 << (PAGE_SHIFT + level * VPN_BITS)) - 1;
+            return Ok(Leaf {
+                pte,
+                level,
+                addr: (ppn << PAGE_SHIFT) | (addr & offset_mask),
+            });
+        }
+
+        Err(page_fault())
+    }
+
+    /// Physical address of a page table entry. A guest's VS-stage tables
+    /// live in guest physical memory, so reading (or, for A/D updates,
+    /// writing) their entries goes through the G-stage.
+    #[allow(clippy::too_many_arguments)]
+    fn table_address(
+        &mut self,
+        bus: &mut Bus,
+        pmp: &Pmp,
+        addr: u64,
+        access: AccessType,
+        origin: Origin,
+        ctx: &AccessContext,
+        g_stage: bool,
+    ) -> Result<u64, Exception> {
+        if g_stage {
+            return Ok(addr);
+        }
+        let Some(hgatp) = ctx.hgatp else {
+            return Ok(addr);
+        };
+        let Some(g_mode) = PagingMode::from_satp((hgatp & MASK_HGATP_MODE) >> 60) else {
+            return Ok(addr);
+        };
+
+        // Implicit accesses are plain reads or writes, even for hlvx.
+        let implicit = AccessContext {
+            hlvx: false,
+            ..*ctx
+        };
+        let root = hgatp & MASK_HGATP_PPN;
+        let leaf = self.walk(
+            bus, pmp, root, g_mode, true, addr, access, origin, &implicit,
+        )?;
+        Ok(leaf.addr)
+    }
+}
+
+/// Checks the R/W/X/U bits of a leaf PTE against an access. G-stage
+/// accesses are all treated as U-mode ones.
+fn permitted(pte: u64, access: AccessType, ctx: &AccessContext, g_stage: bool) -> bool {
+    let user_page = pte & PTE_U != 0;
+    let privilege_ok = match ctx.mode {
+        _ if g_stage => user_page,
+        Mode::User => user_page,
+        // Supervisor code never executes user pages, and only touches
+        // their data with mstatus.SUM set.
+        Mode::Supervisor => !user_page || (ctx.sum && access != AccessType::Instruction),
+        Mode::Machine => true,
+    };
+    let mxr = if g_stage { ctx.hs_mxr } else { ctx.mxr };
+
+    privilege_ok
+        && match access {
+            AccessType::Instruction => pte & PTE_X != 0,
+            AccessType::Load if ctx.hlvx => pte & PTE_X != 0,
+            AccessType::Load => pte & PTE_R != 0 || (mxr && pte & PTE_X != 0),
+            AccessType::Store => pte & PTE_W != 0,
+        }
+}
@@ -1,25 +1,202 @@
-use crate::prelude::{Exception, MemIntf, PLIC_BASE};
+use alloc::{vec, vec::Vec};
 
+use crate::prelude::{Exception, InterruptSink, MemIntf, PLIC_BASE};
+
+/// Each source's priority, 4 bytes apart.
+pub const PLIC_PRIORITY: u64 = 0x0;
+/// The pending bits of all sources, 32 to a word.
 pub const PLIC_PENDING: u64 = 0x1000;
-pub const PLIC_SENABLE: u64 = 0x2000;
-pub const PLIC_SPRIORITY: u64 = 0x201000;
-pub const PLIC_SCLAIM: u64 = 0x201004;
+/// Each context's enable bits, 0x80 bytes apart.
+pub const PLIC_ENABLE: u64 = 0x2000;
+pub const PLIC_ENABLE_STRIDE: u64 = 0x80;
+/// Each context's threshold and claim registers, 0x1000 bytes apart.
+pub const PLIC_THRESHOLD: u64 = 0x200000;
+pub const PLIC_CLAIM: u64 = 0x200004;
+pub const PLIC_CONTEXT_STRIDE: u64 = 0x1000;
+
+// Hart 0's supervisor context.
+pub const PLIC_SENABLE: u64 = PLIC_ENABLE + PLIC_ENABLE_STRIDE;
+pub const PLIC_SPRIORITY: u64 = PLIC_THRESHOLD + PLIC_CONTEXT_STRIDE;
+pub const PLIC_SCLAIM: u64 = PLIC_CLAIM + PLIC_CONTEXT_STRIDE;
+
+/// How many source IDs there are. Source 0 doesn't exist, and claiming
+/// it means nothing was pending.
+pub const PLIC_SOURCES: usize = 1024;
+/// Priorities go from 0, which never interrupts, up to this.
+pub const PLIC_MAX_PRIORITY: u32 = 7;
+
+const WORDS: usize = PLIC_SOURCES / 32;
+
+/// The context for `hart`'s M-mode, or S-mode if `supervisor`, laid out
+/// as on QEMU's virt machine.
+pub const fn plic_context(hart: usize, supervisor: bool) -> usize {
+    2 * hart + supervisor as usize
+}
+
+#[derive(Clone, Copy, Default)]
+struct Context {
+    enable: [u32; WORDS],
+    threshold: u32,
+}
+
+enum Register {
+    Priority(usize),
+    Pending(usize),
+    Enable(usize, usize),
+    Threshold(usize),
+    Claim(usize),
+}
 
 pub struct Plic {
-    pending: u64,
-    senable: u64,
-    spriority: u64,
-    sclaim: u64,
+    priority: Vec<u32>,
+    pending: [u32; WORDS],
+
+    /// The level-triggered lines as the devices drive them.
+    level: [u32; WORDS],
+    /// Edge-triggered requests waiting at their gateways.
+    edge: [u32; WORDS],
+    /// Sources claimed but not completed yet, whose gateways hold back
+    /// further requests until then.
+    claimed: [u32; WORDS],
+
+    contexts: Vec<Context>,
+}
+
+fn bit(words: &[u32; WORDS], source: usize) -> bool {
+    words[source / 32] & (1 << (source % 32)) != 0
+}
+
+fn set_bit(words: &mut [u32; WORDS], source: usize, set: bool) {
+    if set {
+        words[source / 32] |= 1 << (source % 32);
+    } else {
+        words[source / 32] &= !(1 << (source % 32));
+    }
 }
 
 impl Plic {
-    pub fn new() -> Self {
+    pub fn new(harts: usize) -> Self {
         Self {
-            pending: 0,
-            senable: 0,
-            spriority: 0,
-            sclaim: 0,
+            priority: vec![0; PLIC_SOURCES],
+            pending: [0; WORDS],
+            level: [0; WORDS],
+            edge: [0; WORDS],
+            claimed: [0; WORDS],
+            contexts: vec![Context::default(); 2 * harts],
+        }
+    }
+
+    /// Whether `source`'s request is waiting to be claimed.
+    pub fn is_pending(&self, source: usize) -> bool {
+        source < PLIC_SOURCES && bit(&self.pending, source)
+    }
+
+    /// Whether `context` has a pending, enabled source of a priority above
+    /// its threshold, so its hart's MEIP or SEIP should be set.
+    pub fn is_interrupting(&self, context: usize) -> bool {
+        self.best(context).is_some()
+    }
+
+    /// Takes the source `context` should handle next.
+    pub fn claim(&mut self, context: usize) -> usize {
+        let Some(source) = self.best(context) else {
+            return 0;
+        };
+        set_bit(&mut self.pending, source, false);
+        set_bit(&mut self.claimed, source, true);
+        source
+    }
+
+    /// Tells the gateway of a claimed `source` that the handler is done,
+    /// so it forwards the next request. Ignored unless `context` has it
+    /// enabled.
+    pub fn complete(&mut self, context: usize, source: usize) {
+        if source >= PLIC_SOURCES || !bit(&self.contexts[context].enable, source) {
+            return;
         }
+        set_bit(&mut self.claimed, source, false);
+        self.gateway(source);
+    }
+
+    /// Turns a raised line or an edge into a pending request, unless the
+    /// last one is still being handled. Edges seen meanwhile are kept, as
+    /// one, until then.
+    fn gateway(&mut self, source: usize) {
+        if bit(&self.claimed, source) {
+            return;
+        }
+        if bit(&self.level, source) || bit(&self.edge, source) {
+            set_bit(&mut self.pending, source, true);
+            set_bit(&mut self.edge, source, false);
+        }
+    }
+
+    /// The pending and enabled source with the highest priority above the
+    /// threshold, the lowest ID among equals.
+    fn best(&self, context: usize) -> Option<usize> {
+        let context = self.contexts.get(context)?;
+        let mut best = None;
+        let mut best_priority = context.threshold;
+        for (word, (&pending, &enable)) in self.pending.iter().zip(&context.enable).enumerate() {
+            let mut bits = pending & enable;
+            while bits != 0 {
+                let source = word * 32 + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                if self.priority[source] > best_priority {
+                    best = Some(source);
+                    best_priority = self.priority[source];
+                }
+            }
+        }
+        best
+    }
+
+    /// The context and the register within it at `addr`, if it is one of
+    /// an array of `stride`-byte blocks from `base`.
+    fn context(&self, addr: u64, base: u64, stride: u64) -> Option<(usize, u64)> {
+        let offset = addr.checked_sub(base)?;
+        let context = (offset / stride) as usize;
+        (context < self.contexts.len()).then_some((context, offset % stride))
+    }
+
+    fn register(&self, addr: u64) -> Option<Register> {
+        if !addr.is_multiple_of(4) {
+            return None;
+        }
+        if addr < PLIC_PENDING {
+            return Some(Register::Priority((addr / 4) as usize));
+        }
+        if addr < PLIC_PENDING + (WORDS as u64) * 4 {
+            return Some(Register::Pending(((addr - PLIC_PENDING) / 4) as usize));
+        }
+        if let Some((context, offset)) = self.context(addr, PLIC_ENABLE, PLIC_ENABLE_STRIDE) {
+            return ((offset / 4) < WORDS as u64)
+                .then_some(Register::Enable(context, (offset / 4) as usize));
+        }
+        match self.context(addr, PLIC_THRESHOLD, PLIC_CONTEXT_STRIDE) {
+            Some((context, 0)) => Some(Register::Threshold(context)),
+            Some((context, 4)) => Some(Register::Claim(context)),
+            _ => None,
+        }
+    }
+}
+
+/// Sources are numbered by IRQ. Source 0 and IDs out of range are ignored.
+impl InterruptSink for Plic {
+    fn set_level(&mut self, irq: usize, level: bool) {
+        if irq == 0 || irq >= PLIC_SOURCES {
+            return;
+        }
+        set_bit(&mut self.level, irq, level);
+        self.gateway(irq);
+    }
+
+    fn pulse(&mut self, irq: usize) {
+        if irq == 0 || irq >= PLIC_SOURCES {
+            return;
+        }
+        set_bit(&mut self.edge, irq, true);
+        self.gateway(irq);
     }
 }
 
@@ -29,13 +206,15 @@ impl MemIntf for Plic {
             return Err(Exception::LoadAccessFault(addr + PLIC_BASE));
         }
 
-        match addr {
-            PLIC_PENDING => Ok(self.pending),
-            PLIC_SENABLE => Ok(self.senable),
-            PLIC_SPRIORITY => Ok(self.spriority),
-            PLIC_SCLAIM => Ok(self.sclaim),
-            _ => Ok(0),
-        }
+        let val = match self.register(addr) {
+            Some(Register::Priority(source)) => self.priority[source],
+            Some(Register::Pending(word)) => self.pending[word],
+            Some(Register::Enable(context, word)) => self.contexts[context].enable[word],
+            Some(Register::Threshold(context)) => self.contexts[context].threshold,
+            Some(Register::Claim(context)) => self.claim(context) as u32,
+            None => 0,
+        };
+        Ok(val as u64)
     }
 
     fn store(&mut self, addr: u64, val: u64, size: u64) -> Result<(), Exception> {
@@ -43,19 +222,32 @@ impl MemIntf for Plic {
             return Err(Exception::StoreAMOAccessFault(addr + PLIC_BASE));
         }
 
-        match addr {
-            PLIC_PENDING => Ok(self.pending = val),
-            PLIC_SENABLE => Ok(self.senable = val),
-            PLIC_SPRIORITY => Ok(self.spriority = val),
-            PLIC_SCLAIM => Ok(self.sclaim = val),
-            _ => Ok(()),
+        // Source 0 has no priority or enable bit, and pending bits only
+        // change through the gateways and claims.
+        let val = val as u32;
+        match self.register(addr) {
+            Some(Register::Priority(source)) if source != 0 => {
+                self.priority[source] = val.min(PLIC_MAX_PRIORITY);
+            }
+            Some(Register::Enable(context, word)) => {
+                let mask = if word == 0 { !1 } else { !0 };
+                self.contexts[context].enable[word] = val & mask;
+            }
+            Some(Register::Threshold(context)) => {
+                self.contexts[context].threshold = val.min(PLIC_MAX_PRIORITY);
+            }
+            Some(Register::Claim(context)) => self.complete(context, val as usize),
+            _ => {}
         }
+        Ok(())
     }
 
     fn reset(&mut self) {
-        self.pending = 0;
-        self.senable = 0;
-        self.spriority = 0;
-        self.sclaim = 0;
+        self.priority.fill(0);
+        self.pending = [0; WORDS];
+        self.level = [0; WORDS];
+        self.edge = [0; WORDS];
+        self.claimed = [0; WORDS];
+        self.contexts.fill(Context::default());
     }
 }
@@ -0,0 +1,152 @@
+use crate::{cpu::Mode, mmu::AccessType};
+
+// pmpcfg entry fields
+pub const PMP_R: u8 = 1 << 0;
+pub const PMP_W: u8 = 1 << 1;
+pub const PMP_X: u8 = 1 << 2;
+pub const PMP_A: u8 = 0b11 << 3;
+pub const PMP_L: u8 = 1 << 7;
+
+// pmpcfg address-matching modes
+pub const PMP_A_OFF: u8 = 0b00 << 3;
+pub const PMP_A_TOR: u8 = 0b01 << 3;
+pub const PMP_A_NA4: u8 = 0b10 << 3;
+pub const PMP_A_NAPOT: u8 = 0b11 << 3;
+
+pub const PMP_ENTRIES: usize = 64;
+
+/// pmpaddr holds bits 55:2 of a physical address.
+const MASK_PMPADDR: u64 = (1 << 54) - 1;
+
+/// Physical memory protection entries and the checks they imply.
+pub struct Pmp {
+    cfg: [u8; PMP_ENTRIES],
+    addr: [u64; PMP_ENTRIES],
+}
+
+impl Default for Pmp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pmp {
+    pub fn new() -> Self {
+        Pmp {
+            cfg: [0; PMP_ENTRIES],
+            addr: [0; PMP_ENTRIES],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Reads pmpcfg`reg`. On RV64 only the even registers exist, each
+    /// packing the configuration of eight entries.
+    pub fn read_cfg(&self, reg: usize) -> u64 {
+        if !reg.is_multiple_of(2) {
+            return 0;
+        }
+
+        (0..8).fold(0, |val, i| val | (self.cfg[reg * 4 + i] as u64) << (i * 8))
+    }
+
+    pub fn write_cfg(&mut self, reg: usize, val: u64) {
+        if !reg.is_multiple_of(2) {
+            return;
+        }
+
+        for i in 0..8 {
+            let entry = reg * 4 + i;
+            if self.locked(entry) {
+                continue;
+            }
+
+            let mut cfg = (val >> (i * 8)) as u8 & (PMP_L | PMP_A | PMP_X | PMP_W | PMP_R);
+            // W without R is reserved; keep such entries inaccessible.
+            if cfg & (PMP_R | PMP_W) == PMP_W {
+                cfg &= !PMP_W;
+            }
+            self.cfg[entry] = cfg;
+        }
+    }
+
+    pub fn read_addr(&self, entry: usize) -> u64 {
+        self.addr[entry]
+    }
+
+    pub fn write_addr(&mut self, entry: usize, val: u64) {
+        // A locked TOR entry also locks the address below it.
+        let next_locked_tor = entry + 1 < PMP_ENTRIES
+            && self.locked(entry + 1)
+            && self.cfg[entry + 1] & PMP_A == PMP_A_TOR;
+
+        if !self.locked(entry) && !next_locked_tor {
+            self.addr[entry] = val & MASK_PMPADDR;
+        }
+    }
+
+    fn locked(&self, entry: usize) -> bool {
+        self.cfg[entry] & PMP_L != 0
+    }
+
+    /// Byte range `[start, end)` matched by an entry, if it is enabled.
+    fn range(&self, entry: usize) -> Option<(u128, u128)> {
+        let addr = self.addr[entry] as u128;
+
+        match self.cfg[entry] & PMP_A {
+            PMP_A_TOR => {
+                let start = match entry {
+                    0 => 0,
+                    _ => (self.addr[entry - 1] as u128) << 2,
+                };
+                (start < addr << 2).then_some((start, addr << 2))
+            }
+            PMP_A_NA4 => Some((addr << 2, (addr << 2) + 4)),
+            PMP_A_NAPOT => {
+                // The number of trailing ones encodes a 2^(ones + 3) byte region.
+                let ones = self.addr[entry].trailing_ones();
+                let base = (addr & !((1 << ones) - 1)) << 2;
+                Some((base, base + (1 << (ones + 3))))
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks an access of `size` bits at `addr` made with privilege `mode`.
+    /// The lowest-numbered entry that matches any byte decides, and must
+    /// match all of them.
+    pub fn check(&self, addr: u64, size: u64, access: AccessType, mode: Mode) -> bool {
+        let start = addr as u128;
+        let end = start + (size / 8) as u128;
+
+        for entry in 0..PMP_ENTRIES {
+            let Some((lo, hi)) = self.range(entry) else {
+                continue;
+            };
+            if end <= lo || start >= hi {
+                continue;
+            }
+            if start < lo || end > hi {
+                return false;
+            }
+
+            // Unlocked entries don't apply to M-mode.
+            let cfg = self.cfg[entry];
+            if mode == Mode::Machine && cfg & PMP_L == 0 {
+                return true;
+            }
+
+            let perm = match access {
+                AccessType::Instruction => PMP_X,
+                AccessType::Load => PMP_R,
+                AccessType::Store => PMP_W,
+            };
+            return cfg & perm != 0;
+        }
+
+        // Every entry is implemented, so unmatched S/U accesses fail.
+        mode == Mode::Machine
+    }
+}
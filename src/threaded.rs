@@ -0,0 +1,507 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::Duration,
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    bus::{Bus, MemIntf, SharedDevices, RAM_BASE},
+    clint::Clint,
+    cpu::Cpu,
+    csrs::MHARTID,
+    exceptions::Exception,
+    plic::Plic,
+};
+
+/// How often devices are polled, and waiting harts look for interrupts.
+const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+/// Guest RAM that harts on several threads access at once. Clones share
+/// the same memory.
+#[derive(Clone)]
+pub struct SharedRam {
+    words: Arc<[AtomicU64]>,
+    size: u64,
+}
+
+impl SharedRam {
+    /// `size` bytes of zeroed memory.
+    pub fn new(size: u64) -> Self {
+        Self {
+            words: (0..size.div_ceil(8)).map(|_| AtomicU64::new(0)).collect(),
+            size,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Copies `data` into memory from `offset`, e.g. to load an image.
+    pub fn write(&self, offset: u64, data: &[u8]) {
+        for (i, &byte) in data.iter().enumerate() {
+            self.update(offset + i as u64, 8, |_| byte as u64);
+        }
+    }
+
+    /// Copies memory from `offset` into `buf`, e.g. for a snapshot.
+    pub fn read(&self, offset: u64, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = self.get(offset + i as u64, 8) as u8;
+        }
+    }
+
+    fn contains(&self, addr: u64, size: u64) -> bool {
+        matches!(size, 8 | 16 | 32 | 64)
+            && addr
+                .checked_add(size / 8)
+                .is_some_and(|end| end <= self.size)
+    }
+
+    fn within_word(addr: u64, size: u64) -> bool {
+        addr % 8 * 8 + size <= 64
+    }
+
+    fn get(&self, addr: u64, size: u64) -> u64 {
+        if !Self::within_word(addr, size) {
+            return (0..size / 8).fold(0, |val, i| val | self.get(addr + i, 8) << (i * 8));
+        }
+
+        let shift = addr % 8 * 8;
+        (self.words[(addr / 8) as usize].load(Ordering::Relaxed) >> shift) & mask(size)
+    }
+
+    /// Replaces the value at `addr`, which mustn't straddle two words,
+    /// with `op` of it and returns the old one.
+    fn update(&self, addr: u64, size: u64, op: impl FnMut(u64) -> u64) -> u64 {
+        self.update_with(addr, size, Ordering::Relaxed, op)
+    }
+
+    fn update_with(
+        &self,
+        addr: u64,
+        size: u64,
+        order: Ordering,
+        mut op: impl FnMut(u64) -> u64,
+    ) -> u64 {
+        let shift = addr % 8 * 8;
+        let mask = mask(size) << shift;
+        let word = &self.words[(addr / 8) as usize];
+        let old = word
+            .fetch_update(order, Ordering::Relaxed, |word| {
+                let new = op((word & mask) >> shift) << shift;
+                Some((word & !mask) | (new & mask))
+            })
+            .unwrap_or_else(|word| word);
+        (old & mask) >> shift
+    }
+}
+
+fn mask(size: u64) -> u64 {
+    u64::MAX >> (64 - size)
+}
+
+impl MemIntf for SharedRam {
+    fn reset(&mut self) {
+        for word in self.words.iter() {
+            word.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn load(&mut self, addr: u64, size: u64) -> Result<u64, Exception> {
+        if !self.contains(addr, size) {
+            return Err(Exception::LoadAccessFault(addr));
+        }
+
+        Ok(self.get(addr, size))
+    }
+
+    fn store(&mut self, addr: u64, val: u64, size: u64) -> Result<(), Exception> {
+        if !self.contains(addr, size) {
+            return Err(Exception::StoreAMOAccessFault(addr));
+        }
+
+        if Self::within_word(addr, size) {
+            self.update(addr, size, |_| val);
+        } else {
+            for i in 0..size / 8 {
+                self.update(addr + i, 8, |_| val >> (i * 8));
+            }
+        }
+        Ok(())
+    }
+
+    /// Atomic, and sequentially consistent so that it is at least as strong
+    /// as any combination of `aq` and `rl`.
+    fn amo(
+        &mut self,
+        addr: u64,
+        size: u64,
+        op: &mut dyn FnMut(u64) -> u64,
+    ) -> Result<u64, Exception> {
+        if !self.contains(addr, size) || !Self::within_word(addr, size) {
+            return Err(Exception::StoreAMOAccessFault(addr));
+        }
+
+        Ok(self.update_with(addr, size, Ordering::SeqCst, op))
+    }
+}
+
+/// Stands in for the disk of the hart-local buses, whose devices are
+/// never used.
+struct NoDisk;
+
+impl MemIntf for NoDisk {
+    fn reset(&mut self) {}
+
+    fn load(&mut self, addr: u64, _size: u64) -> Result<u64, Exception> {
+        Err(Exception::LoadAccessFault(addr))
+    }
+
+    fn store(&mut self, addr: u64, _val: u64, _size: u64) -> Result<(), Exception> {
+        Err(Exception::StoreAMOAccessFault(addr))
+    }
+}
+
+/// The bus the harts share for devices, with what they've transmitted.
+struct Devices<'a> {
+    bus: Bus<'a>,
+    output: Vec<char>,
+
+    /// Per hart, whether it's waiting for an interrupt or halted, and its
+    /// next timer deadline, so time can skip ahead once all of them are.
+    idle: Vec<bool>,
+    deadlines: Vec<Option<u64>>,
+}
+
+// SAFETY: the bus is built from a `SharedRam` and a disk that are both
+// `Send`, and is only ever used with the lock held.
+unsafe impl Send for Devices<'_> {}
+
+fn lock<'a, 'b>(devices: &'b Mutex<Devices<'a>>) -> MutexGuard<'b, Devices<'a>> {
+    devices.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl SharedDevices for Mutex<Devices<'_>> {
+    fn load(&self, addr: u64, size: u64) -> Result<u64, Exception> {
+        lock(self).bus.load(addr, size)
+    }
+
+    fn store(&self, addr: u64, val: u64, size: u64) -> Result<(), Exception> {
+        let mut devices = lock(self);
+        devices.bus.store(addr, val, size)?;
+
+        // Take what was transmitted right away, so harts writing faster
+        // than the UART is polled don't lose any.
+        if let Some(tx) = devices.bus.uart.tick(None) {
+            devices.output.push(tx);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+enum Request {
+    #[default]
+    Run,
+    Pause,
+    Stop,
+}
+
+#[derive(Default)]
+struct ControlState {
+    request: Request,
+    running: usize,
+    parked: usize,
+}
+
+/// Pauses, resumes and stops the harts of a running [`ThreadedVM`] from
+/// other threads. Harts only ever stop between instructions.
+#[derive(Default)]
+pub struct Control {
+    state: Mutex<ControlState>,
+    changed: Condvar,
+}
+
+impl Control {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ControlState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Asks the harts to pause and waits until they all have. Guest memory
+    /// then holds still and can be snapshotted through a [`SharedRam`]
+    /// clone.
+    pub fn pause(&self) {
+        let mut state = self.lock();
+        if state.request == Request::Run {
+            state.request = Request::Pause;
+        }
+        self.changed.notify_all();
+        while state.request == Request::Pause && state.parked < state.running {
+            state = self
+                .changed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Lets paused or stopped harts run again.
+    pub fn resume(&self) {
+        self.lock().request = Request::Run;
+        self.changed.notify_all();
+    }
+
+    /// Asks the harts to stop, which makes [`ThreadedVM::run`] return with
+    /// all of their state in the VM.
+    pub fn stop(&self) {
+        self.lock().request = Request::Stop;
+        self.changed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.lock().request == Request::Pause
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.lock().request == Request::Stop
+    }
+
+    /// Parks a hart while paused. Returns whether it should keep running.
+    fn checkpoint(&self) -> bool {
+        let mut state = self.lock();
+        if state.request == Request::Pause {
+            state.parked += 1;
+            self.changed.notify_all();
+            while state.request == Request::Pause {
+                state = self
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            state.parked -= 1;
+        }
+        state.request == Request::Run
+    }
+
+    fn exit(&self) {
+        self.lock().running -= 1;
+        self.changed.notify_all();
+    }
+}
+
+/// A machine whose harts each run on their own host thread, against
+/// shared RAM. Device accesses take a lock, and LR/SC and AMOs are host
+/// atomics: an `sc` succeeds if memory still holds what its `lr` read.
+pub struct ThreadedVM<'a> {
+    /// The harts, indexed by `mhartid`.
+    pub harts: Vec<Cpu>,
+    ram: SharedRam,
+    devices: Mutex<Devices<'a>>,
+
+    /// How many instructions a hart runs between looking at devices,
+    /// interrupts and its [`Control`].
+    pub quantum: u64,
+}
+
+impl<'a> ThreadedVM<'a> {
+    pub fn new(ram: &'a mut SharedRam, disk: &'a mut (dyn MemIntf + Send), harts: usize) -> Self {
+        let shared = ram.clone();
+        let ram_len = ram.size();
+        let mut bus = Bus::new(ram, ram_len, disk);
+        bus.clint = Clint::new(harts);
+        bus.plic = Plic::new(harts);
+
+        let harts: Vec<Cpu> = (0..harts)
+            .map(|hartid| {
+                let mut cpu = Cpu::new();
+                cpu.csr[MHARTID] = hartid as u64;
+                cpu.x[2] = RAM_BASE + ram_len;
+                cpu
+            })
+            .collect();
+
+        let devices = Devices {
+            bus,
+            output: Vec::new(),
+            idle: vec![false; harts.len()],
+            deadlines: vec![None; harts.len()],
+        };
+
+        ThreadedVM {
+            harts,
+            ram: shared,
+            devices: Mutex::new(devices),
+            quantum: 1024,
+        }
+    }
+
+    pub fn ram(&self) -> &SharedRam {
+        &self.ram
+    }
+
+    /// The devices, while the harts aren't running.
+    pub fn bus(&mut self) -> &mut Bus<'a> {
+        &mut self
+            .devices
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .bus
+    }
+
+    /// Runs every hart on its own thread until they have all halted by
+    /// jumping to 0 or `control` stops them. This thread meanwhile polls the
+    /// UART: `console` is given each character the guest transmits, and
+    /// also `None` every poll, and returns the next one to receive.
+    ///
+    /// A fatal exception on any hart stops the others, and is returned.
+    pub fn run(
+        &mut self,
+        control: &Control,
+        mut console: impl FnMut(Option<char>) -> Option<char>,
+    ) -> Result<(), Exception> {
+        control.lock().running = self.harts.len();
+        let (ram, devices, quantum) = (&self.ram, &self.devices, self.quantum);
+
+        thread::scope(|scope| {
+            let threads: Vec<_> = self
+                .harts
+                .iter_mut()
+                .map(|cpu| {
+                    scope.spawn(move || {
+                        let result = run_hart(cpu, ram.clone(), devices, control, quantum);
+                        if result.is_err() {
+                            control.stop();
+                        }
+                        control.exit();
+                        result
+                    })
+                })
+                .collect();
+
+            // Poll once more after the harts are done, for their last output.
+            let mut rx = None;
+            loop {
+                let done = threads.iter().all(|thread| thread.is_finished());
+                let output = {
+                    let mut devices = lock(devices);
+                    if let Some(tx) = devices.bus.uart.tick(rx.take()) {
+                        devices.output.push(tx);
+                    }
+                    core::mem::take(&mut devices.output)
+                };
+                for tx in output {
+                    rx = rx.or(console(Some(tx)));
+                }
+                rx = rx.or(console(None));
+                if done {
+                    break;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+
+            threads
+                .into_iter()
+                .try_for_each(|thread| thread.join().expect("hart thread panicked"))
+        })
+    }
+}
+
+/// Runs `cpu` with a bus of its own onto the shared RAM and devices.
+fn run_hart(
+    cpu: &mut Cpu,
+    mut ram: SharedRam,
+    devices: &Mutex<Devices>,
+    control: &Control,
+    quantum: u64,
+) -> Result<(), Exception> {
+    let ram_len = ram.size();
+    let mut disk = NoDisk;
+    let mut bus = Bus::new(&mut ram, ram_len, &mut disk);
+    bus.shared = Some(devices);
+    let hart = cpu.csr[MHARTID] as usize;
+
+    let mut retired = 0;
+    let result = loop {
+        if let Err(e) = sync(cpu, &mut bus, devices, hart, retired) {
+            break Err(e);
+        }
+        retired = 0;
+        if !control.checkpoint() {
+            break Ok(());
+        }
+        if cpu.waiting {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+
+        match run_quantum(cpu, &mut bus, quantum) {
+            Ok(Some(count)) => retired = count,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+
+    // A hart that's done doesn't hold back time for the others.
+    let mut devices = lock(devices);
+    devices.idle[hart] = true;
+    devices.deadlines[hart] = None;
+    result
+}
+
+/// Runs up to `quantum` instructions, stopping early if the hart waits
+/// for an interrupt. Returns how many it ran, or `None` if it halted.
+fn run_quantum(cpu: &mut Cpu, bus: &mut Bus, quantum: u64) -> Result<Option<u64>, Exception> {
+    for count in 1..=quantum {
+        if let Err(e) = cpu.tick(bus) {
+            cpu.handle_exception(e);
+            if e.is_fatal() {
+                return Err(e);
+            }
+        }
+        if cpu.pc == 0 {
+            return Ok(None);
+        }
+        if cpu.waiting {
+            return Ok(Some(count));
+        }
+    }
+    Ok(Some(quantum))
+}
+
+/// Takes pending interrupts and catches up with the shared time, after
+/// moving it on for the `retired` instructions the hart ran since.
+fn sync(
+    cpu: &mut Cpu,
+    bus: &mut Bus,
+    devices: &Mutex<Devices>,
+    hart: usize,
+    retired: u64,
+) -> Result<(), Exception> {
+    let mut devices = lock(devices);
+    let devices = &mut *devices;
+
+    devices.bus.clint.advance(retired);
+    devices.idle[hart] = cpu.waiting;
+    devices.deadlines[hart] = cpu.next_timer_deadline(&devices.bus);
+    if devices.idle.iter().all(|&idle| idle) {
+        if let Some(deadline) = devices.deadlines.iter().flatten().min() {
+            devices.bus.clint.set_mtime(*deadline);
+        }
+    }
+    bus.clint.set_mtime(devices.bus.clint.mtime());
+
+    devices.bus.update_interrupts();
+    if let Some(int) = cpu.check_pending_interrupt(&mut devices.bus)? {
+        cpu.handle_interrupt(int);
+    }
+    Ok(())
+}
@@ -0,0 +1,326 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    cpu::Mode,
+    csrs::{TCONTROL, TDATA1, TDATA2, TINFO, TSELECT},
+    mmu::AccessType,
+};
+
+pub const DEFAULT_TRIGGERS: usize = 4;
+
+// tdata1 trigger types
+pub const TRIGGER_TYPE_NONE: u64 = 0;
+pub const TRIGGER_TYPE_ICOUNT: u64 = 3;
+pub const TRIGGER_TYPE_MCONTROL6: u64 = 6;
+pub const TRIGGER_TYPE_DISABLED: u64 = 15;
+
+// tdata1 fields shared by every type
+pub const MASK_TDATA1_TYPE: u64 = 0xf << 60;
+pub const MASK_TDATA1_DMODE: u64 = 1 << 59;
+
+// mcontrol6 fields
+pub const MASK_MCONTROL6_VS: u64 = 1 << 24;
+pub const MASK_MCONTROL6_VU: u64 = 1 << 23;
+pub const MASK_MCONTROL6_HIT0: u64 = 1 << 22;
+pub const MASK_MCONTROL6_SELECT: u64 = 1 << 21;
+pub const MASK_MCONTROL6_CHAIN: u64 = 1 << 11;
+pub const MASK_MCONTROL6_MATCH: u64 = 0xf << 7;
+pub const MASK_MCONTROL6_M: u64 = 1 << 6;
+pub const MASK_MCONTROL6_S: u64 = 1 << 4;
+pub const MASK_MCONTROL6_U: u64 = 1 << 3;
+pub const MASK_MCONTROL6_EXECUTE: u64 = 1 << 2;
+pub const MASK_MCONTROL6_STORE: u64 = 1 << 1;
+pub const MASK_MCONTROL6_LOAD: u64 = 1 << 0;
+/// Fields kept on a write. size, action, uncertain and uncertainen only
+/// support 0 (any size, breakpoint exception, no uncertainty).
+const MASK_MCONTROL6_WRITE: u64 = MASK_MCONTROL6_VS
+    | MASK_MCONTROL6_VU
+    | MASK_MCONTROL6_HIT0
+    | MASK_MCONTROL6_SELECT
+    | MASK_MCONTROL6_CHAIN
+    | MASK_MCONTROL6_MATCH
+    | MASK_MCONTROL6_M
+    | MASK_MCONTROL6_S
+    | MASK_MCONTROL6_U
+    | MASK_MCONTROL6_EXECUTE
+    | MASK_MCONTROL6_STORE
+    | MASK_MCONTROL6_LOAD;
+
+// mcontrol6 match conditions
+pub const MATCH_EQUAL: u64 = 0;
+pub const MATCH_NAPOT: u64 = 1;
+pub const MATCH_GE: u64 = 2;
+pub const MATCH_LT: u64 = 3;
+
+// icount fields
+pub const MASK_ICOUNT_VS: u64 = 1 << 26;
+pub const MASK_ICOUNT_VU: u64 = 1 << 25;
+pub const MASK_ICOUNT_HIT: u64 = 1 << 24;
+pub const MASK_ICOUNT_COUNT: u64 = 0x3fff << 10;
+pub const MASK_ICOUNT_M: u64 = 1 << 9;
+pub const MASK_ICOUNT_PENDING: u64 = 1 << 8;
+pub const MASK_ICOUNT_S: u64 = 1 << 7;
+pub const MASK_ICOUNT_U: u64 = 1 << 6;
+/// Fields kept on a write; action only supports 0 (breakpoint exception).
+const MASK_ICOUNT_WRITE: u64 = MASK_ICOUNT_VS
+    | MASK_ICOUNT_VU
+    | MASK_ICOUNT_HIT
+    | MASK_ICOUNT_COUNT
+    | MASK_ICOUNT_M
+    | MASK_ICOUNT_PENDING
+    | MASK_ICOUNT_S
+    | MASK_ICOUNT_U;
+
+// tcontrol fields
+pub const MASK_TCONTROL_MTE: u64 = 1 << 3;
+pub const MASK_TCONTROL_MPTE: u64 = 1 << 7;
+
+/// tinfo: Sdtrig version 1.0 with icount, mcontrol6 and disabled triggers.
+const TINFO_VALUE: u64 =
+    1 << 24 | 1 << TRIGGER_TYPE_ICOUNT | 1 << TRIGGER_TYPE_MCONTROL6 | 1 << TRIGGER_TYPE_DISABLED;
+
+/// The Sdtrig trigger module: address and data match triggers (mcontrol6)
+/// and instruction count triggers (icount), which raise breakpoint
+/// exceptions when they fire.
+pub struct Triggers {
+    tselect: usize,
+    tdata1: Vec<u64>,
+    tdata2: Vec<u64>,
+    tcontrol: u64,
+}
+
+impl Default for Triggers {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRIGGERS)
+    }
+}
+
+impl Triggers {
+    /// Creates `count` triggers, all disabled.
+    pub fn new(count: usize) -> Self {
+        Triggers {
+            tselect: 0,
+            tdata1: vec![TRIGGER_TYPE_DISABLED << 60; count],
+            tdata2: vec![0; count],
+            tcontrol: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.tdata1.len());
+    }
+
+    pub fn read(&self, csr: usize) -> u64 {
+        match csr {
+            TSELECT => self.tselect as u64,
+            // With no triggers at all, tdata1 reads as type 0.
+            TDATA1 => self
+                .tdata1
+                .get(self.tselect)
+                .copied()
+                .unwrap_or(TRIGGER_TYPE_NONE),
+            TDATA2 => self.tdata2.get(self.tselect).copied().unwrap_or(0),
+            TINFO if self.tdata1.is_empty() => 1,
+            TINFO => TINFO_VALUE,
+            TCONTROL => self.tcontrol,
+            // tdata3 has no implemented fields.
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, csr: usize, val: u64) {
+        match csr {
+            // Selecting a trigger that doesn't exist keeps the old one, so
+            // software can count them.
+            TSELECT if (val as usize) < self.tdata1.len() => self.tselect = val as usize,
+            TDATA1 if self.tselect < self.tdata1.len() => {
+                self.tdata1[self.tselect] = legalize_tdata1(val)
+            }
+            TDATA2 if self.tselect < self.tdata2.len() => self.tdata2[self.tselect] = val,
+            TCONTROL => self.tcontrol = val & (MASK_TCONTROL_MTE | MASK_TCONTROL_MPTE),
+            // tdata3 and tinfo are read-only.
+            _ => {}
+        }
+    }
+
+    /// Saves and clears tcontrol.MTE on a trap into M-mode, so triggers
+    /// can't fire again inside the handler.
+    pub fn trap_to_machine(&mut self) {
+        let mte = self.tcontrol & MASK_TCONTROL_MTE;
+        self.tcontrol = (self.tcontrol & !(MASK_TCONTROL_MTE | MASK_TCONTROL_MPTE)) | (mte << 4);
+    }
+
+    /// Restores tcontrol.MTE on mret.
+    pub fn machine_return(&mut self) {
+        let mpte = self.tcontrol & MASK_TCONTROL_MPTE;
+        self.tcontrol = (self.tcontrol & !MASK_TCONTROL_MTE) | (mpte >> 4);
+    }
+
+    /// Whether an mcontrol6 trigger fires for an access at `addr` made in
+    /// `mode` (a guest one if `virt`), setting the hit bits of those that
+    /// do. Data triggers only match when `data` is given. A trigger with
+    /// chain set only fires together with all the triggers after it up to
+    /// the end of the chain.
+    pub fn check(
+        &mut self,
+        access: AccessType,
+        addr: u64,
+        data: Option<u64>,
+        mode: Mode,
+        virt: bool,
+    ) -> bool {
+        let mut fired = false;
+        let mut chain_start = 0;
+        let mut chain_matches = true;
+
+        for i in 0..self.tdata1.len() {
+            let tdata1 = self.tdata1[i];
+            let is_mcontrol6 = tdata1 >> 60 == TRIGGER_TYPE_MCONTROL6;
+            chain_matches &=
+                is_mcontrol6 && self.mcontrol6_matches(i, access, addr, data, mode, virt);
+
+            if is_mcontrol6 && tdata1 & MASK_MCONTROL6_CHAIN != 0 {
+                continue;
+            }
+
+            if chain_matches {
+                for trigger in &mut self.tdata1[chain_start..=i] {
+                    *trigger |= MASK_MCONTROL6_HIT0;
+                }
+                fired = true;
+            }
+            chain_start = i + 1;
+            chain_matches = true;
+        }
+
+        fired
+    }
+
+    fn mcontrol6_matches(
+        &self,
+        i: usize,
+        access: AccessType,
+        addr: u64,
+        data: Option<u64>,
+        mode: Mode,
+        virt: bool,
+    ) -> bool {
+        let tdata1 = self.tdata1[i];
+        let tdata2 = self.tdata2[i];
+
+        let access_bit = match access {
+            AccessType::Instruction => MASK_MCONTROL6_EXECUTE,
+            AccessType::Load => MASK_MCONTROL6_LOAD,
+            AccessType::Store => MASK_MCONTROL6_STORE,
+        };
+        let mode_bit = match (mode, virt) {
+            (Mode::Machine, _) => MASK_MCONTROL6_M,
+            (Mode::Supervisor, false) => MASK_MCONTROL6_S,
+            (Mode::User, false) => MASK_MCONTROL6_U,
+            (Mode::Supervisor, true) => MASK_MCONTROL6_VS,
+            (Mode::User, true) => MASK_MCONTROL6_VU,
+        };
+        if tdata1 & access_bit == 0 || !self.enabled_in(tdata1 & mode_bit, mode) {
+            return false;
+        }
+
+        let val = if tdata1 & MASK_MCONTROL6_SELECT != 0 {
+            let Some(data) = data else {
+                return false;
+            };
+            data
+        } else {
+            addr
+        };
+
+        match (tdata1 & MASK_MCONTROL6_MATCH) >> 7 {
+            MATCH_NAPOT => {
+                // The trailing ones of tdata2, and the zero above them,
+                // are ignored.
+                let ignored = (tdata2.trailing_ones() + 1).min(64);
+                let mask = u64::MAX.checked_shl(ignored).unwrap_or(0);
+                val & mask == tdata2 & mask
+            }
+            MATCH_GE => val >= tdata2,
+            MATCH_LT => val < tdata2,
+            _ => val == tdata2,
+        }
+    }
+
+    /// Whether a trigger with mode bit `enabled` may fire in `mode`: in
+    /// M-mode, tcontrol.MTE must also be set.
+    fn enabled_in(&self, enabled: u64, mode: Mode) -> bool {
+        enabled != 0 && (mode != Mode::Machine || self.tcontrol & MASK_TCONTROL_MTE != 0)
+    }
+
+    /// Counts an instruction retired in `mode` (a guest one if `virt`) for
+    /// the icount triggers enabled there. One reaching zero becomes
+    /// pending.
+    pub fn count_instruction(&mut self, mode: Mode, virt: bool) {
+        let mode_bit = icount_mode_bit(mode, virt);
+
+        for i in 0..self.tdata1.len() {
+            let tdata1 = self.tdata1[i];
+            if tdata1 >> 60 != TRIGGER_TYPE_ICOUNT || !self.enabled_in(tdata1 & mode_bit, mode) {
+                continue;
+            }
+
+            let count = (tdata1 & MASK_ICOUNT_COUNT) >> 10;
+            if count == 0 {
+                continue;
+            }
+            let mut tdata1 = (tdata1 & !MASK_ICOUNT_COUNT) | ((count - 1) << 10);
+            if count == 1 {
+                tdata1 |= MASK_ICOUNT_PENDING;
+            }
+            self.tdata1[i] = tdata1;
+        }
+    }
+
+    /// Whether a pending icount trigger fires before the next instruction,
+    /// which is to run in `mode` (a guest one if `virt`).
+    pub fn icount_fires(&mut self, mode: Mode, virt: bool) -> bool {
+        let mode_bit = icount_mode_bit(mode, virt);
+        let mut fired = false;
+
+        for i in 0..self.tdata1.len() {
+            let tdata1 = self.tdata1[i];
+            if tdata1 >> 60 == TRIGGER_TYPE_ICOUNT
+                && tdata1 & MASK_ICOUNT_PENDING != 0
+                && self.enabled_in(tdata1 & mode_bit, mode)
+            {
+                self.tdata1[i] = (tdata1 & !MASK_ICOUNT_PENDING) | MASK_ICOUNT_HIT;
+                fired = true;
+            }
+        }
+
+        fired
+    }
+}
+
+fn icount_mode_bit(mode: Mode, virt: bool) -> u64 {
+    match (mode, virt) {
+        (Mode::Machine, _) => MASK_ICOUNT_M,
+        (Mode::Supervisor, false) => MASK_ICOUNT_S,
+        (Mode::User, false) => MASK_ICOUNT_U,
+        (Mode::Supervisor, true) => MASK_ICOUNT_VS,
+        (Mode::User, true) => MASK_ICOUNT_VU,
+    }
+}
+
+/// Legalizes a tdata1 write. Unsupported types disable the trigger,
+/// unsupported field values become 0 and dmode, only writable from Debug
+/// Mode, stays clear.
+fn legalize_tdata1(val: u64) -> u64 {
+    match val >> 60 {
+        TRIGGER_TYPE_MCONTROL6 => {
+            let mut tdata1 = val & MASK_MCONTROL6_WRITE;
+            if (tdata1 & MASK_MCONTROL6_MATCH) >> 7 > MATCH_LT {
+                tdata1 &= !MASK_MCONTROL6_MATCH;
+            }
+            TRIGGER_TYPE_MCONTROL6 << 60 | tdata1
+        }
+        TRIGGER_TYPE_ICOUNT => TRIGGER_TYPE_ICOUNT << 60 | (val & MASK_ICOUNT_WRITE),
+        _ => TRIGGER_TYPE_DISABLED << 60,
+    }
+}
@@ -1,13 +1,23 @@
-use crate::prelude::{Exception, MemIntf, UART_BASE, UART_SIZE};
+use alloc::collections::VecDeque;
+
+use crate::prelude::{Exception, IrqLine, MemIntf, Trigger, UART_BASE};
 
 // uart interrupt request
-pub const UART_IRQ: u64 = 10;
+pub const UART_IRQ: usize = 10;
 // Receive holding register (for input bytes).
 pub const UART_RHR: u64 = 0;
 // Transmit holding register (for output bytes).
 pub const UART_THR: u64 = 0;
+// Interrupt enable register.
+pub const UART_IER: u64 = 1;
+// Interrupt identification register, when read.
+pub const UART_IIR: u64 = 2;
+// FIFO control register, when written.
+pub const UART_FCR: u64 = 2;
 // Line control register.
 pub const UART_LCR: u64 = 3;
+// Modem control register.
+pub const UART_MCR: u64 = 4;
 // Line status register.
 // LSR BIT 0:
 //     0 = no data in receive holding register or FIFO.
@@ -16,63 +26,284 @@ pub const UART_LCR: u64 = 3;
 //     0 = transmit holding register is full. 16550 will not accept any data for transmission.
 //     1 = transmitter hold register (or FIFO) is empty. CPU can load the next character.
 pub const UART_LSR: u64 = 5;
+// Modem status register.
+pub const UART_MSR: u64 = 6;
+// Scratch register.
+pub const UART_SCR: u64 = 7;
+// The divisor latch, in place of RHR/THR and IER while LCR.DLAB is set.
+pub const UART_DLL: u64 = 0;
+pub const UART_DLM: u64 = 1;
+
+// IER bits: received data (and the timeout), THR empty, line status, modem
+// status.
+pub const MASK_UART_IER_RDI: u8 = 1;
+pub const MASK_UART_IER_THRI: u8 = 1 << 1;
+pub const MASK_UART_IER_RLSI: u8 = 1 << 2;
+pub const MASK_UART_IER_MSI: u8 = 1 << 3;
+
+// IIR interrupt IDs, highest priority first, and the bits set while the
+// FIFOs are enabled.
+pub const UART_IIR_NONE: u8 = 0x1;
+pub const UART_IIR_RLSI: u8 = 0x6;
+pub const UART_IIR_RDI: u8 = 0x4;
+pub const UART_IIR_TIMEOUT: u8 = 0xc;
+pub const UART_IIR_THRI: u8 = 0x2;
+pub const UART_IIR_MSI: u8 = 0x0;
+pub const MASK_UART_IIR_FIFO: u8 = 0xc0;
+
+// FCR bits. The top two pick the RX FIFO level that interrupts: 1, 4, 8
+// or 14 bytes.
+pub const MASK_UART_FCR_ENABLE: u8 = 1;
+pub const MASK_UART_FCR_CLEAR_RX: u8 = 1 << 1;
+pub const MASK_UART_FCR_CLEAR_TX: u8 = 1 << 2;
+pub const MASK_UART_FCR_TRIGGER: u8 = 0xc0;
+
+// LCR divisor latch access bit.
+pub const MASK_UART_LCR_DLAB: u8 = 1 << 7;
+
+// MCR bits. In loopback, transmitted bytes are received instead, and the
+// outputs drive the modem status inputs.
+pub const MASK_UART_MCR_DTR: u8 = 1;
+pub const MASK_UART_MCR_RTS: u8 = 1 << 1;
+pub const MASK_UART_MCR_OUT1: u8 = 1 << 2;
+pub const MASK_UART_MCR_OUT2: u8 = 1 << 3;
+pub const MASK_UART_MCR_LOOP: u8 = 1 << 4;
+
 // The receiver (RX) bit MASK.
 pub const MASK_UART_LSR_RX: u8 = 1;
+// Overrun, parity, framing errors and breaks.
+pub const MASK_UART_LSR_OE: u8 = 1 << 1;
+pub const MASK_UART_LSR_PE: u8 = 1 << 2;
+pub const MASK_UART_LSR_FE: u8 = 1 << 3;
+pub const MASK_UART_LSR_BI: u8 = 1 << 4;
 // The transmitter (TX) bit MASK.
 pub const MASK_UART_LSR_TX: u8 = 1 << 5;
+// Nothing left to transmit at all.
+pub const MASK_UART_LSR_TEMT: u8 = 1 << 6;
+// Some byte in the RX FIFO has an error.
+pub const MASK_UART_LSR_FIFO_ERROR: u8 = 1 << 7;
+pub const MASK_UART_LSR_ERRORS: u8 =
+    MASK_UART_LSR_OE | MASK_UART_LSR_PE | MASK_UART_LSR_FE | MASK_UART_LSR_BI;
+
+// MSR bits: changes since the last read in the low half, the inputs in the
+// high half.
+pub const MASK_UART_MSR_DCTS: u8 = 1;
+pub const MASK_UART_MSR_DDSR: u8 = 1 << 1;
+pub const MASK_UART_MSR_TERI: u8 = 1 << 2;
+pub const MASK_UART_MSR_DDCD: u8 = 1 << 3;
+pub const MASK_UART_MSR_CTS: u8 = 1 << 4;
+pub const MASK_UART_MSR_DSR: u8 = 1 << 5;
+pub const MASK_UART_MSR_RI: u8 = 1 << 6;
+pub const MASK_UART_MSR_DCD: u8 = 1 << 7;
 
+pub const UART_FIFO_SIZE: usize = 16;
+// Ticks without bytes coming in or being read before the bytes below the
+// trigger level interrupt anyway. A byte can come in every tick, so this
+// stands for the 16550's four character times.
+pub const UART_CHAR_TIMEOUT: u32 = 4;
+
+/// An NS16550A. Each tick moves at most one byte in and one out.
 pub struct Uart {
-    uart: [u8; UART_SIZE as usize],
-    new_tx: bool,
-    interrupt: bool,
+    /// Received bytes, each with the LSR error bits it came with.
+    rx: VecDeque<(u8, u8)>,
+    tx: VecDeque<u8>,
+
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+    fifo_enabled: bool,
+    trigger: u8,
+
+    /// LSR.OE, until LSR is read.
+    overrun: bool,
+    /// MSR's low half, until MSR is read.
+    msr_changes: u8,
+    /// Whether THR emptied, until IIR reports it or THR is written.
+    thr_empty_interrupt: bool,
+    /// Ticks since a byte was received or read.
+    idle: u32,
+
+    pub(crate) irq: IrqLine,
 }
 
 impl Uart {
     pub fn new() -> Self {
-        let mut uart = [0; UART_SIZE as usize];
-        uart[UART_LSR as usize] |= MASK_UART_LSR_TX;
-
-        let new_tx = false;
-        let interrupt = false;
-
         Self {
-            uart,
-            new_tx,
-            interrupt,
+            rx: VecDeque::with_capacity(UART_FIFO_SIZE),
+            tx: VecDeque::with_capacity(UART_FIFO_SIZE),
+            ier: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            divisor: 0,
+            fifo_enabled: false,
+            trigger: 0,
+            overrun: false,
+            msr_changes: 0,
+            thr_empty_interrupt: false,
+            idle: 0,
+            irq: IrqLine::new(UART_IRQ, Trigger::Level),
         }
     }
 
+    /// Receives `rx`, and transmits the next byte, if any.
     pub fn tick(&mut self, rx: Option<char>) -> Option<char> {
-        if self.uart[UART_LSR as usize] & MASK_UART_LSR_RX == 0 {
-            if let Some(rx_char) = rx {
-                self.uart[UART_RHR as usize] = rx_char as u8;
+        match rx {
+            Some(rx_char) => self.receive(rx_char as u8, 0),
+            None => self.idle = self.idle.saturating_add(1),
+        }
+
+        let tx = self.tx.pop_front();
+        if tx.is_some() && self.tx.is_empty() {
+            self.thr_empty_interrupt = true;
+        }
+        let tx = match tx {
+            Some(byte) if self.mcr & MASK_UART_MCR_LOOP != 0 => {
+                self.receive(byte, 0);
+                None
+            }
+            tx => tx.map(char::from),
+        };
+
+        self.update_interrupt();
+        tx
+    }
+
+    /// Receives a break: a zero byte with LSR.BI set.
+    pub fn receive_break(&mut self) {
+        self.receive(0, MASK_UART_LSR_BI);
+        self.update_interrupt();
+    }
+
+    pub fn is_interrupting(&self) -> bool {
+        self.irq.is_raised()
+    }
+
+    /// The divisor programmed through the divisor latch.
+    pub fn divisor(&self) -> u16 {
+        self.divisor
+    }
+
+    /// Without FIFOs, the holding registers take a single byte.
+    fn capacity(&self) -> usize {
+        if self.fifo_enabled {
+            UART_FIFO_SIZE
+        } else {
+            1
+        }
+    }
 
-                self.interrupt = true;
+    fn receive(&mut self, byte: u8, errors: u8) {
+        self.idle = 0;
+        if self.rx.len() < self.capacity() {
+            self.rx.push_back((byte, errors));
+        } else {
+            self.overrun = true;
+        }
+    }
+
+    fn trigger_level(&self) -> usize {
+        if !self.fifo_enabled {
+            return 1;
+        }
+        match self.trigger >> 6 {
+            0 => 1,
+            1 => 4,
+            2 => 8,
+            _ => 14,
+        }
+    }
 
-                self.uart[UART_LSR as usize] |= MASK_UART_LSR_RX;
+    fn lsr(&self) -> u8 {
+        let mut lsr = if self.overrun { MASK_UART_LSR_OE } else { 0 };
+        if let Some(&(_, errors)) = self.rx.front() {
+            lsr |= MASK_UART_LSR_RX | errors;
+        }
+        if self.fifo_enabled && self.rx.iter().any(|&(_, errors)| errors != 0) {
+            lsr |= MASK_UART_LSR_FIFO_ERROR;
+        }
+        if self.tx.is_empty() {
+            lsr |= MASK_UART_LSR_TX | MASK_UART_LSR_TEMT;
+        }
+        lsr
+    }
+
+    /// The modem status inputs. Outside of loopback, the other end is
+    /// always there and ready.
+    fn modem_inputs(&self) -> u8 {
+        if self.mcr & MASK_UART_MCR_LOOP == 0 {
+            return MASK_UART_MSR_CTS | MASK_UART_MSR_DSR | MASK_UART_MSR_DCD;
+        }
+        let mut inputs = 0;
+        for (output, input) in [
+            (MASK_UART_MCR_RTS, MASK_UART_MSR_CTS),
+            (MASK_UART_MCR_DTR, MASK_UART_MSR_DSR),
+            (MASK_UART_MCR_OUT1, MASK_UART_MSR_RI),
+            (MASK_UART_MCR_OUT2, MASK_UART_MSR_DCD),
+        ] {
+            if self.mcr & output != 0 {
+                inputs |= input;
             }
         }
+        inputs
+    }
+
+    fn set_mcr(&mut self, mcr: u8) {
+        let old = self.modem_inputs();
+        self.mcr = mcr & 0x1f;
+        let new = self.modem_inputs();
 
-        if self.new_tx {
-            self.new_tx = false;
-            return Some(self.uart[UART_THR as usize] as char);
+        let changed = old ^ new;
+        for (input, change) in [
+            (MASK_UART_MSR_CTS, MASK_UART_MSR_DCTS),
+            (MASK_UART_MSR_DSR, MASK_UART_MSR_DDSR),
+            (MASK_UART_MSR_DCD, MASK_UART_MSR_DDCD),
+        ] {
+            if changed & input != 0 {
+                self.msr_changes |= change;
+            }
         }
+        // RI only reports going inactive.
+        if old & !new & MASK_UART_MSR_RI != 0 {
+            self.msr_changes |= MASK_UART_MSR_TERI;
+        }
+    }
 
-        None
+    /// The interrupt IIR reports, in the 16550's order of priority.
+    fn interrupt_id(&self) -> u8 {
+        let rx_data = self.ier & MASK_UART_IER_RDI != 0;
+        if self.ier & MASK_UART_IER_RLSI != 0 && self.lsr() & MASK_UART_LSR_ERRORS != 0 {
+            UART_IIR_RLSI
+        } else if rx_data && self.rx.len() >= self.trigger_level() {
+            UART_IIR_RDI
+        } else if rx_data
+            && self.fifo_enabled
+            && !self.rx.is_empty()
+            && self.idle >= UART_CHAR_TIMEOUT
+        {
+            UART_IIR_TIMEOUT
+        } else if self.ier & MASK_UART_IER_THRI != 0 && self.thr_empty_interrupt {
+            UART_IIR_THRI
+        } else if self.ier & MASK_UART_IER_MSI != 0 && self.msr_changes != 0 {
+            UART_IIR_MSI
+        } else {
+            UART_IIR_NONE
+        }
     }
 
-    pub fn is_interrupting(&self) -> bool {
-        self.interrupt
+    fn update_interrupt(&mut self) {
+        let interrupting = self.interrupt_id() != UART_IIR_NONE;
+        self.irq.set(interrupting);
     }
 }
 
 impl MemIntf for Uart {
     fn reset(&mut self) {
-        self.uart.fill(0);
-        self.new_tx = false;
-        self.interrupt = false;
-
-        self.uart[UART_LSR as usize] |= MASK_UART_LSR_TX;
+        let irq = self.irq.clone();
+        *self = Self { irq, ..Self::new() };
+        self.update_interrupt();
     }
 
     fn load(&mut self, addr: u64, size: u64) -> Result<u64, Exception> {
@@ -80,13 +311,44 @@ impl MemIntf for Uart {
             return Err(Exception::LoadAccessFault(addr + UART_BASE));
         }
 
-        match addr {
+        let dlab = self.lcr & MASK_UART_LCR_DLAB != 0;
+        let val = match addr {
+            UART_DLL if dlab => self.divisor as u8,
+            UART_DLM if dlab => (self.divisor >> 8) as u8,
             UART_RHR => {
-                self.uart[UART_RHR as usize] &= !MASK_UART_LSR_RX;
-                Ok(self.uart[UART_RHR as usize] as u64)
+                self.idle = 0;
+                self.rx.pop_front().map_or(0, |(byte, _)| byte)
             }
-            _ => Ok(self.uart[addr as usize] as u64),
-        }
+            UART_IER => self.ier,
+            UART_IIR => {
+                let id = self.interrupt_id();
+                if id == UART_IIR_THRI {
+                    self.thr_empty_interrupt = false;
+                }
+                if self.fifo_enabled {
+                    id | MASK_UART_IIR_FIFO
+                } else {
+                    id
+                }
+            }
+            UART_LCR => self.lcr,
+            UART_MCR => self.mcr,
+            UART_LSR => {
+                // Errors are reported once.
+                let lsr = self.lsr();
+                self.overrun = false;
+                if let Some((_, errors)) = self.rx.front_mut() {
+                    *errors = 0;
+                }
+                lsr
+            }
+            UART_MSR => self.modem_inputs() | core::mem::take(&mut self.msr_changes),
+            UART_SCR => self.scr,
+            _ => 0,
+        };
+
+        self.update_interrupt();
+        Ok(val as u64)
     }
 
     fn store(&mut self, addr: u64, val: u64, size: u64) -> Result<(), Exception> {
@@ -94,19 +356,44 @@ impl MemIntf for Uart {
             return Err(Exception::StoreAMOAccessFault(addr + UART_BASE));
         }
 
+        let val = val as u8;
+        let dlab = self.lcr & MASK_UART_LCR_DLAB != 0;
         match addr {
+            UART_DLL if dlab => self.divisor = (self.divisor & 0xff00) | val as u16,
+            UART_DLM if dlab => self.divisor = (self.divisor & 0xff) | (val as u16) << 8,
             UART_THR => {
-                self.new_tx = true;
-
-                self.uart[UART_THR as usize] = val as u8;
-
-                Ok(())
+                // Bytes written to a full FIFO are lost.
+                if self.tx.len() < self.capacity() {
+                    self.tx.push_back(val);
+                }
+                self.thr_empty_interrupt = false;
             }
-            _ => {
-                self.uart[addr as usize] = val as u8;
-
-                Ok(())
+            UART_IER => {
+                // Enabling the THR empty interrupt while it is empty raises it.
+                let enabled = val & !self.ier;
+                self.ier = val & 0xf;
+                if enabled & MASK_UART_IER_THRI != 0 && self.tx.is_empty() {
+                    self.thr_empty_interrupt = true;
+                }
+            }
+            UART_FCR => {
+                let enable = val & MASK_UART_FCR_ENABLE != 0;
+                if enable != self.fifo_enabled || val & MASK_UART_FCR_CLEAR_RX != 0 {
+                    self.rx.clear();
+                }
+                if enable != self.fifo_enabled || val & MASK_UART_FCR_CLEAR_TX != 0 {
+                    self.tx.clear();
+                }
+                self.fifo_enabled = enable;
+                self.trigger = val & MASK_UART_FCR_TRIGGER;
             }
+            UART_LCR => self.lcr = val,
+            UART_MCR => self.set_mcr(val),
+            UART_SCR => self.scr = val,
+            _ => {}
         }
+
+        self.update_interrupt();
+        Ok(())
     }
 }
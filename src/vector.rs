@@ -0,0 +1,1853 @@
+//! The V extension: vector registers, configuration and instructions.
+//!
+//! The register file is kept as one little-endian byte array, so a register
+//! group is simply a run of consecutive registers and elements of any width
+//! can be read straight out of it. Elements are handled as `u64`s holding
+//! their low `eew` bits, and operations sign-extend them where they need to.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    bus::Bus,
+    cpu::Cpu,
+    csrs::*,
+    exceptions::Exception,
+    float::{self, Format, F32, F64, FLAG_NX, RM_DYN, RM_RTZ},
+    inst::Inst,
+};
+
+/// Widest element supported.
+pub const ELEN: u64 = 64;
+/// Register width of a hart created with `Cpu::new`.
+pub const DEFAULT_VLEN: u64 = 128;
+
+/// Reserved encodings and unsupported configurations. `execute_vector`
+/// fills in the instruction bits before the exception leaves the module.
+const ILLEGAL: Exception = Exception::IllegalInstruction(0);
+
+// OP-V funct3 operand categories
+const OPIVV: u32 = 0b000;
+const OPFVV: u32 = 0b001;
+const OPMVV: u32 = 0b010;
+const OPIVI: u32 = 0b011;
+const OPIVX: u32 = 0b100;
+const OPFVF: u32 = 0b101;
+const OPMVX: u32 = 0b110;
+
+/// The 32 vector registers.
+pub struct VectorRegs {
+    vlen: u64,
+    data: Vec<u8>,
+
+    /// Overwrite agnostic tail and masked-off elements with all ones. When
+    /// false they are left undisturbed, which the spec always allows.
+    pub agnostic_ones: bool,
+}
+
+impl VectorRegs {
+    /// Creates registers of `vlen` bits, which must be a power of two from
+    /// 128 to 65536.
+    pub fn new(vlen: u64) -> Self {
+        assert!(
+            vlen.is_power_of_two() && (128..=65536).contains(&vlen),
+            "unsupported VLEN {vlen}"
+        );
+
+        VectorRegs {
+            vlen,
+            data: vec![0; (vlen / 8 * 32) as usize],
+            agnostic_ones: false,
+        }
+    }
+
+    pub fn vlen(&self) -> u64 {
+        self.vlen
+    }
+
+    pub fn vlenb(&self) -> u64 {
+        self.vlen / 8
+    }
+
+    pub fn reset(&mut self) {
+        self.data.fill(0);
+    }
+
+    fn offset(&self, reg: usize, idx: u64, eew: u64) -> usize {
+        reg * self.vlenb() as usize + (idx * eew / 8) as usize
+    }
+
+    /// Reads element `idx` of the `eew`-bit elements of the group starting
+    /// at `reg`.
+    pub fn read(&self, reg: usize, idx: u64, eew: u64) -> u64 {
+        let start = self.offset(reg, idx, eew);
+        let len = eew as usize / 8;
+        let mut bytes = [0; 8];
+        bytes[..len].copy_from_slice(&self.data[start..start + len]);
+        u64::from_le_bytes(bytes)
+    }
+
+    /// Writes element `idx` of the group starting at `reg`, truncating
+    /// `val` to `eew` bits.
+    pub fn write(&mut self, reg: usize, idx: u64, eew: u64, val: u64) {
+        let start = self.offset(reg, idx, eew);
+        let len = eew as usize / 8;
+        self.data[start..start + len].copy_from_slice(&val.to_le_bytes()[..len]);
+    }
+
+    /// Bit `idx` of a mask register.
+    pub fn mask_bit(&self, reg: usize, idx: u64) -> bool {
+        self.data[self.offset(reg, idx / 8, 8)] >> (idx % 8) & 1 != 0
+    }
+
+    pub fn set_mask_bit(&mut self, reg: usize, idx: u64, bit: bool) {
+        let byte = self.offset(reg, idx / 8, 8);
+        self.data[byte] = (self.data[byte] & !(1 << (idx % 8))) | (bit as u8) << (idx % 8);
+    }
+}
+
+/// A legal `vtype` setting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VType {
+    /// Selected element width in bits.
+    pub sew: u64,
+    /// log2 of the register group multiplier, from -3 to 3.
+    pub lmul: i64,
+    pub ta: bool,
+    pub ma: bool,
+}
+
+impl VType {
+    /// Decodes a `vtype` value, returning None for settings the hart
+    /// doesn't support, which set `vill`.
+    pub fn decode(vtype: u64) -> Option<Self> {
+        let fields = MASK_VTYPE_VLMUL | MASK_VTYPE_VSEW | MASK_VTYPE_VTA | MASK_VTYPE_VMA;
+        if vtype & !fields != 0 {
+            return None;
+        }
+
+        let vsew = (vtype & MASK_VTYPE_VSEW) >> 3;
+        let vlmul = vtype & MASK_VTYPE_VLMUL;
+        if vsew > 3 || vlmul == 0b100 {
+            return None;
+        }
+
+        let sew = 8 << vsew;
+        let lmul = ((vlmul << 61) as i64) >> 61;
+        // A fractional group must still hold an ELEN-wide element's worth.
+        if lmul < 0 && sew > ELEN >> -lmul {
+            return None;
+        }
+
+        Some(VType {
+            sew,
+            lmul,
+            ta: vtype & MASK_VTYPE_VTA != 0,
+            ma: vtype & MASK_VTYPE_VMA != 0,
+        })
+    }
+
+    pub fn vlmax(&self, vlen: u64) -> u64 {
+        group_elems(vlen, self.sew, self.lmul)
+    }
+
+    /// log2 of the group multiplier for `eew`-bit elements, keeping the
+    /// SEW/LMUL ratio.
+    fn emul(&self, eew: u64) -> Result<i64, Exception> {
+        let emul = self.lmul + eew.trailing_zeros() as i64 - self.sew.trailing_zeros() as i64;
+        if !(8..=ELEN).contains(&eew) || !(-3..=3).contains(&emul) {
+            return Err(ILLEGAL);
+        }
+
+        Ok(emul)
+    }
+}
+
+/// Number of `eew`-bit elements in a group of multiplier `2^emul`.
+fn group_elems(vlen: u64, eew: u64, emul: i64) -> u64 {
+    if emul >= 0 {
+        (vlen / eew) << emul
+    } else {
+        (vlen / eew) >> -emul
+    }
+}
+
+/// Registers occupied by a group; fractional groups still take one.
+fn group_regs(emul: i64) -> usize {
+    1 << emul.max(0)
+}
+
+/// Register groups must start at a multiple of their size.
+fn check_group(reg: usize, emul: i64) -> Result<(), Exception> {
+    if !reg.is_multiple_of(group_regs(emul)) {
+        return Err(ILLEGAL);
+    }
+
+    Ok(())
+}
+
+/// Checks that a destination group may overlap a source group of another
+/// element width: narrowing destinations only in the lowest-numbered part
+/// of the source, widening destinations only in their highest-numbered
+/// part, and only when the source isn't fractional. Mask registers are
+/// given an `eew` of 1.
+fn check_overlap(
+    vd: usize,
+    d_eew: u64,
+    d_emul: i64,
+    vs: usize,
+    s_eew: u64,
+    s_emul: i64,
+) -> Result<(), Exception> {
+    let (d_regs, s_regs) = (group_regs(d_emul), group_regs(s_emul));
+    if d_eew == s_eew || vd + d_regs <= vs || vs + s_regs <= vd {
+        return Ok(());
+    }
+
+    let legal = if d_eew < s_eew {
+        vd == vs
+    } else {
+        s_emul >= 0 && vs + s_regs == vd + d_regs
+    };
+    if !legal {
+        return Err(ILLEGAL);
+    }
+
+    Ok(())
+}
+
+/// Rejects any overlap between two groups.
+fn check_disjoint(a: usize, a_regs: usize, b: usize, b_regs: usize) -> Result<(), Exception> {
+    if a < b + b_regs && b < a + a_regs {
+        return Err(ILLEGAL);
+    }
+
+    Ok(())
+}
+
+fn sext(val: u64, bits: u64) -> i64 {
+    ((val << (64 - bits)) as i64) >> (64 - bits)
+}
+
+fn fp_format(bits: u64) -> Result<Format, Exception> {
+    match bits {
+        32 => Ok(F32),
+        64 => Ok(F64),
+        _ => Err(ILLEGAL),
+    }
+}
+
+/// How a vector load or store forms its element addresses.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VAddressing {
+    UnitStride,
+    /// Unit-stride load that only traps on element 0, trimming `vl` at the
+    /// first faulting element instead.
+    FaultOnlyFirst,
+    /// `vl<nf>re<eew>` / `vs<nf>r`, ignoring `vtype` and `vl`.
+    WholeRegister,
+    /// `vlm` / `vsm`: `ceil(vl / 8)` bytes of a mask register.
+    Mask,
+    Strided {
+        rs2: usize,
+    },
+    Indexed {
+        vs2: usize,
+    },
+}
+
+/// Second operand of an OP-V instruction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VSrc {
+    V(usize),
+    X(usize),
+    F(usize),
+    I(i64),
+}
+
+#[rustfmt::skip]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VOp {
+    // Integer
+    Vadd, Vsub, Vrsub,
+    Vminu, Vmin, Vmaxu, Vmax,
+    Vand, Vor, Vxor,
+    Vsll, Vsrl, Vsra, Vnsrl, Vnsra,
+    Vadc, Vmadc, Vsbc, Vmsbc,
+    Vmseq, Vmsne, Vmsltu, Vmslt, Vmsleu, Vmsle, Vmsgtu, Vmsgt,
+    Vmul, Vmulh, Vmulhu, Vmulhsu,
+    Vdivu, Vdiv, Vremu, Vrem,
+    Vmacc, Vnmsac, Vmadd, Vnmsub,
+    Vwaddu, Vwadd, Vwsubu, Vwsub, Vwadduw, Vwaddw, Vwsubuw, Vwsubw,
+    Vwmulu, Vwmul, Vwmulsu,
+    Vwmaccu, Vwmacc, Vwmaccsu, Vwmaccus,
+    Vzext(u64), Vsext(u64),
+    Vmerge, Vmv,
+
+    // Fixed-point
+    Vsaddu, Vsadd, Vssubu, Vssub,
+    Vaaddu, Vaadd, Vasubu, Vasub,
+    Vsmul, Vssrl, Vssra, Vnclipu, Vnclip,
+
+    // Reductions
+    Vredsum, Vredand, Vredor, Vredxor, Vredminu, Vredmin, Vredmaxu, Vredmax,
+    Vwredsumu, Vwredsum,
+    Vfredusum, Vfredosum, Vfredmin, Vfredmax, Vfwredusum, Vfwredosum,
+
+    // Mask
+    Vmandn, Vmand, Vmor, Vmxor, Vmorn, Vmnand, Vmnor, Vmxnor,
+    Vcpop, Vfirst, Vmsbf, Vmsif, Vmsof, Viota, Vid,
+
+    // Permutation
+    Vmvxs, Vmvsx, Vfmvfs, Vfmvsf,
+    Vslideup, Vslidedown, Vslide1up, Vslide1down, Vfslide1up, Vfslide1down,
+    Vrgather, Vrgatherei16, Vcompress,
+    /// `vmv<nr>r.v`, copying `nr` whole registers.
+    VmvNr(u64),
+
+    // Floating-point
+    Vfadd, Vfsub, Vfrsub, Vfmul, Vfdiv, Vfrdiv,
+    Vfwadd, Vfwsub, Vfwaddw, Vfwsubw, Vfwmul,
+    Vfmacc, Vfnmacc, Vfmsac, Vfnmsac, Vfmadd, Vfnmadd, Vfmsub, Vfnmsub,
+    Vfwmacc, Vfwnmacc, Vfwmsac, Vfwnmsac,
+    Vfsqrt, Vfrsqrt7, Vfrec7, Vfclass,
+    Vfmin, Vfmax, Vfsgnj, Vfsgnjn, Vfsgnjx,
+    Vmfeq, Vmfne, Vmflt, Vmfle, Vmfgt, Vmfge,
+    Vfmerge, Vfmv,
+    Vfcvtxuf, Vfcvtxf, Vfcvtfxu, Vfcvtfx, Vfcvtrtzxuf, Vfcvtrtzxf,
+    Vfwcvtxuf, Vfwcvtxf, Vfwcvtfxu, Vfwcvtfx, Vfwcvtff, Vfwcvtrtzxuf, Vfwcvtrtzxf,
+    Vfncvtxuf, Vfncvtxf, Vfncvtfxu, Vfncvtfx, Vfncvtff, Vfncvtrodff, Vfncvtrtzxuf, Vfncvtrtzxf,
+}
+
+impl VOp {
+    /// Decodes an OP-V arithmetic instruction from its funct3 and funct6
+    /// fields. The unary groups select their operation with `vs1`, and a
+    /// few operations require `vm` or `vs2` to take a particular value.
+    pub fn decode(funct3: u32, funct6: u32, vs1: usize, vs2: usize, vm: bool) -> Option<VOp> {
+        use VOp::*;
+
+        let op = match (funct3, funct6) {
+            (OPIVV | OPIVX | OPIVI, 0b000000) => Vadd,
+            (OPIVV | OPIVX, 0b000010) => Vsub,
+            (OPIVX | OPIVI, 0b000011) => Vrsub,
+            (OPIVV | OPIVX, 0b000100) => Vminu,
+            (OPIVV | OPIVX, 0b000101) => Vmin,
+            (OPIVV | OPIVX, 0b000110) => Vmaxu,
+            (OPIVV | OPIVX, 0b000111) => Vmax,
+            (OPIVV | OPIVX | OPIVI, 0b001001) => Vand,
+            (OPIVV | OPIVX | OPIVI, 0b001010) => Vor,
+            (OPIVV | OPIVX | OPIVI, 0b001011) => Vxor,
+            (OPIVV | OPIVX | OPIVI, 0b001100) => Vrgather,
+            (OPIVV, 0b001110) => Vrgatherei16,
+            (OPIVX | OPIVI, 0b001110) => Vslideup,
+            (OPIVX | OPIVI, 0b001111) => Vslidedown,
+            (OPIVV | OPIVX | OPIVI, 0b010000) if !vm => Vadc,
+            (OPIVV | OPIVX | OPIVI, 0b010001) => Vmadc,
+            (OPIVV | OPIVX, 0b010010) if !vm => Vsbc,
+            (OPIVV | OPIVX, 0b010011) => Vmsbc,
+            (OPIVV | OPIVX | OPIVI, 0b010111) if !vm => Vmerge,
+            (OPIVV | OPIVX | OPIVI, 0b010111) if vs2 == 0 => Vmv,
+            (OPIVV | OPIVX | OPIVI, 0b011000) => Vmseq,
+            (OPIVV | OPIVX | OPIVI, 0b011001) => Vmsne,
+            (OPIVV | OPIVX, 0b011010) => Vmsltu,
+            (OPIVV | OPIVX, 0b011011) => Vmslt,
+            (OPIVV | OPIVX | OPIVI, 0b011100) => Vmsleu,
+            (OPIVV | OPIVX | OPIVI, 0b011101) => Vmsle,
+            (OPIVX | OPIVI, 0b011110) => Vmsgtu,
+            (OPIVX | OPIVI, 0b011111) => Vmsgt,
+            (OPIVV | OPIVX | OPIVI, 0b100000) => Vsaddu,
+            (OPIVV | OPIVX | OPIVI, 0b100001) => Vsadd,
+            (OPIVV | OPIVX, 0b100010) => Vssubu,
+            (OPIVV | OPIVX, 0b100011) => Vssub,
+            (OPIVV | OPIVX | OPIVI, 0b100101) => Vsll,
+            (OPIVV | OPIVX, 0b100111) => Vsmul,
+            (OPIVI, 0b100111) if vm && matches!(vs1, 0 | 1 | 3 | 7) => VmvNr(vs1 as u64 + 1),
+            (OPIVV | OPIVX | OPIVI, 0b101000) => Vsrl,
+            (OPIVV | OPIVX | OPIVI, 0b101001) => Vsra,
+            (OPIVV | OPIVX | OPIVI, 0b101010) => Vssrl,
+            (OPIVV | OPIVX | OPIVI, 0b101011) => Vssra,
+            (OPIVV | OPIVX | OPIVI, 0b101100) => Vnsrl,
+            (OPIVV | OPIVX | OPIVI, 0b101101) => Vnsra,
+            (OPIVV | OPIVX | OPIVI, 0b101110) => Vnclipu,
+            (OPIVV | OPIVX | OPIVI, 0b101111) => Vnclip,
+            (OPIVV, 0b110000) => Vwredsumu,
+            (OPIVV, 0b110001) => Vwredsum,
+
+            (OPMVV, 0b000000) => Vredsum,
+            (OPMVV, 0b000001) => Vredand,
+            (OPMVV, 0b000010) => Vredor,
+            (OPMVV, 0b000011) => Vredxor,
+            (OPMVV, 0b000100) => Vredminu,
+            (OPMVV, 0b000101) => Vredmin,
+            (OPMVV, 0b000110) => Vredmaxu,
+            (OPMVV, 0b000111) => Vredmax,
+            (OPMVV | OPMVX, 0b001000) => Vaaddu,
+            (OPMVV | OPMVX, 0b001001) => Vaadd,
+            (OPMVV | OPMVX, 0b001010) => Vasubu,
+            (OPMVV | OPMVX, 0b001011) => Vasub,
+            (OPMVX, 0b001110) => Vslide1up,
+            (OPMVX, 0b001111) => Vslide1down,
+            (OPMVV, 0b010000) => match vs1 {
+                0b00000 if vm => Vmvxs,
+                0b10000 => Vcpop,
+                0b10001 => Vfirst,
+                _ => return None,
+            },
+            (OPMVX, 0b010000) if vm && vs2 == 0 => Vmvsx,
+            (OPMVV, 0b010010) => match vs1 {
+                0b00010 => Vzext(8),
+                0b00011 => Vsext(8),
+                0b00100 => Vzext(4),
+                0b00101 => Vsext(4),
+                0b00110 => Vzext(2),
+                0b00111 => Vsext(2),
+                _ => return None,
+            },
+            (OPMVV, 0b010100) => match vs1 {
+                0b00001 => Vmsbf,
+                0b00010 => Vmsof,
+                0b00011 => Vmsif,
+                0b10000 => Viota,
+                0b10001 if vs2 == 0 => Vid,
+                _ => return None,
+            },
+            (OPMVV, 0b010111) if vm => Vcompress,
+            (OPMVV, 0b011000) if vm => Vmandn,
+            (OPMVV, 0b011001) if vm => Vmand,
+            (OPMVV, 0b011010) if vm => Vmor,
+            (OPMVV, 0b011011) if vm => Vmxor,
+            (OPMVV, 0b011100) if vm => Vmorn,
+            (OPMVV, 0b011101) if vm => Vmnand,
+            (OPMVV, 0b011110) if vm => Vmnor,
+            (OPMVV, 0b011111) if vm => Vmxnor,
+            (OPMVV | OPMVX, 0b100000) => Vdivu,
+            (OPMVV | OPMVX, 0b100001) => Vdiv,
+            (OPMVV | OPMVX, 0b100010) => Vremu,
+            (OPMVV | OPMVX, 0b100011) => Vrem,
+            (OPMVV | OPMVX, 0b100100) => Vmulhu,
+            (OPMVV | OPMVX, 0b100101) => Vmul,
+            (OPMVV | OPMVX, 0b100110) => Vmulhsu,
+            (OPMVV | OPMVX, 0b100111) => Vmulh,
+            (OPMVV | OPMVX, 0b101001) => Vmadd,
+            (OPMVV | OPMVX, 0b101011) => Vnmsub,
+            (OPMVV | OPMVX, 0b101101) => Vmacc,
+            (OPMVV | OPMVX, 0b101111) => Vnmsac,
+            (OPMVV | OPMVX, 0b110000) => Vwaddu,
+            (OPMVV | OPMVX, 0b110001) => Vwadd,
+            (OPMVV | OPMVX, 0b110010) => Vwsubu,
+            (OPMVV | OPMVX, 0b110011) => Vwsub,
+            (OPMVV | OPMVX, 0b110100) => Vwadduw,
+            (OPMVV | OPMVX, 0b110101) => Vwaddw,
+            (OPMVV | OPMVX, 0b110110) => Vwsubuw,
+            (OPMVV | OPMVX, 0b110111) => Vwsubw,
+            (OPMVV | OPMVX, 0b111000) => Vwmulu,
+            (OPMVV | OPMVX, 0b111010) => Vwmulsu,
+            (OPMVV | OPMVX, 0b111011) => Vwmul,
+            (OPMVV | OPMVX, 0b111100) => Vwmaccu,
+            (OPMVV | OPMVX, 0b111101) => Vwmacc,
+            (OPMVX, 0b111110) => Vwmaccus,
+            (OPMVV | OPMVX, 0b111111) => Vwmaccsu,
+
+            (OPFVV | OPFVF, 0b000000) => Vfadd,
+            (OPFVV, 0b000001) => Vfredusum,
+            (OPFVV | OPFVF, 0b000010) => Vfsub,
+            (OPFVV, 0b000011) => Vfredosum,
+            (OPFVV | OPFVF, 0b000100) => Vfmin,
+            (OPFVV, 0b000101) => Vfredmin,
+            (OPFVV | OPFVF, 0b000110) => Vfmax,
+            (OPFVV, 0b000111) => Vfredmax,
+            (OPFVV | OPFVF, 0b001000) => Vfsgnj,
+            (OPFVV | OPFVF, 0b001001) => Vfsgnjn,
+            (OPFVV | OPFVF, 0b001010) => Vfsgnjx,
+            (OPFVF, 0b001110) => Vfslide1up,
+            (OPFVF, 0b001111) => Vfslide1down,
+            (OPFVV, 0b010000) if vm && vs1 == 0 => Vfmvfs,
+            (OPFVF, 0b010000) if vm && vs2 == 0 => Vfmvsf,
+            (OPFVV, 0b010010) => match vs1 {
+                0b00000 => Vfcvtxuf,
+                0b00001 => Vfcvtxf,
+                0b00010 => Vfcvtfxu,
+                0b00011 => Vfcvtfx,
+                0b00110 => Vfcvtrtzxuf,
+                0b00111 => Vfcvtrtzxf,
+                0b01000 => Vfwcvtxuf,
+                0b01001 => Vfwcvtxf,
+                0b01010 => Vfwcvtfxu,
+                0b01011 => Vfwcvtfx,
+                0b01100 => Vfwcvtff,
+                0b01110 => Vfwcvtrtzxuf,
+                0b01111 => Vfwcvtrtzxf,
+                0b10000 => Vfncvtxuf,
+                0b10001 => Vfncvtxf,
+                0b10010 => Vfncvtfxu,
+                0b10011 => Vfncvtfx,
+                0b10100 => Vfncvtff,
+                0b10101 => Vfncvtrodff,
+                0b10110 => Vfncvtrtzxuf,
+                0b10111 => Vfncvtrtzxf,
+                _ => return None,
+            },
+            (OPFVV, 0b010011) => match vs1 {
+                0b00000 => Vfsqrt,
+                0b00100 => Vfrsqrt7,
+                0b00101 => Vfrec7,
+                0b10000 => Vfclass,
+                _ => return None,
+            },
+            (OPFVF, 0b010111) if !vm => Vfmerge,
+            (OPFVF, 0b010111) if vs2 == 0 => Vfmv,
+            (OPFVV | OPFVF, 0b011000) => Vmfeq,
+            (OPFVV | OPFVF, 0b011001) => Vmfle,
+            (OPFVV | OPFVF, 0b011011) => Vmflt,
+            (OPFVV | OPFVF, 0b011100) => Vmfne,
+            (OPFVF, 0b011101) => Vmfgt,
+            (OPFVF, 0b011111) => Vmfge,
+            (OPFVV | OPFVF, 0b100000) => Vfdiv,
+            (OPFVF, 0b100001) => Vfrdiv,
+            (OPFVV | OPFVF, 0b100100) => Vfmul,
+            (OPFVF, 0b100111) => Vfrsub,
+            (OPFVV | OPFVF, 0b101000) => Vfmadd,
+            (OPFVV | OPFVF, 0b101001) => Vfnmadd,
+            (OPFVV | OPFVF, 0b101010) => Vfmsub,
+            (OPFVV | OPFVF, 0b101011) => Vfnmsub,
+            (OPFVV | OPFVF, 0b101100) => Vfmacc,
+            (OPFVV | OPFVF, 0b101101) => Vfnmacc,
+            (OPFVV | OPFVF, 0b101110) => Vfmsac,
+            (OPFVV | OPFVF, 0b101111) => Vfnmsac,
+            (OPFVV | OPFVF, 0b110000) => Vfwadd,
+            (OPFVV, 0b110001) => Vfwredusum,
+            (OPFVV | OPFVF, 0b110010) => Vfwsub,
+            (OPFVV, 0b110011) => Vfwredosum,
+            (OPFVV | OPFVF, 0b110100) => Vfwaddw,
+            (OPFVV | OPFVF, 0b110110) => Vfwsubw,
+            (OPFVV | OPFVF, 0b111000) => Vfwmul,
+            (OPFVV | OPFVF, 0b111100) => Vfwmacc,
+            (OPFVV | OPFVF, 0b111101) => Vfwnmacc,
+            (OPFVV | OPFVF, 0b111110) => Vfwmsac,
+            (OPFVV | OPFVF, 0b111111) => Vfwnmsac,
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    /// Operations whose 5-bit immediate is zero-extended rather than
+    /// sign-extended.
+    #[rustfmt::skip]
+    pub fn unsigned_imm(self) -> bool {
+        use VOp::*;
+
+        matches!(
+            self,
+            Vsll | Vsrl | Vsra | Vssrl | Vssra | Vnsrl | Vnsra | Vnclipu | Vnclip
+                | Vrgather | Vslideup | Vslidedown
+        )
+    }
+
+    /// Floating-point operations, which are illegal while `mstatus.FS` is
+    /// Off.
+    #[rustfmt::skip]
+    pub fn is_fp(self) -> bool {
+        use VOp::*;
+
+        matches!(
+            self,
+            Vfredusum | Vfredosum | Vfredmin | Vfredmax | Vfwredusum | Vfwredosum
+                | Vfmvfs | Vfmvsf | Vfslide1up | Vfslide1down
+                | Vfadd | Vfsub | Vfrsub | Vfmul | Vfdiv | Vfrdiv
+                | Vfwadd | Vfwsub | Vfwaddw | Vfwsubw | Vfwmul
+                | Vfmacc | Vfnmacc | Vfmsac | Vfnmsac | Vfmadd | Vfnmadd | Vfmsub | Vfnmsub
+                | Vfwmacc | Vfwnmacc | Vfwmsac | Vfwnmsac
+                | Vfsqrt | Vfrsqrt7 | Vfrec7 | Vfclass
+                | Vfmin | Vfmax | Vfsgnj | Vfsgnjn | Vfsgnjx
+                | Vmfeq | Vmfne | Vmflt | Vmfle | Vmfgt | Vmfge
+                | Vfmerge | Vfmv
+                | Vfcvtxuf | Vfcvtxf | Vfcvtfxu | Vfcvtfx | Vfcvtrtzxuf | Vfcvtrtzxf
+                | Vfwcvtxuf | Vfwcvtxf | Vfwcvtfxu | Vfwcvtfx | Vfwcvtff
+                | Vfwcvtrtzxuf | Vfwcvtrtzxf
+                | Vfncvtxuf | Vfncvtxf | Vfncvtfxu | Vfncvtfx | Vfncvtff | Vfncvtrodff
+                | Vfncvtrtzxuf | Vfncvtrtzxf
+        )
+    }
+
+    /// Operations whose destination is a mask register, or a scalar
+    /// register, rather than an ordinary vector register group.
+    #[rustfmt::skip]
+    fn mask_or_scalar_dest(self) -> bool {
+        use VOp::*;
+
+        matches!(
+            self,
+            Vmadc | Vmsbc | Vmseq | Vmsne | Vmsltu | Vmslt | Vmsleu | Vmsle | Vmsgtu | Vmsgt
+                | Vmfeq | Vmfne | Vmflt | Vmfle | Vmfgt | Vmfge
+                | Vcpop | Vfirst | Vmvxs | Vfmvfs
+        )
+    }
+}
+
+/// State an element operation may read or update.
+struct Env {
+    /// Index and `v0` mask bit of the element being processed.
+    index: u64,
+    mask: bool,
+    vxrm: u64,
+    vxsat: bool,
+    /// Rounding mode of floating-point operations, from `frm`.
+    rm: u64,
+    flags: u64,
+}
+
+impl Env {
+    /// Shifts `v` right by `d` bits, rounding as selected by `vxrm`.
+    fn roundoff(&self, v: i128, d: u64) -> i128 {
+        if d == 0 {
+            return v;
+        }
+
+        let bit = |n: u64| (v >> n) & 1;
+        let below = |n: u64| v & ((1 << n) - 1) != 0;
+        let round = match self.vxrm {
+            VXRM_RNU => bit(d - 1),
+            VXRM_RNE => bit(d - 1) & (below(d - 1) as i128 | bit(d)),
+            VXRM_RDN => 0,
+            _ => (bit(d) == 0 && below(d)) as i128,
+        };
+
+        (v >> d) + round
+    }
+
+    fn clamp_signed(&mut self, v: i128, bits: u64) -> u64 {
+        let max = (1i128 << (bits - 1)) - 1;
+        let min = -max - 1;
+        if v > max || v < min {
+            self.vxsat = true;
+        }
+
+        v.clamp(min, max) as u64
+    }
+
+    fn clamp_unsigned(&mut self, v: i128, bits: u64) -> u64 {
+        let max = (1i128 << bits) - 1;
+        if v > max || v < 0 {
+            self.vxsat = true;
+        }
+
+        v.clamp(0, max) as u64
+    }
+}
+
+/// Narrows to single precision rounding to odd, so that a later rounding
+/// to a narrower format can't round twice.
+fn round_to_odd(a: u64, flags: &mut u64) -> u64 {
+    let mut inexact = 0;
+    let val = float::convert(F64, F32, a, RM_RTZ, &mut inexact);
+    *flags |= inexact;
+
+    if inexact & FLAG_NX != 0 {
+        val | 1
+    } else {
+        val
+    }
+}
+
+impl Cpu {
+    /// Executes a V extension instruction.
+    pub(crate) fn execute_vector(
+        &mut self,
+        bus: &mut Bus,
+        inst: Inst,
+        raw: u32,
+    ) -> Result<(), Exception> {
+        let result = match inst {
+            Inst::Vsetvli { rd, rs1, vtypei } => {
+                let avl = self.avl(rd, rs1);
+                self.set_vtype(rd, avl, vtypei);
+                Ok(())
+            }
+            Inst::Vsetivli { rd, uimm, vtypei } => {
+                self.set_vtype(rd, uimm, vtypei);
+                Ok(())
+            }
+            Inst::Vsetvl { rd, rs1, rs2 } => {
+                let avl = self.avl(rd, rs1);
+                self.set_vtype(rd, avl, self.x[rs2]);
+                Ok(())
+            }
+            Inst::Vload {
+                vd,
+                rs1,
+                addressing,
+                eew,
+                nf,
+                vm,
+            } => self.vector_access(bus, vd, rs1, addressing, eew, nf, vm, false),
+            Inst::Vstore {
+                vs3,
+                rs1,
+                addressing,
+                eew,
+                nf,
+                vm,
+            } => self.vector_access(bus, vs3, rs1, addressing, eew, nf, vm, true),
+            Inst::Varith {
+                op,
+                vd,
+                vs2,
+                src,
+                vm,
+            } => self.vector_arith(op, vd, vs2, src, vm),
+            _ => unreachable!(),
+        };
+
+        match result {
+            Err(Exception::IllegalInstruction(_)) => Err(Exception::IllegalInstruction(raw as u64)),
+            result => {
+                // Faulting accesses leave the element index in vstart, so
+                // they modify vector state too.
+                if result.is_ok() {
+                    self.csr[VSTART] = 0;
+                }
+                self.dirty_vs();
+                result
+            }
+        }
+    }
+
+    /// Application vector length requested by `vsetvl`/`vsetvli`: x[rs1],
+    /// VLMAX when rs1 is x0, or the current `vl` when rd is x0 too.
+    fn avl(&self, rd: usize, rs1: usize) -> u64 {
+        match (rd, rs1) {
+            (0, 0) => self.csr[VL],
+            (_, 0) => u64::MAX,
+            _ => self.x[rs1],
+        }
+    }
+
+    fn set_vtype(&mut self, rd: usize, avl: u64, vtype: u64) {
+        match VType::decode(vtype) {
+            Some(vt) => {
+                self.csr[VTYPE] = vtype;
+                self.csr[VL] = avl.min(vt.vlmax(self.v.vlen()));
+            }
+            None => {
+                self.csr[VTYPE] = MASK_VTYPE_VILL;
+                self.csr[VL] = 0;
+            }
+        }
+
+        self.x[rd] = self.csr[VL];
+    }
+
+    /// The current `vtype`. Instructions that depend on it are illegal while
+    /// `vill` is set.
+    fn vtype(&self) -> Result<VType, Exception> {
+        VType::decode(self.csr[VTYPE]).ok_or(ILLEGAL)
+    }
+
+    fn env(&self) -> Env {
+        Env {
+            index: 0,
+            mask: false,
+            vxrm: (self.csr[VCSR] & MASK_VXRM) >> 1,
+            vxsat: false,
+            rm: self.csr.read(FRM),
+            flags: 0,
+        }
+    }
+
+    /// Commits the saturation and floating-point flags raised by an
+    /// instruction.
+    fn finish(&mut self, env: Env) {
+        if env.vxsat {
+            self.csr[VCSR] |= MASK_VXSAT;
+        }
+        self.accrue_fflags(env.flags);
+    }
+
+    /// Writes ones to elements `from..to` of a group if agnostic elements
+    /// are filled.
+    fn fill_agnostic(&mut self, vd: usize, eew: u64, from: u64, to: u64) {
+        if self.v.agnostic_ones {
+            for i in from..to {
+                self.v.write(vd, i, eew, u64::MAX);
+            }
+        }
+    }
+
+    /// Fills the tail of a mask register, which is always agnostic.
+    fn fill_mask_tail(&mut self, vd: usize, from: u64) {
+        if self.v.agnostic_ones {
+            for i in from..self.v.vlen() {
+                self.v.set_mask_bit(vd, i, true);
+            }
+        }
+    }
+
+    /// Reads the scalar operand of a .vx, .vf or .vi form as an `eew`-bit
+    /// element, or None for .vv forms.
+    fn scalar(&self, src: VSrc, eew: u64) -> Result<Option<u64>, Exception> {
+        let val = match src {
+            VSrc::V(_) => return Ok(None),
+            VSrc::X(rs1) => self.x[rs1],
+            VSrc::I(imm) => imm as u64,
+            VSrc::F(rs1) => self.read_fp(fp_format(eew)?, rs1),
+        };
+
+        Ok(Some(if eew == 64 {
+            val
+        } else {
+            val & ((1 << eew) - 1)
+        }))
+    }
+
+    /// Runs `op(env, vs2[i], src[i], vd[i])` over the body elements and
+    /// writes the results to `vd`. `eew` gives the element widths of vd,
+    /// vs2 and the source operand.
+    #[allow(clippy::too_many_arguments)]
+    fn elementwise(
+        &mut self,
+        vt: VType,
+        vd: usize,
+        vs2: usize,
+        src: VSrc,
+        vm: bool,
+        eew: [u64; 3],
+        mut op: impl FnMut(&mut Env, u64, u64, u64) -> u64,
+    ) -> Result<(), Exception> {
+        let [d_eew, a_eew, b_eew] = eew;
+        let (d_emul, a_emul, b_emul) = (vt.emul(d_eew)?, vt.emul(a_eew)?, vt.emul(b_eew)?);
+
+        check_group(vd, d_emul)?;
+        check_group(vs2, a_emul)?;
+        check_overlap(vd, d_eew, d_emul, vs2, a_eew, a_emul)?;
+        if let VSrc::V(vs1) = src {
+            check_group(vs1, b_emul)?;
+            check_overlap(vd, d_eew, d_emul, vs1, b_eew, b_emul)?;
+        }
+
+        let scalar = self.scalar(src, b_eew)?;
+        let vl = self.csr[VL];
+        let mut env = self.env();
+
+        for i in self.csr[VSTART]..vl {
+            env.index = i;
+            env.mask = self.v.mask_bit(0, i);
+            if !vm && !env.mask {
+                if vt.ma {
+                    self.fill_agnostic(vd, d_eew, i, i + 1);
+                }
+                continue;
+            }
+
+            let a = self.v.read(vs2, i, a_eew);
+            let b = match (scalar, src) {
+                (Some(val), _) => val,
+                (None, VSrc::V(vs1)) => self.v.read(vs1, i, b_eew),
+                _ => unreachable!(),
+            };
+            let d = self.v.read(vd, i, d_eew);
+            let result = op(&mut env, a, b, d);
+            self.v.write(vd, i, d_eew, result);
+        }
+
+        if vt.ta {
+            let end = group_elems(self.v.vlen(), d_eew, d_emul.max(0));
+            self.fill_agnostic(vd, d_eew, vl, end);
+        }
+        self.finish(env);
+
+        Ok(())
+    }
+
+    /// Sets mask bit `i` of `vd` to `op(env, vs2[i], src[i])` for the body
+    /// elements, as the compare instructions and `vmadc`/`vmsbc` do.
+    fn compare(
+        &mut self,
+        vt: VType,
+        vd: usize,
+        vs2: usize,
+        src: VSrc,
+        vm: bool,
+        mut op: impl FnMut(&mut Env, u64, u64) -> bool,
+    ) -> Result<(), Exception> {
+        let sew = vt.sew;
+        check_group(vs2, vt.lmul)?;
+        check_overlap(vd, 1, 0, vs2, sew, vt.lmul)?;
+        if let VSrc::V(vs1) = src {
+            check_group(vs1, vt.lmul)?;
+            check_overlap(vd, 1, 0, vs1, sew, vt.lmul)?;
+        }
+
+        let scalar = self.scalar(src, sew)?;
+        let vl = self.csr[VL];
+        let mut env = self.env();
+
+        for i in self.csr[VSTART]..vl {
+            env.index = i;
+            env.mask = self.v.mask_bit(0, i);
+            if !vm && !env.mask {
+                if vt.ma && self.v.agnostic_ones {
+                    self.v.set_mask_bit(vd, i, true);
+                }
+                continue;
+            }
+
+            let a = self.v.read(vs2, i, sew);
+            let b = match (scalar, src) {
+                (Some(val), _) => val,
+                (None, VSrc::V(vs1)) => self.v.read(vs1, i, sew),
+                _ => unreachable!(),
+            };
+            let bit = op(&mut env, a, b);
+            self.v.set_mask_bit(vd, i, bit);
+        }
+
+        self.fill_mask_tail(vd, vl);
+        self.finish(env);
+
+        Ok(())
+    }
+
+    /// Folds the active elements of vs2 into element 0 of vs1 with `op`,
+    /// writing the result to element 0 of vd. Widening reductions
+    /// accumulate at 2*SEW.
+    #[allow(clippy::too_many_arguments)]
+    fn reduce(
+        &mut self,
+        vt: VType,
+        vd: usize,
+        vs2: usize,
+        vs1: usize,
+        vm: bool,
+        widen: bool,
+        mut op: impl FnMut(&mut Env, u64, u64) -> u64,
+    ) -> Result<(), Exception> {
+        let sew = vt.sew;
+        let acc_eew = if widen { sew * 2 } else { sew };
+        if self.csr[VSTART] != 0 || acc_eew > ELEN {
+            return Err(ILLEGAL);
+        }
+        check_group(vs2, vt.lmul)?;
+
+        let vl = self.csr[VL];
+        if vl == 0 {
+            return Ok(());
+        }
+
+        let mut env = self.env();
+        let mut acc = self.v.read(vs1, 0, acc_eew);
+        for i in 0..vl {
+            if vm || self.v.mask_bit(0, i) {
+                acc = op(&mut env, acc, self.v.read(vs2, i, sew));
+            }
+        }
+
+        self.v.write(vd, 0, acc_eew, acc);
+        if vt.ta {
+            let end = self.v.vlen() / acc_eew;
+            self.fill_agnostic(vd, acc_eew, 1, end);
+        }
+        self.finish(env);
+
+        Ok(())
+    }
+
+    /// Sets each body bit of vd to `op(vs2[i], vs1[i])`.
+    fn mask_logical(
+        &mut self,
+        vd: usize,
+        vs2: usize,
+        vs1: usize,
+        op: impl Fn(bool, bool) -> bool,
+    ) -> Result<(), Exception> {
+        let vl = self.csr[VL];
+        for i in self.csr[VSTART]..vl {
+            let bit = op(self.v.mask_bit(vs2, i), self.v.mask_bit(vs1, i));
+            self.v.set_mask_bit(vd, i, bit);
+        }
+        self.fill_mask_tail(vd, vl);
+
+        Ok(())
+    }
+
+    /// Writes `value(regs, i)` to each active body element of vd, reading
+    /// every source element before writing any. Elements for which it
+    /// returns None are left alone, masked or not.
+    fn permute(
+        &mut self,
+        vt: VType,
+        vd: usize,
+        vm: bool,
+        value: impl Fn(&VectorRegs, u64) -> Option<u64>,
+    ) -> Result<(), Exception> {
+        let sew = vt.sew;
+        check_group(vd, vt.lmul)?;
+
+        let vl = self.csr[VL];
+        let start = self.csr[VSTART];
+        let values: Vec<_> = (start..vl).map(|i| value(&self.v, i)).collect();
+
+        for (i, val) in (start..vl).zip(values) {
+            let Some(val) = val else { continue };
+            if vm || self.v.mask_bit(0, i) {
+                self.v.write(vd, i, sew, val);
+            } else if vt.ma {
+                self.fill_agnostic(vd, sew, i, i + 1);
+            }
+        }
+
+        if vt.ta {
+            let end = group_elems(self.v.vlen(), sew, vt.lmul.max(0));
+            self.fill_agnostic(vd, sew, vl, end);
+        }
+
+        Ok(())
+    }
+
+    fn vector_arith(
+        &mut self,
+        op: VOp,
+        vd: usize,
+        vs2: usize,
+        src: VSrc,
+        vm: bool,
+    ) -> Result<(), Exception> {
+        use VOp::*;
+
+        // Whole-register moves are the only arithmetic that ignores vtype.
+        if let VmvNr(nr) = op {
+            let nr = nr as usize;
+            if !vd.is_multiple_of(nr) || !vs2.is_multiple_of(nr) {
+                return Err(ILLEGAL);
+            }
+
+            let eew = self.vtype().map_or(8, |vt| vt.sew);
+            let evl = nr as u64 * self.v.vlen() / eew;
+            for i in self.csr[VSTART]..evl {
+                let val = self.v.read(vs2, i, eew);
+                self.v.write(vd, i, eew, val);
+            }
+            return Ok(());
+        }
+
+        let vt = self.vtype()?;
+        if op.is_fp() {
+            self.rounding_mode(RM_DYN, 0)?;
+        }
+        // Masked instructions can't overwrite the mask they're reading.
+        if !vm && vd == 0 && !op.mask_or_scalar_dest() {
+            return Err(ILLEGAL);
+        }
+
+        let s = vt.sew;
+        let w = s * 2;
+        let vs1 = match src {
+            VSrc::V(vs1) => vs1,
+            _ => 0,
+        };
+        // Unary operations take no second operand.
+        let none = VSrc::I(0);
+        let ss = [s, s, s];
+        let wide = [w, s, s];
+        let wide_w = [w, w, s];
+        let narrow = [s, w, s];
+        let vstart = self.csr[VSTART];
+        let vl = self.csr[VL];
+
+        match op {
+            Vadd => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| a.wrapping_add(b)),
+            Vsub => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| a.wrapping_sub(b)),
+            Vrsub => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| b.wrapping_sub(a)),
+            Vminu => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| a.min(b)),
+            Vmaxu => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| a.max(b)),
+            Vmin => self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                sext(a, s).min(sext(b, s)) as u64
+            }),
+            Vmax => self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                sext(a, s).max(sext(b, s)) as u64
+            }),
+            Vand => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| a & b),
+            Vor => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| a | b),
+            Vxor => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| a ^ b),
+            Vsll => self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                a << (b & (s - 1))
+            }),
+            Vsrl => self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                a >> (b & (s - 1))
+            }),
+            Vsra => self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                (sext(a, s) >> (b & (s - 1))) as u64
+            }),
+            Vnsrl => self.elementwise(vt, vd, vs2, src, vm, narrow, move |_, a, b, _| {
+                a >> (b & (w - 1))
+            }),
+            Vnsra => self.elementwise(vt, vd, vs2, src, vm, narrow, move |_, a, b, _| {
+                (sext(a, w) >> (b & (w - 1))) as u64
+            }),
+
+            // The carry and borrow in come from v0 rather than masking.
+            Vadc => self.elementwise(vt, vd, vs2, src, true, ss, |e, a, b, _| {
+                a.wrapping_add(b).wrapping_add(e.mask as u64)
+            }),
+            Vsbc => self.elementwise(vt, vd, vs2, src, true, ss, |e, a, b, _| {
+                a.wrapping_sub(b).wrapping_sub(e.mask as u64)
+            }),
+            Vmadc => self.compare(vt, vd, vs2, src, true, move |e, a, b| {
+                let carry = (!vm && e.mask) as u128;
+                (a as u128 + b as u128 + carry) >> s != 0
+            }),
+            Vmsbc => self.compare(vt, vd, vs2, src, true, move |e, a, b| {
+                let borrow = (!vm && e.mask) as u128;
+                (a as u128) < b as u128 + borrow
+            }),
+
+            Vmseq => self.compare(vt, vd, vs2, src, vm, |_, a, b| a == b),
+            Vmsne => self.compare(vt, vd, vs2, src, vm, |_, a, b| a != b),
+            Vmsltu => self.compare(vt, vd, vs2, src, vm, |_, a, b| a < b),
+            Vmsleu => self.compare(vt, vd, vs2, src, vm, |_, a, b| a <= b),
+            Vmsgtu => self.compare(vt, vd, vs2, src, vm, |_, a, b| a > b),
+            Vmslt => self.compare(vt, vd, vs2, src, vm, move |_, a, b| sext(a, s) < sext(b, s)),
+            Vmsle => self.compare(vt, vd, vs2, src, vm, move |_, a, b| {
+                sext(a, s) <= sext(b, s)
+            }),
+            Vmsgt => self.compare(vt, vd, vs2, src, vm, move |_, a, b| sext(a, s) > sext(b, s)),
+
+            Vmul => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| a.wrapping_mul(b)),
+            Vmulh => self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                ((sext(a, s) as i128 * sext(b, s) as i128) >> s) as u64
+            }),
+            Vmulhu => self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                ((a as u128 * b as u128) >> s) as u64
+            }),
+            Vmulhsu => self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                ((sext(a, s) as i128 * b as i128) >> s) as u64
+            }),
+            Vdivu => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| {
+                a.checked_div(b).unwrap_or(u64::MAX)
+            }),
+            Vremu => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, _| {
+                a.checked_rem(b).unwrap_or(a)
+            }),
+            Vdiv => self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                match sext(b, s) {
+                    0 => u64::MAX,
+                    b => sext(a, s).wrapping_div(b) as u64,
+                }
+            }),
+            Vrem => self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                match sext(b, s) {
+                    0 => a,
+                    b => sext(a, s).wrapping_rem(b) as u64,
+                }
+            }),
+            Vmacc => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, d| {
+                d.wrapping_add(b.wrapping_mul(a))
+            }),
+            Vnmsac => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, d| {
+                d.wrapping_sub(b.wrapping_mul(a))
+            }),
+            Vmadd => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, d| {
+                b.wrapping_mul(d).wrapping_add(a)
+            }),
+            Vnmsub => self.elementwise(vt, vd, vs2, src, vm, ss, |_, a, b, d| {
+                a.wrapping_sub(b.wrapping_mul(d))
+            }),
+
+            Vwaddu => self.elementwise(vt, vd, vs2, src, vm, wide, |_, a, b, _| a + b),
+            Vwsubu => self.elementwise(vt, vd, vs2, src, vm, wide, |_, a, b, _| a.wrapping_sub(b)),
+            Vwadd => self.elementwise(vt, vd, vs2, src, vm, wide, move |_, a, b, _| {
+                (sext(a, s) + sext(b, s)) as u64
+            }),
+            Vwsub => self.elementwise(vt, vd, vs2, src, vm, wide, move |_, a, b, _| {
+                (sext(a, s) - sext(b, s)) as u64
+            }),
+            Vwadduw => {
+                self.elementwise(vt, vd, vs2, src, vm, wide_w, |_, a, b, _| a.wrapping_add(b))
+            }
+            Vwsubuw => {
+                self.elementwise(vt, vd, vs2, src, vm, wide_w, |_, a, b, _| a.wrapping_sub(b))
+            }
+            Vwaddw => self.elementwise(vt, vd, vs2, src, vm, wide_w, move |_, a, b, _| {
+                a.wrapping_add(sext(b, s) as u64)
+            }),
+            Vwsubw => self.elementwise(vt, vd, vs2, src, vm, wide_w, move |_, a, b, _| {
+                a.wrapping_sub(sext(b, s) as u64)
+            }),
+            Vwmulu => self.elementwise(vt, vd, vs2, src, vm, wide, |_, a, b, _| a * b),
+            Vwmul => self.elementwise(vt, vd, vs2, src, vm, wide, move |_, a, b, _| {
+                (sext(a, s) * sext(b, s)) as u64
+            }),
+            Vwmulsu => self.elementwise(vt, vd, vs2, src, vm, wide, move |_, a, b, _| {
+                sext(a, s).wrapping_mul(b as i64) as u64
+            }),
+            Vwmaccu => self.elementwise(vt, vd, vs2, src, vm, wide, |_, a, b, d| {
+                d.wrapping_add(b * a)
+            }),
+            Vwmacc => self.elementwise(vt, vd, vs2, src, vm, wide, move |_, a, b, d| {
+                d.wrapping_add((sext(b, s) * sext(a, s)) as u64)
+            }),
+            Vwmaccsu => self.elementwise(vt, vd, vs2, src, vm, wide, move |_, a, b, d| {
+                d.wrapping_add(sext(b, s).wrapping_mul(a as i64) as u64)
+            }),
+            Vwmaccus => self.elementwise(vt, vd, vs2, src, vm, wide, move |_, a, b, d| {
+                d.wrapping_add((b as i64).wrapping_mul(sext(a, s)) as u64)
+            }),
+            Vzext(f) => self.elementwise(vt, vd, vs2, none, vm, [s, s / f, s], |_, a, _, _| a),
+            Vsext(f) => {
+                self.elementwise(vt, vd, vs2, none, vm, [s, s / f, s], move |_, a, _, _| {
+                    sext(a, s / f) as u64
+                })
+            }
+            Vmerge | Vfmerge => {
+                self.elementwise(
+                    vt,
+                    vd,
+                    vs2,
+                    src,
+                    true,
+                    ss,
+                    |e, a, b, _| {
+                        if e.mask {
+                            b
+                        } else {
+                            a
+                        }
+                    },
+                )
+            }
+            Vmv | Vfmv => self.elementwise(vt, vd, 0, src, vm, ss, |_, _, b, _| b),
+
+            Vsaddu => self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                e.clamp_unsigned(a as i128 + b as i128, s)
+            }),
+            Vsadd => self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                e.clamp_signed(sext(a, s) as i128 + sext(b, s) as i128, s)
+            }),
+            Vssubu => self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                e.clamp_unsigned(a as i128 - b as i128, s)
+            }),
+            Vssub => self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                e.clamp_signed(sext(a, s) as i128 - sext(b, s) as i128, s)
+            }),
+            Vaaddu => self.elementwise(vt, vd, vs2, src, vm, ss, |e, a, b, _| {
+                e.roundoff(a as i128 + b as i128, 1) as u64
+            }),
+            Vasubu => self.elementwise(vt, vd, vs2, src, vm, ss, |e, a, b, _| {
+                e.roundoff(a as i128 - b as i128, 1) as u64
+            }),
+            Vaadd => self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                e.roundoff(sext(a, s) as i128 + sext(b, s) as i128, 1) as u64
+            }),
+            Vasub => self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                e.roundoff(sext(a, s) as i128 - sext(b, s) as i128, 1) as u64
+            }),
+            Vsmul => self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                let product = sext(a, s) as i128 * sext(b, s) as i128;
+                let shifted = e.roundoff(product, s - 1);
+                e.clamp_signed(shifted, s)
+            }),
+            Vssrl => self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                e.roundoff(a as i128, b & (s - 1)) as u64
+            }),
+            Vssra => self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                e.roundoff(sext(a, s) as i128, b & (s - 1)) as u64
+            }),
+            Vnclipu => self.elementwise(vt, vd, vs2, src, vm, narrow, move |e, a, b, _| {
+                let shifted = e.roundoff(a as i128, b & (w - 1));
+                e.clamp_unsigned(shifted, s)
+            }),
+            Vnclip => self.elementwise(vt, vd, vs2, src, vm, narrow, move |e, a, b, _| {
+                let shifted = e.roundoff(sext(a, w) as i128, b & (w - 1));
+                e.clamp_signed(shifted, s)
+            }),
+
+            Vredsum => self.reduce(vt, vd, vs2, vs1, vm, false, |_, acc, x| acc.wrapping_add(x)),
+            Vredand => self.reduce(vt, vd, vs2, vs1, vm, false, |_, acc, x| acc & x),
+            Vredor => self.reduce(vt, vd, vs2, vs1, vm, false, |_, acc, x| acc | x),
+            Vredxor => self.reduce(vt, vd, vs2, vs1, vm, false, |_, acc, x| acc ^ x),
+            Vredminu => self.reduce(vt, vd, vs2, vs1, vm, false, |_, acc, x| acc.min(x)),
+            Vredmaxu => self.reduce(vt, vd, vs2, vs1, vm, false, |_, acc, x| acc.max(x)),
+            Vredmin => self.reduce(vt, vd, vs2, vs1, vm, false, move |_, acc, x| {
+                sext(acc, s).min(sext(x, s)) as u64
+            }),
+            Vredmax => self.reduce(vt, vd, vs2, vs1, vm, false, move |_, acc, x| {
+                sext(acc, s).max(sext(x, s)) as u64
+            }),
+            Vwredsumu => self.reduce(vt, vd, vs2, vs1, vm, true, |_, acc, x| acc.wrapping_add(x)),
+            Vwredsum => self.reduce(vt, vd, vs2, vs1, vm, true, move |_, acc, x| {
+                acc.wrapping_add(sext(x, s) as u64)
+            }),
+            // Unordered sums are allowed to be computed in order.
+            Vfredusum | Vfredosum => {
+                let f = fp_format(s)?;
+                self.reduce(vt, vd, vs2, vs1, vm, false, move |e, acc, x| {
+                    float::add(f, acc, x, e.rm, &mut e.flags)
+                })
+            }
+            Vfredmin => {
+                let f = fp_format(s)?;
+                self.reduce(vt, vd, vs2, vs1, vm, false, move |e, acc, x| {
+                    float::min(f, acc, x, &mut e.flags)
+                })
+            }
+            Vfredmax => {
+                let f = fp_format(s)?;
+                self.reduce(vt, vd, vs2, vs1, vm, false, move |e, acc, x| {
+                    float::max(f, acc, x, &mut e.flags)
+                })
+            }
+            Vfwredusum | Vfwredosum => {
+                let f = fp_format(s)?;
+                let wf = fp_format(w)?;
+                self.reduce(vt, vd, vs2, vs1, vm, true, move |e, acc, x| {
+                    let x = float::convert(f, wf, x, e.rm, &mut e.flags);
+                    float::add(wf, acc, x, e.rm, &mut e.flags)
+                })
+            }
+
+            Vmandn => self.mask_logical(vd, vs2, vs1, |a, b| a && !b),
+            Vmand => self.mask_logical(vd, vs2, vs1, |a, b| a && b),
+            Vmor => self.mask_logical(vd, vs2, vs1, |a, b| a || b),
+            Vmxor => self.mask_logical(vd, vs2, vs1, |a, b| a != b),
+            Vmorn => self.mask_logical(vd, vs2, vs1, |a, b| a || !b),
+            Vmnand => self.mask_logical(vd, vs2, vs1, |a, b| !(a && b)),
+            Vmnor => self.mask_logical(vd, vs2, vs1, |a, b| !(a || b)),
+            Vmxnor => self.mask_logical(vd, vs2, vs1, |a, b| a == b),
+            Vcpop | Vfirst => {
+                if vstart != 0 {
+                    return Err(ILLEGAL);
+                }
+
+                let mut set =
+                    (0..vl).filter(|&i| (vm || self.v.mask_bit(0, i)) && self.v.mask_bit(vs2, i));
+                self.x[vd] = match op {
+                    Vcpop => set.count() as u64,
+                    _ => set.next().map_or(u64::MAX, |i| i),
+                };
+                Ok(())
+            }
+            Vmsbf | Vmsif | Vmsof => {
+                if vstart != 0 || vd == vs2 {
+                    return Err(ILLEGAL);
+                }
+
+                let mut found = false;
+                for i in 0..vl {
+                    if !vm && !self.v.mask_bit(0, i) {
+                        if vt.ma && self.v.agnostic_ones {
+                            self.v.set_mask_bit(vd, i, true);
+                        }
+                        continue;
+                    }
+
+                    let bit = self.v.mask_bit(vs2, i);
+                    let out = match op {
+                        Vmsbf => !found && !bit,
+                        Vmsif => !found,
+                        _ => !found && bit,
+                    };
+                    found |= bit;
+                    self.v.set_mask_bit(vd, i, out);
+                }
+                self.fill_mask_tail(vd, vl);
+                Ok(())
+            }
+            Viota => {
+                if vstart != 0 {
+                    return Err(ILLEGAL);
+                }
+                check_disjoint(vd, group_regs(vt.lmul), vs2, 1)?;
+
+                let bits: Vec<_> = (0..vl).map(|i| self.v.mask_bit(vs2, i)).collect();
+                let mut count = 0;
+                self.elementwise(vt, vd, vd, none, vm, ss, move |e, _, _, _| {
+                    let val = count;
+                    count += bits[e.index as usize] as u64;
+                    val
+                })
+            }
+            Vid => self.elementwise(vt, vd, vd, none, vm, ss, |e, _, _, _| e.index),
+
+            Vmvxs => {
+                self.x[vd] = sext(self.v.read(vs2, 0, s), s) as u64;
+                Ok(())
+            }
+            Vfmvfs => {
+                let f = fp_format(s)?;
+                let val = self.v.read(vs2, 0, s);
+                self.write_fp(f, vd, val);
+                Ok(())
+            }
+            Vmvsx | Vfmvsf => {
+                let val = self.scalar(src, s)?.unwrap();
+                if vstart < vl {
+                    self.v.write(vd, 0, s, val);
+                }
+                if vt.ta {
+                    let end = self.v.vlen() / s;
+                    self.fill_agnostic(vd, s, 1, end);
+                }
+                Ok(())
+            }
+            Vslideup | Vslidedown => {
+                let offset = match src {
+                    VSrc::X(rs1) => self.x[rs1],
+                    VSrc::I(imm) => imm as u64,
+                    _ => unreachable!(),
+                };
+                check_group(vs2, vt.lmul)?;
+                let vlmax = vt.vlmax(self.v.vlen());
+
+                if op == Vslideup {
+                    check_disjoint(vd, group_regs(vt.lmul), vs2, group_regs(vt.lmul))?;
+                    self.permute(vt, vd, vm, |v, i| {
+                        (i >= offset).then(|| v.read(vs2, i - offset, s))
+                    })
+                } else {
+                    self.permute(vt, vd, vm, |v, i| match i.checked_add(offset) {
+                        Some(j) if j < vlmax => Some(v.read(vs2, j, s)),
+                        _ => Some(0),
+                    })
+                }
+            }
+            Vslide1up | Vslide1down | Vfslide1up | Vfslide1down => {
+                let val = self.scalar(src, s)?.unwrap();
+                check_group(vs2, vt.lmul)?;
+
+                if matches!(op, Vslide1up | Vfslide1up) {
+                    check_disjoint(vd, group_regs(vt.lmul), vs2, group_regs(vt.lmul))?;
+                    self.permute(vt, vd, vm, |v, i| match i {
+                        0 => Some(val),
+                        _ => Some(v.read(vs2, i - 1, s)),
+                    })
+                } else {
+                    self.permute(vt, vd, vm, |v, i| match i + 1 {
+                        j if j < vl => Some(v.read(vs2, j, s)),
+                        _ => Some(val),
+                    })
+                }
+            }
+            Vrgather | Vrgatherei16 => {
+                let regs = group_regs(vt.lmul);
+                check_group(vs2, vt.lmul)?;
+                check_disjoint(vd, regs, vs2, regs)?;
+                let vlmax = vt.vlmax(self.v.vlen());
+
+                let index_eew = if op == Vrgatherei16 { 16 } else { s };
+                let index_emul = vt.emul(index_eew)?;
+                let scalar = match src {
+                    VSrc::V(vs1) => {
+                        check_group(vs1, index_emul)?;
+                        check_disjoint(vd, regs, vs1, group_regs(index_emul))?;
+                        None
+                    }
+                    VSrc::X(rs1) => Some(self.x[rs1]),
+                    VSrc::I(imm) => Some(imm as u64),
+                    VSrc::F(_) => unreachable!(),
+                };
+
+                self.permute(vt, vd, vm, |v, i| {
+                    let index = scalar.unwrap_or_else(|| v.read(vs1, i, index_eew));
+                    Some(if index < vlmax {
+                        v.read(vs2, index, s)
+                    } else {
+                        0
+                    })
+                })
+            }
+            Vcompress => {
+                if vstart != 0 {
+                    return Err(ILLEGAL);
+                }
+                let regs = group_regs(vt.lmul);
+                check_group(vd, vt.lmul)?;
+                check_group(vs2, vt.lmul)?;
+                check_disjoint(vd, regs, vs2, regs)?;
+                check_disjoint(vd, regs, vs1, 1)?;
+
+                let packed: Vec<_> = (0..vl)
+                    .filter(|&i| self.v.mask_bit(vs1, i))
+                    .map(|i| self.v.read(vs2, i, s))
+                    .collect();
+                for (i, &val) in packed.iter().enumerate() {
+                    self.v.write(vd, i as u64, s, val);
+                }
+
+                let end = group_elems(self.v.vlen(), s, vt.lmul.max(0));
+                self.fill_agnostic(vd, s, packed.len() as u64, end);
+                Ok(())
+            }
+            VmvNr(_) => unreachable!(),
+
+            Vfadd => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                    float::add(f, a, b, e.rm, &mut e.flags)
+                })
+            }
+            Vfsub => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                    float::sub(f, a, b, e.rm, &mut e.flags)
+                })
+            }
+            Vfrsub => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                    float::sub(f, b, a, e.rm, &mut e.flags)
+                })
+            }
+            Vfmul => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                    float::mul(f, a, b, e.rm, &mut e.flags)
+                })
+            }
+            Vfdiv => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                    float::div(f, a, b, e.rm, &mut e.flags)
+                })
+            }
+            Vfrdiv => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                    float::div(f, b, a, e.rm, &mut e.flags)
+                })
+            }
+            Vfwadd | Vfwsub | Vfwmul | Vfwaddw | Vfwsubw => {
+                let (f, wf) = (fp_format(s)?, fp_format(w)?);
+                let (eew, wide_a) = match op {
+                    Vfwaddw | Vfwsubw => (wide_w, true),
+                    _ => (wide, false),
+                };
+                self.elementwise(vt, vd, vs2, src, vm, eew, move |e, a, b, _| {
+                    let a = if wide_a {
+                        a
+                    } else {
+                        float::convert(f, wf, a, e.rm, &mut e.flags)
+                    };
+                    let b = float::convert(f, wf, b, e.rm, &mut e.flags);
+                    match op {
+                        Vfwadd | Vfwaddw => float::add(wf, a, b, e.rm, &mut e.flags),
+                        Vfwsub | Vfwsubw => float::sub(wf, a, b, e.rm, &mut e.flags),
+                        _ => float::mul(wf, a, b, e.rm, &mut e.flags),
+                    }
+                })
+            }
+            // The multiply-adds multiply vs1 (or the scalar) by vs2 or vd
+            // and add or subtract the remaining operand, negating products
+            // and addends by flipping their sign bits.
+            Vfmacc | Vfnmacc | Vfmsac | Vfnmsac | Vfmadd | Vfnmadd | Vfmsub | Vfnmsub => {
+                let f = fp_format(s)?;
+                let sign = f.sign_bit();
+                self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, d| {
+                    let (x, y, z) = match op {
+                        Vfmacc => (b, a, d),
+                        Vfnmacc => (b ^ sign, a, d ^ sign),
+                        Vfmsac => (b, a, d ^ sign),
+                        Vfnmsac => (b ^ sign, a, d),
+                        Vfmadd => (b, d, a),
+                        Vfnmadd => (b ^ sign, d, a ^ sign),
+                        Vfmsub => (b, d, a ^ sign),
+                        _ => (b ^ sign, d, a),
+                    };
+                    float::mul_add(f, x, y, z, e.rm, &mut e.flags)
+                })
+            }
+            Vfwmacc | Vfwnmacc | Vfwmsac | Vfwnmsac => {
+                let (f, wf) = (fp_format(s)?, fp_format(w)?);
+                let sign = wf.sign_bit();
+                self.elementwise(vt, vd, vs2, src, vm, wide, move |e, a, b, d| {
+                    let a = float::convert(f, wf, a, e.rm, &mut e.flags);
+                    let b = float::convert(f, wf, b, e.rm, &mut e.flags);
+                    let (x, z) = match op {
+                        Vfwmacc => (b, d),
+                        Vfwnmacc => (b ^ sign, d ^ sign),
+                        Vfwmsac => (b, d ^ sign),
+                        _ => (b ^ sign, d),
+                    };
+                    float::mul_add(wf, x, a, z, e.rm, &mut e.flags)
+                })
+            }
+            Vfsqrt => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, none, vm, ss, move |e, a, _, _| {
+                    float::sqrt(f, a, e.rm, &mut e.flags)
+                })
+            }
+            Vfrsqrt7 => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, none, vm, ss, move |e, a, _, _| {
+                    float::rsqrt7(f, a, &mut e.flags)
+                })
+            }
+            Vfrec7 => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, none, vm, ss, move |e, a, _, _| {
+                    float::rec7(f, a, e.rm, &mut e.flags)
+                })
+            }
+            Vfclass => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, none, vm, ss, move |_, a, _, _| {
+                    float::classify(f, a)
+                })
+            }
+            Vfmin => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                    float::min(f, a, b, &mut e.flags)
+                })
+            }
+            Vfmax => {
+                let f = fp_format(s)?;
+                self.elementwise(vt, vd, vs2, src, vm, ss, move |e, a, b, _| {
+                    float::max(f, a, b, &mut e.flags)
+                })
+            }
+            Vfsgnj | Vfsgnjn | Vfsgnjx => {
+                let sign = fp_format(s)?.sign_bit();
+                self.elementwise(vt, vd, vs2, src, vm, ss, move |_, a, b, _| {
+                    let b = match op {
+                        Vfsgnj => b,
+                        Vfsgnjn => !b,
+                        _ => a ^ b,
+                    };
+                    (a & !sign) | (b & sign)
+                })
+            }
+            Vmfeq | Vmfne | Vmflt | Vmfle | Vmfgt | Vmfge => {
+                let f = fp_format(s)?;
+                self.compare(vt, vd, vs2, src, vm, move |e, a, b| match op {
+                    Vmfeq => float::eq(f, a, b, &mut e.flags),
+                    Vmfne => !float::eq(f, a, b, &mut e.flags),
+                    Vmflt => float::lt(f, a, b, &mut e.flags),
+                    Vmfle => float::le(f, a, b, &mut e.flags),
+                    Vmfgt => float::lt(f, b, a, &mut e.flags),
+                    _ => float::le(f, b, a, &mut e.flags),
+                })
+            }
+
+            Vfcvtxuf | Vfcvtxf | Vfcvtrtzxuf | Vfcvtrtzxf => {
+                let f = fp_format(s)?;
+                let signed = matches!(op, Vfcvtxf | Vfcvtrtzxf);
+                let rtz = matches!(op, Vfcvtrtzxuf | Vfcvtrtzxf);
+                self.elementwise(vt, vd, vs2, none, vm, ss, move |e, a, _, _| {
+                    let rm = if rtz { RM_RTZ } else { e.rm };
+                    float::to_int(f, a, s as u32, signed, rm, &mut e.flags)
+                })
+            }
+            Vfcvtfxu | Vfcvtfx => {
+                let f = fp_format(s)?;
+                let signed = op == Vfcvtfx;
+                self.elementwise(vt, vd, vs2, none, vm, ss, move |e, a, _, _| {
+                    let a = if signed { sext(a, s) as u64 } else { a };
+                    float::from_int(f, a, 64, signed, e.rm, &mut e.flags)
+                })
+            }
+            Vfwcvtxuf | Vfwcvtxf | Vfwcvtrtzxuf | Vfwcvtrtzxf => {
+                let f = fp_format(s)?;
+                let signed = matches!(op, Vfwcvtxf | Vfwcvtrtzxf);
+                let rtz = matches!(op, Vfwcvtrtzxuf | Vfwcvtrtzxf);
+                self.elementwise(vt, vd, vs2, none, vm, wide, move |e, a, _, _| {
+                    let rm = if rtz { RM_RTZ } else { e.rm };
+                    float::to_int(f, a, w as u32, signed, rm, &mut e.flags)
+                })
+            }
+            Vfwcvtfxu | Vfwcvtfx => {
+                let wf = fp_format(w)?;
+                let signed = op == Vfwcvtfx;
+                self.elementwise(vt, vd, vs2, none, vm, wide, move |e, a, _, _| {
+                    let a = if signed { sext(a, s) as u64 } else { a };
+                    float::from_int(wf, a, 64, signed, e.rm, &mut e.flags)
+                })
+            }
+            Vfwcvtff => {
+                let (f, wf) = (fp_format(s)?, fp_format(w)?);
+                self.elementwise(vt, vd, vs2, none, vm, wide, move |e, a, _, _| {
+                    float::convert(f, wf, a, e.rm, &mut e.flags)
+                })
+            }
+            Vfncvtxuf | Vfncvtxf | Vfncvtrtzxuf | Vfncvtrtzxf => {
+                let wf = fp_format(w)?;
+                let signed = matches!(op, Vfncvtxf | Vfncvtrtzxf);
+                let rtz = matches!(op, Vfncvtrtzxuf | Vfncvtrtzxf);
+                self.elementwise(vt, vd, vs2, none, vm, narrow, move |e, a, _, _| {
+                    let rm = if rtz { RM_RTZ } else { e.rm };
+                    float::to_int(wf, a, s as u32, signed, rm, &mut e.flags)
+                })
+            }
+            Vfncvtfxu | Vfncvtfx => {
+                let f = fp_format(s)?;
+                let signed = op == Vfncvtfx;
+                self.elementwise(vt, vd, vs2, none, vm, narrow, move |e, a, _, _| {
+                    let a = if signed { sext(a, w) as u64 } else { a };
+                    float::from_int(f, a, 64, signed, e.rm, &mut e.flags)
+                })
+            }
+            Vfncvtff | Vfncvtrodff => {
+                let (f, wf) = (fp_format(s)?, fp_format(w)?);
+                self.elementwise(vt, vd, vs2, none, vm, narrow, move |e, a, _, _| {
+                    if op == Vfncvtrodff {
+                        round_to_odd(a, &mut e.flags)
+                    } else {
+                        float::convert(wf, f, a, e.rm, &mut e.flags)
+                    }
+                })
+            }
+        }
+    }
+
+    /// Executes a vector load (or store, with `reg` as vs3).
+    #[allow(clippy::too_many_arguments)]
+    fn vector_access(
+        &mut self,
+        bus: &mut Bus,
+        reg: usize,
+        rs1: usize,
+        addressing: VAddressing,
+        eew: u64,
+        nf: u64,
+        vm: bool,
+        store: bool,
+    ) -> Result<(), Exception> {
+        let base = self.x[rs1];
+        let vstart = self.csr[VSTART];
+
+        // Whole registers and masks move bytes without regard to masking.
+        match addressing {
+            VAddressing::WholeRegister => {
+                let evl = nf * self.v.vlen() / eew;
+                if !reg.is_multiple_of(nf as usize) {
+                    return Err(ILLEGAL);
+                }
+                return self.access_elements(bus, reg, store, eew, vstart..evl, |i| {
+                    base.wrapping_add(i * eew / 8)
+                });
+            }
+            VAddressing::Mask => {
+                self.vtype()?;
+                let evl = self.csr[VL].div_ceil(8);
+                self.access_elements(bus, reg, store, 8, vstart..evl, |i| base.wrapping_add(i))?;
+                if !store {
+                    self.fill_agnostic(reg, 8, evl, self.v.vlenb());
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let vt = self.vtype()?;
+        // Indexed accesses use eew for the indices and SEW for the data.
+        let (data_eew, index) = match addressing {
+            VAddressing::Indexed { vs2 } => {
+                let index_emul = vt.emul(eew)?;
+                check_group(vs2, index_emul)?;
+                (vt.sew, Some((vs2, index_emul)))
+            }
+            _ => (eew, None),
+        };
+        let emul = vt.emul(data_eew)?;
+        let regs = group_regs(emul);
+        if regs * nf as usize > 8 || reg + regs * nf as usize > 32 {
+            return Err(ILLEGAL);
+        }
+        check_group(reg, emul)?;
+        if !vm && reg == 0 && !store {
+            return Err(ILLEGAL);
+        }
+        if let (Some((vs2, index_emul)), false) = (index, store) {
+            for field in 0..nf as usize {
+                let vd = reg + field * regs;
+                if nf > 1 {
+                    check_disjoint(vd, regs, vs2, group_regs(index_emul))?;
+                }
+                check_overlap(vd, data_eew, emul, vs2, eew, index_emul)?;
+            }
+        }
+
+        let stride = match addressing {
+            VAddressing::Strided { rs2 } => self.x[rs2],
+            _ => nf * data_eew / 8,
+        };
+        let bytes = data_eew / 8;
+        let vl = self.csr[VL];
+
+        for i in vstart..vl {
+            if !vm && !self.v.mask_bit(0, i) {
+                if vt.ma && !store {
+                    for field in 0..nf as usize {
+                        self.fill_agnostic(reg + field * regs, data_eew, i, i + 1);
+                    }
+                }
+                continue;
+            }
+
+            let elem_base = match index {
+                Some((vs2, _)) => base.wrapping_add(self.v.read(vs2, i, eew)),
+                None => base.wrapping_add(i.wrapping_mul(stride)),
+            };
+
+            for field in 0..nf {
+                let vd = reg + field as usize * regs;
+                let addr = elem_base.wrapping_add(field * bytes);
+                let result = if store {
+                    let val = self.v.read(vd, i, data_eew);
+                    self.store(bus, addr, val, data_eew)
+                } else {
+                    self.load(bus, addr, data_eew)
+                        .map(|val| self.v.write(vd, i, data_eew, val))
+                };
+
+                if let Err(e) = result {
+                    // Fault-only-first loads only trap on element 0, and
+                    // otherwise shorten vl to the elements that loaded.
+                    if addressing == VAddressing::FaultOnlyFirst && i > 0 {
+                        self.csr[VL] = i;
+                        return Ok(());
+                    }
+                    self.csr[VSTART] = i;
+                    return Err(e);
+                }
+            }
+        }
+
+        if vt.ta && !store {
+            let end = group_elems(self.v.vlen(), data_eew, emul.max(0));
+            for field in 0..nf as usize {
+                self.fill_agnostic(reg + field * regs, data_eew, vl, end);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Loads or stores the `eew`-bit elements `range` of the group at
+    /// `reg`, at the addresses given by `addr`.
+    fn access_elements(
+        &mut self,
+        bus: &mut Bus,
+        reg: usize,
+        store: bool,
+        eew: u64,
+        range: core::ops::Range<u64>,
+        addr: impl Fn(u64) -> u64,
+    ) -> Result<(), Exception> {
+        for i in range {
+            let result = if store {
+                let val = self.v.read(reg, i, eew);
+                self.store(bus, addr(i), val, eew)
+            } else {
+                self.load(bus, addr(i), eew)
+                    .map(|val| self.v.write(reg, i, eew, val))
+            };
+
+            if let Err(e) = result {
+                self.csr[VSTART] = i;
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+}
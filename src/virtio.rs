@@ -1,7 +1,15 @@
-use crate::prelude::{Exception, MemIntf, VIRTIO_BASE};
+use core::mem::{offset_of, size_of};
+
+use crate::prelude::{
+    Exception, IrqLine, MemIntf, Trigger, VirtQUsedusedElem, VirtioBlkRequest, VirtqAvail,
+    VirtqDesc, VirtqUsed, DESC_NUM, RAM_BASE, VIRTIO_BASE,
+};
 
 const MAX_BLOCK_QUEUE: u32 = 1;
 
+// virtio interrupt request
+pub const VIRTIO_IRQ: usize = 1;
+
 pub const VIRTIO_MAGIC: u64 = VIRTIO_BASE + 0x000;
 // The version. 1 is legacy.
 pub const VIRTIO_VERSION: u64 = VIRTIO_BASE + 0x004;
@@ -37,13 +45,23 @@ pub const SECTOR_SIZE: u64 = 512;
 pub const VIRTIO_BLK_T_IN: u32 = 0;
 pub const VIRTIO_BLK_T_OUT: u32 = 1;
 
+// virtio block request status
+pub const VIRTIO_BLK_S_OK: u8 = 0;
+pub const VIRTIO_BLK_S_IOERR: u8 = 1;
+pub const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+// device status bits
+pub const MASK_VIRTIO_STATUS_NEEDS_RESET: u32 = 1 << 6;
+
 // virtqueue descriptor flags
 pub const VIRTQ_DESC_F_NEXT: u16 = 1;
 pub const VIRTQ_DESC_F_WRITE: u16 = 2;
 pub const VIRTQ_DESC_F_INDIRECT: u16 = 4;
 
 pub struct VirtioBlock<'a> {
-    id: u64,
+    /// How many requests of the available ring have been processed, which
+    /// is also the used ring's index.
+    last_avail: u16,
     driver_features: u32,
     page_size: u32,
     queue_sel: u32,
@@ -52,12 +70,13 @@ pub struct VirtioBlock<'a> {
     queue_notify: u32,
     status: u32,
     disk: &'a mut dyn MemIntf,
+    pub(crate) irq: IrqLine,
 }
 
 impl<'a> VirtioBlock<'a> {
     pub fn new(disk_image: &'a mut dyn MemIntf) -> Self {
         Self {
-            id: 0,
+            last_avail: 0,
             driver_features: 0,
             page_size: 0,
             queue_sel: 0,
@@ -66,22 +85,10 @@ impl<'a> VirtioBlock<'a> {
             queue_notify: MAX_BLOCK_QUEUE,
             status: 0,
             disk: disk_image,
+            irq: IrqLine::new(VIRTIO_IRQ, Trigger::Edge),
         }
     }
 
-    pub fn is_interrupting(&mut self) -> bool {
-        if self.queue_notify < MAX_BLOCK_QUEUE {
-            self.queue_notify = MAX_BLOCK_QUEUE;
-            return true;
-        }
-        false
-    }
-
-    pub fn get_new_id(&mut self) -> u64 {
-        self.id = self.id.wrapping_add(1);
-        self.id
-    }
-
     pub fn desc_addr(&self) -> u64 {
         self.queue_pfn as u64 * self.page_size as u64
     }
@@ -94,11 +101,190 @@ impl<'a> VirtioBlock<'a> {
     pub fn write_disk(&mut self, addr: u64, val: u64) {
         self.disk.store(addr, val, 8).unwrap();
     }
+
+    /// Carries out the requests made available since the last notify,
+    /// accessing guest memory through `ram`, and interrupts once they are
+    /// in the used ring. The bus calls it when the queue is notified.
+    ///
+    /// Requests whose buffers can't be accessed fail with an I/O error.
+    /// If the rings themselves can't be, the device stops and sets
+    /// DEVICE_NEEDS_RESET, as only the driver setting it up again helps.
+    pub fn process_queue(&mut self, ram: &mut dyn MemIntf) {
+        if self.queue_notify >= MAX_BLOCK_QUEUE || self.status & MASK_VIRTIO_STATUS_NEEDS_RESET != 0
+        {
+            return;
+        }
+
+        let first = self.last_avail;
+        if self.process_available(ram).is_err() {
+            self.status |= MASK_VIRTIO_STATUS_NEEDS_RESET;
+        }
+        if self.last_avail != first {
+            self.irq.raise();
+        }
+    }
+
+    fn process_available(&mut self, ram: &mut dyn MemIntf) -> Result<(), Exception> {
+        // The legacy layout: the descriptors, then the available ring, and
+        // the used ring from the next 4096-byte boundary.
+        let table = self.desc_addr();
+        let avail = table + (DESC_NUM * size_of::<VirtqDesc>()) as u64;
+        let used = (avail + size_of::<VirtqAvail>() as u64).next_multiple_of(PAGE_SIZE);
+
+        let avail_idx = dma_load(ram, avail + offset_of!(VirtqAvail, idx) as u64, 16)? as u16;
+        while self.last_avail != avail_idx {
+            let slot = (self.last_avail as usize % DESC_NUM) as u64;
+            let head = dma_load(
+                ram,
+                avail + offset_of!(VirtqAvail, ring) as u64 + 2 * slot,
+                16,
+            )?;
+            let len = self.process_request(ram, table, head)?;
+
+            let elem = used
+                + offset_of!(VirtqUsed, ring) as u64
+                + slot * size_of::<VirtQUsedusedElem>() as u64;
+            dma_store(
+                ram,
+                elem + offset_of!(VirtQUsedusedElem, id) as u64,
+                head,
+                32,
+            )?;
+            dma_store(
+                ram,
+                elem + offset_of!(VirtQUsedusedElem, len) as u64,
+                len,
+                32,
+            )?;
+            dma_store(
+                ram,
+                used + offset_of!(VirtqUsed, idx) as u64,
+                self.last_avail.wrapping_add(1) as u64,
+                16,
+            )?;
+            self.last_avail = self.last_avail.wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    /// Carries out the request whose descriptor chain starts at `head`: a
+    /// header, the data buffers, then the status byte. Returns how many
+    /// bytes were written to guest memory, or an error if the chain or
+    /// the status byte can't be accessed.
+    fn process_request(
+        &mut self,
+        ram: &mut dyn MemIntf,
+        table: u64,
+        head: u64,
+    ) -> Result<u64, Exception> {
+        let mut desc = load_desc(ram, table, head)?;
+        let request = load_request(ram, desc.addr);
+        let mut status = match &request {
+            Ok(request) if matches!(request.iotype, VIRTIO_BLK_T_IN | VIRTIO_BLK_T_OUT) => {
+                VIRTIO_BLK_S_OK
+            }
+            Ok(_) => VIRTIO_BLK_S_UNSUPP,
+            Err(_) => VIRTIO_BLK_S_IOERR,
+        };
+        let (iotype, mut offset) = request.map_or((0, 0), |request| {
+            (request.iotype, request.sector.saturating_mul(SECTOR_SIZE))
+        });
+
+        let mut written = 0;
+        // A chain can't be longer than the table, which also stops loops.
+        for _ in 1..DESC_NUM {
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                break;
+            }
+            desc = load_desc(ram, table, desc.next as u64)?;
+            if desc.flags & VIRTQ_DESC_F_NEXT == 0 {
+                dma_store(ram, desc.addr, status as u64, 8)?;
+                return Ok(written + 1);
+            }
+
+            if status == VIRTIO_BLK_S_OK && !self.transfer(ram, iotype, &desc, offset) {
+                status = VIRTIO_BLK_S_IOERR;
+            }
+            offset = offset.saturating_add(desc.len as u64);
+            if iotype == VIRTIO_BLK_T_IN {
+                written += desc.len as u64;
+            }
+        }
+
+        // There was no descriptor left for the status.
+        Ok(written)
+    }
+
+    /// Copies `buffer` from the disk at `offset`, or to it for writes.
+    /// Returns false if either side couldn't be accessed.
+    fn transfer(
+        &mut self,
+        ram: &mut dyn MemIntf,
+        iotype: u32,
+        buffer: &VirtqDesc,
+        offset: u64,
+    ) -> bool {
+        (0..buffer.len as u64).all(|i| {
+            let addr = buffer.addr.wrapping_add(i);
+            let offset = offset.saturating_add(i);
+            let done = if iotype == VIRTIO_BLK_T_IN {
+                self.disk
+                    .load(offset, 8)
+                    .and_then(|byte| dma_store(ram, addr, byte, 8))
+            } else {
+                dma_load(ram, addr, 8).and_then(|byte| self.disk.store(offset, byte, 8))
+            };
+            done.is_ok()
+        })
+    }
+}
+
+/// Loads from guest physical memory, given the RAM behind it.
+fn dma_load(ram: &mut dyn MemIntf, addr: u64, size: u64) -> Result<u64, Exception> {
+    match addr.checked_sub(RAM_BASE) {
+        Some(offset) => ram.load(offset, size),
+        None => Err(Exception::LoadAccessFault(addr)),
+    }
+}
+
+/// Stores to guest physical memory, given the RAM behind it.
+fn dma_store(ram: &mut dyn MemIntf, addr: u64, val: u64, size: u64) -> Result<(), Exception> {
+    match addr.checked_sub(RAM_BASE) {
+        Some(offset) => ram.store(offset, val, size),
+        None => Err(Exception::StoreAMOAccessFault(addr)),
+    }
+}
+
+/// The header of a block request at `addr`.
+fn load_request(ram: &mut dyn MemIntf, addr: u64) -> Result<VirtioBlkRequest, Exception> {
+    Ok(VirtioBlkRequest {
+        iotype: dma_load(ram, addr + offset_of!(VirtioBlkRequest, iotype) as u64, 32)? as u32,
+        reserved: 0,
+        sector: dma_load(ram, addr + offset_of!(VirtioBlkRequest, sector) as u64, 64)?,
+    })
+}
+
+/// Entry `index` of the descriptor table at `table`.
+fn load_desc(ram: &mut dyn MemIntf, table: u64, index: u64) -> Result<VirtqDesc, Exception> {
+    let addr = table + (index % DESC_NUM as u64) * size_of::<VirtqDesc>() as u64;
+    Ok(VirtqDesc {
+        addr: dma_load(ram, addr + offset_of!(VirtqDesc, addr) as u64, 64)?,
+        len: dma_load(ram, addr + offset_of!(VirtqDesc, len) as u64, 32)? as u32,
+        flags: dma_load(ram, addr + offset_of!(VirtqDesc, flags) as u64, 16)? as u16,
+        next: dma_load(ram, addr + offset_of!(VirtqDesc, next) as u64, 16)? as u16,
+    })
 }
 
 impl<'a> MemIntf for VirtioBlock<'a> {
     fn reset(&mut self) {
-        todo!()
+        self.last_avail = 0;
+        self.driver_features = 0;
+        self.page_size = 0;
+        self.queue_sel = 0;
+        self.queue_num = 0;
+        self.queue_pfn = 0;
+        self.queue_notify = MAX_BLOCK_QUEUE;
+        self.status = 0;
     }
 
     fn load(&mut self, addr: u64, size: u64) -> Result<u64, crate::prelude::Exception> {
@@ -113,8 +299,9 @@ impl<'a> MemIntf for VirtioBlock<'a> {
             VIRTIO_VENDOR_ID => Ok(0x554d4551),
             VIRTIO_DEVICE_FEATURES => Ok(0x0),
             VIRTIO_DRIVER_FEATURES => Ok(self.driver_features as u64),
-            VIRTIO_QUEUE_NUM_MAX => Ok(MAX_BLOCK_QUEUE as u64),
-            VIRTIO_QUEUE_PFN => Ok(self.status as u64),
+            VIRTIO_QUEUE_NUM_MAX => Ok(DESC_NUM as u64),
+            VIRTIO_QUEUE_PFN => Ok(self.queue_pfn as u64),
+            VIRTIO_STATUS => Ok(self.status as u64),
             _ => Ok(0),
         }
     }
@@ -131,8 +318,18 @@ impl<'a> MemIntf for VirtioBlock<'a> {
             VIRTIO_GUEST_PAGE_SIZE => Ok(self.page_size = value),
             VIRTIO_QUEUE_SEL => Ok(self.queue_sel = value),
             VIRTIO_QUEUE_NUM => Ok(self.queue_num = value),
-            VIRTIO_QUEUE_PFN => Ok(self.queue_pfn = value),
+            // A new queue starts again from the beginning of its rings.
+            VIRTIO_QUEUE_PFN => {
+                self.queue_pfn = value;
+                self.last_avail = 0;
+                Ok(())
+            }
+            // The bus then has the queue processed.
             VIRTIO_QUEUE_NOTIFY => Ok(self.queue_notify = value),
+            VIRTIO_STATUS if value == 0 => {
+                self.reset();
+                Ok(())
+            }
             VIRTIO_STATUS => Ok(self.status = value),
             _ => Ok(()),
         }
@@ -1,42 +1,134 @@
+use alloc::vec::Vec;
+
 use crate::{
     bus::{Bus, MemIntf, RAM_BASE},
+    clint::Clint,
     cpu::Cpu,
+    csrs::MHARTID,
     exceptions::Exception,
+    plic::Plic,
 };
 
 pub struct VM<'a> {
-    pub cpu: Cpu,
+    /// The harts, indexed by `mhartid`.
+    pub harts: Vec<Cpu>,
     pub bus: Bus<'a>,
+
+    /// How many ticks each hart runs for before the next one gets its turn.
+    /// Harts waiting in `wfi` give up the rest of theirs.
+    pub quantum: u64,
+
+    current: usize,
+    slice: u64,
 }
 
 impl<'a> VM<'a> {
     pub fn new(ram_intf: &'a mut dyn MemIntf, ram_len: u64, disk: &'a mut dyn MemIntf) -> Self {
-        let bus = Bus::new(ram_intf, ram_len, disk);
-        let mut cpu = Cpu::new();
+        Self::with_harts(ram_intf, ram_len, disk, 1)
+    }
+
+    /// A machine with `harts` harts sharing the bus, all starting at the
+    /// same state apart from `mhartid`.
+    pub fn with_harts(
+        ram_intf: &'a mut dyn MemIntf,
+        ram_len: u64,
+        disk: &'a mut dyn MemIntf,
+        harts: usize,
+    ) -> Self {
+        let mut bus = Bus::new(ram_intf, ram_len, disk);
+        bus.clint = Clint::new(harts);
+        bus.plic = Plic::new(harts);
+        bus.snoop = harts > 1;
+
+        let harts = (0..harts)
+            .map(|hartid| {
+                let mut cpu = Cpu::new();
+                cpu.csr[MHARTID] = hartid as u64;
+                cpu.x[2] = RAM_BASE + ram_len;
+                cpu
+            })
+            .collect();
+
+        VM {
+            bus,
+            harts,
+            quantum: 1,
+            current: 0,
+            slice: 0,
+        }
+    }
+
+    /// The hart the next tick runs.
+    pub fn current_hart(&self) -> usize {
+        self.current
+    }
 
-        cpu.x[2] = RAM_BASE + ram_len;
+    /// Whether every hart is waiting in `wfi` with no timer left to skip
+    /// ahead to. Ticking then only polls the UART, so embedders can instead
+    /// sleep until there is input or another interrupt to raise.
+    pub fn is_idle(&self) -> bool {
+        self.harts.iter().all(|hart| hart.waiting) && self.next_timer_deadline().is_none()
+    }
 
-        VM { bus, cpu }
+    fn next_timer_deadline(&self) -> Option<u64> {
+        self.harts
+            .iter()
+            .filter_map(|hart| hart.next_timer_deadline(&self.bus))
+            .min()
     }
 
+    /// Runs one tick of the current hart, then moves on to the next one
+    /// once its quantum is used up.
     pub fn tick(&mut self, char_in: Option<char>) -> Result<Option<char>, Exception> {
-        match self.cpu.tick(&mut self.bus) {
-            Ok(_inst) => {}
-            Err(e) => {
-                self.cpu.handle_exception(e);
-                if e.is_fatal() {
-                    return Err(e);
+        if self.harts.iter().all(|hart| hart.waiting) {
+            // Nothing happens until an interrupt, so jump straight to the next
+            // timer deadline instead of counting up to it.
+            if let Some(deadline) = self.next_timer_deadline() {
+                self.bus.clint.set_mtime(deadline);
+            }
+        }
+
+        let current = self.current;
+        let cpu = &mut self.harts[current];
+        let result = if cpu.waiting {
+            Ok(())
+        } else {
+            let result = cpu.tick(&mut self.bus).map(|_inst| ());
+            self.bus.clint.advance(1);
+            result
+        };
+
+        // Stores by this hart break the other harts' reservations.
+        for store in self.bus.stores.drain(..) {
+            for (hartid, hart) in self.harts.iter_mut().enumerate() {
+                if hartid != current {
+                    hart.invalidate_reservation(store.addr, store.size);
                 }
             }
         }
 
-        match self.cpu.check_pending_interrupt(&mut self.bus)? {
-            Some(int) => self.cpu.handle_interrupt(int),
-            None => (),
+        let cpu = &mut self.harts[current];
+        if let Err(e) = result {
+            cpu.handle_exception(e);
+            if e.is_fatal() {
+                return Err(e);
+            }
+        }
+
+        self.bus.update_interrupts();
+        if let Some(int) = cpu.check_pending_interrupt(&mut self.bus)? {
+            cpu.handle_interrupt(int);
+        }
+
+        let pc = cpu.pc;
+        self.slice += 1;
+        if self.slice >= self.quantum || cpu.waiting {
+            self.slice = 0;
+            self.current = (current + 1) % self.harts.len();
         }
 
-        if self.cpu.pc == 0 {
-            Err(Exception::Breakpoint(self.cpu.pc))
+        if pc == 0 {
+            Err(Exception::Breakpoint(pc))
         } else {
             Ok(self.bus.uart.tick(char_in))
         }
@@ -0,0 +1,129 @@
+mod common;
+
+fn setup(cpu: &mut rrv64g::prelude::Cpu) {
+	cpu.x[1] = 0x8000_0001_f00f_0080;
+	cpu.x[2] = 0xffff_0003;
+	cpu.x[3] = 36;
+}
+
+#[test]
+fn zba_zbb_arithmetic() {
+	let code = [
+		0x082082bb, // add.uw x5, x1, x2
+		0x2020a333, // sh1add x6, x1, x2
+		0x2020c3b3, // sh2add x7, x1, x2
+		0x2020e43b, // sh3add.uw x8, x1, x2
+		0x0a40949b, // slli.uw x9, x1, 36
+		0x4020f533, // andn x10, x1, x2
+		0x4020e5b3, // orn x11, x1, x2
+		0x4020c633, // xnor x12, x1, x2
+		0x0a20e6b3, // max x13, x1, x2
+		0x0a20f733, // maxu x14, x1, x2
+		0x0a20c7b3, // min x15, x1, x2
+		0x0a20d833, // minu x16, x1, x2
+		0x603098b3, // rol x17, x1, x3
+		0x6030d933, // ror x18, x1, x3
+		0x6240d993, // rori x19, x1, 36
+		0x60309a3b, // rolw x20, x1, x3
+		0x6030dabb, // rorw x21, x1, x3
+		0x6040db1b, // roriw x22, x1, 4
+	];
+
+	let (cpu, _) = common::run(&code, code.len(), setup);
+
+	assert_eq!(cpu.x[5], 0x1_f00e_0083);
+	assert_eq!(cpu.x[6], 0x4_e01d_0103);
+	assert_eq!(cpu.x[7], 0x8_c03b_0203);
+	assert_eq!(cpu.x[8], 0x8_8077_0403);
+	assert_eq!(cpu.x[9], 0x00f0_0800_0000_0000);
+	assert_eq!(cpu.x[10], 0x8000_0001_0000_0080);
+	assert_eq!(cpu.x[11], 0xffff_ffff_f00f_fffc);
+	assert_eq!(cpu.x[12], 0x7fff_fffe_f00f_ff7c);
+	assert_eq!(cpu.x[13], 0xffff_0003);
+	assert_eq!(cpu.x[14], 0x8000_0001_f00f_0080);
+	assert_eq!(cpu.x[15], 0x8000_0001_f00f_0080);
+	assert_eq!(cpu.x[16], 0xffff_0003);
+	assert_eq!(cpu.x[17], 0x00f0_0808_0000_001f);
+	assert_eq!(cpu.x[18], 0x1f00_f008_0800_0000);
+	assert_eq!(cpu.x[19], 0x1f00_f008_0800_0000);
+	assert_eq!(cpu.x[20], 0x00f0_080f);
+	assert_eq!(cpu.x[21], 0x0f00_f008);
+	assert_eq!(cpu.x[22], 0x0f00_f008);
+}
+
+#[test]
+fn zbb_unary() {
+	let code = [
+		0x60011293, // clz x5, x2
+		0x60109313, // ctz x6, x1
+		0x60209393, // cpop x7, x1
+		0x6001941b, // clzw x8, x3
+		0x6011149b, // ctzw x9, x2
+		0x6020951b, // cpopw x10, x1
+		0x60409593, // sext.b x11, x1
+		0x60509613, // sext.h x12, x1
+		0x0800c6bb, // zext.h x13, x1
+		0x2870d713, // orc.b x14, x1
+		0x6b80d793, // rev8 x15, x1
+	];
+
+	let (cpu, _) = common::run(&code, code.len(), setup);
+
+	assert_eq!(cpu.x[5], 32);
+	assert_eq!(cpu.x[6], 7);
+	assert_eq!(cpu.x[7], 11);
+	assert_eq!(cpu.x[8], 26);
+	assert_eq!(cpu.x[9], 0);
+	assert_eq!(cpu.x[10], 9);
+	assert_eq!(cpu.x[11], 0xffff_ffff_ffff_ff80);
+	assert_eq!(cpu.x[12], 0x80);
+	assert_eq!(cpu.x[13], 0x80);
+	assert_eq!(cpu.x[14], 0xff00_00ff_ffff_00ff);
+	assert_eq!(cpu.x[15], 0x8000_0ff0_0100_0080);
+}
+
+#[test]
+fn zbc_zbs() {
+	let code = [
+		0x0a209833, // clmul x16, x1, x2
+		0x0a20b8b3, // clmulh x17, x1, x2
+		0x0a20a933, // clmulr x18, x1, x2
+		0x483099b3, // bclr x19, x1, x3
+		0x4830da33, // bext x20, x1, x3
+		0x68309ab3, // binv x21, x1, x3
+		0x28309b33, // bset x22, x1, x3
+		0x4bf09b93, // bclri x23, x1, 63
+		0x4bf0dc13, // bexti x24, x1, 63
+		0x6a809c93, // binvi x25, x1, 40
+		0x28109d13, // bseti x26, x1, 1
+	];
+
+	let (cpu, _) = common::run(&code, code.len(), setup);
+
+	assert_eq!(cpu.x[16], 0x2ffa_5078_ef91_0180);
+	assert_eq!(cpu.x[17], 0x7fff_8001);
+	assert_eq!(cpu.x[18], 0xffff_0002);
+	assert_eq!(cpu.x[19], 0x8000_0001_f00f_0080);
+	assert_eq!(cpu.x[20], 0);
+	assert_eq!(cpu.x[21], 0x8000_0011_f00f_0080);
+	assert_eq!(cpu.x[22], 0x8000_0011_f00f_0080);
+	assert_eq!(cpu.x[23], 0x1_f00f_0080);
+	assert_eq!(cpu.x[24], 1);
+	assert_eq!(cpu.x[25], 0x8000_0101_f00f_0080);
+	assert_eq!(cpu.x[26], 0x8000_0001_f00f_0082);
+}
+
+#[test]
+fn reserved_encodings() {
+	use rrv64g::inst::ENCODING_TABLE;
+
+	for raw in [
+		0x0400d293u32, // srli with imm[11:6] = 0b000001
+		0x6200d29b,    // roriw with shamt[5] set
+		0x0820c6bb,    // zext.h with rs2 != 0 (pack, from Zbkb)
+		0x60309293,    // clz with an unknown rs2 selector
+	] {
+		let typ = ENCODING_TABLE[(raw & 0b1111111) as usize].as_ref().unwrap();
+		assert!(typ.decode(raw).is_err(), "{raw:#010x}");
+	}
+}
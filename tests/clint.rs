@@ -0,0 +1,117 @@
+mod common;
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use rrv64g::prelude::*;
+
+/// addi x9, x9, 1 forever.
+const COUNT: [u32; 2] = [
+	0x00148493, // addi x9, x9, 1
+	0xffdff06f, // jal x0, -4
+];
+
+#[test]
+fn timer_fires() {
+	let mut ram = common::Mem::with_program(&COUNT);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+	vm.harts[0].csr.write(MIE, MASK_MTIP);
+	vm.harts[0].csr[MSTATUS] |= MASK_MIE;
+	vm.bus.store(CLINT_BASE + CLINT_MTIMECMP, 10, 64).unwrap();
+
+	for _ in 0..9 {
+		vm.tick(None).unwrap();
+	}
+	assert_eq!(vm.bus.clint.mtime(), 9);
+	assert_eq!(vm.harts[0].csr.read(MIP) & MASK_MTIP, 0);
+
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], MASK_INTERRUPT_BIT | 7);
+	assert_eq!(vm.harts[0].pc, RAM_BASE + 0x100);
+	assert_eq!(vm.harts[0].x[9], 5);
+
+	// Pushing the compare out clears the interrupt.
+	vm.bus.store(CLINT_BASE + CLINT_MTIMECMP, u64::MAX, 64).unwrap();
+	assert!(!vm.bus.clint.is_interrupting(0));
+}
+
+#[test]
+fn timebase() {
+	let mut ram = common::Mem::with_program(&COUNT);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.bus.clint.frequency = 1_000_000;
+	vm.bus.clint.source = TimeSource::Instructions {
+		per_second: 4_000_000,
+	};
+
+	for _ in 0..10 {
+		vm.tick(None).unwrap();
+	}
+	assert_eq!(vm.bus.clint.mtime(), 2);
+	vm.tick(None).unwrap();
+	vm.tick(None).unwrap();
+	assert_eq!(vm.bus.clint.mtime(), 3, "fractions of a tick carry over");
+}
+
+#[test]
+fn host_time() {
+	static NOW: AtomicU64 = AtomicU64::new(1_000);
+
+	let mut ram = common::Mem::with_program(&COUNT);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.bus.clint.source = TimeSource::Host(Box::new(|| NOW.load(Ordering::Relaxed)));
+
+	vm.tick(None).unwrap();
+	assert_eq!(vm.bus.clint.mtime(), 0, "time counts from the first tick");
+	NOW.fetch_add(500, Ordering::Relaxed);
+	vm.tick(None).unwrap();
+	vm.tick(None).unwrap();
+	assert_eq!(vm.bus.clint.mtime(), 5);
+
+	// Writing mtime counts on from the new value.
+	vm.bus.store(CLINT_BASE + CLINT_MTIME, 100, 64).unwrap();
+	vm.tick(None).unwrap();
+	assert_eq!(vm.bus.clint.mtime(), 100);
+	NOW.fetch_add(1_000, Ordering::Relaxed);
+	vm.tick(None).unwrap();
+	assert_eq!(vm.bus.clint.mtime(), 110);
+}
+
+#[test]
+fn halves() {
+	let mut ram = common::Mem::with_program(&[
+		0x0062a223, // sw x6, 4(x5)
+		0x0002a383, // lw x7, 0(x5)
+		0x0042a403, // lw x8, 4(x5)
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].x[5] = CLINT_BASE + CLINT_MTIMECMP;
+	vm.harts[0].x[6] = 0x1234;
+
+	for _ in 0..3 {
+		vm.tick(None).unwrap();
+	}
+	assert_eq!(vm.bus.clint.mtimecmp(0), 0x1234_ffff_ffff);
+	assert_eq!(vm.harts[0].x[7], 0xffff_ffff_ffff_ffff, "lw sign-extends");
+	assert_eq!(vm.harts[0].x[8], 0x1234);
+
+	vm.bus.store(CLINT_BASE + CLINT_MTIMECMP, 5, 32).unwrap();
+	assert_eq!(vm.bus.clint.mtimecmp(0), 0x1234_0000_0005);
+
+	vm.bus.store(CLINT_BASE + CLINT_MTIME + 4, 2, 32).unwrap();
+	assert_eq!(vm.bus.clint.mtime(), 2 << 32 | 3, "the low half is kept");
+	assert_eq!(vm.bus.load(CLINT_BASE + CLINT_MTIME + 4, 32).unwrap(), 2);
+	assert_eq!(vm.bus.load(CLINT_BASE + CLINT_MTIME, 32).unwrap(), 3);
+
+	assert!(vm.bus.load(CLINT_BASE + CLINT_MTIME + 2, 32).is_err());
+	assert!(vm.bus.load(CLINT_BASE + CLINT_MTIME, 16).is_err());
+	assert!(vm.bus.store(CLINT_BASE + CLINT_MTIME + 4, 0, 64).is_err());
+}
@@ -0,0 +1,88 @@
+#![allow(dead_code)]
+
+use rrv64g::prelude::*;
+
+pub const RAM_SIZE: u64 = 1024 * 1024;
+
+#[derive(Default)]
+pub struct Mem {
+    pub mem: Vec<u8>,
+}
+
+impl Mem {
+    /// Creates a `RAM_SIZE` memory with `program` loaded at its start.
+    pub fn with_program(program: &[u32]) -> Self {
+        let mut mem: Vec<u8> = program.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+        mem.resize(RAM_SIZE as usize, 0);
+
+        Mem { mem }
+    }
+
+    /// Writes a doubleword at `offset` from the start of memory.
+    pub fn write_u64(&mut self, offset: u64, val: u64) {
+        let offset = offset as usize;
+        self.mem[offset..offset + 8].copy_from_slice(&val.to_le_bytes());
+    }
+}
+
+impl MemIntf for Mem {
+    fn reset(&mut self) {
+        self.mem.clear();
+    }
+
+    fn load(&mut self, addr: u64, size: u64) -> Result<u64, Exception> {
+        let addr = addr as usize;
+        let len = size as usize / 8;
+        if addr + len > self.mem.len() {
+            return Err(Exception::LoadAccessFault(addr as u64));
+        }
+
+        let mut bytes = [0; 8];
+        bytes[..len].copy_from_slice(&self.mem[addr..addr + len]);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn store(&mut self, addr: u64, val: u64, size: u64) -> Result<(), Exception> {
+        let addr = addr as usize;
+        let len = size as usize / 8;
+        if addr + len > self.mem.len() {
+            return Err(Exception::StoreAMOAccessFault(addr as u64));
+        }
+
+        self.mem[addr..addr + len].copy_from_slice(&val.to_le_bytes()[..len]);
+        Ok(())
+    }
+}
+
+/// Runs `steps` instructions of `program` from `RAM_BASE`, after letting
+/// `setup` initialize the hart. Returns the hart and its memory.
+pub fn run(program: &[u32], steps: usize, setup: impl FnOnce(&mut Cpu)) -> (Cpu, Mem) {
+    let mut ram = Mem::with_program(program);
+    let mut disk = Mem::default();
+
+    let mut vm = VM::new(&mut ram, RAM_SIZE, &mut disk);
+    vm.harts[0].pc = RAM_BASE;
+    setup(&mut vm.harts[0]);
+
+    for _ in 0..steps {
+        vm.tick(None).unwrap();
+    }
+
+    let cpu = vm.harts.swap_remove(0);
+    (cpu, ram)
+}
+
+/// Grants S- and U-mode access to all of memory with a single NAPOT PMP
+/// entry, as boot firmware would.
+pub fn open_pmp(cpu: &mut Cpu) {
+    cpu.pmp.write_addr(0, u64::MAX);
+    cpu.pmp
+        .write_cfg(0, (PMP_A_NAPOT | PMP_X | PMP_W | PMP_R) as u64);
+}
+
+/// Packs 16-bit instruction parcels into the little-endian words `run` expects.
+pub fn parcels(code: &[u16]) -> Vec<u32> {
+    code.chunks(2)
+        .map(|pair| pair[0] as u32 | (*pair.get(1).unwrap_or(&0) as u32) << 16)
+        .collect()
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+fn parse(isa: &str) -> Result<CpuConfig, IsaError> {
+	isa.parse()
+}
+
+#[test]
+fn isa_strings() {
+	let round_trip = |isa: &str| parse(isa).unwrap().to_string();
+
+	assert_eq!(round_trip("rv64imafdc_zicsr_zifencei_zba"), "rv64imafdc_zicsr_zifencei_zba");
+	assert_eq!(round_trip("RV64IMAC"), "rv64imac");
+	assert_eq!(round_trip("rv64gcv_sstc"), "rv64imafdcv_zicsr_zifencei_sstc");
+	assert_eq!(round_trip("rv64ib_zbc"), "rv64i_zba_zbb_zbc_zbs");
+	assert_eq!(round_trip("rv64id"), "rv64ifd_zicsr", "dependencies are added");
+	assert_eq!(CpuConfig::default(), parse(&CpuConfig::default().to_string()).unwrap());
+
+	assert_eq!(parse("rv32i"), Err(IsaError::Base));
+	assert_eq!(parse("rv64mac"), Err(IsaError::Base));
+	assert_eq!(parse("rv64imafdq"), Err(IsaError::Unsupported("q".into())));
+	assert_eq!(parse("rv64i_zfoo"), Err(IsaError::Unsupported("zfoo".into())));
+}
+
+#[test]
+fn misa_and_ids() {
+	let program = [
+		0x301022f3, // csrrs x5, misa, x0
+		0xf1202373, // csrrs x6, marchid, x0
+	];
+
+	let (cpu, _) = common::run(&program, program.len(), |cpu| {
+		let mut config = parse("rv64imac_zicsr").unwrap();
+		config.marchid = 0x8000_0000_0000_0007;
+		config.mimpid = 0x2;
+		cpu.set_config(config);
+	});
+
+	assert_eq!(cpu.x[5], MISA_MXL_64 | MISA_I | MISA_M | MISA_A | MISA_C | MISA_S | MISA_U);
+	assert_eq!(cpu.x[6], 0x8000_0000_0000_0007);
+	assert_eq!(cpu.csr[MIMPID], 0x2);
+}
+
+/// Executes a single instruction on a `config` hart and returns the hart
+/// after any resulting trap has been taken.
+fn step(inst: u32, config: &CpuConfig) -> Cpu {
+	let mut ram = common::Mem::with_program(&[inst]);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].set_config(config.clone());
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+
+	let _ = vm.tick(None);
+
+	vm.harts.swap_remove(0)
+}
+
+#[test]
+fn unimplemented_extensions_are_illegal() {
+	let config = parse("rv64imac_zicsr").unwrap();
+
+	assert_eq!(step(0x027302b3, &config).pc, RAM_BASE + 4, "mul");
+	for (inst, mtval) in [
+		(0x003170d3, 0x003170d3), // fadd.s f1, f2, f3
+		(0x207322b3, 0x207322b3), // sh1add x5, x6, x7
+		(0xc00022f3, 0xc00022f3), // csrrs x5, cycle, x0
+		(0x600022f3, 0x600022f3), // csrrs x5, hstatus, x0
+		(0x00302573, 0x00302573), // csrrs x10, fcsr, x0
+		(0x0000100f, 0x0000100f), // fence.i
+		(0x00012080, 0x2080),     // c.fld f8, 0(x9)
+	] {
+		let cpu = step(inst, &config);
+		assert_eq!((cpu.csr[MCAUSE], cpu.csr[MTVAL]), (2, mtval), "{inst:#x}");
+		assert_eq!(cpu.pc, RAM_BASE + 0x100);
+	}
+
+	// The same instructions are fine on a hart that has the extensions.
+	let config = parse("rv64gc_zicntr_zba").unwrap();
+	assert_eq!(step(0x207322b3, &config).pc, RAM_BASE + 4);
+	assert_eq!(step(0xc00022f3, &config).pc, RAM_BASE + 4);
+	assert_eq!(step(0x0000100f, &config).pc, RAM_BASE + 4);
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+const MTVEC_ADDR: u64 = RAM_BASE + 0x100;
+
+#[test]
+fn counting_events() {
+	let program = [
+		0x32351073, // csrrw x0, mhpmevent3, x10
+		0x32459073, // csrrw x0, mhpmevent4, x11
+		0x32561073, // csrrw x0, mhpmevent5, x12
+		0x00073303, // ld x6, 0(x14)
+		0xfff28293, // addi x5, x5, -1
+		0xfe029ce3, // bne x5, x0, -8
+		0xc00023f3, // csrrs x7, cycle, x0
+		0xc0202473, // csrrs x8, instret, x0
+		0xc03024f3, // csrrs x9, hpmcounter3, x0
+		0xc04027f3, // csrrs x15, hpmcounter4, x0
+		0x32502873, // csrrs x16, mhpmevent5, x0
+		0xc01028f3, // csrrs x17, time, x0
+		0xb0201073, // csrrw x0, minstret, x0
+		0xb0202973, // csrrs x18, minstret, x0
+	];
+
+	let mut ram = common::Mem::with_program(&program);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].x[5] = 3;
+	vm.harts[0].x[10] = HPM_EVENT_LOADS;
+	vm.harts[0].x[11] = HPM_EVENT_BRANCHES_TAKEN;
+	vm.harts[0].x[12] = 99;
+	vm.harts[0].x[14] = RAM_BASE + 0x800;
+	vm.bus.store(CLINT_BASE + CLINT_MTIME, 1234, 64).unwrap();
+
+	for _ in 0..20 {
+		vm.tick(None).unwrap();
+	}
+
+	let cpu = &vm.harts[0];
+	assert_eq!(cpu.x[7], 13, "cycle counts the reading instruction too");
+	assert_eq!(cpu.x[8], 13);
+	assert_eq!(cpu.x[9], 3, "three loads");
+	assert_eq!(cpu.x[15], 2, "two taken branches");
+	assert_eq!(cpu.x[16], HPM_EVENT_NONE, "unknown events are legalized");
+	assert_eq!(cpu.x[17], 1234 + 17, "time ticks once per instruction by default");
+	assert_eq!(cpu.x[18], 0, "an explicit write replaces the increment");
+	assert_eq!(cpu.csr.read(MINSTRET), 1);
+}
+
+#[test]
+fn inhibit() {
+	let program = [
+		0x32051073, // csrrw x0, mcountinhibit, x10
+		0x00000013, // nop
+		0x00000013, // nop
+		0xb00022f3, // csrrs x5, mcycle, x0
+		0xb02023f3, // csrrs x7, minstret, x0
+	];
+
+	let (cpu, _) = common::run(&program, 5, |cpu| cpu.x[10] = MASK_CY);
+	assert_eq!(cpu.x[5], 1);
+	assert_eq!(cpu.x[7], 4);
+	assert_eq!(cpu.csr.read(MCOUNTINHIBIT), MASK_CY);
+}
+
+/// Reads `cycle` in `mode` and reports whether it trapped.
+fn read_cycle(mode: Mode, mcounteren: u64, scounteren: u64) -> bool {
+	let mut ram = common::Mem::with_program(&[0xc00022f3]); // csrrs x5, cycle, x0
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].mode = mode;
+	vm.harts[0].csr[MTVEC] = MTVEC_ADDR;
+	vm.harts[0].csr.write(MCOUNTEREN, mcounteren);
+	vm.harts[0].csr.write(SCOUNTEREN, scounteren);
+	common::open_pmp(&mut vm.harts[0]);
+
+	let _ = vm.tick(None);
+
+	vm.harts[0].pc == MTVEC_ADDR
+}
+
+#[test]
+fn counter_enables() {
+	assert!(!read_cycle(Mode::Machine, 0, 0));
+
+	assert!(read_cycle(Mode::Supervisor, 0, MASK_CY));
+	assert!(!read_cycle(Mode::Supervisor, MASK_CY, 0));
+
+	assert!(read_cycle(Mode::User, MASK_CY, 0));
+	assert!(read_cycle(Mode::User, MASK_IR, MASK_CY));
+	assert!(!read_cycle(Mode::User, MASK_CY, MASK_CY));
+}
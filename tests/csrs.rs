@@ -0,0 +1,82 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+#[test]
+fn status_aliasing() {
+	let mut csrs = CsrFile::new();
+
+	csrs.write(SSTATUS, MASK_SIE | MASK_MIE | MASK_SUM);
+	assert_eq!(csrs.read(MSTATUS) & (MASK_SIE | MASK_MIE | MASK_SUM), MASK_SIE | MASK_SUM);
+
+	csrs.write(MSTATUS, MASK_MIE | MASK_TSR);
+	assert_eq!(csrs.read(SSTATUS) & (MASK_SIE | MASK_MIE | MASK_TSR), 0);
+	assert_eq!(csrs.read(SSTATUS) & MASK_UXL, 2 << 32, "UXL is fixed to 64-bit");
+
+	// SD follows FS.
+	csrs.write(MSTATUS, MASK_FS);
+	assert_eq!(csrs.read(SSTATUS) & MASK_SD, MASK_SD);
+	csrs.write(SSTATUS, 0);
+	assert_eq!(csrs.read(MSTATUS) & (MASK_FS | MASK_SD), 0);
+	csrs.write(FFLAGS, 1);
+	assert_eq!(csrs.read(MSTATUS) & (MASK_FS | MASK_SD), MASK_FS | MASK_SD);
+}
+
+#[test]
+fn interrupt_views() {
+	let mut csrs = CsrFile::new();
+
+	csrs.write(MIDELEG, !0);
+	assert_eq!(csrs.read(MIDELEG), MASK_SSIP | MASK_STIP | MASK_SEIP | MIDELEG_FIXED);
+
+	csrs.write(MIE, MASK_MTIP | MASK_STIP);
+	assert_eq!(csrs.read(SIE), MASK_STIP);
+	csrs.write(SIE, MASK_SEIP | MASK_MEIP);
+	assert_eq!(csrs.read(MIE), MASK_MTIP | MASK_SEIP);
+
+	// Only SSIP is writable through sip, and M-level bits not through mip.
+	csrs[MIP] = MASK_MTIP;
+	csrs.write(SIP, MASK_SSIP | MASK_STIP);
+	csrs.write(MIP, csrs.read(MIP) & !MASK_MTIP);
+	assert_eq!(csrs.read(MIP), MASK_MTIP | MASK_SSIP);
+	assert_eq!(csrs.read(SIP), MASK_SSIP);
+}
+
+#[test]
+fn warl_fields() {
+	let mut csrs = CsrFile::new();
+
+	csrs.write(MTVEC, 0x8000_0101);
+	csrs.write(MTVEC, 0x8000_0202);
+	assert_eq!(csrs.read(MTVEC), 0x8000_0201, "reserved vector modes are ignored");
+
+	csrs.write(MEPC, 0x8000_0003);
+	assert_eq!(csrs.read(MEPC), 0x8000_0002);
+
+	csrs.write(MEDELEG, !0);
+	assert_eq!(csrs.read(MEDELEG) & (1 << 11), 0, "M-mode ecalls can't be delegated");
+
+	csrs.write(MSTATUS, 0b10 << 11);
+	assert_eq!(csrs.read(MSTATUS) & MASK_MPP, 0);
+
+	let misa = csrs.read(MISA);
+	csrs.write(MISA, 0);
+	assert_eq!(csrs.read(MISA), misa);
+	assert_eq!(misa >> 62, 2, "MXL reports RV64");
+}
+
+#[test]
+fn unimplemented_csrs_are_illegal() {
+	for inst in [
+		0x7c0022f3, // csrrs x5, 0x7c0, x0
+		0x3a101073, // csrrw x0, pmpcfg1, x0
+	] {
+		let mut ram = common::Mem::with_program(&[inst]);
+		let mut disk = common::Mem::default();
+
+		let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+		vm.harts[0].pc = RAM_BASE;
+
+		assert!(matches!(vm.tick(None), Err(Exception::IllegalInstruction(i)) if i == inst as u64));
+	}
+}
@@ -0,0 +1,109 @@
+use rrv64g::float::{self, F32, F64, FLAG_NV, FLAG_NX, FLAG_OF, FLAG_UF, RM_RNE};
+
+/// Small xorshift generator biased towards interesting bit patterns.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn f32(&mut self) -> u32 {
+        let r = self.next();
+        let bits = r as u32;
+        match (r >> 40) % 8 {
+            // Subnormals
+            0 => bits & 0x807f_ffff,
+            // Values close to one
+            1 => (bits & 0x80ff_ffff) | 0x3f00_0000,
+            _ => bits,
+        }
+    }
+
+    fn f64(&mut self) -> u64 {
+        let bits = self.next();
+        match self.next() % 8 {
+            0 => bits & 0x800f_ffff_ffff_ffff,
+            1 => (bits & 0x801f_ffff_ffff_ffff) | 0x3fe0_0000_0000_0000,
+            _ => bits,
+        }
+    }
+}
+
+fn same32(soft: u64, host: f32) -> bool {
+    (host.is_nan() && soft as u32 == 0x7fc0_0000) || soft as u32 == host.to_bits()
+}
+
+fn same64(soft: u64, host: f64) -> bool {
+    (host.is_nan() && soft == 0x7ff8_0000_0000_0000) || soft == host.to_bits()
+}
+
+#[test]
+fn single_matches_host() {
+    let mut rng = Rng(0x1234_5678_9abc_def1);
+    let mut flags = 0;
+
+    for _ in 0..200_000 {
+        let (a, b, c) = (rng.f32(), rng.f32(), rng.f32());
+        let (fa, fb, fc) = (f32::from_bits(a), f32::from_bits(b), f32::from_bits(c));
+        let (a, b, c) = (a as u64, b as u64, c as u64);
+
+        assert!(same32(float::add(F32, a, b, RM_RNE, &mut flags), fa + fb), "{fa:e} + {fb:e}");
+        assert!(same32(float::sub(F32, a, b, RM_RNE, &mut flags), fa - fb), "{fa:e} - {fb:e}");
+        assert!(same32(float::mul(F32, a, b, RM_RNE, &mut flags), fa * fb), "{fa:e} * {fb:e}");
+        assert!(same32(float::div(F32, a, b, RM_RNE, &mut flags), fa / fb), "{fa:e} / {fb:e}");
+        assert!(same32(float::sqrt(F32, a, RM_RNE, &mut flags), fa.sqrt()), "sqrt {fa:e}");
+        assert!(
+            same32(float::mul_add(F32, a, b, c, RM_RNE, &mut flags), fa.mul_add(fb, fc)),
+            "{fa:e} * {fb:e} + {fc:e}"
+        );
+    }
+}
+
+#[test]
+fn double_matches_host() {
+    let mut rng = Rng(0x0fed_cba9_8765_4321);
+    let mut flags = 0;
+
+    for _ in 0..200_000 {
+        let (a, b, c) = (rng.f64(), rng.f64(), rng.f64());
+        let (fa, fb, fc) = (f64::from_bits(a), f64::from_bits(b), f64::from_bits(c));
+
+        assert!(same64(float::add(F64, a, b, RM_RNE, &mut flags), fa + fb), "{fa:e} + {fb:e}");
+        assert!(same64(float::mul(F64, a, b, RM_RNE, &mut flags), fa * fb), "{fa:e} * {fb:e}");
+        assert!(same64(float::div(F64, a, b, RM_RNE, &mut flags), fa / fb), "{fa:e} / {fb:e}");
+        assert!(same64(float::sqrt(F64, a, RM_RNE, &mut flags), fa.sqrt()), "sqrt {fa:e}");
+        assert!(
+            same64(float::mul_add(F64, a, b, c, RM_RNE, &mut flags), fa.mul_add(fb, fc)),
+            "{fa:e} * {fb:e} + {fc:e}"
+        );
+        assert!(
+            same32(float::convert(F64, F32, a, RM_RNE, &mut flags), fa as f32),
+            "{fa:e} as f32"
+        );
+        assert!(same64(float::from_int(F64, a, 64, true, RM_RNE, &mut flags), a as i64 as f64));
+    }
+}
+
+#[test]
+fn exception_flags() {
+    let mut flags = 0;
+    float::mul(F32, 0x7f00_0000, 0x7f00_0000, RM_RNE, &mut flags);
+    assert_eq!(flags, FLAG_OF | FLAG_NX);
+
+    let mut flags = 0;
+    float::mul(F32, 0x0080_0000, 0x3f00_0001, RM_RNE, &mut flags);
+    assert_eq!(flags, FLAG_UF | FLAG_NX);
+
+    let mut flags = 0;
+    let nan = float::sub(F64, F64.inf(false), F64.inf(false), RM_RNE, &mut flags);
+    assert_eq!(nan, F64.canonical_nan());
+    assert_eq!(flags, FLAG_NV);
+
+    let mut flags = 0;
+    assert_eq!(float::to_int(F32, 0xcf00_0001, 32, true, RM_RNE, &mut flags), 0x8000_0000);
+    assert_eq!(flags, FLAG_NV);
+}
@@ -0,0 +1,196 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+const G_ROOT: u64 = 0x10000;
+const VS_ROOT: u64 = 0x14000;
+
+/// Builds a VM whose G-stage maps guest physical gigapage 0 onto RAM_BASE.
+/// Guest code starts at 4 as the VM halts at pc 0.
+fn guest_vm<'a>(ram: &'a mut common::Mem, disk: &'a mut common::Mem) -> VM<'a> {
+	ram.write_u64(G_ROOT, (RAM_BASE >> 12) << 10 | PTE_R | PTE_W | PTE_X | PTE_U | PTE_A | PTE_D | PTE_V);
+
+	let mut vm = VM::new(ram, common::RAM_SIZE, disk);
+	vm.harts[0].pc = RAM_BASE;
+	common::open_pmp(&mut vm.harts[0]);
+	vm.harts[0].csr[HGATP] = SATP_MODE_SV39 << 60 | (RAM_BASE + G_ROOT) >> 12;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+	vm
+}
+
+#[test]
+fn trap_routing() {
+	let mut ram = common::Mem::with_program(&[
+		0x00000013, // nop
+		0x00000073, // ecall
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = guest_vm(&mut ram, &mut disk);
+	vm.harts[0].csr.write(MEDELEG, 1 << 2 | 1 << 8 | 1 << 10);
+	vm.harts[0].csr.write(HEDELEG, 1 << 2 | 1 << 8);
+	vm.harts[0].csr[STVEC] = RAM_BASE + 0x200;
+	vm.harts[0].csr[VSTVEC] = 0x300;
+
+	// ecall from VS-mode can only be taken by HS-mode.
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].virt = true;
+	vm.harts[0].pc = 4;
+	vm.tick(None).unwrap();
+	assert_eq!((vm.harts[0].mode, vm.harts[0].virt), (Mode::Supervisor, false));
+	assert_eq!(vm.harts[0].csr[SCAUSE], 10);
+	assert_eq!(vm.harts[0].csr[SEPC], 4);
+	assert_eq!(vm.harts[0].pc, RAM_BASE + 0x200);
+	assert_eq!(vm.harts[0].csr[HSTATUS] & (MASK_SPV | MASK_SPVP), MASK_SPV | MASK_SPVP);
+
+	// sret returns into the guest.
+	vm.harts[0].csr[MSTATUS] |= MASK_SPP;
+	vm.bus.ram.store(0x200, 0x10200073, 32).unwrap(); // sret
+	vm.tick(None).unwrap();
+	assert_eq!((vm.harts[0].mode, vm.harts[0].virt), (Mode::Supervisor, true));
+	assert_eq!(vm.harts[0].pc, 4);
+
+	// ecall from VU-mode goes to the guest kernel through hedeleg.
+	vm.harts[0].mode = Mode::User;
+	vm.tick(None).unwrap();
+	assert_eq!((vm.harts[0].mode, vm.harts[0].virt), (Mode::Supervisor, true));
+	assert_eq!(vm.harts[0].csr[VSCAUSE], 8);
+	assert_eq!(vm.harts[0].csr[VSEPC], 4);
+	assert_eq!(vm.harts[0].pc, 0x300);
+	assert_eq!(vm.harts[0].csr[VSSTATUS] & MASK_SPP, 0);
+
+	// A VS-level interrupt delegated through hideleg reaches the guest
+	// with a supervisor cause.
+	vm.harts[0].csr.write(HIDELEG, MASK_VSSIP);
+	vm.harts[0].csr.write(HVIP, MASK_VSSIP);
+	vm.harts[0].csr.write(HIE, MASK_VSSIP);
+	vm.harts[0].csr[VSSTATUS] |= MASK_SIE;
+	let interrupt = vm.harts[0].check_pending_interrupt(&mut vm.bus).unwrap().unwrap();
+	vm.harts[0].handle_interrupt(interrupt);
+	assert_eq!(vm.harts[0].csr[VSCAUSE], MASK_INTERRUPT_BIT | 1);
+	assert_eq!(vm.harts[0].csr[VSSTATUS] & (MASK_SIE | MASK_SPIE), MASK_SPIE);
+	assert!(vm.harts[0].virt);
+}
+
+#[test]
+fn two_stage_translation() {
+	let mut ram = common::Mem::with_program(&[
+		0x00053283, // ld x5, 0(x10)
+		0x0005b303, // ld x6, 0(x11)
+		0x00063383, // ld x7, 0(x12)
+	]);
+	// The guest maps its first gigapage to guest physical 0 and its second
+	// to guest physical 1 GiB, which the host doesn't map.
+	let kernel = PTE_R | PTE_W | PTE_X | PTE_A | PTE_D | PTE_V;
+	ram.write_u64(VS_ROOT, kernel);
+	ram.write_u64(VS_ROOT + 8, (1 << 30 >> 12) << 10 | kernel);
+	ram.write_u64(0x20008, 0x1234);
+	let mut disk = common::Mem::default();
+	let mut vm = guest_vm(&mut ram, &mut disk);
+	vm.harts[0].csr[VSATP] = SATP_MODE_SV39 << 60 | VS_ROOT >> 12;
+
+	// M-mode with MPRV and MPV accesses memory as the guest kernel.
+	vm.harts[0].csr[MSTATUS] |= MASK_MPRV | MASK_MPV | 0b01 << 11;
+	vm.harts[0].x[10] = 0x20008;
+	vm.harts[0].x[11] = (1 << 30) + 0x10;
+	vm.harts[0].x[12] = 2 << 30;
+
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].x[5], 0x1234);
+
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 21, "the G-stage faults");
+	assert_eq!(vm.harts[0].csr[MTVAL], (1 << 30) + 0x10);
+	assert_eq!(vm.harts[0].csr[MTVAL2], ((1 << 30) + 0x10) >> 2);
+	assert_ne!(vm.harts[0].csr[MSTATUS] & MASK_GVA, 0);
+
+	vm.harts[0].pc = RAM_BASE + 8;
+	vm.harts[0].csr[MSTATUS] &= !MASK_MPP;
+	vm.harts[0].csr[MSTATUS] |= MASK_MPRV | MASK_MPV | 0b01 << 11;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 13, "the VS-stage faults");
+	assert_eq!(vm.harts[0].csr[MTVAL2], 0);
+}
+
+#[test]
+fn hypervisor_loads_and_stores() {
+	let mut ram = common::Mem::with_program(&[
+		0x00000013, // nop
+		0x6c0542f3, // hlv.d x5, (x10)
+		0x6e65c073, // hsv.d x6, (x11)
+		0x683543f3, // hlvx.wu x7, (x10)
+	]);
+	ram.write_u64(0x20000, 0x1111_2222_3333_4444);
+	let mut disk = common::Mem::default();
+	let mut vm = guest_vm(&mut ram, &mut disk);
+	vm.harts[0].x[6] = 0xabcd;
+	vm.harts[0].x[10] = 0x20000;
+	vm.harts[0].x[11] = 0x20008;
+
+	// HS-mode reads and writes guest memory through the G-stage.
+	vm.harts[0].mode = Mode::Supervisor;
+	for _ in 0..4 {
+		vm.tick(None).unwrap();
+	}
+	assert_eq!(vm.harts[0].x[5], 0x1111_2222_3333_4444);
+	assert_eq!(vm.bus.ram.load(0x20008, 64).unwrap(), 0xabcd);
+	assert_eq!(vm.harts[0].x[7], 0x3333_4444);
+
+	// U-mode needs hstatus.HU.
+	vm.harts[0].mode = Mode::User;
+	vm.harts[0].pc = RAM_BASE + 4;
+	assert!(matches!(vm.tick(None), Err(Exception::IllegalInstruction(0x6c0542f3))));
+	vm.harts[0].mode = Mode::User;
+	vm.harts[0].pc = RAM_BASE + 4;
+	vm.harts[0].csr[HSTATUS] |= MASK_HU;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].mode, Mode::User);
+
+	// Guests can't use them at all.
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].virt = true;
+	vm.harts[0].pc = 4;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 22);
+	assert_eq!(vm.harts[0].csr[MTVAL], 0x6c0542f3);
+}
+
+#[test]
+fn guest_csrs() {
+	let mut ram = common::Mem::with_program(&[
+		0x00000013, // nop
+		0x100022f3, // csrr x5, sstatus
+		0x14031073, // csrw sscratch, x6
+		0x10402473, // csrr x8, sie
+		0x600023f3, // csrr x7, hstatus
+		0x10500073, // wfi
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = guest_vm(&mut ram, &mut disk);
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].virt = true;
+	vm.harts[0].pc = 4;
+	vm.harts[0].csr[VSSTATUS] |= MASK_SIE;
+	vm.harts[0].csr.write(HIDELEG, MASK_VSTIP);
+	vm.harts[0].csr.write(MIE, MASK_VSTIP | MASK_STIP);
+	vm.harts[0].x[6] = 0x5678;
+
+	// VS-mode's supervisor CSRs are the vs* ones.
+	for _ in 0..3 {
+		vm.tick(None).unwrap();
+	}
+	assert_eq!(vm.harts[0].x[5], vm.harts[0].csr[VSSTATUS]);
+	assert_eq!(vm.harts[0].csr[VSSCRATCH], 0x5678);
+	assert_eq!(vm.harts[0].csr[SSCRATCH], 0);
+	assert_eq!(vm.harts[0].x[8], MASK_STIP, "vsie shows delegated VS interrupts as S ones");
+
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 22, "hypervisor CSRs need HS-mode");
+	assert_eq!(vm.harts[0].csr[MTVAL], 0x600023f3);
+
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].virt = true;
+	vm.harts[0].pc = 20;
+	vm.harts[0].csr[HSTATUS] |= MASK_VTW;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 22, "VTW traps wfi");
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+/// Records what it is sent.
+#[derive(Default)]
+struct Recorder {
+	events: Vec<(usize, Option<bool>)>,
+}
+
+impl InterruptSink for Recorder {
+	fn set_level(&mut self, irq: usize, level: bool) {
+		self.events.push((irq, Some(level)));
+	}
+
+	fn pulse(&mut self, irq: usize) {
+		self.events.push((irq, None));
+	}
+}
+
+#[test]
+fn lines() {
+	let mut sink = Recorder::default();
+	let mut level = IrqLine::new(3, Trigger::Level);
+	let mut edge = IrqLine::new(4, Trigger::Edge);
+
+	level.raise();
+	level.raise();
+	level.deliver(&mut sink);
+	level.deliver(&mut sink);
+	assert!(level.is_raised());
+	level.set(false);
+	level.deliver(&mut sink);
+
+	edge.raise();
+	edge.raise();
+	edge.deliver(&mut sink);
+	assert!(!edge.is_raised(), "edge-triggered lines are never held raised");
+	edge.lower();
+	edge.deliver(&mut sink);
+
+	assert_eq!(sink.events, [(3, Some(true)), (3, Some(false)), (4, None)]);
+}
+
+#[test]
+fn edge_gateway() {
+	let mut plic = Plic::new(1);
+	plic.store(PLIC_PRIORITY + 4 * 4, 1, 32).unwrap();
+	plic.store(PLIC_ENABLE, 1 << 4, 32).unwrap();
+
+	plic.pulse(4);
+	assert!(plic.is_pending(4));
+	assert_eq!(plic.load(PLIC_CLAIM, 32).unwrap(), 4);
+	assert!(!plic.is_pending(4), "an edge is gone once claimed");
+
+	plic.pulse(4);
+	plic.pulse(4);
+	assert!(!plic.is_pending(4));
+	plic.store(PLIC_CLAIM, 4, 32).unwrap();
+	assert!(plic.is_pending(4), "the edges while it was claimed come through as one");
+	assert_eq!(plic.load(PLIC_CLAIM, 32).unwrap(), 4);
+	plic.store(PLIC_CLAIM, 4, 32).unwrap();
+	assert!(!plic.is_pending(4));
+}
+
+#[test]
+fn devices_reach_the_hart() {
+	let mut ram = common::Mem::with_program(&[
+		0x00000013, // addi x0, x0, 0
+		0x00000013, // addi x0, x0, 0
+		0x00000013, // addi x0, x0, 0
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	for irq in [UART_IRQ, 20] {
+		vm.bus.store(PLIC_BASE + PLIC_PRIORITY + 4 * irq as u64, 1, 32).unwrap();
+	}
+	vm.bus.store(PLIC_BASE + PLIC_SENABLE, 1 << UART_IRQ | 1 << 20, 32).unwrap();
+	vm.bus.store(UART_BASE + UART_IER, MASK_UART_IER_RDI as u64, 8).unwrap();
+	// The UART interrupts when a byte comes in.
+	vm.tick(Some('a')).unwrap();
+	vm.tick(None).unwrap();
+	assert!(vm.bus.plic.is_pending(UART_IRQ));
+	assert_ne!(vm.harts[0].csr.read(MIP) & MASK_SEIP, 0);
+	assert_eq!(vm.bus.load(PLIC_BASE + PLIC_SCLAIM, 32).unwrap(), UART_IRQ as u64);
+
+	// So does a device of the embedder's.
+	let mut line = IrqLine::new(20, Trigger::Level);
+	line.raise();
+	line.deliver(&mut vm.bus.plic);
+	vm.tick(None).unwrap();
+	assert_eq!(vm.bus.load(PLIC_BASE + PLIC_SCLAIM, 32).unwrap(), 20);
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+const ROOT: u64 = 0x10000;
+const L1: u64 = 0x11000;
+const L0: u64 = 0x12000;
+
+const PROGRAM: [u32; 4] = [
+	0x00053283, // ld x5, 0(x10)
+	0x0065b023, // sd x6, 0(x11)
+	0x00062383, // lw x7, 0(x12)
+	0x0066242f, // amoadd.w x8, x6, (x12)
+];
+
+fn leaf(offset: u64, flags: u64) -> u64 {
+	((RAM_BASE + offset) >> 12) << 10 | flags | PTE_V
+}
+
+#[test]
+fn emulated_across_pages() {
+	let mut ram = common::Mem::with_program(&PROGRAM);
+	// Virtual pages 0x1000 and 0x2000 map to physical pages that aren't
+	// adjacent.
+	let flags = PTE_R | PTE_W | PTE_A | PTE_D;
+	ram.write_u64(ROOT + 2 * 8, leaf(0, PTE_X | flags));
+	ram.write_u64(ROOT, ((RAM_BASE + L1) >> 12) << 10 | PTE_V);
+	ram.write_u64(L1, ((RAM_BASE + L0) >> 12) << 10 | PTE_V);
+	ram.write_u64(L0 + 8, leaf(0x30000, flags));
+	ram.write_u64(L0 + 16, leaf(0x20000, flags));
+	ram.write_u64(0x30ff8, 0x4433_2211_0000_0000);
+	ram.write_u64(0x20000, 0x0000_0000_8877_6655);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	common::open_pmp(&mut vm.harts[0]);
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].csr[SATP] = SATP_MODE_SV39 << 60 | (RAM_BASE + ROOT) >> 12;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+	vm.harts[0].x[10] = 0x1ffc;
+	vm.harts[0].x[11] = 0x1ffe;
+	vm.harts[0].x[6] = 0xaabb_ccdd_eeff_0011;
+	vm.harts[0].x[12] = 0x1ffd;
+
+	for _ in 0..3 {
+		vm.tick(None).unwrap();
+	}
+
+	assert_eq!(vm.harts[0].x[5], 0x8877_6655_4433_2211);
+	assert_eq!(vm.bus.ram.load(0x30ffe, 16).unwrap(), 0x0011);
+	assert_eq!(vm.bus.ram.load(0x20000, 32).unwrap(), 0xccdd_eeff);
+	assert_eq!(vm.bus.ram.load(0x20004, 16).unwrap(), 0xaabb);
+	assert_eq!(vm.harts[0].x[7], 0xffff_ffff_ff00_1122, "lw sign-extends the bytes it gathered");
+
+	// AMOs trap even when loads and stores are emulated.
+	assert!(matches!(vm.tick(None), Err(Exception::StoreAMOAddrMisaligned(0x1ffd))));
+	assert_eq!(vm.harts[0].csr[MCAUSE], 6);
+}
+
+#[test]
+fn trapped() {
+	let mut ram = common::Mem::with_program(&PROGRAM);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].misaligned = MisalignedAccess::Trap;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+	vm.harts[0].x[10] = RAM_BASE + 0x1004;
+	vm.harts[0].x[11] = RAM_BASE + 0x1001;
+
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 4);
+	assert_eq!(vm.harts[0].csr[MTVAL], RAM_BASE + 0x1004);
+	assert_eq!(vm.harts[0].csr[MEPC], RAM_BASE);
+
+	vm.harts[0].pc = RAM_BASE + 4;
+	assert!(vm.tick(None).is_err());
+	assert_eq!(vm.harts[0].csr[MCAUSE], 6);
+	assert_eq!(vm.harts[0].csr[MTVAL], RAM_BASE + 0x1001);
+	assert_eq!(vm.bus.ram.load(0x1000, 64).unwrap(), 0, "nothing is stored");
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+const ROOT: u64 = 0x10000;
+const L1: u64 = 0x11000;
+const L0: u64 = 0x12000;
+
+fn table(offset: u64) -> u64 {
+	((RAM_BASE + offset) >> 12) << 10 | PTE_V
+}
+
+fn leaf(offset: u64, flags: u64) -> u64 {
+	((RAM_BASE + offset) >> 12) << 10 | flags | PTE_V
+}
+
+/// Maps RAM_BASE with an identity gigapage and two user pages at 0x1000 and
+/// 0x2000, of which only the second has A and D set.
+fn page_tables(ram: &mut common::Mem) {
+	ram.write_u64(ROOT + 2 * 8, leaf(0, PTE_R | PTE_W | PTE_X | PTE_A | PTE_D));
+	ram.write_u64(ROOT, table(L1));
+	ram.write_u64(L1, table(L0));
+	ram.write_u64(L0 + 8, leaf(0x20000, PTE_R | PTE_W | PTE_U));
+	ram.write_u64(L0 + 16, leaf(0x21000, PTE_R | PTE_W | PTE_U | PTE_A | PTE_D));
+
+	ram.write_u64(0x20008, 0x1234);
+	ram.write_u64(0x21008, 0x5678);
+}
+
+#[test]
+fn supervisor_translation() {
+	let mut ram = common::Mem::with_program(&[
+		0x00853283, // ld x5, 8(x10)
+		0x00653423, // sd x6, 8(x10)
+		0x0085b383, // ld x7, 8(x11)
+		0x0085b403, // ld x8, 8(x11)
+		0x12000073, // sfence.vma x0, x0
+		0x0085b483, // ld x9, 8(x11)
+		0x10063073, // csrrc x0, sstatus, x12
+		0x00853683, // ld x13, 8(x10)
+	]);
+	page_tables(&mut ram);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	common::open_pmp(&mut vm.harts[0]);
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].csr[SATP] = SATP_MODE_SV39 << 60 | (RAM_BASE + ROOT) >> 12;
+	vm.harts[0].csr[MSTATUS] |= MASK_SUM;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+	vm.harts[0].x[6] = 0xabcd;
+	vm.harts[0].x[10] = 0x1000;
+	vm.harts[0].x[11] = 0x2000;
+	vm.harts[0].x[12] = MASK_SUM;
+
+	for _ in 0..3 {
+		vm.tick(None).unwrap();
+	}
+
+	// Point 0x2000 at the first page behind the TLB's back.
+	vm.bus.ram.store(L0 + 16, leaf(0x20000, PTE_R | PTE_W | PTE_U | PTE_A | PTE_D), 64).unwrap();
+
+	for _ in 0..5 {
+		vm.tick(None).unwrap();
+	}
+
+	assert_eq!(vm.harts[0].x[5], 0x1234);
+	assert_eq!(vm.harts[0].x[7], 0x5678);
+	assert_eq!(vm.harts[0].x[8], 0x5678, "stale translations stay cached until sfence.vma");
+	assert_eq!(vm.harts[0].x[9], 0xabcd);
+
+	assert_eq!(vm.harts[0].csr[MCAUSE], 13, "user pages need SUM");
+	assert_eq!(vm.harts[0].csr[MTVAL], 0x1008);
+	assert_eq!(vm.harts[0].csr[MEPC], RAM_BASE + 7 * 4);
+	assert_eq!(vm.harts[0].x[13], 0);
+
+	let pte = vm.bus.ram.load(L0 + 8, 64).unwrap();
+	assert_eq!(pte & (PTE_A | PTE_D), PTE_A | PTE_D, "A and D are set on access");
+	assert_eq!(vm.bus.ram.load(0x20008, 64).unwrap(), 0xabcd);
+}
+
+#[test]
+fn user_mode_and_mprv() {
+	let mut ram = common::Mem::with_program(&[
+		0x00853283, // ld x5, 8(x10)
+	]);
+	page_tables(&mut ram);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	common::open_pmp(&mut vm.harts[0]);
+	vm.harts[0].csr[SATP] = SATP_MODE_SV39 << 60 | (RAM_BASE + ROOT) >> 12;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+	vm.harts[0].x[10] = 0x2000;
+
+	// M-mode with MPRV translates loads as if running in U-mode.
+	vm.harts[0].csr[MSTATUS] |= MASK_MPRV;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].x[5], 0x5678);
+
+	// U-mode can't execute the supervisor-only code page.
+	vm.harts[0].mode = Mode::User;
+	vm.harts[0].pc = RAM_BASE;
+	common::open_pmp(&mut vm.harts[0]);
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 12);
+	assert_eq!(vm.harts[0].csr[MTVAL], RAM_BASE);
+}
+
+/// Runs loads from `x10` and `x11` in M-mode with MPRV set, so data
+/// accesses are translated as S-mode under `satp` while fetch is not.
+fn mprv_loads(mode: u64, vaddr: u64, bad_vaddr: u64) -> Cpu {
+	let mut ram = common::Mem::with_program(&[
+		0x00053283, // ld x5, 0(x10)
+		0x0005b303, // ld x6, 0(x11)
+	]);
+	// The root's second entry maps a leaf at physical address 0, as large
+	// as the root level of the mode allows.
+	ram.write_u64(ROOT + 8, PTE_R | PTE_A | PTE_V);
+	ram.write_u64(0x1008, 0x4242);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	common::open_pmp(&mut vm.harts[0]);
+	vm.harts[0].csr[SATP] = mode << 60 | (RAM_BASE + ROOT) >> 12;
+	vm.harts[0].csr[MSTATUS] |= MASK_MPRV | (Mode::Supervisor as u64) << 11;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+	vm.harts[0].x[10] = vaddr;
+	vm.harts[0].x[11] = bad_vaddr;
+
+	vm.tick(None).unwrap();
+	vm.tick(None).unwrap();
+
+	vm.harts.swap_remove(0)
+}
+
+#[test]
+fn sv48_terapage() {
+	let cpu = mprv_loads(SATP_MODE_SV48, (1 << 39) + RAM_BASE + 0x1008, 1 << 47);
+
+	assert_eq!(cpu.x[5], 0x4242);
+	assert_eq!(cpu.csr[MCAUSE], 13, "bit 47 must be sign-extended");
+	assert_eq!(cpu.csr[MTVAL], 1 << 47);
+}
+
+#[test]
+fn sv57_petapage() {
+	let cpu = mprv_loads(SATP_MODE_SV57, (1 << 48) + RAM_BASE + 0x1008, 1 << 56);
+
+	assert_eq!(cpu.x[5], 0x4242);
+	assert_eq!(cpu.csr[MCAUSE], 13, "bit 56 must be sign-extended");
+	assert_eq!(cpu.csr[MTVAL], 1 << 56);
+}
+
+#[test]
+fn unsupported_modes_are_ignored() {
+	let code = [
+		0x18061073, // csrrw x0, satp, x12
+		0x180023f3, // csrrs x7, satp, x0
+		0x18069073, // csrrw x0, satp, x13
+		0x18002473, // csrrs x8, satp, x0
+	];
+	let (cpu, _) = common::run(&code, 4, |cpu| {
+		cpu.mmu.max_mode = PagingMode::Sv39;
+		cpu.x[12] = SATP_MODE_SV39 << 60 | 0x123;
+		cpu.x[13] = SATP_MODE_SV48 << 60 | 0x456;
+	});
+
+	assert_eq!(cpu.x[7], SATP_MODE_SV39 << 60 | 0x123);
+	assert_eq!(cpu.x[8], SATP_MODE_SV39 << 60 | 0x123);
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+/// addi x9, x9, 1 forever.
+const COUNT: [u32; 2] = [
+	0x00148493, // addi x9, x9, 1
+	0xffdff06f, // jal x0, -4
+];
+
+fn enable(context: usize) -> u64 {
+	PLIC_ENABLE + context as u64 * PLIC_ENABLE_STRIDE
+}
+
+fn claim(context: usize) -> u64 {
+	PLIC_CLAIM + context as u64 * PLIC_CONTEXT_STRIDE
+}
+
+#[test]
+fn priorities_and_thresholds() {
+	let mut plic = Plic::new(1);
+	for (source, priority) in [(3, 2), (5, 5), (40, 5), (41, 0)] {
+		plic.store(PLIC_PRIORITY + 4 * source as u64, priority, 32).unwrap();
+		plic.set_level(source, true);
+	}
+	plic.store(enable(0), 1 << 3 | 1 << 5, 32).unwrap();
+	plic.store(enable(0) + 4, 1 << 8 | 1 << 9, 32).unwrap();
+	plic.store(PLIC_THRESHOLD, 2, 32).unwrap();
+
+	assert!(plic.is_interrupting(0));
+	assert!(!plic.is_interrupting(1), "the S-mode context enables nothing");
+	assert_eq!(plic.load(claim(0), 32).unwrap(), 5, "the lowest ID among equals");
+	assert_eq!(plic.load(claim(0), 32).unwrap(), 40);
+	assert_eq!(plic.load(claim(0), 32).unwrap(), 0, "3 is at the threshold, 41 never interrupts");
+	assert!(!plic.is_interrupting(0));
+	assert_eq!(plic.load(PLIC_PENDING + 4, 32).unwrap(), 1 << 9);
+
+	plic.store(PLIC_THRESHOLD, 1, 32).unwrap();
+	assert_eq!(plic.load(claim(0), 32).unwrap(), 3);
+
+	plic.store(PLIC_PRIORITY + 4, 100, 32).unwrap();
+	assert_eq!(plic.load(PLIC_PRIORITY + 4, 32).unwrap(), PLIC_MAX_PRIORITY as u64);
+	plic.store(PLIC_PRIORITY, 1, 32).unwrap();
+	assert_eq!(plic.load(PLIC_PRIORITY, 32).unwrap(), 0, "there is no source 0");
+	plic.store(enable(1), 0xffff_ffff, 32).unwrap();
+	assert_eq!(plic.load(enable(1), 32).unwrap(), 0xffff_fffe);
+}
+
+#[test]
+fn gateways() {
+	let mut plic = Plic::new(1);
+	plic.store(PLIC_PRIORITY + 4 * 5, 1, 32).unwrap();
+	plic.store(enable(0), 1 << 5, 32).unwrap();
+
+	plic.set_level(5, true);
+	assert_eq!(plic.load(claim(0), 32).unwrap(), 5);
+	plic.set_level(5, false);
+	plic.set_level(5, true);
+	assert!(!plic.is_pending(5), "nothing is forwarded until completion");
+
+	plic.store(claim(1), 5, 32).unwrap();
+	assert!(!plic.is_pending(5), "the S-mode context doesn't have it enabled");
+	plic.store(claim(0), 5, 32).unwrap();
+	assert!(plic.is_pending(5), "the line is still raised");
+
+	// A request stays pending after the line drops, until it is claimed.
+	plic.set_level(5, false);
+	assert!(plic.is_pending(5));
+	assert_eq!(plic.load(claim(0), 32).unwrap(), 5);
+	plic.store(claim(0), 5, 32).unwrap();
+	assert!(!plic.is_pending(5));
+}
+
+#[test]
+fn machine_external_interrupt() {
+	let mut program = [0; 0x43];
+	program[..2].copy_from_slice(&COUNT);
+	program[0x40..].copy_from_slice(&[
+		0x0042a303, // lw x6, 4(x5)
+		0x0062a223, // sw x6, 4(x5)
+		0x30200073, // mret
+	]);
+	let mut ram = common::Mem::with_program(&program);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+	vm.harts[0].csr.write(MIE, MASK_MEIP);
+	vm.harts[0].csr[MSTATUS] |= MASK_MIE;
+	vm.harts[0].x[5] = PLIC_BASE + PLIC_THRESHOLD;
+	vm.bus.store(PLIC_BASE + PLIC_PRIORITY + 4 * 7, 1, 32).unwrap();
+	vm.bus.store(PLIC_BASE + PLIC_ENABLE, 1 << 7, 32).unwrap();
+
+	vm.bus.plic.set_level(7, true);
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], MASK_INTERRUPT_BIT | 11);
+	assert_eq!(vm.harts[0].pc, RAM_BASE + 0x100);
+
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].x[6], 7);
+	assert_eq!(vm.harts[0].csr.read(MIP) & MASK_MEIP, 0, "claiming clears MEIP");
+
+	vm.bus.plic.set_level(7, false);
+	for _ in 0..2 {
+		vm.tick(None).unwrap();
+	}
+	assert_eq!(vm.harts[0].pc, RAM_BASE + 4);
+	for _ in 0..2 {
+		vm.tick(None).unwrap();
+	}
+	assert_eq!(vm.harts[0].x[9], 2, "no interrupt after the source is done");
+}
+
+#[test]
+fn supervisor_external_interrupt() {
+	let mut ram = common::Mem::with_program(&COUNT);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.bus.store(PLIC_BASE + PLIC_PRIORITY + 4 * 7, 1, 32).unwrap();
+	vm.bus.store(PLIC_BASE + PLIC_SENABLE, 1 << 7, 32).unwrap();
+
+	vm.bus.plic.set_level(7, true);
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr.read(MIP) & (MASK_MEIP | MASK_SEIP), MASK_SEIP);
+}
+
+#[test]
+fn seip_follows_the_plic() {
+	let mut ram = common::Mem::with_program(&[
+		0x00000013, // addi x0, x0, 0
+		0x3440b073, // csrrc x0, mip, x1
+		COUNT[0],
+		COUNT[1],
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].x[1] = MASK_STIP;
+	vm.harts[0].csr.write(MIDELEG, MASK_SEIP);
+	vm.bus.store(PLIC_BASE + PLIC_PRIORITY + 4 * 7, 1, 32).unwrap();
+	vm.bus.store(PLIC_BASE + PLIC_SENABLE, 1 << 7, 32).unwrap();
+
+	// SIE is clear, so nothing takes the interrupt.
+	vm.bus.plic.set_level(7, true);
+	vm.tick(None).unwrap();
+	assert_ne!(vm.harts[0].csr.read(MIP) & MASK_SEIP, 0);
+	assert_ne!(vm.harts[0].csr.read(SIP) & MASK_SEIP, 0);
+	vm.tick(None).unwrap();
+
+	vm.bus.plic.set_level(7, false);
+	assert_eq!(vm.bus.load(PLIC_BASE + PLIC_SCLAIM, 32).unwrap(), 7);
+	vm.bus.store(PLIC_BASE + PLIC_SCLAIM, 7, 32).unwrap();
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr.read(MIP) & MASK_SEIP, 0, "csrc didn't write the PLIC's SEIP back");
+
+	// What software writes is ORed in, and stays after the PLIC's goes.
+	vm.harts[0].csr.write(MIP, MASK_SEIP);
+	vm.bus.plic.set_level(7, true);
+	vm.tick(None).unwrap();
+	vm.bus.plic.set_level(7, false);
+	assert_eq!(vm.bus.load(PLIC_BASE + PLIC_SCLAIM, 32).unwrap(), 7);
+	vm.bus.store(PLIC_BASE + PLIC_SCLAIM, 7, 32).unwrap();
+	vm.tick(None).unwrap();
+	assert_ne!(vm.harts[0].csr.read(MIP) & MASK_SEIP, 0);
+	vm.harts[0].csr.write(MIP, 0);
+	assert_eq!(vm.harts[0].csr.read(MIP) & MASK_SEIP, 0);
+}
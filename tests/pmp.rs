@@ -0,0 +1,77 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+#[test]
+fn csr_configuration_and_faults() {
+	let mut ram = common::Mem::with_program(&[
+		0x3b051073, // csrrw x0, pmpaddr0, x10
+		0x3b159073, // csrrw x0, pmpaddr1, x11
+		0x3a061073, // csrrw x0, pmpcfg0, x12
+		0x3a0026f3, // csrrs x13, pmpcfg0, x0
+		0x00072283, // lw x5, 0(x14)
+		0x0057a023, // sw x5, 0(x15)
+		0x00073303, // ld x6, 0(x14)
+	]);
+	ram.write_u64(0x2000, 0x1122_3344_5566_7788);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	// Run data accesses as S-mode, but keep fetching in M-mode.
+	vm.harts[0].csr[MSTATUS] |= MASK_MPRV | (Mode::Supervisor as u64) << 11;
+	vm.harts[0].x[10] = (RAM_BASE + 0x2000) >> 2;
+	vm.harts[0].x[11] = (RAM_BASE + 0x3000) >> 2 | 0x1ff;
+	vm.harts[0].x[12] = 0x02_1b_11; // NA4 R, NAPOT RW, reserved W-only
+	vm.harts[0].x[14] = RAM_BASE + 0x2000;
+	vm.harts[0].x[15] = RAM_BASE + 0x3000;
+
+	for _ in 0..6 {
+		vm.tick(None).unwrap();
+	}
+
+	assert_eq!(vm.harts[0].x[13], 0x00_1b_11, "W without R is reserved");
+	assert_eq!(vm.harts[0].x[5], 0x5566_7788);
+	assert_eq!(vm.bus.ram.load(0x3000, 32).unwrap(), 0x5566_7788);
+
+	assert!(
+		matches!(vm.tick(None), Err(Exception::LoadAccessFault(addr)) if addr == RAM_BASE + 0x2000),
+		"accesses must lie entirely within the matching entry"
+	);
+	assert_eq!(vm.harts[0].csr[MEPC], RAM_BASE + 6 * 4);
+}
+
+#[test]
+fn matching_and_locking() {
+	let mut pmp = Pmp::new();
+
+	// Unlocked entries constrain S/U-mode but not M-mode, and S/U-mode
+	// accesses that match nothing fail.
+	pmp.write_addr(0, (RAM_BASE + 0x1000) >> 2);
+	pmp.write_cfg(0, (PMP_A_TOR | PMP_R) as u64);
+	assert!(pmp.check(RAM_BASE, 64, AccessType::Load, Mode::Supervisor));
+	assert!(!pmp.check(RAM_BASE, 64, AccessType::Store, Mode::Supervisor));
+	assert!(pmp.check(RAM_BASE, 64, AccessType::Store, Mode::Machine));
+	assert!(!pmp.check(RAM_BASE + 0x1000, 64, AccessType::Load, Mode::User));
+	assert!(pmp.check(RAM_BASE + 0x1000, 64, AccessType::Load, Mode::Machine));
+
+	// Locking entry 1 (a TOR entry) freezes its configuration and both
+	// address registers bounding it, and applies it to M-mode as well.
+	pmp.write_addr(1, (RAM_BASE + 0x2000) >> 2);
+	pmp.write_cfg(0, ((PMP_L | PMP_A_TOR | PMP_X) as u64) << 8 | (PMP_A_TOR | PMP_R) as u64);
+	pmp.write_cfg(0, 0);
+	pmp.write_addr(0, 0);
+	pmp.write_addr(1, 0);
+
+	assert_eq!(pmp.read_cfg(0), 0x8c << 8);
+	assert_eq!(pmp.read_addr(0), (RAM_BASE + 0x1000) >> 2);
+	assert_eq!(pmp.read_addr(1), (RAM_BASE + 0x2000) >> 2);
+	assert!(pmp.check(RAM_BASE + 0x1800, 16, AccessType::Instruction, Mode::Machine));
+	assert!(!pmp.check(RAM_BASE + 0x1800, 64, AccessType::Load, Mode::Machine));
+
+	// NAPOT regions are sized by the trailing ones of pmpaddr.
+	pmp.write_addr(2, (RAM_BASE + 0x4000) >> 2 | 0b0111);
+	pmp.write_cfg(0, ((PMP_A_NAPOT | PMP_W | PMP_R) as u64) << 16);
+	assert!(pmp.check(RAM_BASE + 0x4038, 64, AccessType::Store, Mode::User));
+	assert!(!pmp.check(RAM_BASE + 0x4040, 64, AccessType::Store, Mode::User));
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+const MTVEC_ADDR: u64 = RAM_BASE + 0x100;
+const STVEC_ADDR: u64 = RAM_BASE + 0x200;
+
+/// Executes a single instruction in `mode` and returns the hart after any
+/// resulting trap has been taken.
+fn step(inst: u32, mode: Mode, setup: impl FnOnce(&mut Cpu)) -> Cpu {
+	let mut ram = common::Mem::with_program(&[inst]);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].mode = mode;
+	vm.harts[0].csr[MTVEC] = MTVEC_ADDR;
+	vm.harts[0].csr[STVEC] = STVEC_ADDR;
+	common::open_pmp(&mut vm.harts[0]);
+	setup(&mut vm.harts[0]);
+
+	let _ = vm.tick(None);
+
+	vm.harts.swap_remove(0)
+}
+
+fn assert_illegal(cpu: &Cpu, inst: u32) {
+	assert_eq!(cpu.mode, Mode::Machine);
+	assert_eq!(cpu.pc, MTVEC_ADDR);
+	assert_eq!(cpu.csr[MCAUSE], 2);
+	assert_eq!(cpu.csr[MTVAL], inst as u64);
+	assert_eq!(cpu.csr[MEPC], RAM_BASE);
+}
+
+#[test]
+fn environment_calls() {
+	let cpu = step(0x00000073, Mode::User, |_| {}); // ecall
+	assert_eq!((cpu.mode, cpu.pc), (Mode::Machine, MTVEC_ADDR));
+	assert_eq!(cpu.csr[MCAUSE], 8);
+	assert_eq!(cpu.csr[MEPC], RAM_BASE);
+	assert_eq!(cpu.csr[MTVAL], 0);
+	assert_eq!((cpu.csr[MSTATUS] & MASK_MPP) >> 11, Mode::User as u64);
+
+	let cpu = step(0x00000073, Mode::Supervisor, |cpu| cpu.csr[MEDELEG] = 1 << 9);
+	assert_eq!((cpu.mode, cpu.pc), (Mode::Supervisor, STVEC_ADDR));
+	assert_eq!(cpu.csr[SCAUSE], 9);
+	assert_eq!(cpu.csr[SEPC], RAM_BASE);
+	assert_ne!(cpu.csr[MSTATUS] & MASK_SPP, 0);
+
+	// M-mode traps are never delegated.
+	let cpu = step(0x00000073, Mode::Machine, |cpu| cpu.csr[MEDELEG] = !0);
+	assert_eq!((cpu.mode, cpu.pc), (Mode::Machine, MTVEC_ADDR));
+	assert_eq!(cpu.csr[MCAUSE], 11);
+
+	let cpu = step(0x00100073, Mode::Supervisor, |_| {}); // ebreak
+	assert_eq!(cpu.csr[MCAUSE], 3);
+	assert_eq!(cpu.csr[MTVAL], RAM_BASE);
+}
+
+#[test]
+fn privileged_instructions() {
+	let mret = 0x30200073;
+	assert_illegal(&step(mret, Mode::Supervisor, |_| {}), mret);
+
+	let sret = 0x10200073;
+	assert_illegal(&step(sret, Mode::User, |_| {}), sret);
+	assert_illegal(&step(sret, Mode::Supervisor, |cpu| cpu.csr[MSTATUS] |= MASK_TSR), sret);
+	let cpu = step(sret, Mode::Supervisor, |cpu| cpu.csr[SEPC] = RAM_BASE + 0x40);
+	assert_eq!((cpu.mode, cpu.pc), (Mode::User, RAM_BASE + 0x40));
+
+	let wfi = 0x10500073;
+	assert_illegal(&step(wfi, Mode::User, |_| {}), wfi);
+	assert_illegal(&step(wfi, Mode::Supervisor, |cpu| cpu.csr[MSTATUS] |= MASK_TW), wfi);
+	assert_eq!(step(wfi, Mode::Supervisor, |_| {}).pc, RAM_BASE + 4);
+
+	let sfence = 0x12000073; // sfence.vma x0, x0
+	assert_illegal(&step(sfence, Mode::User, |_| {}), sfence);
+	assert_illegal(&step(sfence, Mode::Supervisor, |cpu| cpu.csr[MSTATUS] |= MASK_TVM), sfence);
+	assert_eq!(step(sfence, Mode::Supervisor, |_| {}).pc, RAM_BASE + 4);
+}
+
+#[test]
+fn csr_privilege() {
+	let read_sstatus = 0x100022f3; // csrrs x5, sstatus, x0
+	assert_illegal(&step(read_sstatus, Mode::User, |_| {}), read_sstatus);
+	assert_eq!(step(read_sstatus, Mode::Supervisor, |_| {}).pc, RAM_BASE + 4);
+
+	let write_mstatus = 0x30029073; // csrrw x0, mstatus, x5
+	assert_illegal(&step(write_mstatus, Mode::Supervisor, |_| {}), write_mstatus);
+
+	// mhartid is read-only, even for M-mode.
+	let read_mhartid = 0xf14022f3; // csrrs x5, mhartid, x0
+	let write_mhartid = 0xf1429073; // csrrw x0, mhartid, x5
+	assert_eq!(step(read_mhartid, Mode::Machine, |_| {}).pc, RAM_BASE + 4);
+	assert_illegal(&step(write_mhartid, Mode::Machine, |_| {}), write_mhartid);
+
+	let read_satp = 0x180022f3; // csrrs x5, satp, x0
+	assert_eq!(step(read_satp, Mode::Supervisor, |_| {}).pc, RAM_BASE + 4);
+	assert_illegal(&step(read_satp, Mode::Supervisor, |cpu| cpu.csr[MSTATUS] |= MASK_TVM), read_satp);
+}
+
+#[test]
+fn taking_an_interrupt_leaves_it_pending() {
+	let cpu = step(0x00000013, Mode::User, |cpu| {
+		cpu.csr.write(MIDELEG, MASK_SSIP | MASK_STIP);
+		cpu.csr.write(MIE, MASK_SSIP | MASK_STIP);
+		cpu.csr.write(MIP, MASK_SSIP | MASK_STIP);
+	});
+
+	assert_eq!(cpu.mode, Mode::Supervisor);
+	assert_eq!(cpu.pc, STVEC_ADDR);
+	assert_eq!(cpu.csr[SCAUSE], MASK_INTERRUPT_BIT | 1);
+	assert_eq!(cpu.csr.read(SIP), MASK_SSIP | MASK_STIP, "only software clears them");
+}
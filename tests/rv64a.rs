@@ -0,0 +1,71 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+#[test]
+fn reservations_and_amos() {
+	let mut code = vec![
+		0x1000a2af, // lr.w x5, (x1)
+		0x1820a32f, // sc.w x6, x2, (x1)
+		0x1820a3af, // sc.w x7, x2, (x1)
+		0x1001b42f, // lr.d x8, (x3)
+		0x0001b023, // sd x0, 0(x3)
+		0x1821b4af, // sc.d x9, x2, (x3)
+		0x0020a52f, // amoadd.w x10, x2, (x1)
+		0xe040a5af, // amomaxu.w x11, x4, (x1)
+		0xa020a62f, // amomax.w x12, x2, (x1)
+		0x0841b6af, // amoswap.d x13, x4, (x3)
+		0x4021b72f, // amoor.d x14, x2, (x3)
+		0x0000a783, // lw x15, 0(x1)
+		0x0001b803, // ld x16, 0(x3)
+		0x1000a8af, // lr.w x17, (x1)
+	];
+	let steps = code.len();
+	code.resize(0x40, 0);
+	code.extend([
+		0x8000_0000, 0, // word at x1
+		0x0000_1122, 0, // doubleword at x3
+	]);
+
+	let (cpu, _) = common::run(&code, steps, |cpu| {
+		cpu.x[1] = RAM_BASE + 0x100;
+		cpu.x[2] = 5;
+		cpu.x[3] = RAM_BASE + 0x108;
+		cpu.x[4] = u64::MAX;
+	});
+
+	assert_eq!(cpu.x[5], 0xffff_ffff_8000_0000, "lr.w sign-extends");
+	assert_eq!(cpu.x[6], 0, "sc.w after lr.w succeeds");
+	assert_eq!(cpu.x[7], 1, "sc.w clears the reservation");
+	assert_eq!(cpu.x[8], 0x1122);
+	assert_eq!(cpu.x[9], 1, "stores invalidate the reservation");
+	assert_eq!(cpu.x[10], 5);
+	assert_eq!(cpu.x[11], 10);
+	assert_eq!(cpu.x[12], u64::MAX, "amo*.w sign-extends the old value");
+	assert_eq!(cpu.x[13], 0, "the failed sc.d must not store");
+	assert_eq!(cpu.x[14], u64::MAX);
+	assert_eq!(cpu.x[15], 5, "amomax.w compares signed words");
+	assert_eq!(cpu.x[16], u64::MAX);
+	assert_eq!(
+		cpu.reservation,
+		Some(Reservation { addr: RAM_BASE + 0x100, size: 32 })
+	);
+}
+
+#[test]
+fn misaligned_amo_traps() {
+	let mut ram = common::Mem::with_program(&[
+		0x000a202f, // amoadd.w x0, x0, (x20)
+	]);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].x[20] = RAM_BASE + 0x102;
+
+	assert!(matches!(
+		vm.tick(None),
+		Err(Exception::StoreAMOAddrMisaligned(addr)) if addr == RAM_BASE + 0x102
+	));
+	assert_eq!(vm.harts[0].csr[MEPC], RAM_BASE);
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+#[test]
+fn compressed_program() {
+	let code = common::parcels(&[
+		0x4515, // c.li a0, 5
+		0x050d, // c.addi a0, 3
+		0x85aa, // c.mv a1, a0
+		0x058a, // c.slli a1, 2
+		0x7139, // c.addi16sp sp, -64
+		0xe42e, // c.sdsp a1, 8(sp)
+		0x6622, // c.ldsp a2, 8(sp)
+		0x0297, 0x0000, // auipc t0, 0
+		0x0814, // c.addi4spn a3, sp, 16
+		0xe119, // c.bnez a0, 6
+		0x4705, // c.li a4, 1
+		0x4705, // c.li a4, 1
+		0xc119, // c.beqz a0, 6
+		0x0001, // c.nop
+		0x0793, 0x0070, // addi a5, x0, 7
+		0x0297, 0x0000, // auipc t0, 0
+		0x02a9, // c.addi t0, 10
+		0x9282, // c.jalr t0
+		0x4709, // c.li a4, 2
+		0x0001, // c.nop
+	]);
+
+	let (cpu, ram) = common::run(&code, 17, |_| {});
+
+	let sp = RAM_BASE + common::RAM_SIZE - 64;
+	assert_eq!(cpu.x[10], 8);
+	assert_eq!(cpu.x[11], 32);
+	assert_eq!(cpu.x[12], 32, "c.ldsp should read back what c.sdsp stored");
+	assert_eq!(ram.mem[(sp - RAM_BASE + 8) as usize], 32);
+	assert_eq!(cpu.x[2], sp);
+	assert_eq!(cpu.x[13], sp + 16);
+	assert_eq!(cpu.x[14], 0, "taken branches and c.jalr should skip c.li a4");
+	assert_eq!(cpu.x[15], 7);
+	assert_eq!(cpu.x[5], RAM_BASE + 44);
+	assert_eq!(cpu.x[1], RAM_BASE + 42, "c.jalr links to pc + 2");
+	assert_eq!(cpu.pc, RAM_BASE + 46);
+}
+
+#[test]
+fn fetch_across_page_boundary() {
+	let mut code = vec![0x0001; 0x7ff]; // c.nop
+	code.extend([0x0793, 0x0070]); // addi a5, x0, 7
+
+	let (cpu, _) = common::run(&common::parcels(&code), 1, |cpu| cpu.pc = RAM_BASE + 0xffe);
+
+	assert_eq!(cpu.x[15], 7);
+	assert_eq!(cpu.pc, RAM_BASE + 0x1002);
+}
+
+#[test]
+fn misaligned_target_without_c() {
+	let mut ram = common::Mem::with_program(&[
+		0x0020006f, // jal x0, 2
+	]);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].csr[MISA] &= !MISA_C;
+
+	assert!(matches!(
+		vm.tick(None),
+		Err(Exception::InstructionAddrMisalignment(addr)) if addr == RAM_BASE + 2
+	));
+	assert_eq!(vm.harts[0].csr[MEPC], RAM_BASE, "mepc should point at the jump");
+}
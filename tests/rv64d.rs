@@ -0,0 +1,41 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+#[test]
+fn double_precision() {
+	let mut code = vec![
+		0x0100b087, // fld f1, 16(x1)
+		0x0180b107, // fld f2, 24(x1)
+		0x0220a1d3, // fadd.d f3, f1, f2, rdn
+		0x0230b027, // fsd f3, 32(x1)
+		0x40108253, // fcvt.s.d f4, f1, rne
+		0x420202d3, // fcvt.d.s f5, f4
+		0xc22112d3, // fcvt.l.d x5, f2, rtz
+		0xc2311353, // fcvt.lu.d x6, f2, rtz
+		0x001023f3, // csrrs x7, fflags, x0
+		0x00108353, // fadd.s f6, f1, f1, rne
+		0xe2008453, // fmv.x.d x8, f1
+		0xe20114d3, // fclass.d x9, f2
+		0x5a028453, // fsqrt.d f8, f5, rne
+	];
+	code.resize(20, 0);
+	code.extend([
+		0x9999999a, 0x3fb99999, // 0.1
+		0x00000000, 0xc0040000, // -2.5
+	]);
+
+	let (cpu, ram) = common::run(&code, 13, |cpu| cpu.x[1] = RAM_BASE + 0x40);
+
+	assert_eq!(cpu.f[3], 0xc003_3333_3333_3334, "fadd.d should round down");
+	assert_eq!(ram.mem[0x60..0x68], 0xc003_3333_3333_3334u64.to_le_bytes());
+	assert_eq!(cpu.f[4], 0xffff_ffff_3dcc_cccd, "fcvt.s.d result is NaN-boxed");
+	assert_eq!(cpu.f[5], 0x3fb9_9999_a000_0000);
+	assert_eq!(cpu.x[5] as i64, -2);
+	assert_eq!(cpu.x[6], 0, "negative values saturate to zero");
+	assert_eq!(cpu.x[7], 0b10001, "fflags should hold NV | NX");
+	assert_eq!(cpu.f[6], 0xffff_ffff_7fc0_0000, "doubles aren't valid singles");
+	assert_eq!(cpu.x[8], 0x3fb9_9999_9999_999a);
+	assert_eq!(cpu.x[9], 1 << 1, "fclass.d should report a negative normal");
+	assert_eq!(cpu.f[8], 0x3fd4_3d13_64cf_eb7b);
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+const ONE: u64 = 0x3f80_0000;
+const BOXED_ONE: u64 = 0xffff_ffff_3f80_0000;
+
+#[test]
+fn rounding_modes() {
+	let code = [
+		0xf00080d3, // fmv.w.x f1, x1
+		0xf0010153, // fmv.w.x f2, x2
+		0x002081d3, // fadd.s f3, f1, f2, rne
+		0x0020b253, // fadd.s f4, f1, f2, rup
+		0x001022f3, // csrrs x5, fflags, x0
+		0x1010f2c3, // fmadd.s f5, f1, f1, f2, dyn
+	];
+
+	let (cpu, _) = common::run(&code, 6, |cpu| {
+		cpu.x[1] = ONE;
+		cpu.x[2] = 0x3380_0000; // 2^-24, half an ulp of 1.0
+		cpu.csr[FCSR] = 0b011 << 5; // frm = RUP
+	});
+
+	assert_eq!(cpu.f[3], BOXED_ONE);
+	assert_eq!(cpu.f[4], BOXED_ONE + 1);
+	assert_eq!(cpu.x[5], 0b00001, "fflags should only report NX");
+	assert_eq!(cpu.f[5], BOXED_ONE + 1, "fmadd.s should round with frm");
+	assert_eq!(cpu.csr[MSTATUS] & MASK_FS, MASK_FS, "FS should be Dirty");
+}
+
+#[test]
+fn special_values() {
+	let code = [
+		0xf0000053, // fmv.w.x f0, x0
+		0xf00080d3, // fmv.w.x f1, x1
+		0x18008353, // fdiv.s f6, f1, f0, rne
+		0xc0031353, // fcvt.w.s x6, f6, rtz
+		0xe00303d3, // fmv.x.w x7, f6
+		0xe0031453, // fclass.s x8, f6
+		0x003024f3, // csrrs x9, fcsr, x0
+	];
+
+	let (cpu, _) = common::run(&code, 7, |cpu| cpu.x[1] = ONE);
+
+	assert_eq!(cpu.f[6], 0xffff_ffff_7f80_0000, "1.0 / 0.0 should be +inf");
+	assert_eq!(cpu.x[6], 0x7fff_ffff, "fcvt.w.s of +inf should saturate");
+	assert_eq!(cpu.x[7], 0x7f80_0000);
+	assert_eq!(cpu.x[8], 1 << 7, "fclass.s should report +inf");
+	assert_eq!(cpu.x[9], 0b11000, "fcsr should hold NV | DZ");
+}
+
+#[test]
+fn nan_boxing() {
+	let code = [
+		0x002081d3, // fadd.s f3, f1, f2, rne
+		0x28208253, // fmin.s f4, f1, f2
+		0xe00183d3, // fmv.x.w x7, f3
+	];
+
+	let (cpu, _) = common::run(&code, 3, |cpu| {
+		cpu.f[1] = ONE; // not NaN-boxed
+		cpu.f[2] = BOXED_ONE;
+	});
+
+	assert_eq!(cpu.f[3], 0xffff_ffff_7fc0_0000, "unboxed operand reads as NaN");
+	assert_eq!(cpu.f[4], BOXED_ONE, "fmin.s should ignore the NaN operand");
+	assert_eq!(cpu.x[7], 0x7fc0_0000);
+}
+
+#[test]
+fn load_store() {
+	let code = [
+		0x0080a087, // flw f1, 8(x1)
+		0x0010a627, // fsw f1, 12(x1)
+		0x40490fdb, // 3.1415927
+	];
+
+	let (cpu, ram) = common::run(&code, 2, |cpu| cpu.x[1] = RAM_BASE);
+
+	assert_eq!(cpu.f[1], 0xffff_ffff_4049_0fdb);
+	assert_eq!(ram.mem[12..16], 0x40490fdbu32.to_le_bytes());
+}
+
+#[test]
+fn disabled_fpu_traps() {
+	let mut ram = common::Mem::with_program(&[
+		0x002081d3, // fadd.s f3, f1, f2, rne
+	]);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].csr[MSTATUS] &= !MASK_FS;
+
+	assert!(matches!(vm.tick(None), Err(Exception::IllegalInstruction(0x002081d3))));
+}
@@ -1,73 +1,74 @@
-extern crate std;
+mod common;
 
-use rrv64g::prelude::*;
-
-pub struct Mem {
-    pub mem: Vec<u8>,
-}
-
-impl MemIntf for Mem {
-    fn reset(&mut self) {
-        self.mem.clear();
-    }
-
-    fn load(&self, addr: u64) -> Result<u32, Exception> {
-        let addr = addr as usize;
-        if addr + 3 < self.mem.len() {
-            Ok((self.mem[addr] as u32)
-                | (self.mem[addr+1] as u32) << 8
-                | (self.mem[addr+2] as u32) << 16
-                | (self.mem[addr+3] as u32) << 24)
-        } else {
-            Err(Exception::AddressOutOfBounds(addr as u64))
-        }
-    }
-
-    fn store(&mut self, addr: u64, val: u32) -> Result<(), Exception> {
-        let addr = addr as usize;
-        if addr < self.mem.len() {
-            self.mem[addr]     = (val & 0xff) as u8;
-            self.mem[addr + 1] = ((val >> 8) & 0xff) as u8;
-            self.mem[addr + 2] = ((val >> 16) & 0xff) as u8;
-            self.mem[addr + 3] = ((val >> 24) & 0xff) as u8;
-            Ok(())
-        } else {
-            Err(Exception::AddressOutOfBounds(addr as u64))
-        }
-    }
-}
+use rrv64g::{
+	bus::RAM_BASE,
+	inst::{Inst, ENCODING_TABLE},
+};
 
 #[test]
 fn decoding() {
-	println!("{:?}", Cpu::decode(0xfae7d4e3));
+	// bge a5, a4, -88
+	let inst = ENCODING_TABLE[(0xfae7d4e3u32 & 0b1111111) as usize]
+		.as_ref()
+		.unwrap()
+		.decode(0xfae7d4e3);
 
-	assert_eq!(0, 1);
+	assert!(matches!(inst, Ok(Inst::Bge { rs1: 15, rs2: 14, imm: -88 })), "{:?}", inst);
 }
 
 #[test]
 fn integer_arithmetic() {
-	let code = vec![
-		0x13, 0x08, 0x50, 0x00, // addi x16, x0, 5 => mv 5 to x16
+	let code = [
+		0x00500813, // addi x16, x0, 5 => mv 5 to x16
 	];
 
-	// Create a memory with our program
-	let mut mem: Mem = Mem { mem: code };
+	let (cpu, _) = common::run(&code, 1, |_| {});
 
-	// Create a memory map with our memory
-	let mem_map: &mut [MemMapEntry] = &mut [
-		(
-			MemType::Ram,
-			MemLoc { start: 0x00000000, len: mem.mem.len() as u64 },
-			&mut mem,
-		),
+	assert!(cpu.x[16] == 5, "Addi fail");
+}
+
+#[test]
+fn shifts() {
+	let code = [
+		0x03c0d293, // srli x5, x1, 60
+		0x43c0d313, // srai x6, x1, 60
+		0x003093b3, // sll x7, x1, x3
+		0x0030d433, // srl x8, x1, x3
+		0x4030d4b3, // sra x9, x1, x3
+		0x0040953b, // sllw x10, x1, x4
+		0x000155bb, // srlw x11, x2, x0
+		0x0040d61b, // srliw x12, x1, 4
 	];
 
-	// Create the rest of the emulator
-	let mut cpu = Cpu::new(mem_map);
+	let (cpu, _) = common::run(&code, code.len(), |cpu| {
+		cpu.x[1] = 0x8000_0001_f00f_0080;
+		cpu.x[2] = 0xffff_0003;
+		cpu.x[3] = 36;
+		cpu.x[4] = 1;
+	});
+
+	assert_eq!(cpu.x[5], 0x8);
+	assert_eq!(cpu.x[6], 0xffff_ffff_ffff_fff8);
+	assert_eq!(cpu.x[7], 0x00f0_0800_0000_0000, "shift amounts use six bits");
+	assert_eq!(cpu.x[8], 0x0800_0000);
+	assert_eq!(cpu.x[9], 0xffff_ffff_f800_0000);
+	assert_eq!(cpu.x[10], 0xffff_ffff_e01e_0100, "word results are sign-extended");
+	assert_eq!(cpu.x[11], 0xffff_ffff_ffff_0003);
+	assert_eq!(cpu.x[12], 0x0f00_f008);
+}
 
-	let e = cpu.tick();
+#[test]
+fn fence_i() {
+	let code = [
+		0x0062a023, // sw x6, 0(x5)
+		0x0000100f, // fence.i
+		0x00000000, // overwritten with addi x7, x0, 42
+	];
 
-	println!("{:?}", e);
+	let (cpu, _) = common::run(&code, code.len(), |cpu| {
+		cpu.x[5] = RAM_BASE + 8;
+		cpu.x[6] = 0x02a00393;
+	});
 
-	assert!(cpu.regs.x[16] == 5, "Addi fail");
+	assert_eq!(cpu.x[7], 42);
 }
@@ -0,0 +1,48 @@
+mod common;
+
+#[test]
+fn multiply_divide() {
+	let code = [
+		0x022092b3, // mulh x5, x1, x2
+		0x0220a333, // mulhsu x6, x1, x2
+		0x0220b3b3, // mulhu x7, x1, x2
+		0x0220843b, // mulw x8, x1, x2
+		0x0241c4b3, // div x9, x3, x4
+		0x0200c533, // div x10, x1, x0
+		0x0241e5b3, // rem x11, x3, x4
+		0x0200e633, // rem x12, x1, x0
+		0x0200d6b3, // divu x13, x1, x0
+		0x0200f733, // remu x14, x1, x0
+		0x0200c7bb, // divw x15, x1, x0
+		0x0220d83b, // divuw x16, x1, x2
+		0x0200e8bb, // remw x17, x1, x0
+		0x0200f93b, // remuw x18, x1, x0
+		0x024a49bb, // divw x19, x20, x4
+		0x024a6abb, // remw x21, x20, x4
+	];
+
+	let (cpu, _) = common::run(&code, code.len(), |cpu| {
+		cpu.x[1] = -7i64 as u64;
+		cpu.x[2] = 0x8000_0000_0000_0001;
+		cpu.x[3] = i64::MIN as u64;
+		cpu.x[4] = -1i64 as u64;
+		cpu.x[20] = i32::MIN as u64;
+	});
+
+	assert_eq!(cpu.x[5], 3);
+	assert_eq!(cpu.x[6], 0xffff_ffff_ffff_fffc);
+	assert_eq!(cpu.x[7], 0x7fff_ffff_ffff_fffd);
+	assert_eq!(cpu.x[8], -7i64 as u64);
+	assert_eq!(cpu.x[9], i64::MIN as u64, "signed overflow returns the dividend");
+	assert_eq!(cpu.x[10], u64::MAX, "division by zero returns all ones");
+	assert_eq!(cpu.x[11], 0, "signed overflow leaves no remainder");
+	assert_eq!(cpu.x[12], -7i64 as u64, "remainder by zero returns the dividend");
+	assert_eq!(cpu.x[13], u64::MAX);
+	assert_eq!(cpu.x[14], -7i64 as u64);
+	assert_eq!(cpu.x[15], u64::MAX);
+	assert_eq!(cpu.x[16], 0xffff_ffff_ffff_fff9, "*W results are sign-extended");
+	assert_eq!(cpu.x[17], -7i64 as u64);
+	assert_eq!(cpu.x[18], -7i64 as u64);
+	assert_eq!(cpu.x[19], i32::MIN as u64);
+	assert_eq!(cpu.x[21], 0);
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+fn smp_vm<'a>(ram: &'a mut common::Mem, disk: &'a mut common::Mem, harts: usize) -> VM<'a> {
+	let mut vm = VM::with_harts(ram, common::RAM_SIZE, disk, harts);
+	for hart in vm.harts.iter_mut() {
+		hart.pc = RAM_BASE;
+		hart.csr[MTVEC] = RAM_BASE + 0x100;
+	}
+	vm
+}
+
+#[test]
+fn round_robin() {
+	let mut ram = common::Mem::with_program(&[
+		0xf14022f3, // csrrs x5, mhartid, x0
+		0x00130313, // addi x6, x6, 1
+		0x00130313, // addi x6, x6, 1
+		0x00130313, // addi x6, x6, 1
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = smp_vm(&mut ram, &mut disk, 2);
+	vm.quantum = 2;
+
+	let mut order = Vec::new();
+	for _ in 0..6 {
+		order.push(vm.current_hart());
+		vm.tick(None).unwrap();
+	}
+
+	assert_eq!(order, [0, 0, 1, 1, 0, 0]);
+	assert_eq!((vm.harts[0].x[5], vm.harts[0].x[6]), (0, 3));
+	assert_eq!((vm.harts[1].x[5], vm.harts[1].x[6]), (1, 1));
+}
+
+#[test]
+fn software_interrupt() {
+	let mut ram = common::Mem::with_program(&[
+		0x00000013, // addi x0, x0, 0
+		0x00752223, // sw x7, 4(x10)
+		0x10500073, // wfi
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = smp_vm(&mut ram, &mut disk, 2);
+	vm.harts[0].x[7] = 1;
+	vm.harts[0].x[10] = CLINT_BASE + CLINT_MSIP;
+	vm.harts[1].pc = RAM_BASE + 8;
+	vm.harts[1].csr.write(MIE, MASK_MSIP);
+	vm.harts[1].csr[MSTATUS] |= MASK_MIE;
+
+	vm.tick(None).unwrap();
+	vm.tick(None).unwrap();
+	assert!(vm.harts[1].waiting);
+
+	// Hart 0 writes hart 1's msip.
+	vm.tick(None).unwrap();
+	assert_eq!(vm.bus.load(CLINT_BASE + CLINT_MSIP + 4, 32).unwrap(), 1);
+	vm.tick(None).unwrap();
+	assert!(!vm.harts[1].waiting);
+	assert_eq!(vm.harts[1].csr[MCAUSE], MASK_INTERRUPT_BIT | 3);
+	assert_eq!(vm.harts[1].csr[MEPC], RAM_BASE + 12);
+	assert_eq!(vm.harts[1].pc, RAM_BASE + 0x100);
+	assert_eq!(vm.harts[0].csr[MIP] & MASK_MSIP, 0);
+}
+
+#[test]
+fn timers_are_per_hart() {
+	let mut ram = common::Mem::with_program(&[]);
+	let mut disk = common::Mem::default();
+	let mut vm = smp_vm(&mut ram, &mut disk, 2);
+
+	vm.bus.store(CLINT_BASE + CLINT_MTIMECMP + 8, 0, 64).unwrap();
+	for hart in vm.harts.iter_mut() {
+		hart.check_pending_interrupt(&mut vm.bus).unwrap();
+	}
+
+	assert_eq!(vm.harts[0].csr[MIP] & MASK_MTIP, 0);
+	assert_ne!(vm.harts[1].csr[MIP] & MASK_MTIP, 0);
+	assert_eq!(vm.bus.load(CLINT_BASE + CLINT_MTIMECMP, 64).unwrap(), u64::MAX);
+}
+
+/// Hart 0 runs `lr.w` and `sc.w` around a store by hart 1 to
+/// `RAM_BASE + store_offset`, returning the result of the `sc.w`.
+fn sc_around_store(store_offset: u64) -> u64 {
+	let mut ram = common::Mem::with_program(&[
+		0x1005a42f, // lr.w x8, (x11)
+		0x1875a4af, // sc.w x9, x7, (x11)
+		0x0075a023, // sw x7, 0(x11)
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = smp_vm(&mut ram, &mut disk, 2);
+	vm.harts[0].x[11] = RAM_BASE + 0x1000;
+	vm.harts[1].x[11] = RAM_BASE + store_offset;
+	vm.harts[1].pc = RAM_BASE + 8;
+
+	for _ in 0..3 {
+		vm.tick(None).unwrap();
+	}
+	vm.harts[0].x[9]
+}
+
+#[test]
+fn reservations_across_harts() {
+	assert_eq!(sc_around_store(0x1000), 1, "the other hart's store breaks the reservation");
+	assert_eq!(sc_around_store(0x1008), 0);
+}
+
+#[test]
+fn plic_contexts() {
+	let mut ram = common::Mem::with_program(&[]);
+	let mut disk = common::Mem::default();
+	let mut vm = smp_vm(&mut ram, &mut disk, 2);
+
+	let context = plic_context(1, true) as u64;
+	let threshold = PLIC_BASE + PLIC_THRESHOLD + context * PLIC_CONTEXT_STRIDE;
+	let enable = PLIC_BASE + PLIC_ENABLE + context * PLIC_ENABLE_STRIDE;
+	vm.bus.store(threshold, 7, 32).unwrap();
+	vm.bus.store(enable, 1 << UART_IRQ, 32).unwrap();
+
+	assert_eq!(vm.bus.load(threshold, 32).unwrap(), 7);
+	assert_eq!(vm.bus.load(enable, 32).unwrap(), 1 << UART_IRQ);
+	assert_eq!(vm.bus.load(PLIC_BASE + PLIC_SPRIORITY, 32).unwrap(), 0, "hart 0's context is separate");
+	assert_eq!(vm.bus.load(PLIC_BASE + PLIC_SENABLE, 32).unwrap(), 0);
+}
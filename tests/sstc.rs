@@ -0,0 +1,90 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+const STIMECMP_WRITE: u32 = 0x14d51073; // csrrw x0, stimecmp, x10
+
+fn supervisor_vm<'a>(ram: &'a mut common::Mem, disk: &'a mut common::Mem) -> VM<'a> {
+	let mut vm = VM::new(ram, common::RAM_SIZE, disk);
+	vm.harts[0].pc = RAM_BASE;
+	common::open_pmp(&mut vm.harts[0]);
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+	vm.harts[0].csr[STVEC] = RAM_BASE + 0x200;
+	vm.harts[0].csr.write(MCOUNTEREN, MASK_TM);
+	vm.harts[0].csr.write(MENVCFG, MASK_STCE);
+	vm
+}
+
+#[test]
+fn supervisor_timer() {
+	let mut ram = common::Mem::with_program(&[
+		STIMECMP_WRITE,
+		0x00000013, // nop
+		0x14d022f3, // csrrs x5, stimecmp, x0
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = supervisor_vm(&mut ram, &mut disk);
+	vm.harts[0].csr.write(MIDELEG, MASK_STIP);
+	vm.harts[0].csr.write(MIE, MASK_STIP);
+	vm.harts[0].csr[MSTATUS] |= MASK_SIE;
+	vm.harts[0].x[10] = 1005;
+	vm.bus.store(CLINT_BASE + CLINT_MTIME, 1000, 64).unwrap();
+
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MIP] & MASK_STIP, 0);
+	assert_eq!(vm.harts[0].csr[STIMECMP], 1005);
+
+	// M-mode can't fake the timer while Sstc owns STIP.
+	vm.harts[0].csr.write(MIP, MASK_STIP);
+	assert_eq!(vm.harts[0].csr[MIP] & MASK_STIP, 0);
+
+	vm.bus.store(CLINT_BASE + CLINT_MTIME, 1005, 64).unwrap();
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].mode, Mode::Supervisor, "the interrupt goes straight to S-mode");
+	assert_eq!(vm.harts[0].csr[SCAUSE], MASK_INTERRUPT_BIT | 5);
+	assert_eq!(vm.harts[0].csr[SEPC], RAM_BASE + 8);
+	assert_eq!(vm.harts[0].pc, RAM_BASE + 0x200);
+
+	// Moving the compare into the future clears STIP again.
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].x[10] = u64::MAX;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MIP] & MASK_STIP, 0);
+}
+
+#[test]
+fn access_control() {
+	let mut ram = common::Mem::with_program(&[STIMECMP_WRITE]);
+	let mut disk = common::Mem::default();
+	let mut vm = supervisor_vm(&mut ram, &mut disk);
+
+	// Without menvcfg.STCE, S-mode can't see stimecmp.
+	vm.harts[0].csr.write(MENVCFG, 0);
+	assert!(matches!(vm.tick(None), Err(Exception::IllegalInstruction(0x14d51073))));
+
+	// Guests also need henvcfg.STCE, and then write vstimecmp.
+	vm.harts[0].csr.write(MENVCFG, MASK_STCE);
+	vm.harts[0].csr.write(HCOUNTEREN, MASK_TM);
+	vm.harts[0].csr[HGATP] = 0;
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].virt = true;
+	vm.harts[0].pc = RAM_BASE;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 22);
+
+	vm.harts[0].csr.write(HENVCFG, MASK_STCE);
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].virt = true;
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].x[10] = 7;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[VSTIMECMP], 7);
+	assert_eq!(vm.harts[0].csr[STIMECMP], 0);
+	assert_eq!(vm.harts[0].csr[MIP] & MASK_VSTIP, 0);
+
+	// The guest's time includes htimedelta.
+	vm.harts[0].csr.write(HTIMEDELTA, 7);
+	vm.harts[0].check_pending_interrupt(&mut vm.bus).unwrap();
+	assert_ne!(vm.harts[0].csr[MIP] & MASK_VSTIP, 0);
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "std")]
+
+mod common;
+
+use std::{thread, time::Duration};
+
+use rrv64g::prelude::*;
+
+const COUNTER: u64 = 0x1000;
+
+fn with_program(program: &[u32]) -> SharedRam {
+	let ram = SharedRam::new(common::RAM_SIZE);
+	let bytes: Vec<u8> = program.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+	ram.write(0, &bytes);
+	ram
+}
+
+fn threaded_vm<'a>(ram: &'a mut SharedRam, disk: &'a mut common::Mem, harts: usize) -> ThreadedVM<'a> {
+	let mut vm = ThreadedVM::new(ram, disk, harts);
+	for hart in vm.harts.iter_mut() {
+		hart.pc = RAM_BASE;
+		hart.x[6] = 1;
+		hart.x[10] = RAM_BASE + COUNTER;
+	}
+	vm
+}
+
+fn counter(vm: &mut ThreadedVM) -> u64 {
+	vm.bus().load(RAM_BASE + COUNTER, 32).unwrap()
+}
+
+#[test]
+fn amos_are_atomic() {
+	let mut ram = with_program(&[
+		0x000022b7, // lui x5, 2
+		0x71028293, // addi x5, x5, 1808
+		0x0065202f, // amoadd.w x0, x6, (x10)
+		0xfff28293, // addi x5, x5, -1
+		0xfe029ce3, // bne x5, x0, -8
+		0x00000067, // jalr x0, 0(x0)
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = threaded_vm(&mut ram, &mut disk, 4);
+
+	vm.run(&Control::new(), |_| None).unwrap();
+	assert_eq!(counter(&mut vm), 40000);
+}
+
+#[test]
+fn lr_sc_loops() {
+	let mut ram = with_program(&[
+		0x000022b7, // lui x5, 2
+		0x71028293, // addi x5, x5, 1808
+		0x100523af, // lr.w x7, (x10)
+		0x00138393, // addi x7, x7, 1
+		0x1875242f, // sc.w x8, x7, (x10)
+		0xfe041ae3, // bne x8, x0, -12
+		0xfff28293, // addi x5, x5, -1
+		0xfe0296e3, // bne x5, x0, -20
+		0x00000067, // jalr x0, 0(x0)
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = threaded_vm(&mut ram, &mut disk, 4);
+
+	vm.run(&Control::new(), |_| None).unwrap();
+	assert_eq!(counter(&mut vm), 40000);
+}
+
+#[test]
+fn uart_from_every_hart() {
+	let mut ram = with_program(&[
+		0xf14022f3, // csrrs x5, mhartid, x0
+		0x04128293, // addi x5, x5, 65
+		0x10000337, // lui x6, 0x10000
+		0x00530023, // sb x5, 0(x6)
+		0x00000067, // jalr x0, 0(x0)
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = threaded_vm(&mut ram, &mut disk, 4);
+
+	let mut output = Vec::new();
+	vm.run(&Control::new(), |tx| {
+		output.extend(tx);
+		None
+	})
+	.unwrap();
+
+	output.sort();
+	assert_eq!(output, ['A', 'B', 'C', 'D']);
+}
+
+#[test]
+fn pause_and_stop() {
+	let mut ram = with_program(&[
+		0x0065202f, // amoadd.w x0, x6, (x10)
+		0xffdff06f, // jal x0, -4
+	]);
+	let snapshot = ram.clone();
+	let mut disk = common::Mem::default();
+	let mut vm = threaded_vm(&mut ram, &mut disk, 2);
+	let control = Control::new();
+
+	let read_counter = || {
+		let mut bytes = [0; 4];
+		snapshot.read(COUNTER, &mut bytes);
+		u32::from_le_bytes(bytes)
+	};
+
+	thread::scope(|scope| {
+		scope.spawn(|| {
+			thread::sleep(Duration::from_millis(20));
+			control.pause();
+			let paused = read_counter();
+			thread::sleep(Duration::from_millis(20));
+			assert_eq!(read_counter(), paused, "paused harts don't run");
+
+			control.resume();
+			thread::sleep(Duration::from_millis(20));
+			assert!(read_counter() > paused);
+			control.stop();
+		});
+
+		vm.run(&control, |_| None).unwrap();
+	});
+
+	assert!(control.is_stopped());
+	for hart in &vm.harts {
+		assert!((RAM_BASE..RAM_BASE + 8).contains(&hart.pc));
+	}
+}
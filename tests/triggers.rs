@@ -0,0 +1,183 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+const MTVEC_ADDR: u64 = RAM_BASE + 0x100;
+
+fn mcontrol6(match_: u64, flags: u64) -> u64 {
+	TRIGGER_TYPE_MCONTROL6 << 60 | match_ << 7 | flags
+}
+
+/// Programs trigger 0 from M-mode the way firmware would, with M-mode
+/// triggers enabled in tcontrol, then runs `program` in `mode`.
+fn vm_with_trigger<'a>(
+	ram: &'a mut common::Mem,
+	disk: &'a mut common::Mem,
+	tdata1: u64,
+	tdata2: u64,
+	mode: Mode,
+) -> VM<'a> {
+	let mut vm = VM::new(ram, common::RAM_SIZE, disk);
+	vm.harts[0].pc = RAM_BASE;
+	common::open_pmp(&mut vm.harts[0]);
+	vm.harts[0].csr[MTVEC] = MTVEC_ADDR;
+	vm.harts[0].x[10] = tdata1;
+	vm.harts[0].x[11] = tdata2;
+	vm.harts[0].x[12] = MASK_TCONTROL_MTE;
+
+	for _ in 0..4 {
+		vm.tick(None).unwrap();
+	}
+	vm.harts[0].mode = mode;
+	vm
+}
+
+const SETUP: [u32; 4] = [
+	0x7a001073, // csrrw x0, tselect, x0
+	0x7a151073, // csrrw x0, tdata1, x10
+	0x7a259073, // csrrw x0, tdata2, x11
+	0x7a561073, // csrrw x0, tcontrol, x12
+];
+
+#[test]
+fn execute_breakpoint() {
+	let mut program = SETUP.to_vec();
+	program.extend([
+		0x00128293, // addi x5, x5, 1
+		0x00128293, // addi x5, x5, 1
+	]);
+	let mut ram = common::Mem::with_program(&program);
+	let mut disk = common::Mem::default();
+	let tdata1 = mcontrol6(MATCH_EQUAL, MASK_MCONTROL6_M | MASK_MCONTROL6_EXECUTE);
+	let mut vm = vm_with_trigger(&mut ram, &mut disk, tdata1, RAM_BASE + 20, Mode::Machine);
+
+	vm.tick(None).unwrap();
+	vm.tick(None).unwrap();
+
+	assert_eq!(vm.harts[0].x[5], 1, "the instruction doesn't run");
+	assert_eq!(vm.harts[0].csr[MCAUSE], 3);
+	assert_eq!(vm.harts[0].csr[MEPC], RAM_BASE + 20);
+	assert_eq!(vm.harts[0].csr[MTVAL], RAM_BASE + 20);
+	assert_eq!(vm.harts[0].pc, MTVEC_ADDR);
+	assert_ne!(vm.harts[0].triggers.read(TDATA1) & MASK_MCONTROL6_HIT0, 0);
+	assert_eq!(vm.harts[0].triggers.read(TCONTROL), MASK_TCONTROL_MPTE, "MTE is saved in MPTE");
+}
+
+#[test]
+fn watchpoints() {
+	let mut program = SETUP.to_vec();
+	program.extend([
+		0x0086b383, // ld x7, 8(x13)
+		0x0066b023, // sd x6, 0(x13)
+	]);
+	let mut ram = common::Mem::with_program(&program);
+	ram.write_u64(0x2008, 0x55);
+	let mut disk = common::Mem::default();
+
+	// A stack guard covering the 256 bytes at 0x2000, for U-mode stores.
+	let tdata1 = mcontrol6(MATCH_NAPOT, MASK_MCONTROL6_U | MASK_MCONTROL6_STORE);
+	let mut vm = vm_with_trigger(&mut ram, &mut disk, tdata1, RAM_BASE + 0x2000 + 0x7f, Mode::User);
+	vm.harts[0].x[6] = 0xdead;
+	vm.harts[0].x[13] = RAM_BASE + 0x2000;
+
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].x[7], 0x55, "loads don't match");
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 3);
+	assert_eq!(vm.harts[0].csr[MTVAL], RAM_BASE + 0x2000);
+	assert_eq!(vm.harts[0].csr[MEPC], RAM_BASE + 20);
+	assert_eq!(vm.bus.ram.load(0x2000, 64).unwrap(), 0, "the store doesn't happen");
+
+	// A load data trigger fires before rd is written.
+	let tdata1 = mcontrol6(MATCH_EQUAL, MASK_MCONTROL6_S | MASK_MCONTROL6_LOAD | MASK_MCONTROL6_SELECT);
+	vm.harts[0].triggers.write(TDATA1, tdata1);
+	vm.harts[0].triggers.write(TDATA2, 0x55);
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].pc = RAM_BASE + 16;
+	vm.harts[0].x[7] = 0;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 3);
+	assert_eq!(vm.harts[0].csr[MTVAL], RAM_BASE + 0x2008);
+	assert_eq!(vm.harts[0].x[7], 0);
+}
+
+#[test]
+fn chained_range() {
+	let program = [
+		0x0086b383, // ld x7, 8(x13)
+	];
+	let mut ram = common::Mem::with_program(&program);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	common::open_pmp(&mut vm.harts[0]);
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].csr[MTVEC] = MTVEC_ADDR;
+
+	// Loads from [0x1000, 0x1010) with a >= trigger chained to a < one.
+	let flags = MASK_MCONTROL6_S | MASK_MCONTROL6_LOAD;
+	vm.harts[0].triggers.write(TSELECT, 1);
+	vm.harts[0].triggers.write(TDATA1, mcontrol6(MATCH_GE, flags | MASK_MCONTROL6_CHAIN));
+	vm.harts[0].triggers.write(TDATA2, RAM_BASE + 0x1000);
+	vm.harts[0].triggers.write(TSELECT, 2);
+	vm.harts[0].triggers.write(TDATA1, mcontrol6(MATCH_LT, flags));
+	vm.harts[0].triggers.write(TDATA2, RAM_BASE + 0x1010);
+
+	vm.harts[0].x[13] = RAM_BASE + 0x1008;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].pc, RAM_BASE + 4, "0x1010 is past the range");
+
+	vm.harts[0].mode = Mode::Supervisor;
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].x[13] = RAM_BASE + 0x1000;
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].csr[MCAUSE], 3);
+	assert_eq!(vm.harts[0].csr[MTVAL], RAM_BASE + 0x1008);
+}
+
+#[test]
+fn instruction_count() {
+	let mut program = SETUP.to_vec();
+	program.extend([
+		0x00128293, // addi x5, x5, 1
+		0x00128293, // addi x5, x5, 1
+		0x00128293, // addi x5, x5, 1
+	]);
+	let mut ram = common::Mem::with_program(&program);
+	let mut disk = common::Mem::default();
+	let tdata1 = TRIGGER_TYPE_ICOUNT << 60 | 2 << 10 | MASK_ICOUNT_U;
+	let mut vm = vm_with_trigger(&mut ram, &mut disk, tdata1, 0, Mode::User);
+
+	for _ in 0..3 {
+		vm.tick(None).unwrap();
+	}
+
+	assert_eq!(vm.harts[0].x[5], 2, "the trigger fires after two instructions");
+	assert_eq!(vm.harts[0].csr[MCAUSE], 3);
+	assert_eq!(vm.harts[0].csr[MEPC], RAM_BASE + 24);
+	assert_eq!(vm.harts[0].csr[MTVAL], 0);
+	let tdata1 = vm.harts[0].triggers.read(TDATA1);
+	assert_eq!(tdata1 & (MASK_ICOUNT_HIT | MASK_ICOUNT_PENDING | MASK_ICOUNT_COUNT), MASK_ICOUNT_HIT);
+}
+
+#[test]
+fn discovery() {
+	let program = [
+		0x7a071073, // csrrw x0, tselect, x14
+		0x7a002473, // csrrs x8, tselect, x0
+		0x7a4024f3, // csrrs x9, tinfo, x0
+		0x7a151073, // csrrw x0, tdata1, x10
+		0x7a1027f3, // csrrs x15, tdata1, x0
+	];
+
+	let (cpu, _) = common::run(&program, program.len(), |cpu| {
+		cpu.triggers = Triggers::new(2);
+		cpu.x[14] = 2;
+		// Type 2 (legacy mcontrol) isn't supported.
+		cpu.x[10] = 2 << 60 | MASK_MCONTROL6_EXECUTE;
+	});
+
+	assert_eq!(cpu.x[8], 0, "only triggers 0 and 1 exist");
+	assert_eq!(cpu.x[9], 1 << 24 | 1 << 15 | 1 << 6 | 1 << 3);
+	assert_eq!(cpu.x[15], TRIGGER_TYPE_DISABLED << 60);
+}
@@ -0,0 +1,144 @@
+use rrv64g::prelude::*;
+
+fn read(uart: &mut Uart, reg: u64) -> u8 {
+	uart.load(reg, 8).unwrap() as u8
+}
+
+fn write(uart: &mut Uart, reg: u64, val: u8) {
+	uart.store(reg, val as u64, 8).unwrap();
+}
+
+#[test]
+fn fifos_and_trigger_levels() {
+	let mut uart = Uart::new();
+	write(&mut uart, UART_FCR, MASK_UART_FCR_ENABLE | 1 << 6);
+	write(&mut uart, UART_IER, MASK_UART_IER_RDI);
+	assert_eq!(read(&mut uart, UART_IIR), UART_IIR_NONE | MASK_UART_IIR_FIFO);
+
+	for c in "abc".chars() {
+		uart.tick(Some(c));
+	}
+	assert!(!uart.is_interrupting(), "below the trigger level of 4");
+	uart.tick(Some('d'));
+	assert!(uart.is_interrupting());
+	assert_eq!(read(&mut uart, UART_IIR), UART_IIR_RDI | MASK_UART_IIR_FIFO);
+
+	assert_eq!(read(&mut uart, UART_RHR), b'a');
+	assert!(!uart.is_interrupting(), "deasserts below the trigger level");
+	for _ in 0..UART_CHAR_TIMEOUT {
+		uart.tick(None);
+	}
+	assert_eq!(read(&mut uart, UART_IIR), UART_IIR_TIMEOUT | MASK_UART_IIR_FIFO);
+	assert_eq!(read(&mut uart, UART_RHR), b'b');
+	assert!(!uart.is_interrupting(), "reading restarts the timeout");
+
+	assert_eq!(read(&mut uart, UART_RHR), b'c');
+	assert_eq!(read(&mut uart, UART_RHR), b'd');
+	assert_eq!(read(&mut uart, UART_LSR) & MASK_UART_LSR_RX, 0);
+
+	// The RX FIFO holds 16 bytes.
+	for _ in 0..=UART_FIFO_SIZE {
+		uart.tick(Some('x'));
+	}
+	assert_eq!(read(&mut uart, UART_LSR) & MASK_UART_LSR_OE, MASK_UART_LSR_OE);
+	write(&mut uart, UART_FCR, MASK_UART_FCR_ENABLE | MASK_UART_FCR_CLEAR_RX);
+	assert_eq!(read(&mut uart, UART_LSR) & MASK_UART_LSR_RX, 0);
+}
+
+#[test]
+fn transmitting() {
+	let mut uart = Uart::new();
+	write(&mut uart, UART_FCR, MASK_UART_FCR_ENABLE);
+	for &byte in b"hi" {
+		write(&mut uart, UART_THR, byte);
+	}
+	assert_eq!(read(&mut uart, UART_LSR) & (MASK_UART_LSR_TX | MASK_UART_LSR_TEMT), 0);
+
+	write(&mut uart, UART_IER, MASK_UART_IER_THRI);
+	assert!(!uart.is_interrupting());
+	assert_eq!(uart.tick(None), Some('h'));
+	assert_eq!(uart.tick(None), Some('i'));
+	assert_eq!(uart.tick(None), None);
+	assert!(uart.is_interrupting());
+	assert_eq!(read(&mut uart, UART_IIR), UART_IIR_THRI | MASK_UART_IIR_FIFO);
+	assert!(!uart.is_interrupting(), "reading IIR clears the THR empty interrupt");
+
+	// Enabling it while THR is empty raises it straight away.
+	write(&mut uart, UART_IER, 0);
+	write(&mut uart, UART_IER, MASK_UART_IER_THRI);
+	assert!(uart.is_interrupting());
+	write(&mut uart, UART_THR, b'!');
+	assert!(!uart.is_interrupting(), "writing THR clears it too");
+}
+
+#[test]
+fn interrupt_priority() {
+	let mut uart = Uart::new();
+	write(&mut uart, UART_IER, 0xf);
+	assert_eq!(read(&mut uart, UART_IIR), UART_IIR_THRI);
+	write(&mut uart, UART_MCR, MASK_UART_MCR_LOOP);
+	assert_eq!(read(&mut uart, UART_IIR), UART_IIR_MSI);
+
+	uart.tick(Some('a'));
+	assert_eq!(read(&mut uart, UART_IIR), UART_IIR_RDI);
+	uart.receive_break();
+	assert_eq!(read(&mut uart, UART_IIR), UART_IIR_RLSI, "the second byte overran");
+
+	assert_eq!(read(&mut uart, UART_LSR), MASK_UART_LSR_RX | MASK_UART_LSR_OE | MASK_UART_LSR_TX | MASK_UART_LSR_TEMT);
+	assert_eq!(read(&mut uart, UART_IIR), UART_IIR_RDI, "reading LSR clears its interrupt");
+	assert_eq!(read(&mut uart, UART_RHR), b'a');
+	assert_eq!(read(&mut uart, UART_MSR) & 0xf, MASK_UART_MSR_DCTS | MASK_UART_MSR_DDSR | MASK_UART_MSR_DDCD);
+	assert_eq!(read(&mut uart, UART_IIR), UART_IIR_NONE);
+	assert!(!uart.is_interrupting());
+}
+
+#[test]
+fn breaks() {
+	let mut uart = Uart::new();
+	write(&mut uart, UART_FCR, MASK_UART_FCR_ENABLE);
+	uart.tick(Some('a'));
+	uart.receive_break();
+
+	assert_eq!(read(&mut uart, UART_LSR) & !(MASK_UART_LSR_TX | MASK_UART_LSR_TEMT), MASK_UART_LSR_RX | MASK_UART_LSR_FIFO_ERROR);
+	assert_eq!(read(&mut uart, UART_RHR), b'a');
+	let lsr = read(&mut uart, UART_LSR);
+	assert_eq!(lsr & (MASK_UART_LSR_BI | MASK_UART_LSR_FIFO_ERROR), MASK_UART_LSR_BI | MASK_UART_LSR_FIFO_ERROR);
+	assert_eq!(read(&mut uart, UART_LSR) & MASK_UART_LSR_ERRORS, 0, "errors are reported once");
+	assert_eq!(read(&mut uart, UART_RHR), 0);
+}
+
+#[test]
+fn loopback_and_modem_status() {
+	let mut uart = Uart::new();
+	assert_eq!(read(&mut uart, UART_MSR), MASK_UART_MSR_CTS | MASK_UART_MSR_DSR | MASK_UART_MSR_DCD);
+
+	write(&mut uart, UART_MCR, MASK_UART_MCR_LOOP | MASK_UART_MCR_RTS | MASK_UART_MCR_OUT1);
+	assert_eq!(read(&mut uart, UART_MSR), MASK_UART_MSR_CTS | MASK_UART_MSR_RI | MASK_UART_MSR_DDSR | MASK_UART_MSR_DDCD);
+	write(&mut uart, UART_MCR, MASK_UART_MCR_LOOP | MASK_UART_MCR_RTS);
+	assert_eq!(read(&mut uart, UART_MSR), MASK_UART_MSR_CTS | MASK_UART_MSR_TERI);
+
+	write(&mut uart, UART_THR, b'z');
+	assert_eq!(uart.tick(None), None, "nothing leaves in loopback");
+	assert_eq!(read(&mut uart, UART_RHR), b'z');
+}
+
+#[test]
+fn divisor_latch_and_scratch() {
+	let mut uart = Uart::new();
+	write(&mut uart, UART_IER, MASK_UART_IER_RDI);
+	write(&mut uart, UART_LCR, MASK_UART_LCR_DLAB | 0x3);
+	write(&mut uart, UART_DLL, 0x0c);
+	write(&mut uart, UART_DLM, 0x01);
+	assert_eq!(read(&mut uart, UART_DLM), 0x01);
+	write(&mut uart, UART_LCR, 0x3);
+	assert_eq!(uart.divisor(), 0x10c);
+	assert_eq!(read(&mut uart, UART_IER), MASK_UART_IER_RDI, "IER is untouched");
+	assert_eq!(read(&mut uart, UART_LCR), 0x3);
+
+	write(&mut uart, UART_SCR, 0x5a);
+	assert_eq!(read(&mut uart, UART_SCR), 0x5a);
+
+	uart.reset();
+	assert_eq!(read(&mut uart, UART_SCR), 0);
+	assert_eq!(uart.divisor(), 0);
+}
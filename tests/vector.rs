@@ -0,0 +1,166 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+fn elements(cpu: &Cpu, reg: usize, eew: u64, count: u64) -> Vec<u64> {
+	(0..count).map(|i| cpu.v.read(reg, i, eew)).collect()
+}
+
+#[test]
+fn integer_loads_and_stores() {
+	let mut code = vec![
+		0x0d0072d7, // vsetvli x5, x0, e32, m1, ta, ma
+		0x0200e087, // vle32.v v1, (x1)
+		0x01008113, // addi x2, x1, 16
+		0x02016107, // vle32.v v2, (x2)
+		0x021101d7, // vadd.vv v3, v1, v2
+		0x9611e257, // vmul.vx v4, v1, x3
+		0x0e1532d7, // vrsub.vi v5, v1, 10
+		0x04008213, // addi x4, x1, 64
+		0x020261a7, // vse32.v v3, (x4)
+		0xc081f357, // vsetivli x6, 3, e16, m1, tu, mu
+		0x0a70d307, // vlse16.v v6, (x1), x7
+		0x026023d7, // vredsum.vs v7, v6, v0
+		0x42702457, // vmv.x.s x8, v7
+		0xc22024f3, // csrr x9, vlenb
+		0xc2102573, // csrr x10, vtype
+		0xc20025f3, // csrr x11, vl
+	];
+	code.resize(0x40, 0);
+	code.extend([1, 2, 3, 4, 10, 20, 30, 40]);
+
+	let (cpu, ram) = common::run(&code, 16, |cpu| {
+		cpu.x[1] = RAM_BASE + 0x100;
+		cpu.x[3] = 3;
+		cpu.x[7] = 4;
+	});
+
+	assert_eq!(cpu.x[5], 4, "vl should be VLMAX for e32, m1");
+	assert_eq!(elements(&cpu, 3, 32, 4), [11, 22, 33, 44]);
+	assert_eq!(elements(&cpu, 4, 32, 4), [3, 6, 9, 12]);
+	assert_eq!(elements(&cpu, 5, 32, 4), [9, 8, 7, 6]);
+	assert_eq!(ram.mem[0x140..0x144], 11u32.to_le_bytes());
+	assert_eq!(ram.mem[0x14c..0x150], 44u32.to_le_bytes());
+	assert_eq!(cpu.x[6], 3);
+	assert_eq!(elements(&cpu, 6, 16, 3), [1, 2, 3], "strided load picks the low halves");
+	assert_eq!(cpu.x[8], 6);
+	assert_eq!(cpu.x[9], DEFAULT_VLEN / 8);
+	assert_eq!(cpu.x[10], 0b001 << 3);
+	assert_eq!(cpu.x[11], 3);
+	assert_eq!(cpu.csr.read(MSTATUS) & MASK_VS, MASK_VS, "VS should be dirty");
+}
+
+#[test]
+fn fixed_point() {
+	let code = [
+		0xcc027057, // vsetivli x0, 4, e8, m1, ta, ma
+		0x5e0fb0d7, // vmv.v.i v1, -1
+		0x5e013157, // vmv.v.i v2, 2
+		0x821101d7, // vsaddu.vv v3, v1, v2
+		0x009022f3, // csrr x5, vxsat
+		0x22112257, // vaaddu.vv v4, v1, v2
+		0x00a15073, // csrwi vxrm, 2
+		0x221122d7, // vaaddu.vv v5, v1, v2
+		0x42402357, // vmv.x.s x6, v4
+		0x425023d7, // vmv.x.s x7, v5
+		0x00f02473, // csrr x8, vcsr
+		0x423024d7, // vmv.x.s x9, v3
+	];
+
+	let (cpu, _) = common::run(&code, code.len(), |_| {});
+
+	assert_eq!(cpu.x[5], 1, "vsaddu should saturate");
+	assert_eq!(cpu.x[6] as i64, -127, "rnu rounds 128.5 up");
+	assert_eq!(cpu.x[7] as i64, -128, "rdn truncates 128.5");
+	assert_eq!(cpu.x[8], VXRM_RDN << 1 | 1);
+	assert_eq!(cpu.x[9], u64::MAX);
+}
+
+#[test]
+fn floating_point() {
+	let code = [
+		0xcd027057, // vsetivli x0, 4, e32, m1, ta, ma
+		0x5e00d0d7, // vfmv.v.f v1, f1
+		0x02115157, // vfadd.vf v2, v1, f2
+		0xb2109157, // vfmacc.vv v2, v1, v1
+		0x062211d7, // vfredusum.vs v3, v2, v4
+		0x423011d7, // vfmv.f.s f3, v3
+		0x76215057, // vmfgt.vf v0, v2, f2
+		0x420822d7, // vcpop.m x5, v0
+		0xcd717057, // vsetivli x0, 2, e32, mf2, ta, ma
+		0x4a161357, // vfwcvt.f.f.v v6, v1
+		0xcd817057, // vsetivli x0, 2, e64, m1, ta, ma
+		0x42601257, // vfmv.f.s f4, v6
+	];
+
+	let (cpu, _) = common::run(&code, code.len(), |cpu| {
+		cpu.f[1] = 0xffff_ffff_3fc0_0000; // 1.5
+		cpu.f[2] = 0xffff_ffff_4010_0000; // 2.25
+	});
+
+	assert_eq!(elements(&cpu, 2, 32, 4), [0x40c0_0000; 4], "1.5 + 2.25 + 1.5 * 1.5 = 6");
+	assert_eq!(cpu.f[3], 0xffff_ffff_41c0_0000, "vfmv.f.s NaN-boxes singles");
+	assert_eq!(cpu.x[5], 4);
+	assert_eq!(cpu.f[4], 0x3ff8_0000_0000_0000);
+}
+
+#[test]
+fn masks_and_permutes() {
+	let code = [
+		0xc1027057, // vsetivli x0, 4, e32, m1, tu, mu
+		0x5208a0d7, // vid.v v1
+		0x7210b057, // vmsleu.vi v0, v1, 1
+		0x00153157, // vadd.vi v2, v1, 10, v0.t
+		0x420822d7, // vcpop.m x5, v0
+		0x3e10b1d7, // vslidedown.vi v3, v1, 1
+		0x3a113257, // vslideup.vi v4, v1, 2
+		0x3211b2d7, // vrgather.vi v5, v1, 3
+		0x5e102357, // vcompress.vm v6, v1, v0
+		0x4208a357, // vfirst.m x6, v0
+		0x5200a3d7, // vmsbf.m v7, v0
+		0x76002457, // vmnand.mm v8, v0, v0
+	];
+
+	let (cpu, _) = common::run(&code, code.len(), |_| {});
+
+	assert_eq!(elements(&cpu, 1, 32, 4), [0, 1, 2, 3]);
+	assert_eq!(cpu.v.read(0, 0, 8) & 0xf, 0b0011);
+	assert_eq!(elements(&cpu, 2, 32, 4), [10, 11, 0, 0], "masked-off elements are undisturbed");
+	assert_eq!(cpu.x[5], 2);
+	assert_eq!(elements(&cpu, 3, 32, 4), [1, 2, 3, 0]);
+	assert_eq!(elements(&cpu, 4, 32, 4), [0, 0, 0, 1]);
+	assert_eq!(elements(&cpu, 5, 32, 4), [3; 4]);
+	assert_eq!(elements(&cpu, 6, 32, 4), [0, 1, 0, 0]);
+	assert_eq!(cpu.x[6], 0);
+	assert_eq!(cpu.v.read(7, 0, 8) & 0xf, 0);
+	assert_eq!(cpu.v.read(8, 0, 8) & 0xf, 0b1100);
+}
+
+#[test]
+fn illegal_configurations() {
+	let code = [
+		0x801072d7, // vsetvl x5, x0, x1
+		0xc2102373, // csrr x6, vtype
+		0x021101d7, // vadd.vv v3, v1, v2
+	];
+	let mut ram = common::Mem::with_program(&code);
+	let mut disk = common::Mem::default();
+
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].x[5] = 1;
+	// SEW=128 is reserved.
+	vm.harts[0].x[1] = 0b100 << 3;
+
+	vm.tick(None).unwrap();
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].x[5], 0, "vl should be cleared");
+	assert_eq!(vm.harts[0].x[6], MASK_VTYPE_VILL);
+	assert!(matches!(vm.tick(None), Err(Exception::IllegalInstruction(0x021101d7))));
+
+	// With mstatus.VS off, even vsetvl traps.
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].csr[MSTATUS] &= !MASK_VS;
+	assert!(matches!(vm.tick(None), Err(Exception::IllegalInstruction(0x801072d7))));
+	assert_ne!(vm.harts[0].csr.read(MISA) & MISA_V, 0);
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+/// Where the driver's structures go in RAM.
+const QUEUE: u64 = RAM_BASE + 0x10000;
+const AVAIL: u64 = QUEUE + DESC_NUM as u64 * 16;
+const USED: u64 = QUEUE + PAGE_SIZE;
+const HEADER: u64 = RAM_BASE + 0x20000;
+const BUFFER: u64 = RAM_BASE + 0x21000;
+const STATUS: u64 = RAM_BASE + 0x21200;
+
+fn disk() -> common::Mem {
+	common::Mem {
+		mem: (0..4 * SECTOR_SIZE).map(|i| (i % 251) as u8).collect(),
+	}
+}
+
+/// Sets up the queue the way a legacy driver does.
+fn init(vm: &mut VM) {
+	vm.bus.store(VIRTIO_GUEST_PAGE_SIZE, PAGE_SIZE, 32).unwrap();
+	vm.bus.store(VIRTIO_QUEUE_SEL, 0, 32).unwrap();
+	vm.bus.store(VIRTIO_QUEUE_NUM, DESC_NUM as u64, 32).unwrap();
+	vm.bus.store(VIRTIO_QUEUE_PFN, QUEUE / PAGE_SIZE, 32).unwrap();
+}
+
+fn desc(vm: &mut VM, index: u64, addr: u64, len: u64, flags: u16, next: u64) {
+	let desc = QUEUE + 16 * index;
+	vm.bus.store(desc, addr, 64).unwrap();
+	vm.bus.store(desc + 8, len, 32).unwrap();
+	vm.bus.store(desc + 12, flags as u64, 16).unwrap();
+	vm.bus.store(desc + 14, next, 16).unwrap();
+}
+
+/// Makes a request for `sector`, to or from `buffer`, available from
+/// descriptor `head`, and notifies the device.
+fn request(vm: &mut VM, head: u64, iotype: u32, sector: u64, buffer: u64) {
+	vm.bus.store(HEADER, iotype as u64, 32).unwrap();
+	vm.bus.store(HEADER + 8, sector, 64).unwrap();
+	let data_flags = if iotype == VIRTIO_BLK_T_IN { VIRTQ_DESC_F_WRITE } else { 0 };
+	desc(vm, head, HEADER, 16, VIRTQ_DESC_F_NEXT, head + 1);
+	desc(vm, head + 1, buffer, SECTOR_SIZE, data_flags | VIRTQ_DESC_F_NEXT, head + 2);
+	desc(vm, head + 2, STATUS, 1, VIRTQ_DESC_F_WRITE, 0);
+	vm.bus.store(STATUS, 0xff, 8).unwrap();
+
+	let idx = vm.bus.load(AVAIL + 2, 16).unwrap();
+	vm.bus.store(AVAIL + 4 + 2 * (idx % DESC_NUM as u64), head, 16).unwrap();
+	vm.bus.store(AVAIL + 2, idx + 1, 16).unwrap();
+	vm.bus.store(VIRTIO_QUEUE_NOTIFY, 0, 32).unwrap();
+}
+
+#[test]
+fn reading_a_sector() {
+	let mut ram = common::Mem::with_program(&[
+		0x00000013, // addi x0, x0, 0
+	]);
+	let mut disk = disk();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.bus.store(PLIC_BASE + PLIC_PRIORITY + 4 * VIRTIO_IRQ as u64, 1, 32).unwrap();
+	vm.bus.store(PLIC_BASE + PLIC_SENABLE, 1 << VIRTIO_IRQ, 32).unwrap();
+	init(&mut vm);
+
+	vm.bus.store(VIRTIO_QUEUE_NOTIFY, 0, 32).unwrap();
+	vm.bus.update_interrupts();
+	assert!(!vm.bus.plic.is_pending(VIRTIO_IRQ), "nothing was made available");
+
+	request(&mut vm, 0, VIRTIO_BLK_T_IN, 2, BUFFER);
+	for i in 0..SECTOR_SIZE {
+		let expected = (2 * SECTOR_SIZE + i) % 251;
+		assert_eq!(vm.bus.load(BUFFER + i, 8).unwrap(), expected);
+	}
+	assert_eq!(vm.bus.load(STATUS, 8).unwrap(), VIRTIO_BLK_S_OK as u64);
+	assert_eq!(vm.bus.load(USED + 2, 16).unwrap(), 1);
+	assert_eq!(vm.bus.load(USED + 4, 32).unwrap(), 0, "the head of the chain");
+	assert_eq!(vm.bus.load(USED + 8, 32).unwrap(), SECTOR_SIZE + 1);
+
+	vm.tick(None).unwrap();
+	assert!(vm.bus.plic.is_pending(VIRTIO_IRQ));
+	assert_ne!(vm.harts[0].csr.read(MIP) & MASK_SEIP, 0);
+}
+
+#[test]
+fn writing_a_sector() {
+	let mut ram = common::Mem::with_program(&[]);
+	let mut disk = disk();
+	{
+		let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+		init(&mut vm);
+		for i in 0..SECTOR_SIZE {
+			vm.bus.store(BUFFER + i, 0xa5, 8).unwrap();
+		}
+		request(&mut vm, 3, VIRTIO_BLK_T_OUT, 1, BUFFER);
+		assert_eq!(vm.bus.load(STATUS, 8).unwrap(), VIRTIO_BLK_S_OK as u64);
+		assert_eq!(vm.bus.load(USED + 8, 32).unwrap(), 1, "only the status was written");
+
+		// The last sector is as far as the disk goes.
+		request(&mut vm, 3, VIRTIO_BLK_T_OUT, 4, BUFFER);
+		assert_eq!(vm.bus.load(STATUS, 8).unwrap(), VIRTIO_BLK_S_IOERR as u64);
+		request(&mut vm, 3, 8, 0, BUFFER);
+		assert_eq!(vm.bus.load(STATUS, 8).unwrap(), VIRTIO_BLK_S_UNSUPP as u64);
+		assert_eq!(vm.bus.load(USED + 2, 16).unwrap(), 3);
+	}
+
+	let sector = SECTOR_SIZE as usize;
+	assert!(disk.mem[sector..2 * sector].iter().all(|&byte| byte == 0xa5));
+	assert_eq!(disk.mem[2 * sector], (2 * SECTOR_SIZE % 251) as u8);
+}
+
+#[test]
+fn failures_are_reported_by_the_device() {
+	let mut ram = common::Mem::with_program(&[
+		0x0002a023, // sw x0, 0(x5)
+	]);
+	let mut disk = disk();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].x[5] = VIRTIO_QUEUE_NOTIFY;
+	vm.bus.store(VIRTIO_GUEST_PAGE_SIZE, PAGE_SIZE, 32).unwrap();
+
+	// Without a queue, the guest's notify still completes.
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].pc, RAM_BASE + 4);
+	assert_eq!(vm.harts[0].csr[MCAUSE], 0);
+	let status = vm.bus.load(VIRTIO_STATUS, 32).unwrap();
+	assert_eq!(status as u32, MASK_VIRTIO_STATUS_NEEDS_RESET);
+
+	// Buffers outside RAM fail just that request.
+	vm.bus.store(VIRTIO_STATUS, 0, 32).unwrap();
+	init(&mut vm);
+	request(&mut vm, 0, VIRTIO_BLK_T_IN, 0, 0x1000);
+	assert_eq!(vm.bus.load(STATUS, 8).unwrap(), VIRTIO_BLK_S_IOERR as u64);
+	assert_eq!(vm.bus.load(USED + 2, 16).unwrap(), 1);
+	assert_eq!(vm.bus.load(VIRTIO_STATUS, 32).unwrap(), 0);
+}
+
+#[test]
+fn reinitialising_the_queue() {
+	let mut ram = common::Mem::with_program(&[]);
+	let mut disk = disk();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	init(&mut vm);
+	request(&mut vm, 0, VIRTIO_BLK_T_IN, 0, BUFFER);
+	request(&mut vm, 0, VIRTIO_BLK_T_IN, 0, BUFFER);
+	assert_eq!(vm.bus.load(USED + 2, 16).unwrap(), 2);
+
+	// A driver starting over zeroes its rings, and only its new request
+	// is processed.
+	vm.bus.store(VIRTIO_STATUS, 0, 32).unwrap();
+	for offset in (0..2 * PAGE_SIZE).step_by(8) {
+		vm.bus.store(QUEUE + offset, 0, 64).unwrap();
+	}
+	init(&mut vm);
+	request(&mut vm, 0, VIRTIO_BLK_T_IN, 1, BUFFER);
+	assert_eq!(vm.bus.load(USED + 2, 16).unwrap(), 1);
+	assert_eq!(vm.bus.load(BUFFER, 8).unwrap(), SECTOR_SIZE % 251);
+}
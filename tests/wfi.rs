@@ -0,0 +1,59 @@
+mod common;
+
+use rrv64g::prelude::*;
+
+const WFI: u32 = 0x10500073;
+
+#[test]
+fn skips_to_timer() {
+	let mut ram = common::Mem::with_program(&[WFI]);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].csr[MTVEC] = RAM_BASE + 0x100;
+	vm.harts[0].csr.write(MIE, MASK_MTIP);
+	vm.harts[0].csr[MSTATUS] |= MASK_MIE;
+	vm.bus.store(CLINT_BASE + CLINT_MTIMECMP, 1_000_000, 64).unwrap();
+
+	vm.tick(None).unwrap();
+	assert!(vm.harts[0].waiting);
+	assert!(!vm.is_idle(), "the timer is armed");
+
+	vm.tick(None).unwrap();
+	assert_eq!(vm.bus.clint.mtime(), 1_000_000);
+	assert!(!vm.harts[0].waiting);
+	assert_eq!(vm.harts[0].csr[MCAUSE], MASK_INTERRUPT_BIT | 7);
+	assert_eq!(vm.harts[0].csr[MEPC], RAM_BASE + 4);
+	assert_eq!(vm.harts[0].pc, RAM_BASE + 0x100);
+}
+
+#[test]
+fn woken_by_uart() {
+	let mut ram = common::Mem::with_program(&[
+		WFI,
+		0x00128293, // addi x5, x5, 1
+	]);
+	let mut disk = common::Mem::default();
+	let mut vm = VM::new(&mut ram, common::RAM_SIZE, &mut disk);
+	vm.harts[0].pc = RAM_BASE;
+	vm.harts[0].csr.write(MIE, MASK_SEIP);
+	vm.bus.store(PLIC_BASE + PLIC_PRIORITY + 4 * UART_IRQ as u64, 1, 32).unwrap();
+	vm.bus.store(PLIC_BASE + PLIC_SENABLE, 1 << UART_IRQ, 32).unwrap();
+	vm.bus.store(UART_BASE + UART_IER, MASK_UART_IER_RDI as u64, 8).unwrap();
+
+	vm.tick(None).unwrap();
+	for _ in 0..3 {
+		assert!(vm.is_idle());
+		vm.tick(None).unwrap();
+	}
+	assert_eq!(vm.harts[0].pc, RAM_BASE + 4);
+	assert_eq!(vm.bus.clint.mtime(), 1, "only wfi itself takes time without a deadline");
+
+	// The interrupt isn't globally enabled, so execution resumes after wfi.
+	vm.tick(Some('a')).unwrap();
+	vm.tick(None).unwrap();
+	assert!(!vm.is_idle());
+	vm.tick(None).unwrap();
+	assert_eq!(vm.harts[0].x[5], 1);
+	assert_eq!(vm.harts[0].mode, Mode::Machine);
+}